@@ -0,0 +1,13 @@
+fn main() {
+    // ffi特性下编译C的冒烟测试，只有src/example.rs的test_ffi_c引用它，其他情况下不会被链接
+    #[cfg(feature = "ffi")]
+    {
+        println!("cargo:rerun-if-changed=tests/c/ffi_smoke.c");
+        println!("cargo:rerun-if-changed=include/pi_world.h");
+        cc::Build::new()
+            .file("tests/c/ffi_smoke.c")
+            .include("include")
+            .compile("pi_world_ffi_smoke");
+    }
+    println!("cargo:rerun-if-changed=build.rs");
+}
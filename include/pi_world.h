@@ -0,0 +1,61 @@
+/* pi_world的C接口，需要开启ffi特性，说明见src/ffi.rs */
+#ifndef PI_WORLD_H
+#define PI_WORLD_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct WorldHandle WorldHandle;
+typedef struct QueryHandle QueryHandle;
+
+/* 错误码，0为成功 */
+enum PiWorldError {
+    PI_WORLD_OK = 0,
+    PI_WORLD_NULL_POINTER = -1,
+    PI_WORLD_NO_SUCH_COMPONENT = -2,
+    PI_WORLD_NO_SUCH_ENTITY = -3,
+    PI_WORLD_DUPLICATE_COMPONENT = -4,
+    PI_WORLD_MISSING_COMPONENT = -5,
+    PI_WORLD_NO_MATCH_ARCHETYPE = -6,
+    PI_WORLD_NO_MATCH_ENTITY = -7,
+    PI_WORLD_NO_SUCH_ROW = -8,
+    PI_WORLD_NO_SUCH_RES = -9,
+    PI_WORLD_REPEAT_ALTER = -10,
+    PI_WORLD_GROUP_LOCKED = -11,
+    PI_WORLD_MISSING_READ_ACCESS = -12,
+    PI_WORLD_NOT_DEFAULTABLE = -13,
+    PI_WORLD_DUPLICATE_ENTITY = -14,
+    PI_WORLD_TOO_MANY_ENTITIES = -15,
+    PI_WORLD_NOT_SERIALIZABLE = -17,
+};
+
+/* ptrs依次为读的组件和写的组件的指针，只在本次回调内有效，回调内不能调用同一world的其他接口 */
+typedef void (*PiQueryCallback)(uint64_t entity, uint8_t *const *ptrs, void *user_data);
+
+uint64_t pi_entity_null(void);
+void pi_world_destroy(WorldHandle *handle);
+/* values[i]只在调用期间读取，按位复制进world，失败返回pi_entity_null() */
+uint64_t pi_world_spawn(WorldHandle *handle, const uint64_t *comp_ids, const uint8_t *const *values, size_t count);
+int32_t pi_world_despawn(WorldHandle *handle, uint64_t entity);
+bool pi_world_contains(WorldHandle *handle, uint64_t entity);
+void pi_world_settle(WorldHandle *handle);
+int32_t pi_query_create(WorldHandle *handle,
+                        const uint64_t *read_ids, size_t read_count,
+                        const uint64_t *write_ids, size_t write_count,
+                        const uint64_t *with_ids, size_t with_count,
+                        const uint64_t *without_ids, size_t without_count,
+                        QueryHandle **out);
+/* QueryHandle必须在WorldHandle之前释放 */
+void pi_query_destroy(QueryHandle *query);
+int32_t pi_query_iter(WorldHandle *handle, QueryHandle *query, PiQueryCallback callback, void *user_data);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -27,7 +27,9 @@ use crate::column::{BlobRef, Column};
 use crate::fetch::FetchComponents;
 use crate::filter::FilterComponents;
 use crate::insert::Bundle;
+use crate::invariant::same_layout;
 use crate::query::{LocalIndex, Query, QueryError, QueryIter, QueryState};
+use crate::table::DeferredScope;
 use crate::system::SystemMeta;
 use crate::system_params::SystemParam;
 use crate::utils::VecExt;
@@ -254,8 +256,9 @@ impl<
             PhantomData,
         )
     }
-    fn align(world: &World, _system_meta: &SystemMeta, state: &mut Self::State) {
-        state.0.align(world);
+    fn align(world: &World, system_meta: &SystemMeta, state: &mut Self::State) {
+        let len = system_meta.align_len(world);
+        state.0.align_to(world, len);
     }
 
     fn get_param<'w>(
@@ -375,6 +378,8 @@ impl<A: Bundle> AlterState<A> {
         components: A,
         tick: Tick,
     ) -> Result<bool, QueryError> {
+        check_frozen(world);
+        let _scope = DeferredScope::enter();
         let mapping = unsafe { self.vec.get_unchecked_mut(ar_index.index()) };
         // println!("alter: {:?}", (e, src_row, ar_index));
         let (is_new, _new_ar) = self.state.find_mapping(world, mapping, false);
@@ -402,6 +407,7 @@ impl<A: Bundle> AlterState<A> {
             addr.mark();
         }
         let (_, dst_row) = mapping.dst.alloc();
+        mapping.dst.record_allocated(dst_row.into(), e);
         // println!("alter: {:?}", (e, src_row, dst_row, &mapping.dst));
         let item = unsafe {
             self.bundle_vec
@@ -409,6 +415,7 @@ impl<A: Bundle> AlterState<A> {
                 .assume_init_ref()
         };
         A::insert(item, components, e, dst_row.into(), tick);
+        self.state.init_required(world, mapping, e, dst_row.into(), tick);
         // 记录移除行
         mapping.push(
             addr.row,
@@ -475,6 +482,7 @@ impl AState {
         vec: &mut Vec<ArchetypeMapping>, // 记录所有的原型映射
         mapping_dirtys: &mut Vec<LocalIndex>,
     ) {
+        let _scope = DeferredScope::enter();
         // 处理标记移除的条目， 将要移除的组件释放，将相同的组件拷贝
         for ar_index in mapping_dirtys.drain(..) {
             let am = unsafe { vec.get_unchecked_mut(ar_index.index()) };
@@ -488,13 +496,13 @@ impl AState {
                 if old.is_null() {
                     // 已经被destroy
                     // 目标原型上移除该行
-                    self.destroy_add_columns(am, *dst_row, *e);
+                    self.destroy_add_columns(world, am, *dst_row, *e);
                     // 删除move条目
                     am.moves.swap_remove(i);
                 }
             }
             self.move_columns(am);
-            self.remove_columns(am);
+            self.remove_columns(world, am);
             // 设置目标原型的entity及entity上的EntityAddr
             for (_, dst_row, e) in am.moves.iter() {
                 am.dst.set(*dst_row, *e);
@@ -503,11 +511,32 @@ impl AState {
             am.moves.clear();
         }
     }
-    /// 目标原型上移除该行， 并且销毁add的列
-    pub(crate) fn destroy_add_columns(&self, am: &ArchetypeMapping, dst_row: Row, e: Entity) {
+    // 用默认值初始化目标原型上补上的依赖组件
+    pub(crate) fn init_required(
+        &self,
+        world: &World,
+        am: &ArchetypeMapping,
+        e: Entity,
+        dst_row: Row,
+        tick: Tick,
+    ) {
+        for i in am.required_indexs.clone() {
+            let c = unsafe { self.adding.get_unchecked(i) };
+            let column = c.blob_ref_unchecked(am.dst_index);
+            world.required.default_fn(c.info().index).unwrap()(column.load(dst_row, e));
+            column.added_tick(e, dst_row, tick);
+        }
+    }
+    /// 目标原型上移除该行， 并且销毁add的列，按world的释放策略立即释放或记入释放队列
+    pub(crate) fn destroy_add_columns(&self, world: &World, am: &ArchetypeMapping, dst_row: Row, e: Entity) {
+        let deferred = world.cleanup_queue.is_deferred();
         for index in am.add_indexs.clone() {
             let c = unsafe { self.adding.get_unchecked(index) };
             if c.info().drop_fn.is_some() {
+                if deferred {
+                    world.cleanup_queue.defer(c, am.dst_index, dst_row, e);
+                    continue;
+                }
                 let column = c.blob_ref_unchecked(am.dst_index);
                 column.drop_row_unchecked(dst_row, e);
             }
@@ -519,6 +548,10 @@ impl AState {
     pub(crate) fn move_columns(&self, am: &mut ArchetypeMapping) {
         for index in am.move_indexs.clone() {
             let c = unsafe { self.moving.get_unchecked(index) };
+            debug_assert!(
+                am.dst.get_column(c.info().index).is_some_and(|d| same_layout(c, d)),
+                "moved column {} has a different layout in archetype {}", c.info().type_name(), am.dst.name()
+            );
             let src_column = c.blob_ref_unchecked(am.src.index());
             let dst_column = c.blob_ref_unchecked(am.dst.index());
             Self::move_column(src_column, dst_column, &am.moves, c.info().is_tick());
@@ -537,16 +570,27 @@ impl AState {
         }
         if is_tick {
             for (src_row, dst_row, _e) in moves.iter() {
-                let tick = src_column.get_tick_unchecked(*src_row);
-                dst_column.set_tick_unchecked(*dst_row, tick);
+                src_column.copy_ticks_to(*src_row, &dst_column, *dst_row);
             }
         }
     }
-    // 将需要移除的全部源组件移除，如果目标原型的移除列上有对应监听，则记录移除行
-    pub(crate) fn remove_columns(&self, am: &mut ArchetypeMapping) {
+    // 将需要移除的全部源组件移除，按world的释放策略立即释放或记入释放队列，如果目标原型的移除列上有对应监听，则记录移除行
+    pub(crate) fn remove_columns(&self, world: &World, am: &mut ArchetypeMapping) {
+        let deferred = world.cleanup_queue.is_deferred();
+        // 先调用所有钩子，再释放，钩子中能读取同时被移除的组件
+        let removed = || am.removed_indexs.clone().map(|i| unsafe { self.removing.get_unchecked(i) });
+        if removed().any(|c| c.info.on_remove.is_some()) {
+            for (src_row, _dst_row, e) in am.moves.iter() {
+                world.run_remove_hooks(&am.src, *src_row, *e, removed());
+            }
+        }
         for i in am.removed_indexs.clone().into_iter() {
             let c = unsafe { self.removing.get_unchecked(i) };
-            if c.info().drop_fn.is_some() {
+            if c.info().drop_fn.is_some() && deferred {
+                for (src_row, _dst_row, e) in am.moves.iter() {
+                    world.cleanup_queue.defer(c, am.src.index(), *src_row, *e);
+                }
+            } else if c.info().drop_fn.is_some() {
                 let column = c.blob_ref_unchecked(am.src.index());
                 for (src_row, _dst_row, e) in am.moves.iter() {
                     // println!("drop_row_unchecked====={:?}", (c.info.type_name(), i, am.src.index(), _e,  src_row));
@@ -577,7 +621,7 @@ impl AState {
         let move_start = self.moving.len();
         let removing_start = self.removing.len();
         // 如果本地没有找到，则创建components，去world上查找或创建
-        let info = mapping.src.alter(
+        let mut info = mapping.src.alter(
             world,
             &mut self.sorted_add_removes,
             &mut self.adding,
@@ -585,7 +629,37 @@ impl AState {
             &mut self.removing,
             existed_adding_is_move,
         );
+        let required_start = self.adding.len();
+        if !world.required.is_empty() {
+            // 补上新增组件缺少的依赖，本次移除的组件不补
+            let added: Vec<ComponentIndex> = self.adding[add_start..]
+                .iter()
+                .map(|c| c.info().index)
+                .collect();
+            let mut present: Vec<ComponentIndex> = info
+                .sorted_components
+                .iter()
+                .map(|c| c.info().index)
+                .collect();
+            present.extend(
+                self.sorted_add_removes
+                    .iter()
+                    .filter(|(_, add)| !*add)
+                    .map(|(i, _)| *i),
+            );
+            let missing = world.required.missing(&added, &present);
+            if !missing.is_empty() {
+                let mut vec = info.sorted_components;
+                for r in missing {
+                    let c = world.get_column(r).unwrap().clone();
+                    self.adding.push(c.clone());
+                    vec.push(c);
+                }
+                info = ArchetypeInfo::sort(vec);
+            }
+        }
         mapping.add_indexs = add_start..self.adding.len();
+        mapping.required_indexs = required_start..self.adding.len();
         mapping.move_indexs = move_start..self.moving.len();
         mapping.removed_indexs = removing_start..self.removing.len();
         // 有可能和本system的ar重合，转成立地修改，由于alter是有可写引用的，也不会有引用被改写的问题
@@ -596,6 +670,10 @@ impl AState {
             return (true, false);
         }
         let dst = world.find_archtype(info);
+        #[cfg(debug_assertions)]
+        if let Err(e) = world.check_edge_columns(&mapping.src, &dst) {
+            panic!("alter edge {} -> {}: {}", mapping.src.name(), dst.name(), e);
+        }
         mapping.dst_index = dst.index();
         mapping.dst = dst;
         (true, true)
@@ -612,6 +690,8 @@ impl AState {
         if !src_row.is_null() {
             mapping.src.mark_remove(src_row);
             mapping.move_columns(src_row, dst_row, e, &self.moving);
+            let removed = mapping.removed_indexs.clone().map(|i| unsafe { self.removing.get_unchecked(i) });
+            world.run_remove_hooks(&mapping.src, src_row, e, removed);
             mapping.remove_columns(src_row, e, &self.removing);
         }
         // 写目标行的Entity
@@ -621,9 +701,10 @@ impl AState {
     }
     /// 销毁实体
     fn destroy(&self, world: &World, e: Entity) -> Result<bool, QueryError> {
+        check_frozen(world);
         let (addr, _local_index) = self.check(world, e)?;
         if addr.row.is_null() {
-            world.entities.remove(e).unwrap();
+            world.on_entity_removed(world.empty_archetype(), Row::null(), e);
             return Ok(true);
         }
         let ar = unsafe { world.get_archetype_unchecked(addr.archetype_index()) };
@@ -631,11 +712,13 @@ impl AState {
     }
     /// 销毁
     pub(crate) fn destroy_row(world: &World, ar: &Archetype, row: Row) -> Result<bool, QueryError> {
-        let e = ar.destroy(row);
+        let _scope = DeferredScope::enter();
+        world.run_destroy_hooks(ar, row);
+        let e = ar.destroy(row, &world.cleanup_queue);
         if e.is_null() {
             return Err(QueryError::NoSuchRow(row));
         }
-        world.entities.remove(e).unwrap();
+        world.on_entity_removed(ar, row, e);
         Ok(true)
     }
     // // 检查entity是否正确，包括对应的原型是否在本查询内，并将查询到的原型本地位置记到cache_mapping上
@@ -678,6 +761,7 @@ pub struct ArchetypeMapping {
     pub(crate) dst: ShareArchetype,            // 映射到的目标原型
     pub(crate) dst_index: ArchetypeIndex,      // 目标原型在World原型数组中的位置
     pub(crate) add_indexs: Range<usize>,       // 目标原型上新增的组件的起始和结束位置
+    pub(crate) required_indexs: Range<usize>,  // 新增组件中补上的依赖组件，在add_indexs的末尾
     pub(crate) move_indexs: Range<usize>,      // 源原型和目标原型的组件映射的起始和结束位置
     pub(crate) removed_indexs: Range<usize>,   // 源原型上被移除的组件的起始和结束位置
     pub(crate) moves: Vec<(Row, Row, Entity)>, // 本次标记移动的条目
@@ -691,6 +775,7 @@ impl ArchetypeMapping {
             dst_index: ArchetypeIndex::null(),
             move_indexs: 0..0,
             add_indexs: 0..0,
+            required_indexs: 0..0,
             removed_indexs: 0..0,
             // move_removed_indexs: 0..0,
             moves: Default::default(),
@@ -720,6 +805,10 @@ impl ArchetypeMapping {
     ) {
         for index in self.move_indexs.clone() {
             let c = unsafe { moving.get_unchecked(index) };
+            debug_assert!(
+                self.dst.get_column(c.info().index).is_some_and(|d| same_layout(c, d)),
+                "moved column {} has a different layout in archetype {}", c.info().type_name(), self.dst.name()
+            );
             let src_column = c.blob_ref_unchecked(self.src.index());
             let dst_column = c.blob_ref_unchecked(self.dst.index());
             self.move_column(
@@ -745,8 +834,7 @@ impl ArchetypeMapping {
         let src_data: *mut u8 = src_column.get_row(src_row, e);
         dst_column.write_row(dst_row, e, src_data);
         if is_tick {
-            let tick = src_column.get_tick_unchecked(src_row);
-            dst_column.set_tick_unchecked(dst_row, tick);
+            src_column.copy_ticks_to(src_row, &dst_column, dst_row);
         }
     }
     pub(crate) fn remove_columns(&self, src_row: Row, e: Entity, removing: &Vec<Share<Column>>) {
@@ -764,6 +852,15 @@ impl ArchetypeMapping {
     }
 }
 
+// tick冻结时Alter的结构修改会带着冻结的tick进入添加事件，调试模式下禁止
+#[inline(always)]
+fn check_frozen(_world: &World) {
+    debug_assert!(
+        !_world.is_ticks_frozen(),
+        "Alter: structural change while ticks are frozen"
+    );
+}
+
 pub struct AlterIter<'w, Q: FetchComponents + 'static, F: FilterComponents + 'static, A: Bundle> {
     it: QueryIter<'w, Q, F>,
     state: &'w mut AlterState<A>,
@@ -774,6 +871,7 @@ impl<'w, Q: FetchComponents, F: FilterComponents, A: Bundle> AlterIter<'w, Q, F,
     }
     /// 标记销毁当前迭代的实体
     pub fn destroy(&mut self) -> Result<bool, QueryError> {
+        check_frozen(self.it.world);
         AState::destroy_row(&self.it.world, &self.it.ar, self.it.row)
     }
     pub fn alter(&mut self, components: A) -> Result<bool, QueryError> {
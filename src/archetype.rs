@@ -7,29 +7,32 @@
 /// 可能在执行图的某个特定时刻，多个system对新原型有插入的需求，这个时候Alter利用AppendVec的原子保护来保证插入安全。
 /// Alter在本地没有找到原型时，有2种情况，一种是找到已存在的原型，一种是没有原型要新创建原型。
 /// 新创建原型，通过ready来保护，通知执行图调整图。
-/// 已存在的原型，是有可能正在被其他System读写。为了杜绝这种情况，要求在对应原型创建时，ArchetypeDepend计算依赖时，将返回Alter后的原型idArchetypeDepend::Alter(u128)，执行图会查找或新建alter原型id的图节点，并保证一定会在图依赖上有安全的读写。
+/// 已存在的原型，是有可能正在被其他System读写。为了杜绝这种情况，要求在对应原型创建时，ArchetypeDepend计算依赖时，将返回Alter后的原型idArchetypeDepend::Alter(ComponentId)，执行图会查找或新建alter原型id的图节点，并保证一定会在图依赖上有安全的读写。
 ///
 /// 只有主调度完毕后，每个原型进行整理，只有整理才会调整Row。在整理前，Row都是递增的。
 ///
 use core::fmt::*;
-use std::any::TypeId;
+use std::any::{Any, TypeId};
 use std::borrow::Cow;
-use std::hash::{DefaultHasher, Hash, Hasher};
-use std::mem::{needs_drop, size_of, transmute};
+use std::mem::{align_of, needs_drop, size_of};
 use std::ops::{Deref, DerefMut};
 use std::sync::atomic::Ordering;
 
 use bitflags::bitflags;
 use pi_null::Null;
-use pi_share::{Share, ShareBool};
+use pi_share::{Share, ShareBool, ShareMutex};
 
 use crate::column::Column;
+use crate::component_id::{archetype_id, ComponentId};
+use crate::diff::{ApplyFn, DiffFn};
+use crate::stamp::CloneFn;
 use crate::system::TypeInfo;
 use crate::table::Table;
 use crate::world::{ComponentIndex, SetFromWorld, World};
 
 pub type ShareArchetype = Share<Archetype>;
 
+/// 实体在原型中的行，整理时可能被移动，外部缓存时需要配合原型的行代数判断是否有效
 #[derive(Debug, Clone, Copy, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Row(pub(crate) u32);
 impl Row {
@@ -60,6 +63,7 @@ impl pi_null::Null for Row {
         self.0 == u32::null()
     }
 }
+/// 原型在world上的索引
 #[derive(Debug, Clone, Copy, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ArchetypeIndex(pub(crate) i32);
 impl ArchetypeIndex {
@@ -110,14 +114,14 @@ bitflags! {
 #[derive(Debug, PartialEq)]
 pub enum ArchetypeDepend {
     Flag(Flags),
-    Alter((u128, Cow<'static, str>, Vec<Share<ComponentInfo>>)),
+    Alter((ComponentId, Cow<'static, str>, Vec<Share<ComponentInfo>>)),
 }
 #[derive(Debug, PartialEq)]
 pub struct ArchetypeDependResult {
     pub flag: Flags,
     pub reads: Vec<ComponentIndex>,
     pub writes: Vec<ComponentIndex>,
-    pub alters: Vec<(u128, Cow<'static, str>, Vec<Share<ComponentInfo>>)>,
+    pub alters: Vec<(ComponentId, Cow<'static, str>, Vec<Share<ComponentInfo>>)>,
 }
 impl ArchetypeDependResult {
     pub fn new() -> Self {
@@ -184,6 +188,7 @@ pub struct Archetype {
     name: Cow<'static, str>,
     table: Table,
     pub(crate) ready: ShareBool, //表示是否已就绪，执行图已经修改正确
+    user_data: ShareMutex<Vec<(TypeId, Share<dyn Any + Send + Sync>)>>, // 用户数据，每种类型一个
 }
 
 impl Archetype {
@@ -221,6 +226,7 @@ impl Archetype {
             name,
             table: Table::new(info.sorted_components),
             ready: ShareBool::new(false),
+            user_data: ShareMutex::new(Vec::new()),
         }
     }
     // 获得所在的World原型index
@@ -238,6 +244,24 @@ impl Archetype {
     pub fn ready(&self) -> bool {
         self.ready.load(Ordering::Relaxed)
     }
+    /// 设置原型的用户数据，同类型的覆盖。在World::on_archetype_created的钩子中设置，
+    /// 可以保证任何system迭代该原型前数据已存在
+    pub fn set_user_data<T: Send + Sync + 'static>(&self, value: T) {
+        let value: Share<dyn Any + Send + Sync> = Share::new(value);
+        let tid = TypeId::of::<T>();
+        let mut vec = self.user_data.lock().unwrap();
+        match vec.iter_mut().find(|(t, _)| *t == tid) {
+            // 旧值由持有者的引用计数保持，覆盖不会使已取出的值失效
+            Some((_, v)) => *v = value,
+            None => vec.push((tid, value)),
+        }
+    }
+    /// 获得原型的用户数据，返回共享的引用，之后的覆盖不影响已取出的值
+    pub fn get_user_data<T: Send + Sync + 'static>(&self) -> Option<Share<T>> {
+        let vec = self.user_data.lock().unwrap();
+        let (_, v) = vec.iter().find(|(t, _)| *t == TypeId::of::<T>())?;
+        Share::downcast::<T>(v.clone()).ok()
+    }
     // 从本原型上计算改变后了原型信息， 在该原型下添加一些组件，删除一些组件，得到新原型信息，及移动的组件
     pub(crate) fn alter(
         &self,
@@ -360,8 +384,9 @@ impl Debug for Archetype {
         f.debug_struct("Archetype")
             .field("id", &self.id)
             .field("name", &self.name)
-            .field("table", &self.table)
             .field("index", &self.index)
+            .field("table", &self.table)
+            .field("ready", &self.ready())
             .finish()
     }
 }
@@ -377,13 +402,7 @@ impl ArchetypeInfo {
         Self::new(components)
     }
     pub(crate) fn new(sorted_components: Vec<Share<Column>>) -> Self {
-        let mut hasher = DefaultHasher::new();
-        // let mut id = 0;
-        for c in sorted_components.iter() {
-            c.info().index.hash(&mut hasher);
-            // id ^= c.info().id();
-        }
-        let id = hasher.finish();
+        let id = archetype_id(sorted_components.iter().map(|c| c.info().index));
         Self {
             id,
             sorted_components,
@@ -414,18 +433,26 @@ pub struct ComponentInfo {
     pub set_fn: Option<fn(&mut World, *mut u8)>,
     pub index: ComponentIndex, // 在world上的索引
     pub mem_size: u32,             // 内存大小
+    pub mem_align: u32,            // 内存对齐，create时按大小推断，不小于实际的对齐
     pub tick_info: u8,            // tick信息 tick = 1 changed = 2 added = 4 removed = 8
+    pub diff_fn: Option<DiffFn>,   // 差异函数，用于World::diff_since
+    pub apply_fn: Option<ApplyFn>, // 应用补丁的函数，用于World::apply_diff
+    pub clone_fn: Option<CloneFn>, // 复制函数，用于World::stamp
+    pub raw_pod: bool,             // 声明了RawPod，可以按原始字节导出
+    pub stable_name: Option<&'static str>, // 注册的稳定名称，用于计算stable_id
 }
 impl ComponentInfo {
     pub fn of<T: 'static>(tick_info: u8) -> ComponentInfo {
-        ComponentInfo::create(
+        let mut info = ComponentInfo::create(
             TypeId::of::<T>(),
             std::any::type_name::<T>().into(),
             get_drop::<T>(),
             <T as SetFromWorld>::set_fn(),
             size_of::<T>() as u32,
             tick_info,
-        )
+        );
+        info.mem_align = align_of::<T>() as u32;
+        info
     }
     pub fn create(
         type_id: TypeId,
@@ -441,8 +468,15 @@ impl ComponentInfo {
             drop_fn,
             set_fn,
             mem_size,
+            // 大小是对齐的整数倍，大小的最大2的幂因子不小于实际的对齐
+            mem_align: if mem_size == 0 { 1 } else { 1 << mem_size.trailing_zeros() },
             index: ComponentIndex::null(),
             tick_info,
+            diff_fn: None,
+            apply_fn: None,
+            clone_fn: None,
+            raw_pod: false,
+            stable_name: None,
         }
     }
     pub fn type_id(&self) -> &TypeId {
@@ -455,18 +489,29 @@ impl ComponentInfo {
     pub fn size(&self) -> usize {
         self.mem_size as usize
     }
-    pub fn id(&self) -> u128 {
-        unsafe { transmute::<TypeId, u128>(*self.type_id()) }.into()
+    /// 稳定id，注册了稳定名称时由稳定名称计算，否则由类型名计算。
+    /// 类型名在不同的编译器版本间可能不同，需要持久化或跨进程比较时应注册稳定名称，见World::register_stable_name
+    pub fn stable_id(&self) -> u64 {
+        let name = match self.stable_name {
+            Some(name) => name,
+            None => self.type_name(),
+        };
+        // FNV-1a
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for b in name.as_bytes() {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
+    pub fn id(&self) -> ComponentId {
+        ComponentId::of(self.type_id())
     }
     pub fn is_tick(&self) -> bool {
         self.tick_info > 0
     }
-    pub fn calc_id(vec: &Vec<ComponentInfo>) -> u128 {
-        let mut id = 0;
-        for c in vec.iter() {
-            id ^= c.id();
-        }
-        id
+    pub fn calc_id(vec: &Vec<ComponentInfo>) -> ComponentId {
+        vec.iter().fold(ComponentId::default(), |id, c| id ^ c.id())
     }
 }
 
@@ -0,0 +1,359 @@
+//! 实体归档
+//! 将实体从world中移出，组件数据按原型放入紧凑的缓冲中，之后可以再恢复到world上，恢复的实体会分配新的Entity。
+//! 组件数据是按位移动的，归档期间组件的所有权在归档中，归档被丢弃时会释放其中的组件。
+//! 恢复时新旧实体记录在EntityMapper中，注册了引用策略（register_reference_policy）的组件，其中引用的实体在所有实体恢复后用映射修正。
+//! 归档可以转为ArchiveData用serde写入磁盘，组件按RawPod的原始字节或注册的差异函数（register_diffable）编码，其他组件不能转换。
+//! 原型快照只复制一个原型的组件数据，不移除实体，可用于编辑器中对一类实体的撤销。
+//! let archive = world.archive_filtered::<With<Chunk3>>(); world.unarchive(archive, &mut mapper);
+//!
+
+use std::collections::{HashMap, HashSet};
+use std::mem::size_of;
+
+use pi_key_alloter::{Key, KeyData};
+use pi_null::Null;
+use pi_share::Share;
+use serde::{Deserialize, Serialize};
+
+use crate::archetype::{ArchetypeIndex, ArchetypeInfo, Row};
+use crate::column::Column;
+use crate::filter::FilterComponents;
+use crate::merge::EntityMapper;
+use crate::query::QueryError;
+use crate::reference::MapFn;
+use crate::world::{ComponentIndex, Entity, World};
+
+/// 归档的实体，可以发送到其他线程写入磁盘
+#[derive(Default)]
+pub struct EntityArchive {
+    groups: Vec<ArchiveGroup>,
+}
+unsafe impl Send for EntityArchive {}
+unsafe impl Sync for EntityArchive {}
+
+// 同一原型的实体
+struct ArchiveGroup {
+    columns: Vec<Share<Column>>, // 按组件索引排序的列
+    entities: Vec<Entity>,       // 归档时的实体
+    data: Vec<Vec<u128>>,        // 每列的数据，按实体顺序连续存放，用u128保证对齐
+}
+impl ArchiveGroup {
+    fn new(columns: Vec<Share<Column>>) -> Self {
+        let data = vec![Vec::new(); columns.len()];
+        ArchiveGroup {
+            columns,
+            entities: Vec::new(),
+            data,
+        }
+    }
+    // 获得指定列的第i个实体的数据
+    fn get(&mut self, column: usize, i: usize) -> *mut u8 {
+        let size = self.columns[column].info().size();
+        unsafe { (self.data[column].as_mut_ptr() as *mut u8).add(i * size) }
+    }
+    // 为下一个实体分配每列的空间，返回实体的序号
+    fn alloc(&mut self) -> usize {
+        let i = self.entities.len();
+        for (c, vec) in self.columns.iter().zip(self.data.iter_mut()) {
+            let size = c.info().size();
+            vec.resize(((i + 1) * size).div_ceil(size_of::<u128>()), 0);
+        }
+        i
+    }
+}
+
+/// 可以序列化的归档，组件用稳定id对应，实体为Entity的位
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveData {
+    pub groups: Vec<ArchiveGroupData>,
+}
+/// 同一原型的实体，values按组件再按实体存放每个组件值的编码
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveGroupData {
+    pub components: Vec<u64>,
+    pub entities: Vec<u64>,
+    pub values: Vec<Vec<Vec<u8>>>,
+}
+
+impl EntityArchive {
+    /// 归档的实体数量
+    pub fn len(&self) -> usize {
+        self.groups.iter().map(|g| g.entities.len()).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// 归档时的实体
+    pub fn entities(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.groups.iter().flat_map(|g| g.entities.iter().copied())
+    }
+    /// 转为可以序列化的数据，归档不变。有组件既不是RawPod也没有注册差异函数，则返回NotSerializable
+    pub fn to_data(&self) -> Result<ArchiveData, QueryError> {
+        let mut data = ArchiveData::default();
+        for g in self.groups.iter() {
+            let mut gd = ArchiveGroupData {
+                components: g.columns.iter().map(|c| c.info().stable_id()).collect(),
+                entities: g.entities.iter().map(|e| e.data().as_ffi()).collect(),
+                values: Vec::with_capacity(g.columns.len()),
+            };
+            for (c, vec) in g.columns.iter().zip(g.data.iter()) {
+                let info = c.info();
+                let size = info.size();
+                let mut values = Vec::with_capacity(g.entities.len());
+                for i in 0..g.entities.len() {
+                    let ptr = unsafe { (vec.as_ptr() as *const u8).add(i * size) };
+                    let mut value = Vec::new();
+                    if size > 0 {
+                        if info.raw_pod {
+                            value.extend_from_slice(unsafe { std::slice::from_raw_parts(ptr, size) });
+                        } else if let Some(diff_fn) = info.diff_fn {
+                            diff_fn(std::ptr::null(), ptr, &mut value);
+                        } else {
+                            return Err(QueryError::NotSerializable(info.index));
+                        }
+                    }
+                    values.push(value);
+                }
+                gd.values.push(values);
+            }
+            data.groups.push(gd);
+        }
+        Ok(data)
+    }
+}
+impl Drop for EntityArchive {
+    fn drop(&mut self) {
+        // 释放归档中还存在的组件
+        for g in self.groups.iter_mut() {
+            for c in 0..g.columns.len() {
+                if let Some(f) = g.columns[c].info().drop_fn {
+                    for i in 0..g.entities.len() {
+                        f(g.get(c, i));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 原型的快照，组件数据按位复制，所以只支持不需要释放的组件
+pub struct ArchetypeSnapshot {
+    id: u64,
+    /// 快照时的实体，及其按列顺序的组件数据
+    pub entities: Vec<(Entity, Vec<Vec<u8>>)>,
+}
+impl ArchetypeSnapshot {
+    /// 原型的id
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl World {
+    /// 对指定id的原型做快照，原型不存在或有需要释放的组件，则返回None
+    pub fn snapshot_archetype(&self, id: u64) -> Option<ArchetypeSnapshot> {
+        let ar = self.archetype_map.get(&id)?.clone();
+        if ar.get_columns().iter().any(|c| c.info().drop_fn.is_some()) {
+            return None;
+        }
+        let mut entities = Vec::new();
+        for row in 0..ar.len().index() {
+            let row: Row = row.into();
+            let e = match ar.live_entity(row) {
+                Some(e) => e,
+                None => continue,
+            };
+            let mut data = Vec::with_capacity(ar.column_len());
+            for c in ar.get_columns().iter() {
+                let size = c.info().size();
+                let mut vec = vec![0u8; size];
+                if size > 0 {
+                    let src = c.blob_ref_unchecked(ar.index()).get_row(row, e);
+                    unsafe { src.copy_to_nonoverlapping(vec.as_mut_ptr(), size) };
+                }
+                data.push(vec);
+            }
+            entities.push((e, data));
+        }
+        Some(ArchetypeSnapshot { id, entities })
+    }
+    /// 用快照恢复原型中实体的组件数据，并记录修改。
+    /// 只恢复仍在该原型中的实体，已销毁或已移到其他原型的实体被忽略，返回恢复的实体数量
+    pub fn restore_archetype(&mut self, snapshot: ArchetypeSnapshot) -> usize {
+        let ar = match self.archetype_map.get(&snapshot.id) {
+            Some(ar) => ar.clone(),
+            None => return 0,
+        };
+        let tick = self.tick();
+        let mut count = 0;
+        for (e, data) in snapshot.entities.iter() {
+            let addr = match self.entities.get(*e) {
+                Some(addr) => *addr,
+                None => continue,
+            };
+            if addr.archetype_index() != ar.index() || ar.live_entity(addr.row) != Some(*e) {
+                continue;
+            }
+            for (c, vec) in ar.get_columns().iter().zip(data.iter()) {
+                let c = c.blob_ref_unchecked(ar.index());
+                if c.info.size() > 0 {
+                    c.write_row(addr.row, *e, vec.as_ptr() as *mut u8);
+                }
+                c.changed_tick(*e, addr.row, tick);
+            }
+            count += 1;
+        }
+        count
+    }
+    /// 归档匹配过滤器的所有实体
+    pub fn archive_filtered<F: FilterComponents + 'static>(&mut self) -> EntityArchive {
+        let mut q = self.make_query::<Entity, F>();
+        let entities: Vec<Entity> = q.iter(self).collect();
+        self.archive_entities(&entities).unwrap()
+    }
+    /// 归档指定的实体，组件数据被移动到归档中，实体从world上移除。
+    /// 有任何实体不存在或重复出现，则返回错误，world不变
+    pub fn archive_entities(&mut self, entities: &[Entity]) -> Result<EntityArchive, QueryError> {
+        let mut set = HashSet::with_capacity(entities.len());
+        for e in entities {
+            if !self.entities.contains_key(*e) {
+                return Err(QueryError::NoSuchEntity(*e));
+            }
+            if !set.insert(*e) {
+                return Err(QueryError::DuplicateEntity(*e));
+            }
+        }
+        let mut archive = EntityArchive::default();
+        let mut map: HashMap<ArchetypeIndex, usize> = HashMap::new();
+        for e in entities {
+            let addr = *self.entities.get(*e).unwrap();
+            if addr.row.is_null() {
+                // 空实体
+                let index = *map.entry(ArchetypeIndex::null()).or_insert_with(|| {
+                    archive.groups.push(ArchiveGroup::new(Vec::new()));
+                    archive.groups.len() - 1
+                });
+                archive.groups[index].entities.push(*e);
+                self.on_entity_removed(&self.empty_archetype, addr.row, *e);
+                continue;
+            }
+            let ar_index = addr.archetype_index();
+            let ar = unsafe { self.archetype_arr.get_unchecked(ar_index.index()) };
+            let index = *map.entry(ar_index).or_insert_with(|| {
+                archive.groups.push(ArchiveGroup::new(ar.get_columns().clone()));
+                archive.groups.len() - 1
+            });
+            let g = &mut archive.groups[index];
+            let i = g.alloc();
+            for (ci, c) in ar.get_columns().iter().enumerate() {
+                let size = c.info().size();
+                if size > 0 {
+                    let src = c.blob_ref_unchecked(ar_index).get_row(addr.row, *e);
+                    unsafe { src.copy_to_nonoverlapping(g.get(ci, i), size) };
+                }
+            }
+            g.entities.push(*e);
+            // 组件的所有权已移动到归档中，只标记移除，不释放组件
+            ar.mark_remove(addr.row);
+            self.on_entity_removed(ar, addr.row, *e);
+        }
+        Ok(archive)
+    }
+    /// 恢复归档的实体，恢复的实体数量。归档时的实体和新实体记录在mapper中，
+    /// 注册了引用策略的组件中引用的实体在所有实体恢复后用mapper修正，没有映射的实体不变
+    pub fn unarchive(&mut self, mut archive: EntityArchive, mapper: &mut EntityMapper) -> usize {
+        let tick = self.tick();
+        let mut count = 0;
+        let mut fixes: Vec<(Share<Column>, ArchetypeIndex, Row, Entity, MapFn)> = Vec::new();
+        // 组件的所有权移动回world，取出的ArchiveGroup不会释放组件
+        for mut g in std::mem::take(&mut archive.groups) {
+            count += g.entities.len();
+            if g.columns.is_empty() {
+                for old in g.entities.iter() {
+                    mapper.insert(*old, self.spawn_empty());
+                }
+                continue;
+            }
+            let maps: Vec<Option<MapFn>> = g
+                .columns
+                .iter()
+                .map(|c| self.references.map_fn(c.info().type_id()))
+                .collect();
+            let ar = self.find_archtype(ArchetypeInfo::sort(g.columns.clone()));
+            for i in 0..g.entities.len() {
+                let (r, row) = ar.alloc();
+                let row: Row = row.into();
+                let e = self.insert_addr(ar.index(), row);
+                for ci in 0..g.columns.len() {
+                    let src = g.get(ci, i);
+                    let c = g.columns[ci].blob_ref_unchecked(ar.index());
+                    if c.info.size() > 0 {
+                        c.write_row(row, e, src);
+                    }
+                    c.added_tick(e, row, tick);
+                    if let Some(map) = maps[ci] {
+                        fixes.push((g.columns[ci].clone(), ar.index(), row, e, map));
+                    }
+                }
+                *r = e;
+                ar.record_allocated(row, e);
+                mapper.insert(g.entities[i], e);
+            }
+        }
+        for (c, index, row, e, map) in fixes {
+            map(c.blob_ref_unchecked(index).get_row(row, e), &mut |r| mapper.map(r));
+        }
+        count
+    }
+    /// 从序列化的数据重建归档，组件按稳定id在world上查找。
+    /// 组件没有注册或不能从数据还原（不是RawPod也没有注册差异函数、大小不符），则返回错误
+    pub fn archive_from_data(&self, data: &ArchiveData) -> Result<EntityArchive, QueryError> {
+        let mut ids: HashMap<u64, Share<Column>> = HashMap::new();
+        for c in self.component_arr.iter() {
+            ids.insert(c.info().stable_id(), c.clone());
+        }
+        let mut archive = EntityArchive::default();
+        for gd in data.groups.iter() {
+            let mut columns = Vec::with_capacity(gd.components.len());
+            for id in gd.components.iter() {
+                match ids.get(id) {
+                    Some(c) => columns.push(c.clone()),
+                    None => return Err(QueryError::NoSuchComponent(ComponentIndex::null())),
+                }
+            }
+            let mut g = ArchiveGroup::new(columns);
+            if gd.values.len() != g.columns.len() {
+                return Err(QueryError::NotSerializable(ComponentIndex::null()));
+            }
+            // 先检查所有值，失败时不会留下未初始化的组件
+            for (c, values) in g.columns.iter().zip(gd.values.iter()) {
+                let info = c.info();
+                if values.len() != gd.entities.len()
+                    || (info.size() > 0 && !info.raw_pod && info.apply_fn.is_none())
+                    || (info.raw_pod && values.iter().any(|v| v.len() != info.size()))
+                {
+                    return Err(QueryError::NotSerializable(info.index));
+                }
+            }
+            for (i, e) in gd.entities.iter().enumerate() {
+                g.alloc();
+                for ci in 0..g.columns.len() {
+                    let dst = g.get(ci, i);
+                    let info = g.columns[ci].info();
+                    let value = &gd.values[ci][i];
+                    if info.size() == 0 {
+                        continue;
+                    }
+                    if info.raw_pod {
+                        unsafe { value.as_ptr().copy_to_nonoverlapping(dst, value.len()) };
+                    } else {
+                        info.apply_fn.unwrap()(dst, value);
+                    }
+                }
+                g.entities.push(KeyData::from_ffi(*e).into());
+            }
+            archive.groups.push(g);
+        }
+        Ok(archive)
+    }
+}
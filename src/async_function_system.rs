@@ -68,6 +68,10 @@ where
     fn initialize(&mut self, world: &mut World) {
         self.param.initialize(world)
     }
+    #[inline]
+    fn is_read_only(&self) -> bool {
+        self.param.is_read_only()
+    }
     // /// system depend the archetype.
     // fn archetype_depend(
     //     &self,
@@ -129,4 +133,7 @@ macro_rules! impl_async_system_function {
 
 // Note that we rely on the highest impl to be <= the highest order of the tuple impls
 // of `SystemParam` created.
+#[cfg(not(feature = "large-tuples"))]
 all_tuples!(impl_async_system_function, 0, 16, F);
+#[cfg(feature = "large-tuples")]
+all_tuples!(impl_async_system_function, 0, 24, F);
@@ -0,0 +1,148 @@
+//! 有预算的查询迭代
+//! 迭代大量行的system会阻塞整帧，可以给一次迭代设置预算，超出预算后迭代器不再返回，
+//! 调用者用cursor()得到的位置在下一帧继续，或者自行决定升级处理。
+//! 预算按返回的数量计数，时间预算每隔若干项才读取一次时钟，时钟可以替换，用于测试。
+//! let mut it = q.iter_budgeted(Budget::time(Duration::from_micros(500)));
+//! for item in &mut it { ... } if it.was_exhausted() { *cursor = it.cursor(); }
+//!
+
+use std::time::{Duration, Instant};
+
+use crate::fetch::FetchComponents;
+use crate::filter::FilterComponents;
+use crate::query::{Query, QueryCursor, QueryIter};
+
+/// 时间预算默认的检查间隔
+pub const TIME_CHECK_INTERVAL: usize = 64;
+
+/// 一次迭代的预算
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    max_items: usize,
+    time: Option<(Duration, usize)>, // 时间预算及读取时钟的间隔
+    clock: fn() -> Instant,
+}
+impl Budget {
+    /// 最多返回max_items项
+    pub fn items(max_items: usize) -> Self {
+        Self {
+            max_items,
+            time: None,
+            clock: Instant::now,
+        }
+    }
+    /// 超过limit后停止，每TIME_CHECK_INTERVAL项检查一次，实际耗时会略超过limit
+    pub fn time(limit: Duration) -> Self {
+        Self {
+            max_items: usize::MAX,
+            time: Some((limit, TIME_CHECK_INTERVAL)),
+            clock: Instant::now,
+        }
+    }
+    /// 同时限制数量
+    pub fn max_items(mut self, max_items: usize) -> Self {
+        self.max_items = max_items;
+        self
+    }
+    /// 设置读取时钟的间隔
+    pub fn check_every(mut self, interval: usize) -> Self {
+        if let Some((_, i)) = &mut self.time {
+            *i = interval.max(1);
+        }
+        self
+    }
+    /// 替换时钟
+    pub fn with_clock(mut self, clock: fn() -> Instant) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+/// 有预算的迭代器
+pub struct BudgetedIter<'w, Q: FetchComponents + 'static, F: FilterComponents + 'static> {
+    iter: QueryIter<'w, Q, F>,
+    budget: Budget,
+    start: Option<Instant>,
+    count: usize,
+    exhausted: bool,
+}
+impl<'w, Q: FetchComponents, F: FilterComponents> BudgetedIter<'w, Q, F> {
+    pub fn new(mut iter: QueryIter<'w, Q, F>, budget: Budget, cursor: Option<QueryCursor>) -> Self {
+        if let Some(cursor) = cursor {
+            iter.resume(cursor);
+        }
+        Self {
+            iter,
+            start: budget.time.map(|_| (budget.clock)()),
+            budget,
+            count: 0,
+            exhausted: false,
+        }
+    }
+    /// 是否因预算用完而停止，恰好在最后一项用完时也为true，继续迭代不会再返回
+    pub fn was_exhausted(&self) -> bool {
+        self.exhausted
+    }
+    /// 已返回的数量
+    pub fn yielded(&self) -> usize {
+        self.count
+    }
+    /// 当前的迭代位置，用resume_budgeted继续
+    pub fn cursor(&self) -> QueryCursor {
+        self.iter.cursor()
+    }
+    // 检查预算是否用完
+    #[inline(always)]
+    fn exhaust(&mut self) -> bool {
+        if self.count >= self.budget.max_items {
+            return true;
+        }
+        if let (Some((limit, interval)), Some(start)) = (self.budget.time, self.start) {
+            if self.count > 0 && self.count % interval == 0 && (self.budget.clock)() - start >= limit {
+                return true;
+            }
+        }
+        false
+    }
+}
+impl<'w, Q: FetchComponents, F: FilterComponents> Iterator for BudgetedIter<'w, Q, F> {
+    type Item = Q::Item<'w>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.exhausted {
+            return None;
+        }
+        if self.exhaust() {
+            self.exhausted = true;
+            return None;
+        }
+        let item = self.iter.next()?;
+        self.count += 1;
+        Some(item)
+    }
+}
+
+impl<'w, Q: FetchComponents, F: FilterComponents> Query<'w, Q, F> {
+    /// 有预算的迭代
+    pub fn iter_budgeted(
+        &self,
+        budget: Budget,
+    ) -> BudgetedIter<'_, <Q as FetchComponents>::ReadOnly, F> {
+        BudgetedIter::new(self.iter(), budget, None)
+    }
+    /// 从上次用完预算的位置继续
+    pub fn resume_budgeted(
+        &self,
+        cursor: QueryCursor,
+        budget: Budget,
+    ) -> BudgetedIter<'_, <Q as FetchComponents>::ReadOnly, F> {
+        BudgetedIter::new(self.iter(), budget, Some(cursor))
+    }
+    pub fn iter_budgeted_mut(&mut self, budget: Budget) -> BudgetedIter<'_, Q, F> {
+        BudgetedIter::new(self.iter_mut(), budget, None)
+    }
+    pub fn resume_budgeted_mut(&mut self, cursor: QueryCursor, budget: Budget) -> BudgetedIter<'_, Q, F> {
+        BudgetedIter::new(self.iter_mut(), budget, Some(cursor))
+    }
+}
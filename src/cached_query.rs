@@ -0,0 +1,168 @@
+//! 可缓存的查询
+//! 每帧都要用大量几乎不变的数据重建相同输出的system（比如UI、渲染准备），可以用CachedQuery判断上次运行后是否有相关的修改，
+//! 没有则直接跳过，有则只处理脏的原型。
+//! 每个原型记录结构版本（行的分配、移除和整理），以及查询读写的组件在该原型上的添加和修改次数，只比较计数，不遍历行。
+//! 查询读写的组件会被设置为记录tick。本system通过该查询做的修改，在下次运行时也会使原型变脏。
+//! fn sys(q: CachedQuery<&Node>) { if !q.is_dirty() { return; } for index in q.changed_archetypes() { ... } }
+//!
+
+use std::mem::transmute;
+use std::ops::{Deref, DerefMut};
+
+use pi_share::Share;
+
+use crate::archetype::{ArchetypeIndex, Row, ShareArchetype, COMPONENT_TICK};
+use crate::column::Column;
+use crate::fetch::FetchComponents;
+use crate::filter::FilterComponents;
+use crate::query::{Query, QueryState};
+use crate::system::{Relation, SystemMeta};
+use crate::system_params::SystemParam;
+use crate::world::{Tick, World};
+
+// 上次运行时原型的计数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ArchetypeMark {
+    structure: (Row, usize, u32),
+    changes: usize,
+}
+
+pub struct CachedQueryState<Q: FetchComponents + 'static, F: FilterComponents + 'static> {
+    query: QueryState<Q, F>,
+    columns: Vec<Share<Column>>, // 查询读写的列
+    marks: Vec<ArchetypeMark>,   // 和查询的原型顺序一致
+    dirty: Vec<ArchetypeIndex>,  // 本次运行时脏的原型
+}
+
+impl<Q: FetchComponents, F: FilterComponents> CachedQueryState<Q, F> {
+    pub fn create(world: &mut World, system_meta: &mut SystemMeta) -> Self {
+        let query = QueryState::create(world, system_meta);
+        let mut columns: Vec<Share<Column>> = Vec::new();
+        for r in query.related.vec.iter() {
+            match r {
+                Relation::Read(i)
+                | Relation::Write(i)
+                | Relation::OptRead(i)
+                | Relation::OptWrite(i)
+                | Relation::ShareWrite(i) => {
+                    if columns.iter().any(|c| c.info().index == *i) {
+                        continue;
+                    }
+                    // 组件需要记录tick，才有修改的计数
+                    let mut info = world.get_column(*i).unwrap().info().clone();
+                    info.tick_info |= COMPONENT_TICK;
+                    columns.push(world.add_component_info(info).1);
+                }
+                _ => (),
+            }
+        }
+        Self {
+            query,
+            columns,
+            marks: Vec::new(),
+            dirty: Vec::new(),
+        }
+    }
+    pub fn query(&self) -> &QueryState<Q, F> {
+        &self.query
+    }
+    // 计算原型的计数
+    fn mark(&self, ar: &ShareArchetype) -> ArchetypeMark {
+        let mut changes = 0usize;
+        for c in self.columns.iter() {
+            if let Some(b) = c.blob_ref(ar.index()) {
+                changes = changes.wrapping_add(b.change_count());
+            }
+        }
+        ArchetypeMark {
+            structure: ar.structure_version(),
+            changes,
+        }
+    }
+    // 和上次运行时的计数比较，找出脏的原型，新匹配的原型总是脏的
+    fn update(&mut self) {
+        self.dirty.clear();
+        for i in 0..self.query.archetypes.len() {
+            let ar = &self.query.archetypes[i];
+            let mark = self.mark(ar);
+            if i < self.marks.len() {
+                if self.marks[i] == mark {
+                    continue;
+                }
+                self.marks[i] = mark;
+            } else {
+                self.marks.push(mark);
+            }
+            self.dirty.push(ar.index());
+        }
+    }
+}
+
+pub struct CachedQuery<'w, Q: FetchComponents + 'static, F: FilterComponents + 'static = ()> {
+    query: Query<'w, Q, F>,
+    dirty: &'w Vec<ArchetypeIndex>,
+}
+
+impl<'w, Q: FetchComponents, F: FilterComponents> CachedQuery<'w, Q, F> {
+    /// 上次运行后是否有相关的修改
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+    /// 上次运行后有修改的原型，按原型索引递增
+    pub fn changed_archetypes(&self) -> &[ArchetypeIndex] {
+        self.dirty
+    }
+    /// 指定的原型上次运行后是否有修改
+    pub fn is_archetype_dirty(&self, index: ArchetypeIndex) -> bool {
+        self.dirty.binary_search(&index).is_ok()
+    }
+}
+
+impl<'w, Q: FetchComponents, F: FilterComponents> Deref for CachedQuery<'w, Q, F> {
+    type Target = Query<'w, Q, F>;
+    fn deref(&self) -> &Self::Target {
+        &self.query
+    }
+}
+impl<'w, Q: FetchComponents, F: FilterComponents> DerefMut for CachedQuery<'w, Q, F> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.query
+    }
+}
+
+impl<'a, Q: FetchComponents + 'static, F: FilterComponents + Send + Sync> SystemParam
+    for CachedQuery<'a, Q, F>
+{
+    type State = CachedQueryState<Q, F>;
+    type Item<'w> = CachedQuery<'w, Q, F>;
+
+    fn init_state(world: &mut World, system_meta: &mut SystemMeta) -> Self::State {
+        Self::State::create(world, system_meta)
+    }
+    fn align(world: &World, system_meta: &SystemMeta, state: &mut Self::State) {
+        let len = system_meta.align_len(world);
+        state.query.align_to(world, len);
+    }
+
+    fn get_param<'w>(
+        world: &'w World,
+        _system_meta: &'w SystemMeta,
+        state: &'w mut Self::State,
+        tick: Tick,
+    ) -> Self::Item<'w> {
+        state.update();
+        CachedQuery {
+            query: Query::new(world, &mut state.query, tick),
+            dirty: &state.dirty,
+        }
+    }
+
+    fn get_self<'w>(
+        world: &'w World,
+        system_meta: &'w SystemMeta,
+        state: &'w mut Self::State,
+        tick: Tick,
+    ) -> Self {
+        unsafe { transmute(Self::get_param(world, system_meta, state, tick)) }
+    }
+}
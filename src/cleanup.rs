@@ -0,0 +1,91 @@
+//! 被移除组件的释放时机
+//! Alter在销毁时（system结束后）释放被移除的组件，销毁实体时立即释放实体的组件，释放发生在执行该system的线程上，组件的Drop和其他system的先后不确定。
+//! 设置CleanupPolicy::EndOfFrame后，Alter移除的组件和销毁实体的组件都只记入world的队列，由World::cleanup在帧末统一释放，
+//! 释放顺序为原型、组件、行的升序，和system的执行顺序无关。
+//! 整理前会先调用cleanup，执行器也可以把cleanup作为单独的阶段显式调用。
+//! World没有实现Drop，未整理就销毁world时，应调用World::shutdown释放队列中的组件，直接drop时队列中的组件不会被释放。
+//! world.set_cleanup_policy(CleanupPolicy::EndOfFrame);
+//!
+
+use pi_append_vec::SafeVec;
+use pi_share::Share;
+
+use crate::archetype::{ArchetypeIndex, Row};
+use crate::column::Column;
+use crate::world::{Entity, World};
+
+/// 被移除组件的释放策略
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CleanupPolicy {
+    /// 每个system结束时释放
+    #[default]
+    PerSystemAfter,
+    /// 帧末由World::cleanup统一释放，包括被移除的组件和被销毁实体的组件
+    EndOfFrame,
+}
+
+// 待释放的组件
+struct PendingDrop {
+    column: Share<Column>,
+    ar_index: ArchetypeIndex,
+    row: Row,
+    e: Entity,
+}
+
+#[derive(Default)]
+pub(crate) struct CleanupQueue {
+    policy: CleanupPolicy,
+    pending: SafeVec<PendingDrop>,
+}
+impl CleanupQueue {
+    #[inline(always)]
+    pub(crate) fn is_deferred(&self) -> bool {
+        self.policy == CleanupPolicy::EndOfFrame
+    }
+    // 记录待释放的组件，行在整理前不会被复用
+    pub(crate) fn defer(&self, column: &Share<Column>, ar_index: ArchetypeIndex, row: Row, e: Entity) {
+        self.pending.insert(PendingDrop {
+            column: column.clone(),
+            ar_index,
+            row,
+            e,
+        });
+    }
+}
+
+impl World {
+    /// 设置被移除组件的释放策略，切换回PerSystemAfter时立即释放队列中的组件
+    pub fn set_cleanup_policy(&mut self, policy: CleanupPolicy) {
+        if policy == CleanupPolicy::PerSystemAfter {
+            self.cleanup();
+        }
+        self.cleanup_queue.policy = policy;
+    }
+    pub fn cleanup_policy(&self) -> CleanupPolicy {
+        self.cleanup_queue.policy
+    }
+    /// 队列中待释放的组件数量
+    pub fn pending_cleanup_len(&self) -> usize {
+        self.cleanup_queue.pending.len()
+    }
+    /// 释放队列中的组件，返回释放的数量。必须在整理前调用，整理时会自动调用
+    pub fn cleanup(&mut self) -> usize {
+        let len = self.cleanup_queue.pending.len();
+        if len == 0 {
+            return 0;
+        }
+        let mut vec: Vec<&PendingDrop> = self.cleanup_queue.pending.iter().map(|p| &*p).collect();
+        vec.sort_by_key(|p| (p.ar_index, p.column.info().index, p.row));
+        for p in vec {
+            p.column
+                .blob_ref_unchecked(p.ar_index)
+                .drop_row_unchecked(p.row, p.e);
+        }
+        self.cleanup_queue.pending.clear(0);
+        len
+    }
+    /// 释放队列中的组件后销毁world。原型上被标记移除的行不会随原型释放，未整理的world应通过该方法销毁
+    pub fn shutdown(mut self) {
+        self.cleanup();
+    }
+}
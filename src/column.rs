@@ -1,9 +1,11 @@
 use core::fmt::*;
 use std::{
+    any::TypeId,
     cell::SyncUnsafeCell,
     mem::transmute,
+    marker::PhantomData,
     ops::{Deref, DerefMut, Range},
-    sync::atomic::AtomicUsize,
+    sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
 };
 
 use pi_append_vec::SafeVec;
@@ -13,8 +15,10 @@ use pi_share::Share;
 
 use crate::{
     archetype::{Archetype, ArchetypeIndex, ComponentInfo, Row, ShareArchetype},
+    deferred_drop::DropQueue,
+    remove_hook::RemoveHook,
     event::ComponentEventVec,
-    world::{Entity, Tick},
+    world::{Entity, Tick, World},
 };
 
 #[cfg(debug_assertions)]
@@ -26,6 +30,7 @@ pub struct Column {
     pub(crate) info: ColumnInfo,
     pub(crate) arr: Arr<BlobTicks>,
     pub(crate) last_len: SyncUnsafeCell<usize>,
+    pub(crate) archetypes: SafeVec<ArchetypeIndex>, // 包含该列的原型索引
 }
 impl Column {
     pub fn memsize(&self) -> usize {
@@ -42,6 +47,16 @@ impl Column {
         result += self.info.memsize();
         result
     }
+    /// tick数组占用的内存
+    pub fn ticks_memsize(&self) -> usize {
+        let mut result = 0;
+        for idx in 0..self.arr.capacity(0) {
+            if let Some(item) = self.arr.get(idx) {
+                result += item.ticks_memsize();
+            }
+        }
+        result
+    }
     #[inline(always)]
     pub fn new(info: ComponentInfo) -> Self {
         // log::warn!("New Column");
@@ -50,10 +65,17 @@ impl Column {
                 changed: None,
                 added: None,
                 removed: None,
+                policy: TickPolicy::default(),
+                span: None,
+                span_frame: AtomicU32::new(0),
+                guard: ColumnGuard::default(),
+                deferred_drop: None,
+                on_remove: None,
                 info,
             },
             arr: Arr::default(),
             last_len: SyncUnsafeCell::new(0usize.into()),
+            archetypes: SafeVec::default(),
         }
     }
     #[inline(always)]
@@ -64,10 +86,48 @@ impl Column {
     pub fn info_mut(&mut self) -> &mut ComponentInfo {
         &mut self.info.info
     }
+    /// tick的存储策略
+    #[inline(always)]
+    pub fn tick_policy(&self) -> TickPolicy {
+        self.info.policy
+    }
     // 初始化原型对应列的blob
     pub fn init_blob(&self, index: ArchetypeIndex) {
         *unsafe { &mut *self.last_len.get() } = index.index() + 1;
         unsafe { self.arr.load_alloc(index.index()).blob.set_vec_capacity(0) };
+        self.archetypes.insert(index);
+    }
+    /// 修改事件的监听者数量
+    pub fn changed_listener_count(&self) -> usize {
+        self.info.changed.as_ref().map_or(0, |r| r.listener_count())
+    }
+    /// 添加事件的监听者数量
+    pub fn added_listener_count(&self) -> usize {
+        self.info.added.as_ref().map_or(0, |r| r.listener_count())
+    }
+    /// 包含该列的原型数量
+    #[inline(always)]
+    pub fn archetypes_len(&self) -> usize {
+        self.archetypes.len()
+    }
+    /// 包含该列的原型索引，不保证顺序
+    pub fn archetype_indexs(&self) -> impl Iterator<Item = ArchetypeIndex> + '_ {
+        self.archetypes.iter().map(|ar| *ar)
+    }
+    /// 跨原型迭代该列上所有存活实体的组件，T必须是该列的组件类型
+    pub fn iter<'a, T: 'static>(
+        &'a self,
+        world: &'a World,
+    ) -> impl Iterator<Item = (Entity, &'a T)> + 'a {
+        assert_eq!(self.info.type_id(), &TypeId::of::<T>());
+        self.archetypes.iter().filter_map(move |index| {
+            let ar = world.get_archetype(*index)?;
+            let c = self.blob_ref(*index)?;
+            Some((0..ar.len().index()).filter_map(move |row| {
+                let row: Row = row.into();
+                ar.live_entity(row).map(|e| (e, c.get::<T>(row, e)))
+            }))
+        }).flatten()
     }
     // 列是否包含指定原型
     pub fn contains(&self, index: ArchetypeIndex) -> bool {
@@ -93,8 +153,11 @@ impl Column {
                 unsafe { self.arr.load_unchecked(index.index()) }
             );
         }
+        self.info.guard.read(self.info.type_name());
+        let blob = unsafe { self.arr.load_unchecked(index.index()) };
+        blob.guard.read(self.info.type_name());
         BlobRef::new(
-            unsafe { self.arr.load_unchecked(index.index()) },
+            blob,
             &self.info,
             #[cfg(debug_assertions)]
             index,
@@ -122,6 +185,8 @@ impl Column {
         if blob.blob.vec_capacity().is_null() {
             return None;
         }
+        self.info.guard.read(self.info.type_name());
+        blob.guard.read(self.info.type_name());
         Some(BlobRef::new(
             blob,
             &self.info,
@@ -129,6 +194,65 @@ impl Column {
             index,
         ))
     }
+    /// 批量将src原型的行复制到dst原型的行，rows为(源行, 目标行)，src和dst不能是同一个原型。
+    /// 先按源行排序，源行和目标行都连续并且都在连续内存中的行，合并成一次内存复制
+    pub fn copy_rows(&self, src: ArchetypeIndex, dst: ArchetypeIndex, rows: &[(Row, Row)]) {
+        debug_assert_ne!(src, dst);
+        let src_ref = self.blob_ref_unchecked(src);
+        let dst_ref = self.blob_ref_unchecked(dst);
+        let sorted;
+        let rows = if rows.windows(2).all(|w| w[0].0 <= w[1].0) {
+            rows
+        } else {
+            let mut vec = rows.to_vec();
+            vec.sort_unstable_by_key(|r| r.0);
+            sorted = vec;
+            &sorted
+        };
+        let size = self.info.size();
+        if size > 0 {
+            let src_capacity = src_ref.blob.blob.vec_capacity();
+            let dst_capacity = dst_ref.blob.blob.vec_capacity();
+            let mut i = 0;
+            while i < rows.len() {
+                let (src_row, dst_row) = unsafe { *rows.get_unchecked(i) };
+                // 计算源行和目标行都连续的长度
+                let mut n = 1;
+                while i + n < rows.len() {
+                    let (s, d) = unsafe { *rows.get_unchecked(i + n) };
+                    if s.0 != src_row.0 + n as u32 || d.0 != dst_row.0 + n as u32 {
+                        break;
+                    }
+                    n += 1;
+                }
+                if n > 1
+                    && (src_row.index() + n) * size <= src_capacity
+                    && (dst_row.index() + n) * size <= dst_capacity
+                {
+                    // 都在连续内存中，一次复制
+                    unsafe {
+                        src_ref
+                            .get_blob(src_row)
+                            .copy_to_nonoverlapping(dst_ref.load_blob(dst_row), n * size)
+                    };
+                } else {
+                    for (s, d) in rows[i..i + n].iter() {
+                        unsafe {
+                            src_ref
+                                .get_blob(*s)
+                                .copy_to_nonoverlapping(dst_ref.load_blob(*d), size)
+                        };
+                    }
+                }
+                i += n;
+            }
+        }
+        if self.info.is_tick() {
+            for (s, d) in rows.iter() {
+                src_ref.copy_ticks_to(*s, &dst_ref, *d);
+            }
+        }
+    }
     /// 扫描当前的所有原型，设置已有的实体，主要是解决不同的Plugin，依次添加时，Changed监听和tick被后设置的问题
     pub(crate) fn update<F>(&self, archetypes: &SafeVec<ShareArchetype>, set_fn: F)
     where
@@ -158,12 +282,14 @@ impl Column {
     }
     /// 整理内存
     pub(crate) fn settle(&mut self) {
+        let _exclusive = self.info.guard.exclusive(self.info.type_name());
+        self.archetypes.settle(0);
         let len = *self.last_len.get_mut();
         if len > self.arr.vec_capacity() {
             self.arr.settle(len, 0, 1);
         }
     }
-    /// 整理合并指定原型的空位
+    /// 整理合并指定原型的空位，只独占该原型的blob，不影响其他原型上的BlobRef
     pub(crate) fn settle_by_index(
         &mut self,
         index: ArchetypeIndex,
@@ -171,6 +297,22 @@ impl Column {
         additional: usize,
         action: &Vec<(Row, Row)>,
     ) {
+        let name = self.info.type_name();
+        unsafe { self.arr.get_unchecked_mut(index.index()) }.guard.enter(name);
+        self.settle_blob(index, len, additional, action);
+        unsafe { self.arr.get_unchecked_mut(index.index()) }.guard.leave();
+    }
+    fn settle_blob(
+        &mut self,
+        index: ArchetypeIndex,
+        len: usize,
+        additional: usize,
+        action: &Vec<(Row, Row)>,
+    ) {
+        if !action.is_empty() {
+            // 行被移动，粗粒度修改已记录的范围失效
+            *unsafe { self.arr.get_unchecked_mut(index.index()) }.coarse.get_mut() = 0;
+        }
         if self.info.size() == 0 {
             return;
         }
@@ -202,14 +344,22 @@ impl Column {
                     let dst_data: *mut u8 = r.load_blob(*dst);
                     src_data.copy_to_nonoverlapping(dst_data, self.info.size());
                     // 及其tick
-                    let tick = r.get_tick_unchecked(*src);
-                    r.set_tick_unchecked(*dst, tick);
+                    r.copy_ticks_to(*src, &r, *dst);
                 }
             }
             // 整理合并blob内存
             blob.blob.settle(len, additional, self.info.size());
             // 整理合并ticks内存
-            blob.ticks.settle(len, additional, 1);
+            if self.info.policy.compressed {
+                blob.added_deltas.settle(len, additional, 1);
+                blob.changed_deltas.settle(len, additional, 1);
+            } else {
+                blob.added_ticks.settle(len, additional, 1);
+                blob.changed_ticks.settle(len, additional, 1);
+            }
+            if self.info.span.is_some() {
+                blob.spans.settle(len, additional, 1);
+            }
             return;
         }
         for (src, dst) in action.iter() {
@@ -223,6 +373,21 @@ impl Column {
         // 整理合并blob内存
         blob.blob.settle(len, additional, self.info.size());
     }
+    /// 压缩tick的列，在整理时推进指定原型的base，重写已饱和的差值
+    pub(crate) fn rebase_ticks(&mut self, index: ArchetypeIndex, len: usize, now: Tick) {
+        if !self.info.policy.compressed || !self.info.is_tick() {
+            return;
+        }
+        let blob = unsafe { self.arr.get_unchecked_mut(index.index()) };
+        let base = blob.base;
+        if *now <= *base + REBASE_SPAN {
+            return;
+        }
+        let new_base: Tick = (*now - REBASE_KEEP).into();
+        rebase_deltas(&blob.added_deltas, len, base, new_base, now);
+        rebase_deltas(&blob.changed_deltas, len, base, new_base, now);
+        blob.base = new_base;
+    }
 }
 impl Debug for Column {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
@@ -237,6 +402,12 @@ pub(crate) struct ColumnInfo {
     pub(crate) changed: Option<Share<ComponentEventVec>>,
     pub(crate) added: Option<Share<ComponentEventVec>>,
     pub(crate) removed: Option<Share<ComponentEventVec>>,
+    pub(crate) policy: TickPolicy,
+    pub(crate) span: Option<SpanDedup>, // 记录帧内修改范围时的计数方式，None为不记录
+    pub(crate) span_frame: AtomicU32,   // 帧序号，整理时推进
+    pub(crate) guard: ColumnGuard,      // 列的读写检查
+    pub(crate) deferred_drop: Option<Share<DropQueue>>, // 延迟释放的缓冲，None为立即释放
+    pub(crate) on_remove: Option<RemoveHook>, // 移除时的钩子，在释放前调用
     pub(crate) info: ComponentInfo,
 }
 impl Deref for ColumnInfo {
@@ -262,6 +433,93 @@ impl ColumnInfo {
     }
 }
 
+/// 列的读写检查
+/// 整理会重写内存，此时其他线程持有的BlobRef都不安全，原来只靠调度的约定避免。
+/// state的最低位为整理中的标记，每次整理结束后加1，整体作为纪元。列和每个原型的blob各有一个：
+/// - 列的纪元只在Column::settle（重排所有原型的blob）时推进；
+/// - blob的纪元在settle_by_index、Table::reserve整理该原型时推进，不影响其他原型上的BlobRef；
+/// - 获得BlobRef（blob_ref、blob_ref_unchecked）时记录两个纪元，在整理中则panic；
+/// - BlobRef每次访问数据和tick时检查纪元未变，跨过整理继续使用则panic；
+/// - 整理需要独占，重复进入则panic。
+///
+/// panic信息包含组件的类型名。开启unchecked-sync特性后，检查全部去掉，由调度器保证访问不冲突
+#[derive(Debug, Default)]
+pub(crate) struct ColumnGuard {
+    #[cfg(not(feature = "unchecked-sync"))]
+    state: AtomicU32,
+}
+impl ColumnGuard {
+    #[cfg(not(feature = "unchecked-sync"))]
+    #[inline(always)]
+    fn epoch(&self) -> u32 {
+        self.state.load(Ordering::Acquire)
+    }
+    // 获得读的票据，列在整理中则panic
+    #[inline(always)]
+    pub(crate) fn read(&self, _name: &str) {
+        #[cfg(not(feature = "unchecked-sync"))]
+        if self.epoch() & 1 != 0 {
+            guard_violation(_name, "is read while being settled");
+        }
+    }
+    // 独占列，直到返回值被释放
+    #[inline]
+    pub(crate) fn exclusive(&self, name: &str) -> ColumnExclusive<'_> {
+        self.enter(name);
+        ColumnExclusive {
+            #[cfg(not(feature = "unchecked-sync"))]
+            guard: self,
+            _marker: PhantomData,
+        }
+    }
+    // 开始独占，必须和leave配对
+    #[inline]
+    pub(crate) fn enter(&self, _name: &str) {
+        #[cfg(not(feature = "unchecked-sync"))]
+        {
+            let mut old = self.state.load(Ordering::Relaxed);
+            loop {
+                if old & 1 != 0 {
+                    guard_violation(_name, "is settled concurrently");
+                }
+                match self.state.compare_exchange_weak(
+                    old,
+                    old.wrapping_add(1),
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(v) => old = v,
+                }
+            }
+        }
+    }
+    // 结束独占，推进纪元
+    #[inline(always)]
+    pub(crate) fn leave(&self) {
+        #[cfg(not(feature = "unchecked-sync"))]
+        self.state.fetch_add(1, Ordering::Release);
+    }
+}
+// 独占列的期间，释放时推进纪元
+pub(crate) struct ColumnExclusive<'a> {
+    #[cfg(not(feature = "unchecked-sync"))]
+    guard: &'a ColumnGuard,
+    _marker: PhantomData<&'a ColumnGuard>,
+}
+impl Drop for ColumnExclusive<'_> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        #[cfg(not(feature = "unchecked-sync"))]
+        self.guard.leave();
+    }
+}
+#[cold]
+#[inline(never)]
+fn guard_violation(name: &str, reason: &str) -> ! {
+    panic!("column {} {}", name, reason)
+}
+
 struct Blob(Arr<u8>);
 impl Default for Blob {
     fn default() -> Self {
@@ -298,17 +556,150 @@ impl Blob {
             val + 24
         }
     }
+    /// 实际分配的组件内存，len行的容量乘以组件的大小
+    pub fn data_memsize(&self, len: usize, mem_size: usize) -> usize {
+        if self.0.vec_capacity().is_null() {
+            0
+        } else {
+            self.0.capacity(len) * mem_size
+        }
+    }
+}
+
+/// tick的存储策略
+/// 压缩模式下，每行的tick只用1个字节保存相对原型base的差值，内存为完整模式的1/4。
+/// 差值为0表示不晚于base（很旧），差值为255表示超出了可表示的范围（很新），base在整理时推进。
+/// 还原出的tick总是不早于真实的tick，所以修改不会被漏掉，
+/// 但饱和后，旧的行可能被误报为已修改或已添加（比如很久没运行的system）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TickPolicy {
+    compressed: bool,
+    frame_scoped: bool,
+}
+impl TickPolicy {
+    /// 完整的tick，默认策略
+    pub fn full() -> Self {
+        Self::default()
+    }
+    /// 压缩的tick
+    pub fn compressed() -> Self {
+        Self {
+            compressed: true,
+            frame_scoped: false,
+        }
+    }
+    /// 帧内组件，不放入原型，存放在world的帧内存储中，每帧整理时全部清除，见transient模块
+    pub fn frame_scoped() -> Self {
+        Self {
+            compressed: false,
+            frame_scoped: true,
+        }
+    }
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+    pub fn is_frame_scoped(&self) -> bool {
+        self.frame_scoped
+    }
+}
+
+/// 同一帧内修改次数的计数方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SpanDedup {
+    /// 同一tick内的多次修改只计一次，和Changed的记录一致
+    #[default]
+    PerTick,
+    /// 每次修改都计数
+    PerWrite,
+}
+
+/// 帧内修改次数的上限，超过后不再增加
+pub const CHANGE_SPAN_MAX: u32 = u8::MAX as u32;
+
+/// 行在本帧内的修改范围，添加也算修改
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChangeSpanItem {
+    pub first_changed: Tick,
+    pub last_changed: Tick,
+    pub change_count_this_frame: u32, // 不超过CHANGE_SPAN_MAX
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SpanCell {
+    first: Tick,
+    last: Tick,
+    frame: u32,
+    count: u8,
+}
+
+// 压缩tick的饱和差值，表示很新
+const DELTA_NEW: u8 = u8::MAX;
+// 当前tick超过base这么多时，整理时推进base
+const REBASE_SPAN: u32 = 128;
+// 推进后的base比当前tick小这么多，保留的差值用于表示整理前的修改
+const REBASE_KEEP: u32 = 64;
+
+// 将tick压缩为相对base的差值
+#[inline(always)]
+fn encode_tick(base: Tick, tick: Tick) -> u8 {
+    if *tick <= *base {
+        0
+    } else {
+        (*tick - *base).min(DELTA_NEW as u32) as u8
+    }
+}
+// 还原差值的有效tick，不早于真实的tick
+#[inline(always)]
+fn decode_tick(base: Tick, delta: u8) -> Tick {
+    if delta == DELTA_NEW {
+        Tick::max()
+    } else {
+        (*base + delta as u32).into()
+    }
+}
+// 将差值重写为相对新base的差值，饱和的差值按当前tick还原
+fn rebase_deltas(arr: &Arr<u8>, len: usize, base: Tick, new_base: Tick, now: Tick) {
+    for row in 0..len {
+        if let Some(d) = arr.load(row) {
+            let tick = if *d == DELTA_NEW {
+                now
+            } else {
+                decode_tick(base, *d)
+            };
+            *d = encode_tick(new_base, tick);
+        }
+    }
 }
 
 #[derive(Default)]
 pub(crate) struct BlobTicks {
     blob: Blob,
-    pub(crate) ticks: Arr<Tick>,
+    pub(crate) added_ticks: Arr<Tick>,   // 添加时的tick，只在添加时设置
+    pub(crate) changed_ticks: Arr<Tick>, // 修改时的tick，添加时也会设置
+    pub(crate) added_deltas: Arr<u8>,    // 压缩模式下，添加tick相对base的差值
+    pub(crate) changed_deltas: Arr<u8>,  // 压缩模式下，修改tick相对base的差值
+    pub(crate) spans: Arr<SpanCell>,     // 帧内的修改范围，只在记录修改范围时使用
+    pub(crate) base: Tick,               // 压缩模式下的基准tick
+    coarse: AtomicU64,                   // 粗粒度修改已记录的tick（高32位）和行数（低32位）
+    changes: AtomicUsize,                // 添加和修改的次数，用于不遍历行判断原型是否有修改
+    guard: ColumnGuard,                  // 该原型的blob的纪元，整理该原型时推进
 }
 impl BlobTicks {
     pub fn memsize(&self) -> usize {
-        
-        self.blob.memsize() + self.ticks.vec_capacity() * 4
+        self.blob.memsize()
+            + self.added_ticks.vec_capacity() * 4
+            + self.changed_ticks.vec_capacity() * 4
+            + self.added_deltas.vec_capacity()
+            + self.changed_deltas.vec_capacity()
+            + self.spans.vec_capacity() * std::mem::size_of::<SpanCell>()
+    }
+    /// tick数组占用的内存
+    pub fn ticks_memsize(&self) -> usize {
+        self.memsize() - self.blob.memsize()
+    }
+    /// 组件和tick实际分配的内存，不包括结构自身
+    pub fn data_memsize(&self, len: usize, mem_size: usize) -> usize {
+        self.blob.data_memsize(len, mem_size) + self.ticks_memsize()
     }
 }
 
@@ -318,7 +709,12 @@ pub struct BlobRef<'a> {
     pub(crate) info: &'a ColumnInfo,
     #[cfg(debug_assertions)]
     index: ArchetypeIndex,
+    #[cfg(not(feature = "unchecked-sync"))]
+    epoch: (u32, u32), // 获得时列和blob的纪元
 }
+// 列通过world在线程间共享，行的读写由访问检查和行的划分保证不冲突，组件自身的Send和Sync由持有BlobRef的类型约束
+unsafe impl Send for BlobRef<'_> {}
+unsafe impl Sync for BlobRef<'_> {}
 
 impl<'a> BlobRef<'a> {
     #[inline(always)]
@@ -332,44 +728,273 @@ impl<'a> BlobRef<'a> {
             info,
             #[cfg(debug_assertions)]
             index,
+            #[cfg(not(feature = "unchecked-sync"))]
+            epoch: (info.guard.epoch(), blob.guard.epoch()),
+        }
+    }
+    // 检查获得后列和该原型的blob没有被整理过
+    #[inline(always)]
+    fn check_epoch(&self) {
+        #[cfg(not(feature = "unchecked-sync"))]
+        if (self.info.guard.epoch(), self.blob.guard.epoch()) != self.epoch {
+            guard_violation(self.info.type_name(), "was settled while a BlobRef was held");
         }
     }
+    /// 获得修改的tick，压缩模式下为还原的有效tick
     #[inline(always)]
     pub fn get_tick_unchecked(&self, row: Row) -> Tick {
+        self.check_epoch();
+        if self.info.policy.compressed {
+            return self
+                .blob
+                .changed_deltas
+                .get(row.index())
+                .map_or(Tick::default(), |d| decode_tick(self.blob.base, *d));
+        }
         self.blob
-            .ticks
+            .changed_ticks
             .get(row.index())
             .map_or(Tick::default(), |t| *t)
     }
+    /// 获得添加的tick，压缩模式下为还原的有效tick
+    #[inline(always)]
+    pub fn get_added_tick_unchecked(&self, row: Row) -> Tick {
+        self.check_epoch();
+        if self.info.policy.compressed {
+            return self
+                .blob
+                .added_deltas
+                .get(row.index())
+                .map_or(Tick::default(), |d| decode_tick(self.blob.base, *d));
+        }
+        self.blob
+            .added_ticks
+            .get(row.index())
+            .map_or(Tick::default(), |t| *t)
+    }
+    /// 行是否在last_run后被修改
+    #[inline(always)]
+    pub fn is_changed(&self, row: Row, last_run: Tick) -> bool {
+        self.get_tick_unchecked(row) > last_run
+    }
+    /// 行是否在last_run后被添加
+    #[inline(always)]
+    pub fn is_added(&self, row: Row, last_run: Tick) -> bool {
+        self.get_added_tick_unchecked(row) > last_run
+    }
     #[inline]
     pub fn added_tick(&self, e: Entity, row: Row, tick: Tick) {
         // println!("added_tick===={:?}", (e, row, tick, self.info.type_name()));
         if !self.info.is_tick() {
             return;
         }
-        *self.blob.ticks.load_alloc(row.index()) = tick;
+        self.set_added_tick_unchecked(row, tick);
+        self.set_tick_unchecked(row, tick);
+        self.record_span(row, tick, true);
+        self.blob.changes.fetch_add(1, Ordering::Relaxed);
         if let Some(vec) = &self.info.added {
             vec.record(e);
         }
     }
+    /// 行被销毁，在修改和添加的事件列表上记录墓碑，只和监听者数量有关，不扫描事件
+    #[inline(always)]
+    pub(crate) fn tombstone(&self, row: Row) {
+        if !self.info.is_tick() {
+            return;
+        }
+        if let Some(vec) = &self.info.changed {
+            // 添加时同时设置了修改tick，但只记录添加事件。修改tick和添加tick相同时，没有添加之后的修改事件
+            let changed = self.get_tick_unchecked(row);
+            if changed != self.get_added_tick_unchecked(row) {
+                vec.tombstone(changed);
+            }
+        }
+        if let Some(vec) = &self.info.added {
+            vec.tombstone(self.get_added_tick_unchecked(row));
+        }
+    }
     #[inline]
     pub fn changed_tick(&self, e: Entity, row: Row, tick: Tick) {
         // println!("changed_tick: {:?}", (e, row, tick, self.info.is_tick(), ));
         if !self.info.is_tick() {
             return;
         }
-        let old = self.blob.ticks.load_alloc(row.index());
-        if *old >= tick {
+        let updated = self.update_tick(row, tick);
+        self.record_span(row, tick, updated);
+        if !updated {
             return;
         }
-        *old = tick;
+        self.blob.changes.fetch_add(1, Ordering::Relaxed);
         if let Some(vec) = &self.info.changed {
             vec.record(e);
         }
     }
+    // 记录帧内的修改范围，updated为修改的tick是否变大
+    #[inline(always)]
+    fn record_span(&self, row: Row, tick: Tick, updated: bool) {
+        match self.info.span {
+            Some(SpanDedup::PerWrite) => (),
+            Some(SpanDedup::PerTick) if updated => (),
+            _ => return,
+        }
+        self.check_epoch();
+        let frame = self.info.span_frame.load(Ordering::Relaxed);
+        let cell = self.blob.spans.load_alloc(row.index());
+        if cell.frame != frame || cell.count == 0 {
+            *cell = SpanCell {
+                first: tick,
+                last: tick,
+                frame,
+                count: 1,
+            };
+            return;
+        }
+        if tick > cell.last {
+            cell.last = tick;
+        }
+        cell.count = cell.count.saturating_add(1);
+    }
+    /// 行在本帧内的修改范围，本帧没有修改时次数为0，首末tick都为最后修改的tick
+    #[inline(always)]
+    pub fn change_span(&self, row: Row) -> ChangeSpanItem {
+        self.check_epoch();
+        let frame = self.info.span_frame.load(Ordering::Relaxed);
+        match self.blob.spans.get(row.index()) {
+            Some(cell) if cell.frame == frame && cell.count > 0 => ChangeSpanItem {
+                first_changed: cell.first,
+                last_changed: cell.last,
+                change_count_this_frame: cell.count as u32,
+            },
+            _ => {
+                let tick = self.get_tick_unchecked(row);
+                ChangeSpanItem {
+                    first_changed: tick,
+                    last_changed: tick,
+                    change_count_this_frame: 0,
+                }
+            }
+        }
+    }
+    /// 添加和修改的次数，只在记录tick时计数，只增不减
+    #[inline(always)]
+    pub fn change_count(&self) -> usize {
+        self.blob.changes.load(Ordering::Relaxed)
+    }
+    // 更新修改的tick，返回tick是否变大
+    #[inline(always)]
+    fn update_tick(&self, row: Row, tick: Tick) -> bool {
+        self.check_epoch();
+        if self.info.policy.compressed {
+            let delta = encode_tick(self.blob.base, tick);
+            let old = self.blob.changed_deltas.load_alloc(row.index());
+            // 饱和的差值不知道真实的tick，总是认为变化了
+            if *old != DELTA_NEW && *old >= delta {
+                return false;
+            }
+            *old = delta;
+            return true;
+        }
+        let old = self.blob.changed_ticks.load_alloc(row.index());
+        if *old >= tick {
+            return false;
+        }
+        *old = tick;
+        true
+    }
+    /// 粗粒度的修改，设置行的修改tick，但同一个tick内每个原型只记录一次修改事件，
+    /// 记录的是原型中当时的全部实体，之后新增的行在被修改时再补记录
+    pub fn coarse_changed_tick(&self, row: Row, tick: Tick, ar: &Archetype) {
+        if !self.info.is_tick() {
+            return;
+        }
+        let updated = self.update_tick(row, tick);
+        self.record_span(row, tick, updated);
+        if !updated {
+            return;
+        }
+        self.blob.changes.fetch_add(1, Ordering::Relaxed);
+        let vec = match &self.info.changed {
+            Some(vec) => vec,
+            None => return,
+        };
+        let len = ar.len().index();
+        let mut old = self.blob.coarse.load(Ordering::Relaxed);
+        loop {
+            let (t, recorded) = ((old >> 32) as u32, old as u32 as usize);
+            let start = if t == *tick {
+                if row.index() < recorded {
+                    return;
+                }
+                recorded
+            } else {
+                0
+            };
+            let new = ((*tick as u64) << 32) | len as u64;
+            match self
+                .blob
+                .coarse
+                .compare_exchange_weak(old, new, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => {
+                    let es: Vec<Entity> = (start..len)
+                        .filter_map(|r| ar.live_entity(r.into()))
+                        .collect();
+                    vec.batch_record(&es);
+                    return;
+                }
+                Err(v) => old = v,
+            }
+        }
+    }
+    /// 批量的changed_tick，tick实际变化的实体，在最后一次性记录到修改事件上
+    pub fn batch_changed(&self, rows: &[(Row, Entity)], tick: Tick) {
+        if !self.info.is_tick() {
+            return;
+        }
+        let mut changed = Vec::new();
+        for (row, e) in rows {
+            let updated = self.update_tick(*row, tick);
+            self.record_span(*row, tick, updated);
+            if updated {
+                changed.push(*e);
+            }
+        }
+        if !changed.is_empty() {
+            self.blob.changes.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(vec) = &self.info.changed {
+            vec.batch_record(&changed);
+        }
+    }
+    /// 设置修改的tick
     #[inline]
     pub fn set_tick_unchecked(&self, row: Row, tick: Tick) {
-        *self.blob.ticks.load_alloc(row.index()) = tick;
+        self.check_epoch();
+        if self.info.policy.compressed {
+            *self.blob.changed_deltas.load_alloc(row.index()) = encode_tick(self.blob.base, tick);
+            return;
+        }
+        *self.blob.changed_ticks.load_alloc(row.index()) = tick;
+    }
+    /// 设置添加的tick
+    #[inline]
+    pub fn set_added_tick_unchecked(&self, row: Row, tick: Tick) {
+        self.check_epoch();
+        if self.info.policy.compressed {
+            *self.blob.added_deltas.load_alloc(row.index()) = encode_tick(self.blob.base, tick);
+            return;
+        }
+        *self.blob.added_ticks.load_alloc(row.index()) = tick;
+    }
+    /// 将行的添加和修改tick复制到目标列的行上
+    #[inline]
+    pub fn copy_ticks_to(&self, row: Row, dst: &BlobRef, dst_row: Row) {
+        dst.set_added_tick_unchecked(dst_row, self.get_added_tick_unchecked(row));
+        dst.set_tick_unchecked(dst_row, self.get_tick_unchecked(row));
+        if dst.info.span.is_some() {
+            *dst.blob.spans.load_alloc(dst_row.index()) =
+                self.blob.spans.get(row.index()).copied().unwrap_or_default();
+        }
     }
     fn trace(&self, row: Row, e: Entity, path: &str, src_data: *mut u8) {
         #[cfg(debug_assertions)]
@@ -442,26 +1067,36 @@ impl<'a> BlobRef<'a> {
         assert!(!row.is_null());
         self.trace(row, e, "drop_row", std::ptr::null_mut());
         if let Some(f) = self.info.drop_fn {
-            f(self.get_blob(row))
+            self.drop_blob(f, row)
         }
     }
     #[inline(always)]
     pub fn drop_row_unchecked(&self, row: Row, e: Entity) {
         assert!(!row.is_null());
         self.trace(row, e, "drop_row_unchecked", std::ptr::null_mut());
-        self.info.drop_fn.unwrap()(self.get_blob(row))
+        self.drop_blob(self.info.drop_fn.unwrap(), row)
+    }
+    // 延迟释放的组件移入缓冲，否则立即释放
+    #[inline(always)]
+    fn drop_blob(&self, f: fn(*mut u8), row: Row) {
+        match &self.info.deferred_drop {
+            Some(q) => q.push(self.get_blob(row)),
+            None => f(self.get_blob(row)),
+        }
     }
 
     // 如果没有分配内存，则返回的指针为is_null()
     #[inline(always)]
     pub fn get_blob(&self, row: Row) -> *mut u8 {
         assert!(!row.is_null());
+        self.check_epoch();
         unsafe { transmute(self.blob.blob.get_multiple(row.index(), self.info.size())) }
     }
     // 一定会返回分配后的内存
     #[inline(always)]
     pub fn load_blob(&self, row: Row) -> *mut u8 {
         assert!(!row.is_null());
+        self.check_epoch();
         unsafe {
             transmute(
                 self.blob
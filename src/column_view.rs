@@ -0,0 +1,174 @@
+//! 按列直接访问组件
+//! 不经过Query的逐行fetch，每个包含组件T的原型返回一个列视图，适用于对单个组件做批量计算的system。
+//! 声明对组件T的写，冲突检查和Query<&mut T>一致。
+//! 修改的tick可以逐行记录（get_mut、iter_mut返回Mut），也可以按原型整体记录（iter_mut_all）。
+//! fn sys(mut cols: ColumnsMut<Age0>) { for mut view in cols.iter_mut() { for mut v in view.iter_mut() { v.0 += 1; } } }
+//!
+
+use std::marker::PhantomData;
+use std::mem::transmute;
+
+use crate::archetype::{Archetype, Row};
+use crate::fetch::{ColumnTick, Mut};
+use crate::query::QueryState;
+use crate::system::SystemMeta;
+use crate::system_params::SystemParam;
+use crate::world::{Entity, Tick, World};
+
+pub struct ColumnsMut<'w, T: 'static> {
+    world: &'w World,
+    state: &'w mut QueryState<&'static mut T, ()>,
+    tick: Tick,
+}
+unsafe impl<T> Send for ColumnsMut<'_, T> {}
+unsafe impl<T> Sync for ColumnsMut<'_, T> {}
+
+impl<'w, T: 'static> ColumnsMut<'w, T> {
+    pub fn new(world: &'w World, state: &'w mut QueryState<&'static mut T, ()>, tick: Tick) -> Self {
+        ColumnsMut { world, state, tick }
+    }
+    pub fn world(&self) -> &World {
+        self.world
+    }
+    pub fn tick(&self) -> Tick {
+        self.tick
+    }
+    pub fn last_run(&self) -> Tick {
+        self.state.last_run
+    }
+    /// 包含该组件的原型数量
+    pub fn len(&self) -> usize {
+        self.state.archetypes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.state.archetypes.is_empty()
+    }
+    /// 获得第i个包含该组件的原型的列视图
+    pub fn get_mut(&mut self, i: usize) -> Option<ColumnViewMut<'_, T>> {
+        let ar = self.state.archetypes.get(i)?;
+        Some(ColumnViewMut::new(
+            ar,
+            ColumnTick::new(
+                self.state.fetch_state.blob_ref_unchecked(ar.index()),
+                self.tick,
+                self.state.last_run,
+            ),
+        ))
+    }
+    /// 迭代每个包含该组件的非空原型的列视图
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = ColumnViewMut<'_, T>> + '_ {
+        let (tick, last_run) = (self.tick, self.state.last_run);
+        let column = &self.state.fetch_state;
+        self.state
+            .archetypes
+            .iter()
+            .filter(|ar| ar.len().index() > 0)
+            .map(move |ar| {
+                ColumnViewMut::new(
+                    ar,
+                    ColumnTick::new(column.blob_ref_unchecked(ar.index()), tick, last_run),
+                )
+            })
+    }
+}
+
+impl<T: 'static + Send + Sync> SystemParam for ColumnsMut<'_, T> {
+    type State = QueryState<&'static mut T, ()>;
+    type Item<'w> = ColumnsMut<'w, T>;
+
+    fn init_state(world: &mut World, system_meta: &mut SystemMeta) -> Self::State {
+        Self::State::create(world, system_meta)
+    }
+    fn align(world: &World, system_meta: &SystemMeta, state: &mut Self::State) {
+        let len = system_meta.align_len(world);
+        state.align_to(world, len);
+    }
+
+    fn get_param<'w>(
+        world: &'w World,
+        _system_meta: &'w SystemMeta,
+        state: &'w mut Self::State,
+        tick: Tick,
+    ) -> Self::Item<'w> {
+        ColumnsMut::new(world, state, tick)
+    }
+
+    fn get_self<'w>(
+        world: &'w World,
+        system_meta: &'w SystemMeta,
+        state: &'w mut Self::State,
+        tick: Tick,
+    ) -> Self {
+        unsafe { transmute(Self::get_param(world, system_meta, state, tick)) }
+    }
+}
+
+impl<'w, T: 'static> Drop for ColumnsMut<'w, T> {
+    fn drop(&mut self) {
+        // tick冻结时不推进last_run
+        if !self.world.is_ticks_frozen() {
+            self.state.last_run = self.tick;
+        }
+    }
+}
+
+/// 一个原型上组件T的列视图，行包括已被移除但还未整理的空行，空行的实体为null
+pub struct ColumnViewMut<'a, T: 'static> {
+    ar: &'a Archetype,
+    c: ColumnTick<'a>,
+    _p: PhantomData<T>,
+}
+
+impl<'a, T: 'static> ColumnViewMut<'a, T> {
+    fn new(ar: &'a Archetype, c: ColumnTick<'a>) -> Self {
+        Self {
+            ar,
+            c,
+            _p: PhantomData,
+        }
+    }
+    pub fn archetype(&self) -> &Archetype {
+        self.ar
+    }
+    /// 行数
+    pub fn len(&self) -> usize {
+        self.ar.len().index()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// 每行的实体
+    pub fn entities(&self) -> impl Iterator<Item = Entity> + '_ {
+        (0..self.len()).map(|row| self.ar.get_unchecked(row.into()))
+    }
+    /// 获得指定行的组件，行越界或已被移除，返回None
+    pub fn get_mut(&mut self, row: Row) -> Option<Mut<'_, T>> {
+        let e = self.ar.live_entity(row)?;
+        Some(Mut::new(&self.c, e, row))
+    }
+    /// 迭代存活的行，修改时逐行记录tick
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = Mut<'_, T>> + '_ {
+        let c = &self.c;
+        let ar = self.ar;
+        (0..self.len()).filter_map(move |row| {
+            let row: Row = row.into();
+            ar.live_entity(row).map(|e| Mut::new(c, e, row))
+        })
+    }
+    /// 先将全部存活的行标记为已修改，然后迭代组件的可变引用，不再逐行记录tick
+    pub fn iter_mut_all(&mut self) -> impl Iterator<Item = (Entity, &mut T)> + '_ {
+        let rows: Vec<(Row, Entity)> = (0..self.len())
+            .filter_map(|row| {
+                let row: Row = row.into();
+                self.ar.live_entity(row).map(|e| (row, e))
+            })
+            .collect();
+        self.c.column.batch_changed(&rows, self.c.tick);
+        let column = &self.c.column;
+        let ar = self.ar;
+        (0..self.len()).filter_map(move |row| {
+            let row: Row = row.into();
+            ar.live_entity(row).map(|e| (e, column.get_mut::<T>(row, e)))
+        })
+    }
+}
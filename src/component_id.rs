@@ -0,0 +1,93 @@
+//! 组件和原型的id
+//! 组件id由TypeId经固定种子的哈希计算，不依赖TypeId的内存布局；原型的alter依赖用组件id的异或表示。
+//! 默认为128位（wide-ids特性），wasm32上u128的异或和比较较慢，固定使用64位。
+//! 原型表的键是组件索引的64位哈希，两种宽度下相同。查找原型时会检查组件是否一致，哈希冲突时按固定步长探测下一个键，原型的id为实际使用的键。
+//! let id = ComponentInfo::of::<Pos>(0).id();
+//!
+
+use std::any::TypeId;
+use std::fmt::{Display, Formatter};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ops::BitXor;
+
+use crate::archetype::{Archetype, ArchetypeInfo};
+use crate::world::ComponentIndex;
+
+/// id的位宽
+#[cfg(all(feature = "wide-ids", not(target_arch = "wasm32")))]
+pub type IdBits = u128;
+#[cfg(not(all(feature = "wide-ids", not(target_arch = "wasm32"))))]
+pub type IdBits = u64;
+
+// 用固定的种子哈希，同一进程内结果不变
+fn hash_with<T: Hash + ?Sized>(seed: u64, value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    seed.hash(&mut hasher);
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+// 窄模式下IdBits就是u64
+#[allow(clippy::useless_conversion)]
+#[inline(always)]
+fn widen(v: u64) -> IdBits {
+    v.into()
+}
+
+/// 组件id，多个组件的id为各组件id的异或
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ComponentId(IdBits);
+impl ComponentId {
+    /// 由TypeId计算
+    pub fn of(type_id: &TypeId) -> Self {
+        let lo = widen(hash_with(0, type_id));
+        #[cfg(all(feature = "wide-ids", not(target_arch = "wasm32")))]
+        let lo = lo | (widen(hash_with(1, type_id)) << 64);
+        ComponentId(lo)
+    }
+    /// 由原型id构造，用于执行图中原型的节点
+    pub fn from_archetype(id: u64) -> Self {
+        ComponentId(widen(id))
+    }
+    #[inline(always)]
+    pub fn bits(&self) -> IdBits {
+        self.0
+    }
+}
+impl BitXor for ComponentId {
+    type Output = Self;
+    #[inline(always)]
+    fn bitxor(self, rhs: Self) -> Self {
+        ComponentId(self.0 ^ rhs.0)
+    }
+}
+impl Display for ComponentId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:x}", self.0)
+    }
+}
+
+// 原型表的键，由排序后的组件索引计算
+pub(crate) fn archetype_id(sorted: impl Iterator<Item = ComponentIndex>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for index in sorted {
+        index.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+// 原型表中id相同的原型，组件是否相同，不同则是哈希冲突
+pub(crate) fn same_components(ar: &Archetype, info: &ArchetypeInfo) -> bool {
+    ar.get_columns().len() == info.sorted_components.len()
+        && ar
+            .get_columns()
+            .iter()
+            .zip(info.sorted_components.iter())
+            .all(|(a, b)| a.info().index == b.info().index)
+}
+
+// 哈希冲突时，探测的下一个id
+#[inline(always)]
+pub(crate) fn next_archetype_id(id: u64) -> u64 {
+    id.wrapping_add(0x9e37_79b9_7f4a_7c15)
+}
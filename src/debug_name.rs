@@ -0,0 +1,97 @@
+//! 实体的调试名称
+//! 开启debug-names特性后，可以给实体设置名称，World::entity_label和QueryError::describe格式化实体时会带上名称。
+//! 名称按实体保存，Alter改变实体的原型不影响名称。World::destroy_entity销毁的实体立即回收名称，
+//! 其他方式（Alter、EntityEditor、归档）销毁的实体在整理时回收。
+//! fn debug(q: Query<(Entity, NameOf)>) { for (e, name) in q.iter() { ... } }
+//!
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use pi_append_vec::SafeVec;
+
+use crate::archetype::{ArchetypeIndex, Row};
+use crate::fetch::FetchComponents;
+use crate::query::QueryError;
+use crate::system::SystemMeta;
+use crate::world::{Entity, Tick, World};
+
+/// 实体名称的存储
+#[derive(Default)]
+pub struct EntityNames {
+    map: HashMap<Entity, Cow<'static, str>>,
+    destroyed: SafeVec<Entity>, // 上次整理后销毁的有名称的实体
+}
+impl EntityNames {
+    // 记录销毁的实体，没有名称的实体不记录
+    #[inline(always)]
+    pub(crate) fn record_destroyed(&self, e: Entity) {
+        if self.map.contains_key(&e) {
+            self.destroyed.insert(e);
+        }
+    }
+    #[inline(always)]
+    fn get(&self, e: Entity) -> Option<&str> {
+        self.map.get(&e).map(|name| name.as_ref())
+    }
+}
+
+impl World {
+    /// 设置实体的调试名称，已有则替换
+    pub fn set_entity_name(&mut self, e: Entity, name: Cow<'static, str>) -> Result<(), QueryError> {
+        if !self.contains_entity(e) {
+            return Err(QueryError::NoSuchEntity(e));
+        }
+        self.names.map.insert(e, name);
+        Ok(())
+    }
+    /// 实体的调试名称，实体已销毁则返回None
+    pub fn entity_name(&self, e: Entity) -> Option<&str> {
+        if !self.contains_entity(e) {
+            return None;
+        }
+        self.names.get(e)
+    }
+    /// 有调试名称的实体数量，包括已销毁但还未整理的实体
+    pub fn entity_names_len(&self) -> usize {
+        self.names.map.len()
+    }
+    // 立即回收实体的名称
+    pub(crate) fn remove_entity_name(&mut self, e: Entity) {
+        self.names.map.remove(&e);
+    }
+    // 整理时回收销毁的实体的名称
+    pub(crate) fn sweep_names(&mut self) {
+        for e in self.names.destroyed.iter() {
+            self.names.map.remove(e);
+        }
+        self.names.destroyed.clear(0);
+    }
+}
+
+/// 读取实体的调试名称，没有则为None
+pub struct NameOf;
+impl FetchComponents for NameOf {
+    type Fetch<'w> = &'w EntityNames;
+    type Item<'w> = Option<&'w str>;
+    type ReadOnly = Self;
+    type State = ();
+
+    fn init_state(_world: &mut World, _meta: &mut SystemMeta) -> Self::State {}
+    #[inline]
+    fn init_fetch<'w>(
+        world: &'w World,
+        _state: &'w Self::State,
+        _index: ArchetypeIndex,
+        _tick: Tick,
+        _last_run: Tick,
+    ) -> Self::Fetch<'w> {
+        &world.names
+    }
+
+    #[inline(always)]
+    fn fetch<'w>(fetch: &Self::Fetch<'w>, _row: Row, e: Entity) -> Self::Item<'w> {
+        let names: &'w EntityNames = fetch;
+        names.get(e)
+    }
+}
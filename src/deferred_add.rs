@@ -0,0 +1,79 @@
+//! 迭代中延迟添加组件
+//! 迭代查询时原型被借用，不能立即添加组件。Query::defer_add将(实体, 组件)记入world上线程安全的缓冲，在独占world时统一添加：
+//! 日程的每个阶段结束后，以及整理开始时。所以同一阶段后续的system看不到，之后阶段的system和下一帧可以看到。
+//! 同一实体的同一组件多次添加，只保留最后的值；添加时实体已销毁，则忽略。
+//! for (e, a) in q.iter() { if a.0 > 10 { q.defer_add(e, Age1(0)).unwrap(); } }
+//!
+
+use std::any::TypeId;
+use std::collections::HashMap;
+
+use pi_share::ShareMutex;
+
+use crate::fetch::FetchComponents;
+use crate::filter::FilterComponents;
+use crate::insert::Bundle;
+use crate::query::{Query, QueryError};
+use crate::world::{Entity, World};
+
+type AddFn = Box<dyn FnOnce(&mut World, Entity) + Send>;
+
+#[derive(Default)]
+pub(crate) struct DeferredAdds {
+    pending: ShareMutex<Vec<((Entity, TypeId), AddFn)>>,
+    index: ShareMutex<HashMap<(Entity, TypeId), usize>>, // 在pending中的位置，用于替换重复的添加
+}
+impl DeferredAdds {
+    pub(crate) fn push<C: Bundle + Send + 'static>(&self, e: Entity, value: C) {
+        let key = (e, TypeId::of::<C>());
+        let f: AddFn = Box::new(move |world, e| {
+            let _ = world.make_entity_editor().add_components(e, value);
+        });
+        let mut pending = self.pending.lock().unwrap();
+        let mut index = self.index.lock().unwrap();
+        match index.get(&key) {
+            // 旧的值被释放
+            Some(i) => pending[*i].1 = f,
+            None => {
+                index.insert(key, pending.len());
+                pending.push((key, f));
+            }
+        }
+    }
+    #[inline(always)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pending.lock().unwrap().is_empty()
+    }
+    fn take(&self) -> Vec<((Entity, TypeId), AddFn)> {
+        self.index.lock().unwrap().clear();
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+}
+
+impl<'w, Q: FetchComponents, F: FilterComponents> Query<'w, Q, F> {
+    /// 延迟给实体添加组件，已有该组件则替换。实体不存在时返回错误
+    pub fn defer_add<C: Bundle + Send + 'static>(&self, e: Entity, value: C) -> Result<(), QueryError> {
+        if !self.world.contains_entity(e) {
+            return Err(QueryError::NoSuchEntity(e));
+        }
+        self.world.deferred_adds.push(e, value);
+        Ok(())
+    }
+}
+
+impl World {
+    /// 执行延迟添加的组件，按添加的顺序执行，已销毁的实体被忽略。返回执行的数量
+    pub fn flush_deferred_adds(&mut self) -> usize {
+        if self.deferred_adds.is_empty() {
+            return 0;
+        }
+        let mut count = 0;
+        for ((e, _), f) in self.deferred_adds.take() {
+            if self.contains_entity(e) {
+                f(self, e);
+                count += 1;
+            }
+        }
+        count
+    }
+}
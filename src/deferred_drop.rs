@@ -0,0 +1,175 @@
+//! 组件的延迟释放
+//! 持有GPU、IO资源的组件，Drop可能要数毫秒，集中销毁时即使用CleanupPolicy::EndOfFrame也会卡住一帧。
+//! 注册为延迟释放的组件，销毁、移除或整理时不调用drop_fn，而是将组件的字节移动到列上的待释放缓冲中，
+//! 之后由World::run_deferred_drops，或在工作线程上由DeferredDropRunner::run执行真正的释放。
+//! 字节在释放行时立即移出，行被复用不影响待释放的组件。组件必须是Send，释放发生在别的线程上。
+//! world.register_deferred_drop::<GpuBuffer>(); let runner = world.deferred_drop_runner();
+//! std::thread::spawn(move || loop { runner.run(usize::MAX); ... });
+//!
+
+use std::alloc::{alloc, dealloc, Layout};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use pi_share::{Share, ShareMutex};
+
+use crate::archetype::get_drop;
+use crate::world::{ComponentIndex, World};
+
+/// 延迟释放的统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeferredDropStats {
+    pub components: usize, // 注册为延迟释放的组件数量
+    pub pending: usize,    // 待释放的数量
+    pub dropped: usize,    // 已释放的数量
+}
+
+// 待释放的组件，字节紧密排列，释放时复制到对齐的内存中
+#[derive(Default)]
+struct Pending {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+// 一个组件的待释放缓冲
+pub(crate) struct DropQueue {
+    drop_fn: fn(*mut u8),
+    layout: Layout,
+    pending: ShareMutex<Pending>,
+    dropped: AtomicUsize,
+}
+impl DropQueue {
+    fn new<T: Send + 'static>() -> Option<Self> {
+        Some(Self {
+            drop_fn: get_drop::<T>()?,
+            layout: Layout::new::<T>(),
+            pending: ShareMutex::new(Pending::default()),
+            dropped: AtomicUsize::new(0),
+        })
+    }
+    // 将组件的字节移入缓冲，之后ptr处的数据视为已移出
+    pub(crate) fn push(&self, ptr: *mut u8) {
+        let size = self.layout.size();
+        let mut p = self.pending.lock().unwrap();
+        p.bytes
+            .extend_from_slice(unsafe { std::slice::from_raw_parts(ptr, size) });
+        p.len += 1;
+    }
+    pub(crate) fn pending_len(&self) -> usize {
+        self.pending.lock().unwrap().len
+    }
+    // 释放最多budget个组件，返回释放的数量
+    pub(crate) fn run(&self, budget: usize) -> usize {
+        let size = self.layout.size();
+        let (bytes, n) = {
+            let mut p = self.pending.lock().unwrap();
+            let n = p.len.min(budget);
+            if n == 0 {
+                return 0;
+            }
+            p.len -= n;
+            let bytes: Vec<u8> = if p.len == 0 {
+                std::mem::take(&mut p.bytes)
+            } else {
+                p.bytes.drain(..n * size).collect()
+            };
+            (bytes, n)
+        };
+        // 移出锁后再释放，释放时不阻塞写入
+        let scratch = if size == 0 {
+            std::ptr::without_provenance_mut(self.layout.align())
+        } else {
+            unsafe { alloc(self.layout) }
+        };
+        for i in 0..n {
+            unsafe {
+                bytes.as_ptr().add(i * size).copy_to_nonoverlapping(scratch, size);
+            }
+            (self.drop_fn)(scratch);
+        }
+        if size > 0 {
+            unsafe { dealloc(scratch, self.layout) };
+        }
+        self.dropped.fetch_add(n, Ordering::Relaxed);
+        n
+    }
+}
+impl Drop for DropQueue {
+    fn drop(&mut self) {
+        // 随world释放时，释放还未释放的组件
+        self.run(usize::MAX);
+    }
+}
+
+/// 在工作线程上执行延迟释放，持有各列的待释放缓冲，可以移动到其他线程
+#[derive(Clone)]
+pub struct DeferredDropRunner {
+    queues: Vec<Share<DropQueue>>,
+}
+impl DeferredDropRunner {
+    /// 释放最多budget个组件，返回释放的数量
+    pub fn run(&self, mut budget: usize) -> usize {
+        let mut count = 0;
+        for q in self.queues.iter() {
+            if budget == 0 {
+                break;
+            }
+            let n = q.run(budget);
+            budget -= n;
+            count += n;
+        }
+        count
+    }
+    /// 待释放的数量
+    pub fn pending(&self) -> usize {
+        self.queues.iter().map(|q| q.pending_len()).sum()
+    }
+}
+
+impl World {
+    /// 将组件T注册为延迟释放，T没有Drop时返回false
+    pub fn register_deferred_drop<T: Send + 'static>(&mut self) -> bool {
+        let index = self.init_component::<T>();
+        let column = unsafe { self.component_arr.get_unchecked_mut(index.index()) };
+        if column.info.deferred_drop.is_some() {
+            return true;
+        }
+        let queue = match DropQueue::new::<T>() {
+            Some(q) => q,
+            None => return false,
+        };
+        let c = unsafe { Share::get_mut_unchecked(column) };
+        c.info.deferred_drop = Some(Share::new(queue));
+        true
+    }
+    /// 执行最多budget个延迟释放，返回释放的数量
+    pub fn run_deferred_drops(&self, budget: usize) -> usize {
+        self.deferred_drop_runner().run(budget)
+    }
+    /// 获得延迟释放的执行器，之后注册的组件不包含在内
+    pub fn deferred_drop_runner(&self) -> DeferredDropRunner {
+        DeferredDropRunner {
+            queues: self
+                .component_arr
+                .iter()
+                .filter_map(|c| c.info.deferred_drop.clone())
+                .collect(),
+        }
+    }
+    /// 延迟释放的统计
+    pub fn deferred_drop_stats(&self) -> DeferredDropStats {
+        let mut stats = DeferredDropStats::default();
+        for q in self.component_arr.iter().filter_map(|c| c.info.deferred_drop.as_ref()) {
+            stats.components += 1;
+            stats.pending += q.pending_len();
+            stats.dropped += q.dropped.load(Ordering::Relaxed);
+        }
+        stats
+    }
+    /// 组件index的待释放数量
+    pub fn pending_deferred_drops(&self, index: ComponentIndex) -> usize {
+        self.component_arr
+            .get(index.index())
+            .and_then(|c| c.info.deferred_drop.as_ref())
+            .map_or(0, |q| q.pending_len())
+    }
+}
@@ -0,0 +1,224 @@
+//! 组件差异，用于回滚网络同步
+//! 组件注册diff_fn和apply_fn后，World::diff_since只访问上次比较后修改过的行，输出(实体, 组件稳定id, 补丁)，
+//! 以及上次比较后创建和销毁的实体（包括没有可比较组件的实体）。接收端用World::apply_diff应用，并维护远端实体到本地实体的映射。
+//! 注册差异时同时注册组件的稳定名称，两端用稳定名称计算的id对应组件，不依赖类型名。
+//! 只跟踪注册了差异的组件，已有实体上组件的增删不在差异中。
+//! world.register_diffable::<Pos>("game.pos"); world.diff_since(&mut baseline, &mut diff);
+//!
+
+use std::collections::{HashMap, HashSet};
+
+use pi_share::Share;
+
+use crate::archetype::{ArchetypeInfo, ComponentInfo, COMPONENT_TICK};
+use crate::column::Column;
+use crate::world::{Entity, Tick, World};
+
+/// 比较组件的新旧值，有差异则将补丁写入out并返回true，old为空指针表示没有旧值
+pub type DiffFn = fn(old: *const u8, new: *const u8, out: &mut Vec<u8>) -> bool;
+/// 将补丁应用到组件上
+pub type ApplyFn = fn(dst: *mut u8, patch: &[u8]);
+
+/// register_diffable使用的完整值补丁的编码。按字段显式写出，不直接复制内存，内存中可能有未初始化的填充字节
+pub trait DiffValue: PartialEq + Copy + 'static {
+    /// 将值写入out
+    fn encode(&self, out: &mut Vec<u8>);
+    /// 从补丁还原值，补丁不合法时返回None
+    fn decode(patch: &[u8]) -> Option<Self>;
+}
+macro_rules! impl_diff_value {
+    ($($t: ty),*) => {
+        $(impl DiffValue for $t {
+            fn encode(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+            fn decode(patch: &[u8]) -> Option<Self> {
+                Some(<$t>::from_le_bytes(patch.try_into().ok()?))
+            }
+        })*
+    };
+}
+impl_diff_value!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+/// 上次比较时的状态
+#[derive(Debug, Default, Clone)]
+pub struct DiffBaseline {
+    tick: Tick,
+    entities: HashSet<Entity>,
+}
+impl DiffBaseline {
+    /// 下次比较的下界，上次比较后推进的tick
+    pub fn tick(&self) -> Tick {
+        self.tick
+    }
+}
+
+/// 两次比较之间的差异，都按实体及组件稳定id排序
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct WorldDiff {
+    pub spawned: Vec<Entity>,
+    pub despawned: Vec<Entity>,
+    pub patches: Vec<(Entity, u64, Vec<u8>)>,
+}
+impl WorldDiff {
+    pub fn clear(&mut self) {
+        self.spawned.clear();
+        self.despawned.clear();
+        self.patches.clear();
+    }
+    pub fn is_empty(&self) -> bool {
+        self.spawned.is_empty() && self.despawned.is_empty() && self.patches.is_empty()
+    }
+}
+
+// 完整值的差异，值不同就输出整个值的编码
+fn diff_value<T: DiffValue>(old: *const u8, new: *const u8, out: &mut Vec<u8>) -> bool {
+    let new = unsafe { &*(new as *const T) };
+    if !old.is_null() && unsafe { &*(old as *const T) } == new {
+        return false;
+    }
+    new.encode(out);
+    true
+}
+// dst可能是新分配未初始化的行，T是Copy，直接写入
+fn apply_value<T: DiffValue>(dst: *mut u8, patch: &[u8]) {
+    let value = match T::decode(patch) {
+        Some(v) => v,
+        None => panic!("invalid diff patch for {}", std::any::type_name::<T>()),
+    };
+    unsafe { (dst as *mut T).write(value) };
+}
+
+impl World {
+    /// 注册组件的差异函数和稳定名称，组件会记录修改tick
+    pub fn register_diff<T: 'static>(&mut self, name: &'static str, diff_fn: DiffFn, apply_fn: ApplyFn) {
+        self.register_stable_name::<T>(name);
+        let mut column = self.add_component_info(ComponentInfo::of::<T>(COMPONENT_TICK)).1;
+        let info = unsafe { Share::get_mut_unchecked(&mut column) }.info_mut();
+        info.diff_fn = Some(diff_fn);
+        info.apply_fn = Some(apply_fn);
+    }
+    /// 用完整值的编码作为补丁注册组件的差异
+    pub fn register_diffable<T: DiffValue>(&mut self, name: &'static str) {
+        self.register_diff::<T>(name, diff_value::<T>, apply_value::<T>)
+    }
+    /// 计算baseline后的差异。修改tick不早于baseline的tick的行都会被访问。
+    /// 比较后推进world的tick，将推进后的tick作为baseline的下界，比较之后的修改不会和已输出的修改在同一tick而丢失。
+    /// tick被冻结时不能推进，该tick内的修改在下次比较时会再输出一次，应用完整值的补丁是幂等的
+    pub fn diff_since(&self, baseline: &mut DiffBaseline, out: &mut WorldDiff) {
+        out.clear();
+        let since = baseline.tick;
+        let mut entities = HashSet::with_capacity(baseline.entities.len());
+        let mut patch = Vec::new();
+        for ar in self.archetype_arr.iter() {
+            let columns: Vec<&Share<Column>> = ar
+                .get_columns()
+                .iter()
+                .filter(|c| c.info().diff_fn.is_some())
+                .collect();
+            for row in 0..ar.len().index() {
+                let row = row.into();
+                let e = match ar.live_entity(row) {
+                    Some(e) => e,
+                    None => continue,
+                };
+                entities.insert(e);
+                let spawned = !baseline.entities.contains(&e);
+                if spawned {
+                    out.spawned.push(e);
+                }
+                for c in columns.iter() {
+                    let b = c.blob_ref_unchecked(ar.index());
+                    if !spawned && b.get_tick_unchecked(row) < since {
+                        continue;
+                    }
+                    patch.clear();
+                    let diff_fn = c.info().diff_fn.unwrap();
+                    if diff_fn(std::ptr::null(), b.get_row(row, e), &mut patch) {
+                        out.patches.push((e, c.info().stable_id(), patch.clone()));
+                    }
+                }
+            }
+        }
+        for e in baseline.entities.iter() {
+            if !entities.contains(e) {
+                out.despawned.push(*e);
+            }
+        }
+        out.spawned.sort();
+        out.despawned.sort();
+        out.patches.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+        baseline.entities = entities;
+        self.increment_tick();
+        baseline.tick = self.tick();
+    }
+    /// 应用差异，mapper为远端实体到本地实体的映射，创建和销毁的实体会更新到mapper中
+    pub fn apply_diff(&mut self, diff: &WorldDiff, mapper: &mut HashMap<Entity, Entity>) {
+        let mut columns: HashMap<u64, Share<Column>> = HashMap::new();
+        for c in self.component_arr.iter() {
+            if c.info().apply_fn.is_some() {
+                columns.insert(c.info().stable_id(), c.clone());
+            }
+        }
+        for e in diff.despawned.iter() {
+            if let Some(local) = mapper.remove(e) {
+                let _ = self.destroy_entity(local);
+            }
+        }
+        let tick = self.tick();
+        let mut i = 0;
+        while i < diff.patches.len() {
+            let e = diff.patches[i].0;
+            let mut end = i + 1;
+            while end < diff.patches.len() && diff.patches[end].0 == e {
+                end += 1;
+            }
+            let patches = &diff.patches[i..end];
+            i = end;
+            if diff.spawned.binary_search(&e).is_ok() && !mapper.contains_key(&e) {
+                // 用补丁中的组件创建实体
+                let vec = patches
+                    .iter()
+                    .filter_map(|(_, id, _)| columns.get(id).cloned())
+                    .collect();
+                let ar = self.find_archtype(ArchetypeInfo::sort(vec));
+                let (r, row) = ar.alloc();
+                let row = row.into();
+                let local = self.insert_addr(ar.index(), row);
+                for (_, id, patch) in patches {
+                    if let Some(c) = columns.get(id) {
+                        let b = c.blob_ref_unchecked(ar.index());
+                        c.info().apply_fn.unwrap()(b.load(row, local), patch);
+                        b.added_tick(local, row, tick);
+                    }
+                }
+                *r = local;
+                ar.record_allocated(row, local);
+                mapper.insert(e, local);
+                continue;
+            }
+            let local = match mapper.get(&e) {
+                Some(local) => *local,
+                None => continue,
+            };
+            let addr = match self.entities.get(local) {
+                Some(addr) => *addr,
+                None => continue,
+            };
+            for (_, id, patch) in patches {
+                let b = match columns.get(id).and_then(|c| c.blob_ref(addr.archetype_index())) {
+                    Some(b) => b,
+                    None => continue,
+                };
+                b.info.apply_fn.unwrap()(b.get_row(addr.row, local), patch);
+                b.changed_tick(local, addr.row, tick);
+            }
+        }
+        // 没有补丁的新实体
+        for e in diff.spawned.iter() {
+            if !mapper.contains_key(e) {
+                mapper.insert(*e, self.spawn_empty());
+            }
+        }
+    }
+}
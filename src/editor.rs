@@ -19,8 +19,9 @@ impl AState {
             match c.info().set_fn {
                 Some(fun) => fun(world, dst_data),
                 None => {
+                    // 调用前已检查，不应该到达这里
                     log::error!("{:?} is not set_fn!!!", (c, &dst_column));
-                    panic!("{:?} is not set_fn!!!", (c, dst_column))
+                    panic!("component {} has no default value, can't add it to archetype {}", c.info().type_name(), am.dst.name())
                 },
             };
             dst_column.added_tick(e, dst_row, tick)
@@ -131,8 +132,15 @@ impl<'w> EntityEditor<'w> {
         if mapping.dst.id() == mapping.src.id() {
             return Ok(());
         }
+        // 添加的组件没有提供值，必须有默认值
+        self.world.check_defaultable(
+            mapping.add_indexs.clone().map(|i| &state.adding[i]),
+            mapping.dst_index,
+            "EntityEditor::alter_components",
+        )?;
 
         let (_, dst_row) = mapping.dst.alloc();
+        mapping.dst.record_allocated(dst_row.into(), e);
         // println!("edit2: {:?}", (e, addr.row, dst_row, &mapping.dst_index));
 
         let tick = self.world.tick();
@@ -154,14 +162,52 @@ impl<'w> EntityEditor<'w> {
         let info = ArchetypeInfo::sort(components);
         // todo 将Archetype的id改为[ComponentIndex]的hash值，这样尝试获取原型
         let ar = self.world.find_archtype(info);
+        self.world.check_defaultable(ar.get_columns().iter(), ar.index(), "EntityEditor::insert_entity_by_index")?;
         let (r, row) = ar.alloc();
         let e = self.world.insert_addr(ar.index(), row.into());
         let tick = self.world.tick();
         // println!("mapping: {}")
         ar.init_row(self.world, row.into(), e, tick);
         *r = e;
+        ar.record_allocated(row.into(), e);
         Ok(e)
     }
+    /// 根据组件id列表批量插入count个实体，组件都为默认值
+    pub fn insert_entities_by_index(
+        &mut self,
+        components: &[ComponentIndex],
+        count: usize,
+    ) -> Result<Vec<Entity>, QueryError> {
+        let components = components
+            .iter()
+            .map(|index| self.world.get_column(*index).unwrap().clone())
+            .collect();
+        let info = ArchetypeInfo::sort(components);
+        let ar = self.world.find_archtype(info);
+        self.world.check_defaultable(ar.get_columns().iter(), ar.index(), "EntityEditor::insert_entities_by_index")?;
+        let mut slots = Vec::with_capacity(count);
+        let mut entities = Vec::with_capacity(count);
+        let mut start = Row::null();
+        for _ in 0..count {
+            let (r, row) = ar.alloc();
+            let row: Row = row.into();
+            if start.is_null() {
+                start = row;
+            }
+            entities.push(self.world.insert_addr(ar.index(), row));
+            slots.push(r);
+        }
+        for (r, e) in slots.into_iter().zip(entities.iter()) {
+            *r = *e;
+        }
+        let tick = self.world.tick();
+        // 独占world，分配的行是连续的
+        ar.bulk_init_rows(self.world, start, count, tick);
+        for (i, e) in entities.iter().enumerate() {
+            ar.record_allocated(Row(start.0 + i as u32), *e);
+        }
+        Ok(entities)
+    }
     // todo editer 应该支持Insert的Bundle
 
     /// 删除实体
@@ -171,7 +217,7 @@ impl<'w> EntityEditor<'w> {
             None => return Err(QueryError::NoSuchEntity(e)),
         };
         if addr.row.is_null() {
-            self.world.entities.remove(e).unwrap();
+            self.world.on_entity_removed(&self.world.empty_archetype, Row::null(), e);
             return Ok(());
         }
         let ar_index = addr.archetype_index();
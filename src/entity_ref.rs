@@ -0,0 +1,120 @@
+//! 查询之外访问单个实体的组件
+//! 编辑器、调试器和脚本绑定按实体读写任意组件，不需要声明查询。EntityRef/EntityMut创建时确定实体的原型和行，之后按类型取组件。
+//! EntityMut::get_mut返回Mut，和查询一样在修改时记录changed_tick，Changed过滤器可以看到修改。
+//! if let Some(mut e) = world.entity_mut(e) { e.get_mut::<Age>().unwrap().0 += 1; }
+//!
+
+use std::any::TypeId;
+
+use pi_null::Null;
+
+use crate::archetype::{Archetype, Row};
+use crate::fetch::{ColumnTick, Mut};
+use crate::world::{Entity, World};
+
+/// 实体的只读访问
+#[derive(Clone, Copy)]
+pub struct EntityRef<'w> {
+    world: &'w World,
+    e: Entity,
+    ar: &'w Archetype,
+    row: Row,
+}
+
+impl<'w> EntityRef<'w> {
+    /// 实体
+    pub fn id(&self) -> Entity {
+        self.e
+    }
+    /// 实体所在的原型，没有组件的实体为空原型
+    pub fn archetype(&self) -> &'w Archetype {
+        self.ar
+    }
+    /// 实体是否有指定组件
+    pub fn contains<T: 'static>(&self) -> bool {
+        self.ar
+            .contains(self.world.get_component_index(&TypeId::of::<T>()))
+    }
+    /// 获得实体的指定组件，没有该组件返回None
+    pub fn get<T: 'static>(&self) -> Option<&'w T> {
+        if self.row.is_null() {
+            return None;
+        }
+        let index = self.world.get_component_index(&TypeId::of::<T>());
+        let c = self.world.get_column(index)?.blob_ref(self.ar.index())?;
+        Some(c.get::<T>(self.row, self.e))
+    }
+}
+
+/// 实体的可写访问，持有world的独占借用
+pub struct EntityMut<'w> {
+    world: &'w mut World,
+    e: Entity,
+    ar: &'w Archetype,
+    row: Row,
+}
+
+impl<'w> EntityMut<'w> {
+    /// 转为只读访问
+    pub fn as_ref(&self) -> EntityRef<'_> {
+        EntityRef {
+            world: self.world,
+            e: self.e,
+            ar: self.ar,
+            row: self.row,
+        }
+    }
+    /// 实体
+    pub fn id(&self) -> Entity {
+        self.e
+    }
+    /// 实体所在的原型，没有组件的实体为空原型
+    pub fn archetype(&self) -> &Archetype {
+        self.ar
+    }
+    /// 实体是否有指定组件
+    pub fn contains<T: 'static>(&self) -> bool {
+        self.as_ref().contains::<T>()
+    }
+    /// 获得实体的指定组件，没有该组件返回None
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.as_ref().get::<T>()
+    }
+    /// 获得实体的指定组件的可写引用，修改时记录changed_tick
+    pub fn get_mut<T: 'static>(&mut self) -> Option<Mut<'_, T>> {
+        if self.row.is_null() {
+            return None;
+        }
+        let world: &World = self.world;
+        let index = world.get_component_index(&TypeId::of::<T>());
+        let c = world.get_column(index)?.blob_ref(self.ar.index())?;
+        let t = world.tick();
+        Some(Mut::new(&ColumnTick::new(c, t, t), self.e, self.row))
+    }
+}
+
+impl World {
+    /// 获得实体的只读访问，实体不存在返回None
+    pub fn entity(&self, e: Entity) -> Option<EntityRef<'_>> {
+        let addr = self.entities.get(e)?;
+        let ar = self.get_archetype(addr.archetype_index())?;
+        Some(EntityRef {
+            world: self,
+            e,
+            ar,
+            row: addr.row,
+        })
+    }
+    /// 获得实体的可写访问，实体不存在返回None
+    pub fn entity_mut(&mut self, e: Entity) -> Option<EntityMut<'_>> {
+        let addr = *self.entities.get(e)?;
+        // 原型由world的archetype_arr持有，EntityMut存在期间world被独占借用，原型不会被释放
+        let ar: &Archetype = unsafe { &*(&**self.get_archetype(addr.archetype_index())? as *const Archetype) };
+        Some(EntityMut {
+            world: self,
+            e,
+            ar,
+            row: addr.row,
+        })
+    }
+}
@@ -3,12 +3,13 @@
 use std::any::{Any, TypeId};
 use std::borrow::Cow;
 use std::marker::PhantomData;
-use std::mem::{size_of, transmute};
-use std::ops::Deref;
+use std::mem::{needs_drop, size_of, transmute};
+use std::ptr;
+use std::ops::{Deref, Range};
 use std::sync::atomic::Ordering;
 
 use pi_append_vec::{SafeVec, SafeVecIter};
-use pi_share::{Share, ShareUsize};
+use pi_share::{Share, ShareMutex, ShareUsize};
 
 use crate::archetype::{ComponentInfo, COMPONENT_TICK};
 
@@ -19,11 +20,34 @@ use crate::world::*;
 
 pub type ComponentEventVec = EventVec<Entity>;
 
+/// 停滞的监听器，事件列表中一直有未读的事件，但监听器连续多次整理都没有读取，通常是监听的system没有被调度
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StalledReport {
+    pub record: Cow<'static, str>, // 事件列表的名字
+    pub owner: Cow<'static, str>,  // 监听器所在的system
+    pub listener_index: usize,
+    pub frames: u32,   // 连续没有读取的整理次数
+    pub unread: usize, // 未读的事件数量
+}
+
+// 监听器的读取情况
+#[derive(Debug, Default)]
+struct ListenerWatch {
+    owner: Cow<'static, str>,
+    last_read: usize, // 上次整理后的读取长度
+    stalled: u32,     // 连续没有读取的整理次数
+}
+
 #[derive(Debug, Default)]
 pub struct EventVec<E> {
     name: Cow<'static, str>,
     listeners: Vec<ShareUsize>, // 每个监听器的已读取的长度
+    watches: Vec<ListenerWatch>, // 每个监听器的读取情况
+    read_ticks: Vec<ShareUsize>, // 每个监听器上次读取时的tick
+    dead: Vec<ShareUsize>,       // 每个监听器未读事件中，实体已销毁的估计数量
     vec: SafeVec<E>,            // 记录的事件
+    len: ShareUsize,            // 已发布的事件数量，读取只能看到已发布的事件
+    batch: ShareMutex<()>,      // 批量记录之间互斥
 }
 unsafe impl<E> Send for EventVec<E> {}
 unsafe impl<E> Sync for EventVec<E> {}
@@ -33,7 +57,12 @@ impl<E> EventVec<E> {
         Self {
             name: name.into(),
             listeners: Vec::new(),
+            watches: Vec::new(),
+            read_ticks: Vec::new(),
+            dead: Vec::new(),
             vec: SafeVec::default(),
+            len: ShareUsize::new(0),
+            batch: ShareMutex::new(()),
         }
     }
     pub fn capacity(&self) -> usize {
@@ -42,48 +71,144 @@ impl<E> EventVec<E> {
     pub fn name(&self) -> &str {
         &self.name
     }
-    /// 插入一个监听者，返回监听者的位置
-    pub(crate) fn insert_listener(&mut self) -> usize {
+    /// 监听者的数量
+    pub fn listener_count(&self) -> usize {
+        self.listeners.len()
+    }
+    /// 插入一个监听者，owner为监听者所在的system，返回监听者的位置
+    pub(crate) fn insert_listener(&mut self, owner: Cow<'static, str>) -> usize {
         // let listeners = unsafe { &mut *self.listeners.get() };
         let listener_index = self.listeners.len();
         self.listeners.push(ShareUsize::new(0));
+        self.read_ticks.push(ShareUsize::new(0));
+        self.dead.push(ShareUsize::new(0));
+        self.watches.push(ListenerWatch {
+            owner,
+            ..Default::default()
+        });
         listener_index
     }
     #[inline(always)]
     pub(crate) fn record(&self, e: E) {
-        self.vec.insert(e);
+        let index = self.vec.insert(e);
+        self.publish(index, index + 1);
+    }
+    /// 批量记录事件，用于在迭代结束时一次性提交，而不是每行记录。
+    /// 整批事件写入后只更新一次长度，读取者要么看到整批事件，要么都看不到
+    pub(crate) fn batch_record(&self, events: &[E])
+    where
+        E: Clone,
+    {
+        let Some((first, rest)) = events.split_first() else {
+            return;
+        };
+        // 批量记录之间互斥，避免一批的发布带出另一批写了一半的事件
+        let _lock = self.batch.lock();
+        let start = self.vec.insert(first.clone());
+        let mut end = start + 1;
+        for e in rest {
+            end = self.vec.insert(e.clone()) + 1;
+        }
+        self.publish(start, end);
+    }
+    // 发布[start, end)的事件，等之前的事件都发布后一次更新长度。
+    // vec的插入按位置顺序完成，所以end之前的事件都已写入，穿插在批次中的单个事件随批次一起可见
+    #[inline(always)]
+    fn publish(&self, start: usize, end: usize) {
+        while self.len.load(Ordering::Acquire) < start {
+            std::hint::spin_loop();
+        }
+        self.len.fetch_max(end, Ordering::Release);
+    }
+    // 已发布的事件数量
+    #[inline(always)]
+    fn published(&self) -> usize {
+        self.len.load(Ordering::Acquire)
     }
     /// 获得指定监听者的读取长度
     pub(crate) fn len(&self, listener_index: usize) -> usize {
         let read_len = unsafe { self.listeners.get_unchecked(listener_index) };
-        self.vec.len() - read_len.load(std::sync::atomic::Ordering::Relaxed)
+        self.published() - read_len.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    /// 未读事件中估计存活的数量，减去了销毁时记录的墓碑
+    pub(crate) fn live_len(&self, listener_index: usize) -> usize {
+        let dead = unsafe { self.dead.get_unchecked(listener_index) };
+        self.len(listener_index)
+            .saturating_sub(dead.load(Ordering::Relaxed))
+    }
+    /// 记录监听者读取时的tick，之后修改的实体才会在其未读事件中
+    pub(crate) fn set_read_tick(&self, listener_index: usize, tick: Tick) {
+        let t = unsafe { self.read_ticks.get_unchecked(listener_index) };
+        t.store(tick.index(), Ordering::Relaxed);
+    }
+    /// 实体被销毁，tick为实体最后一次记录事件的tick。
+    /// 对每个监听者，如果tick在其上次读取之后，则实体至少有一个未读事件，墓碑加1。墓碑不超过未读数量
+    pub(crate) fn tombstone(&self, tick: Tick) {
+        let len = self.published();
+        for ((read_len, read_tick), dead) in self
+            .listeners
+            .iter()
+            .zip(self.read_ticks.iter())
+            .zip(self.dead.iter())
+        {
+            if tick.index() <= read_tick.load(Ordering::Relaxed) {
+                continue;
+            }
+            let unread = len - read_len.load(Ordering::Relaxed);
+            if dead.load(Ordering::Relaxed) < unread {
+                dead.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+    // 读取位置推进到末尾，墓碑也都已读
+    #[inline(always)]
+    fn reset_dead(&self, listener_index: usize) {
+        unsafe { self.dead.get_unchecked(listener_index) }.store(0, Ordering::Relaxed);
     }
 
     /// 标记为已读
     pub(crate) fn mark_read(&self, listener_index: usize) {
-        let len = self.vec.len();
+        let len = self.published();
         if len > 0 {
             let read_len = unsafe { self.listeners.get_unchecked(listener_index) };
             read_len.store(len, std::sync::atomic::Ordering::Relaxed);
         }
+        self.reset_dead(listener_index);
+    }
+    /// 将指定监听者的读取位置推进到末尾，返回未读事件的范围
+    pub(crate) fn take_unread(&self, listener_index: usize) -> Range<usize> {
+        let end = self.published();
+        let read_len = unsafe { self.listeners.get_unchecked(listener_index) };
+        let start = read_len.swap(end, Ordering::Relaxed);
+        self.reset_dead(listener_index);
+        start..end
+    }
+    #[inline(always)]
+    pub(crate) fn get(&self, index: usize) -> Option<&E> {
+        if index >= self.published() {
+            return None;
+        }
+        self.vec.get(index)
     }
     /// 获得指定监听者的读取长度
     pub(crate) fn get_iter(&self, listener_index: usize) -> SafeVecIter<'_, E> {
-        let end = self.vec.len();
+        let end = self.published();
         // 从上次读取到的位置开始读取
         let read_len = unsafe { self.listeners.get_unchecked(listener_index) };
         let start = read_len.swap(end, Ordering::Relaxed);
+        self.reset_dead(listener_index);
         self.vec.slice(start..end)
     }
     /// 判断是否能够清空事件列表， 如果所有的监听器都读取了全部的事件列表，才可以清空事件列表， 返回Ok(len)表示可以清空，事件列表长度为len，返回Err((len, index))表示不能清空，len表示事件列表的长度，index表示监听器的最小读取长度，即index之前的监听器已经读取完毕，index及之后的监听器还未读取完毕
     pub(crate) fn can_clear(&mut self) -> Result<usize, (usize, usize)> {
-        let len = self.vec.len();
+        let len = *self.len.get_mut();
         if len == 0 {
             return Ok(0);
         }
-        let mut min = 0;
+        let mut min = if self.listeners.is_empty() { 0 } else { usize::MAX };
         for read_len in self.listeners.iter_mut() {
-            min = min.max(*read_len.get_mut());
+            min = min.min(*read_len.get_mut());
         }
         if min < len {
             return Err((len, min));
@@ -94,22 +219,80 @@ impl<E> EventVec<E> {
     /// 清理方法
     pub(crate) fn clear(&mut self) {
         self.vec.clear(0);
+        *self.len.get_mut() = 0;
         for read_len in self.listeners.iter_mut() {
             *read_len.get_mut() = 0;
         }
+        for dead in self.dead.iter_mut() {
+            *dead.get_mut() = 0;
+        }
     }
     /// 清理部分已读的事件列表
     pub(crate) fn clear_part(&mut self, index: usize) {
-        self.vec.remain_settle(index..usize::MAX, 0);
         if index == 0 {
             return;
         }
+        // SafeVec::remain_settle不会修改SafeVec自身的长度，之后的插入会一直等待，所以将未读的事件移到新的SafeVec中
+        let len = *self.len.get_mut();
+        let rest: Vec<E> = (index..len)
+            .map(|i| unsafe { ptr::read(self.vec.get_unchecked(i)) })
+            .collect();
+        if needs_drop::<E>() {
+            for i in 0..index {
+                unsafe { ptr::drop_in_place(self.vec.get_unchecked_mut(i)) };
+            }
+        }
+        // 事件都已移出或释放，内部长度清零，旧的SafeVec释放时不会再次释放事件
+        self.vec.remain_settle(0..0, 0);
+        let vec = SafeVec::with_capacity(rest.len());
+        for e in rest {
+            vec.insert(e);
+        }
+        self.vec = vec;
+        *self.len.get_mut() -= index;
         for read_len in self.listeners.iter_mut() {
             *read_len.get_mut() -= index;
         }
     }
+    // 更新监听器的停滞次数，有未读的事件并且读取位置没有前进，则停滞次数加1
+    fn watch(&mut self) {
+        let len = *self.len.get_mut();
+        for (read_len, w) in self.listeners.iter_mut().zip(self.watches.iter_mut()) {
+            let read = *read_len.get_mut();
+            if read < len && read == w.last_read {
+                w.stalled += 1;
+            } else {
+                w.stalled = 0;
+            }
+        }
+    }
+    /// 停滞次数不小于min_frames的监听器
+    pub(crate) fn stalled(&self, min_frames: u32, result: &mut Vec<StalledReport>) {
+        let len = self.published();
+        for (i, w) in self.watches.iter().enumerate() {
+            if w.stalled > 0 && w.stalled >= min_frames {
+                let read = self.listeners[i].load(Ordering::Relaxed);
+                result.push(StalledReport {
+                    record: self.name.clone(),
+                    owner: w.owner.clone(),
+                    listener_index: i,
+                    frames: w.stalled,
+                    unread: len - read,
+                });
+            }
+        }
+    }
     // 整理方法， 返回是否已经将事件列表清空，只有所有的监听器都读取了全部的事件列表，才可以清空事件列表
     pub(crate) fn settle(&mut self) -> bool {
+        self.watch();
+        let r = self.settle_records();
+        // 记录整理后的读取长度
+        for (read_len, w) in self.listeners.iter_mut().zip(self.watches.iter_mut()) {
+            w.last_read = *read_len.get_mut();
+        }
+        r
+    }
+    fn settle_records(&mut self) -> bool {
         match self.can_clear() {
             Ok(len) => {
                 if len > 0 {
@@ -128,10 +311,23 @@ impl<E> EventVec<E> {
     }
 }
 
+impl EventVec<Entity> {
+    /// 修正已记录的事件中的实体，整理时调用，必须保证没有其他线程读写
+    pub(crate) fn map_entities(&self, f: &mut dyn FnMut(Entity) -> Entity) {
+        for i in 0..self.published() {
+            let e = unsafe { self.vec.load_unchecked(i) };
+            *e = f(*e);
+        }
+    }
+}
+
 impl<E: 'static> Settle for EventVec<E> {
     fn settle(&mut self) {
         self.settle();
     }
+    fn stalled(&self, min_frames: u32, result: &mut Vec<StalledReport>) {
+        self.stalled(min_frames, result);
+    }
 }
 impl<E: 'static> Downcast for EventVec<E> {
     fn into_any(self: Share<Self>) -> Share<dyn Any + Send + Sync> {
@@ -185,9 +381,10 @@ impl<E: 'static> SystemParam for Event<'_, E> {
     type State = (Share<EventVec<E>>, usize);
     type Item<'w> = Event<'w, E>;
 
-    fn init_state(world: &mut World, _system_meta: &mut SystemMeta) -> Self::State {
+    fn init_state(world: &mut World, system_meta: &mut SystemMeta) -> Self::State {
         let mut vec = init_state(world);
-        let index = unsafe { Share::get_mut_unchecked(&mut vec).insert_listener() };
+        let owner = system_meta.type_name().to_string().into();
+        let index = unsafe { Share::get_mut_unchecked(&mut vec).insert_listener(owner) };
         (vec, index)
     }
     #[inline]
@@ -265,19 +462,19 @@ impl<T: 'static> SystemParam for ComponentChanged<'_, T> {
     type State = (Share<ComponentEventVec>, usize);
     type Item<'w> = ComponentChanged<'w, T>;
 
-    fn init_state(world: &mut World, _meta: &mut SystemMeta) -> Self::State {
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
         let info = ComponentInfo::of::<T>(COMPONENT_TICK);
-        init_changed_state(world, TypeId::of::<ComponentChanged<'static, T>>(), info)
+        init_changed_state(world, meta, TypeId::of::<ComponentChanged<'static, T>>(), info)
     }
 
     #[inline]
     fn get_param<'world>(
-        _world: &'world World,
+        world: &'world World,
         _system_meta: &'world SystemMeta,
         state: &'world mut Self::State,
-        _tick: Tick,
+        tick: Tick,
     ) -> Self::Item<'world> {
-        ComponentChanged(ComponentEvent::new(&state.0, state.1))
+        ComponentChanged(ComponentEvent::new(world, &state.0, state.1, tick))
     }
     #[inline]
     fn get_self<'world>(
@@ -305,19 +502,19 @@ impl<T: 'static> SystemParam for ComponentAdded<'_, T> {
     type State = (Share<ComponentEventVec>, usize);
     type Item<'w> = ComponentAdded<'w, T>;
 
-    fn init_state(world: &mut World, _meta: &mut SystemMeta) -> Self::State {
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
         let info = ComponentInfo::of::<T>(0);
-        init_added_state(world, TypeId::of::<ComponentAdded<'static, T>>(), info)
+        init_added_state(world, meta, TypeId::of::<ComponentAdded<'static, T>>(), info)
     }
 
     #[inline]
     fn get_param<'world>(
-        _world: &'world World,
+        world: &'world World,
         _system_meta: &'world SystemMeta,
         state: &'world mut Self::State,
-        _tick: Tick,
+        tick: Tick,
     ) -> Self::Item<'world> {
-        ComponentAdded(ComponentEvent::new(&state.0, state.1))
+        ComponentAdded(ComponentEvent::new(world, &state.0, state.1, tick))
     }
     #[inline]
     fn get_self<'world>(
@@ -343,19 +540,19 @@ impl<T: 'static> SystemParam for ComponentRemoved<'_, T> {
     type State = (Share<ComponentEventVec>, usize);
     type Item<'w> = ComponentRemoved<'w, T>;
 
-    fn init_state(world: &mut World, _meta: &mut SystemMeta) -> Self::State {
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
         let info = ComponentInfo::of::<T>(0);
-        init_removed_state(world, TypeId::of::<ComponentRemoved<'static, T>>(), info)
+        init_removed_state(world, meta, TypeId::of::<ComponentRemoved<'static, T>>(), info)
     }
 
     #[inline]
     fn get_param<'world>(
-        _world: &'world World,
+        world: &'world World,
         _system_meta: &'world SystemMeta,
         state: &'world mut Self::State,
-        _tick: Tick,
+        tick: Tick,
     ) -> Self::Item<'world> {
-        ComponentRemoved(ComponentEvent::new(&state.0, state.1))
+        ComponentRemoved(ComponentEvent::new(world, &state.0, state.1, tick))
     }
     #[inline]
     fn get_self<'world>(
@@ -369,16 +566,25 @@ impl<T: 'static> SystemParam for ComponentRemoved<'_, T> {
 }
 
 pub struct ComponentEvent<'w, T: 'static> {
+    world: &'w World,
     pub(crate) record: &'w Share<ComponentEventVec>,
     pub(crate) listener_index: usize,
+    pub(crate) tick: Tick,
     _p: PhantomData<T>,
 }
 impl<'w, T: 'static> ComponentEvent<'w, T> {
     #[inline]
-    pub(crate) fn new(record: &'w Share<ComponentEventVec>, listener_index: usize) -> Self {
+    pub(crate) fn new(
+        world: &'w World,
+        record: &'w Share<ComponentEventVec>,
+        listener_index: usize,
+        tick: Tick,
+    ) -> Self {
         Self {
+            world,
             record,
             listener_index,
+            tick,
             _p: PhantomData,
         }
     }
@@ -390,11 +596,28 @@ impl<'w, T: 'static> ComponentEvent<'w, T> {
     pub fn len(&self) -> usize {
         self.record.len(self.listener_index)
     }
+    /// 未读事件中估计存活的数量。销毁实体时在事件列表上记录墓碑，不用扫描事件，
+    /// 同一实体有多个未读事件时只记一个墓碑，所以估计值不小于实际存活的事件数量
+    #[inline(always)]
+    pub fn live_len(&self) -> usize {
+        self.record.live_len(self.listener_index)
+    }
     pub fn iter(&self) -> SafeVecIter<'_, Entity> {
+        self.record.set_read_tick(self.listener_index, self.tick);
         self.record.get_iter(self.listener_index)
     }
+    /// 只迭代存活实体的事件，存活的事件都已迭代时提前结束，大量销毁后不用遍历剩余的死事件
+    pub fn iter_live(&self) -> impl Iterator<Item = Entity> + '_ {
+        let live = self.live_len();
+        let world = self.world;
+        self.iter()
+            .map(|e| *e)
+            .filter(move |e| world.contains_entity(*e))
+            .take(live)
+    }
     /// 标记为已读
     pub fn mark_read(&self) {
+        self.record.set_read_tick(self.listener_index, self.tick);
         self.record.mark_read(self.listener_index);
     }
 }
@@ -411,8 +634,8 @@ fn init_state<E: 'static>(world: &mut World) -> Share<EventVec<E>> {
     }
 }
 
-fn init_changed_state(world: &mut World, typeid: TypeId, info: ComponentInfo) -> (Share<ComponentEventVec>, usize) {
-    let (r, c) = init_component_state(world, info, |info| match &info.changed {
+fn init_changed_state(world: &mut World, meta: &SystemMeta, typeid: TypeId, info: ComponentInfo) -> (Share<ComponentEventVec>, usize) {
+    let (r, c) = init_component_state(world, meta, info, |info| match &info.changed {
         Some(r) => r.clone(),
         None => {
             let r = Share::new(ComponentEventVec::new(info.info.type_name().clone()));
@@ -429,8 +652,8 @@ fn init_changed_state(world: &mut World, typeid: TypeId, info: ComponentInfo) ->
     }
     r
 }
-fn init_added_state(world: &mut World, typeid: TypeId, info: ComponentInfo) -> (Share<ComponentEventVec>, usize) {
-    let r = init_component_state(world, info, |info| match &info.added {
+fn init_added_state(world: &mut World, meta: &SystemMeta, typeid: TypeId, info: ComponentInfo) -> (Share<ComponentEventVec>, usize) {
+    let r = init_component_state(world, meta, info, |info| match &info.added {
         Some(r) => r.clone(),
         None => {
             let r = Share::new(ComponentEventVec::new(info.info.type_name().clone()));
@@ -443,8 +666,8 @@ fn init_added_state(world: &mut World, typeid: TypeId, info: ComponentInfo) -> (
     r
 }
 
-fn init_removed_state(world: &mut World, typeid: TypeId, info: ComponentInfo) -> (Share<ComponentEventVec>, usize) {
-    let r = init_component_state(world, info, |info| match &info.removed {
+pub(crate) fn init_removed_state(world: &mut World, meta: &SystemMeta, typeid: TypeId, info: ComponentInfo) -> (Share<ComponentEventVec>, usize) {
+    let r = init_component_state(world, meta, info, |info| match &info.removed {
         Some(r) => r.clone(),
         None => {
             let r = Share::new(ComponentEventVec::new(info.info.type_name().clone()));
@@ -459,6 +682,7 @@ fn init_removed_state(world: &mut World, typeid: TypeId, info: ComponentInfo) ->
 
 fn init_component_state<F>(
     world: &mut World,
+    meta: &SystemMeta,
     info: ComponentInfo,
     get_fn: F,
 ) -> ((Share<ComponentEventVec>, usize), Share<Column>)
@@ -468,6 +692,6 @@ where
     let mut column = world.add_component_info(info).1;
     let c = unsafe { Share::get_mut_unchecked(&mut column) };
     let mut vec = get_fn(&mut c.info);
-    let index = unsafe { Share::get_mut_unchecked(&mut vec) }.insert_listener();
+    let index = unsafe { Share::get_mut_unchecked(&mut vec) }.insert_listener(meta.type_name().to_string().into());
     ((vec, index), column)
 }
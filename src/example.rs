@@ -315,7 +315,7 @@ mod test_mod {
     use super::*;
     use crate::{
         // app::*,
-        archetype::{Archetype, ComponentInfo, Row}, column::{BlobTicks, Column}, debug::{ArchetypeDebug, ColumnDebug}, editor::EntityEditor, schedule::Update, schedule_config::IntoSystemConfigs, system::{relate, Relation, SystemMeta, TypeInfo}, table::Table, world
+        archetype::{Archetype, ComponentInfo, Row, COMPONENT_TICK}, column::{BlobTicks, Column}, debug::{ArchetypeDebug, ColumnDebug}, editor::EntityEditor, schedule::Update, schedule_config::IntoSystemConfigs, system::{relate, Relation, SystemMeta, TypeInfo}, table::Table, world, world::ComponentIndex
     };
     use fixedbitset::FixedBitSet;
     // use bevy_utils::dbg;
@@ -658,6 +658,39 @@ mod test_mod {
             };
         });
     }
+
+    // 逐个插入10万实体，和bench_insert_batch对比
+    #[bench]
+    fn bench_insert_loop(b: &mut Bencher) {
+        b.iter(move || {
+            let mut world = World::new();
+            let i = world.make_insert::<(Transform, Position, Rotation, Velocity)>();
+            for a in 0..100_000 {
+                i.insert(&world, (
+                    Transform([a as f32; 16]),
+                    Position([a as f32; 3]),
+                    Rotation([a as f32; 3]),
+                    Velocity([a as f32; 3]),
+                ));
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_insert_batch(b: &mut Bencher) {
+        b.iter(move || {
+            let mut world = World::new();
+            let iter = (0..100_000).map(|a| {
+                (
+                    Transform([a as f32; 16]),
+                    Position([a as f32; 3]),
+                    Rotation([a as f32; 3]),
+                    Velocity([a as f32; 3]),
+                )
+            });
+            world.insert_batch(iter);
+        });
+    }
     #[test]
     pub fn simple_insert() {
         for _ in 0..1 {
@@ -2143,4 +2176,4814 @@ mod test_mod {
         app.run();
         println!("=======================end");
     }
+
+    struct Physics;
+    impl GroupLabel for Physics {
+        const NAME: &'static str = "Physics";
+    }
+
+    #[test]
+    fn test_component_group() {
+        let mut world = World::new();
+        let members = vec![
+            world.init_component::<Age0>(),
+            world.init_component::<Age1>(),
+            world.init_component::<Age2>(),
+        ];
+        world.define_component_group(Physics::NAME, members.clone()).unwrap();
+        world.make_insert::<(Age0,)>().insert(&world, (Age0(0),));
+        world.make_insert::<(Age1, Age3)>().insert(&world, (Age1(1), Age3(3)));
+        world.make_insert::<(Age0, Age1, Age2)>().insert(&world, (Age0(0), Age1(1), Age2(2)));
+        world.make_insert::<(Age3,)>().insert(&world, (Age3(3),));
+        world.settle();
+
+        let any = world.make_query::<Entity, InAnyGroup<Physics>>();
+        let expand = world.make_query::<Entity, Or<(With<Age0>, With<Age1>, With<Age2>)>>();
+        let indexs = |archetypes: &Vec<crate::archetype::ShareArchetype>| archetypes.iter().map(|ar| ar.index()).collect::<Vec<_>>();
+        assert_eq!(indexs(&any.archetypes), indexs(&expand.archetypes));
+        assert_eq!(any.archetypes_len(), 3);
+
+        let all = world.make_query::<Entity, InAllGroup<Physics>>();
+        let expand = world.make_query::<Entity, (With<Age0>, With<Age1>, With<Age2>)>();
+        assert_eq!(indexs(&all.archetypes), indexs(&expand.archetypes));
+        assert_eq!(all.archetypes_len(), 1);
+
+        let mut q = world.make_query::<GroupPtrs<Physics>, InAnyGroup<Physics>>();
+        let mut count = 0;
+        for row in q.iter(&world) {
+            for (index, ptr) in row.iter() {
+                if index == members[2] {
+                    assert_eq!(unsafe { &*(ptr as *const Age2) }, &Age2(2));
+                }
+                count += 1;
+            }
+        }
+        assert_eq!(count, 5);
+        // 组已被使用，不允许再修改
+        assert_eq!(
+            world.define_component_group(Physics::NAME, vec![members[0]]),
+            Err(QueryError::GroupLocked(world.get_component_group_id(Physics::NAME).unwrap()))
+        );
+    }
+    #[test]
+    #[should_panic]
+    fn test_component_group_conflict() {
+        let mut world = World::new();
+        let members = vec![
+            world.init_component::<Age0>(),
+            world.init_component::<Age1>(),
+            world.init_component::<Age2>(),
+        ];
+        world.define_component_group(Physics::NAME, members).unwrap();
+        let mut meta = SystemMeta::new(TypeInfo::of::<SystemMeta>());
+        let _ = crate::query::QueryState::<GroupPtrs<Physics>, ()>::create(&mut world, &mut meta);
+        let _ = crate::query::QueryState::<&mut Age1, ()>::create(&mut world, &mut meta);
+        meta.check_conflict();
+    }
+
+    #[test]
+    fn test_register_query_state() {
+        let mut world = World::new();
+        let index = world.register_query_state::<(Entity, &Age0), ()>();
+        world.make_insert::<(Age0,)>().insert(&world, (Age0(1),));
+        world.make_insert::<(Age0, Age1)>().insert(&world, (Age0(2), Age1(1)));
+        world.settle();
+        assert_eq!(world.get_query_state::<(Entity, &Age0), ()>(index).unwrap().archetypes_len(), 2);
+        assert!(world.get_query_state::<(Entity, &Age1), ()>(index).is_none());
+        let q = world.get_registered_query::<(Entity, &Age0), ()>(index).unwrap();
+        assert_eq!(q.iter().map(|(_, a)| a.0).sum::<usize>(), 3);
+    }
+
+    #[test]
+    fn test_query_state_debug() {
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0, Age1)>();
+        for n in 0..100_000 {
+            i.insert(&world, (Age0(n), Age1(n)));
+        }
+        world.settle();
+        let q = world.make_query::<(Entity, &Age0), With<Age1>>();
+        let s = format!("{:?}", q);
+        assert!(s.starts_with("QueryState {"));
+        assert!(s.contains("archetypes_len: 1"));
+        assert!(s.contains("100000"));
+        assert!(s.len() < 1024, "{}", s);
+        let ar = world.get_archetype(q.archetypes[0].index()).unwrap();
+        assert!(format!("{:?}", ar).len() < 1024);
+        assert_eq!(ar.dump_rows(3).lines().count(), 3);
+    }
+
+    #[test]
+    fn test_copy_rows() {
+        let cc = Column::new(ComponentInfo::of::<Age0>(COMPONENT_TICK));
+        cc.init_blob(0usize.into());
+        cc.init_blob(1usize.into());
+        let src = cc.blob_ref_unchecked(0usize.into());
+        for i in 0..8 {
+            src.write(Row(i), Entity::null(), Age0(i as usize));
+            src.set_tick_unchecked(Row(i), (i as usize + 10).into());
+        }
+        let rows = [(Row(5), Row(3)), (Row(0), Row(0)), (Row(1), Row(1)), (Row(2), Row(2)), (Row(7), Row(4))];
+        cc.copy_rows(0usize.into(), 1usize.into(), &rows);
+        let dst = cc.blob_ref_unchecked(1usize.into());
+        for (s, d) in rows {
+            assert_eq!(dst.get::<Age0>(d, Entity::null()), &Age0(s.0 as usize));
+            assert_eq!(dst.get_tick_unchecked(d), (s.0 as usize + 10).into());
+        }
+    }
+
+    #[test]
+    fn test_archive() {
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0, Age21)>();
+        let parent = i.insert(&world, (Age0(1), Age21(vec![1, 2, 3])));
+        let i2 = world.make_insert::<(Age0, PassModelID)>();
+        let child = i2.insert(&world, (Age0(2), PassModelID(parent)));
+        let other = i.insert(&world, (Age0(3), Age21(vec![4])));
+        let empty = world.spawn_empty();
+        world.settle();
+        // 重复的实体返回错误，world不变
+        assert_eq!(
+            world.archive_entities(&[parent, child, parent]).err(),
+            Some(QueryError::DuplicateEntity(parent))
+        );
+        assert!(world.contains(parent) && world.contains(child));
+        let archive = world.archive_entities(&[parent, child, empty]).unwrap();
+        assert_eq!(archive.len(), 3);
+        assert!(!world.contains_entity(parent) && !world.contains_entity(child) && !world.contains_entity(empty));
+        // 有堆上数据的组件不能转为归档数据
+        assert!(matches!(archive.to_data(), Err(QueryError::NotSerializable(_))));
+        world.get_component_mut::<Age0>(other).unwrap().0 = 30;
+        world.settle();
+
+        // 注册了引用策略的组件，恢复时用映射修正引用的实体
+        impl MapEntities for PassModelID {
+            fn map_entities(&mut self, f: &mut dyn FnMut(Entity) -> Entity) {
+                self.0 = f(self.0);
+            }
+        }
+        world.register_reference_policy::<PassModelID>(ReferencePolicy::Nullify);
+        let mut mapper = EntityMapper::default();
+        assert_eq!(world.unarchive(archive, &mut mapper), 3);
+        let (parent, child) = (mapper.map(parent), mapper.map(child));
+        assert_eq!(world.get_component::<Age21>(parent).unwrap().0, vec![1, 2, 3]);
+        assert_eq!(world.get_component::<Age0>(child).unwrap().0, 2);
+        assert_eq!(world.get_component::<PassModelID>(child).unwrap().0, parent);
+        assert_eq!(world.get_component::<Age0>(other).unwrap().0, 30);
+        assert!(world.contains_entity(mapper.map(empty)));
+        assert_eq!(world.len(), 4);
+
+        // 按过滤器归档，转为可以序列化的数据，在另一个线程上重建
+        fn serde_data<T: serde::Serialize + serde::de::DeserializeOwned + Send>(t: T) -> T {
+            t
+        }
+        world.register_raw_pod::<Age1>();
+        world.register_diffable::<Age0>("age0");
+        let i = world.make_insert::<(Age0, Age1)>();
+        let chunk: Vec<Entity> = (0..3).map(|n| i.insert(&world, (Age0(n), Age1(n * 10)))).collect();
+        world.settle();
+        let archive = world.archive_filtered::<With<Age1>>();
+        assert_eq!(archive.len(), 3);
+        let data = std::thread::spawn(move || serde_data(archive.to_data().unwrap()))
+            .join()
+            .unwrap();
+        assert_eq!(data.groups.len(), 1);
+        let archive = world.archive_from_data(&data).unwrap();
+        let mut mapper = EntityMapper::default();
+        assert_eq!(world.unarchive(archive, &mut mapper), 3);
+        for (n, e) in chunk.iter().enumerate() {
+            let e = mapper.map(*e);
+            assert_eq!(world.get_component::<Age0>(e).unwrap().0, n);
+            assert_eq!(world.get_component::<Age1>(e).unwrap().0, n * 10);
+        }
+    }
+
+    #[test]
+    fn test_swap_entities() {
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0, Age21)>();
+        let e1 = i.insert(&world, (Age0(1), Age21(vec![1])));
+        let e2 = i.insert(&world, (Age0(2), Age21(vec![2])));
+        let e3 = i.insert(&world, (Age0(3), Age21(vec![3])));
+        let other = world.make_insert::<(Age1,)>().insert(&world, (Age1(1),));
+        world.settle();
+        assert_eq!(world.swap_entities(e1, other), Err(QueryError::NoMatchArchetype));
+        world.swap_entities(e1, e3).unwrap();
+        assert_eq!(world.get_component::<Age0>(e1).unwrap().0, 1);
+        assert_eq!(world.get_component::<Age21>(e3).unwrap().0, vec![3]);
+        let mut q = world.make_query::<(Entity, &Age0), ()>();
+        // 查询是倒序迭代行的
+        let vec: Vec<_> = q.iter(&world).map(|(e, a)| (e, a.0)).collect();
+        assert_eq!(vec, vec![(e1, 1), (e2, 2), (e3, 3)]);
+    }
+
+    // 用随机的组件集合创建原型
+    fn random_archetypes(world: &mut World, components: &[ComponentIndex], count: usize) {
+        let mut rng = rand::thread_rng();
+        let mut editor = world.make_entity_editor();
+        for _ in 0..count {
+            let mut vec: Vec<ComponentIndex> = components.iter().filter(|_| rng.gen_bool(0.5)).copied().collect();
+            if vec.is_empty() {
+                vec.push(components[0]);
+            }
+            editor.insert_entity_by_index(&vec).unwrap();
+        }
+    }
+    fn init_ages(world: &mut World) -> Vec<ComponentIndex> {
+        vec![
+            world.init_component::<Age0>(), world.init_component::<Age1>(), world.init_component::<Age2>(),
+            world.init_component::<Age3>(), world.init_component::<Age4>(), world.init_component::<Age6>(),
+            world.init_component::<Age9>(), world.init_component::<Age10>(), world.init_component::<Age12>(),
+            world.init_component::<Age13>(), world.init_component::<Age14>(), world.init_component::<Age15>(),
+        ]
+    }
+    // 随机的查询关系
+    fn random_qstate(components: &[ComponentIndex]) -> crate::query::QState {
+        let mut rng = rand::thread_rng();
+        let mut meta = SystemMeta::new(TypeInfo::of::<SystemMeta>());
+        for c in components {
+            match rng.gen_range(0..8) {
+                0 => meta.relate(Relation::Read(*c)),
+                1 => meta.relate(Relation::Write(*c)),
+                2 => meta.relate(Relation::Without(*c)),
+                3 => meta.relate(Relation::OptRead(*c)),
+                _ => (),
+            }
+        }
+        crate::query::QState::new(&mut meta)
+    }
+    #[test]
+    fn test_align_index() {
+        let mut world = World::new();
+        let components = init_ages(&mut world);
+        random_archetypes(&mut world, &components, 200);
+        world.settle();
+        let mut qs: Vec<_> = (0..50).map(|_| random_qstate(&components)).collect();
+        for q in qs.iter_mut() {
+            q.align(&world);
+        }
+        // 分两次对齐
+        random_archetypes(&mut world, &components, 200);
+        world.settle();
+        for q in qs.iter_mut() {
+            let mut brute = crate::query::QState::new(&mut {
+                let mut meta = SystemMeta::new(TypeInfo::of::<SystemMeta>());
+                for r in q.related.vec.iter() {
+                    meta.relate(r.clone());
+                }
+                meta
+            });
+            brute.scan(&world, world.archetype_arr.len(), &mut |_| Ok(()));
+            q.align(&world);
+            let a: Vec<_> = q.archetypes.iter().map(|ar| ar.index()).collect();
+            let b: Vec<_> = brute.archetypes.iter().map(|ar| ar.index()).collect();
+            assert_eq!(a, b, "{:?}", q.related);
+        }
+    }
+    #[bench]
+    fn bench_align(b: &mut Bencher) {
+        let mut world = World::new();
+        let components = init_ages(&mut world);
+        random_archetypes(&mut world, &components, 3000);
+        world.settle();
+        b.iter(|| {
+            for _ in 0..400 {
+                let mut q = random_qstate(&components);
+                q.align(&world);
+            }
+        });
+    }
+
+    #[test]
+    fn test_query_statistics() {
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0, Age1)>();
+        for n in 0..10 {
+            i.insert(&world, (Age0(n), Age1(n)));
+        }
+        world.make_insert::<(Age0,)>().insert(&world, (Age0(0),));
+        world.settle();
+        let mut q = world.make_query::<&Age0, Changed<Age1>>();
+        assert_eq!(q.iter(&world).count(), 10);
+        let s = q.statistics();
+        assert_eq!(s.archetypes_checked, 1);
+        assert_eq!((s.entities_visited, s.entities_yielded), (10, 10));
+        assert_eq!(q.iter(&world).count(), 10);
+        assert_eq!(q.statistics().entities_visited, 20);
+        q.reset_statistics();
+        assert_eq!(q.statistics(), crate::query::QueryStatistics::default());
+    }
+
+    #[test]
+    fn test_columns_mut() {
+        use crate::system_params::SystemParam;
+        let mut world = World::new();
+        // 先创建Changed的查询，组件才会记录tick
+        let mut q = world.make_query::<Entity, Changed<Age0>>();
+        let i = world.make_insert::<(Age0, Age1)>();
+        for n in 0..4 {
+            i.insert(&world, (Age0(n), Age1(n)));
+        }
+        let i = world.make_insert::<(Age0,)>();
+        for n in 0..3 {
+            i.insert(&world, (Age0(n),));
+        }
+        world.settle();
+        q.align(&world);
+        assert_eq!(q.get_param(&world).iter().count(), 7);
+        assert_eq!(q.get_param(&world).iter().count(), 0);
+        world.increment_tick();
+
+        let mut meta = SystemMeta::new(TypeInfo::of::<ColumnsMut<'static, Age0>>());
+        let mut state = ColumnsMut::<Age0>::init_state(&mut world, &mut meta);
+        ColumnsMut::<Age0>::align(&world, &meta, &mut state);
+        {
+            let mut cols = ColumnsMut::<Age0>::get_param(&world, &meta, &mut state, world.tick());
+            assert_eq!(cols.len(), 2);
+            for mut view in cols.iter_mut() {
+                assert_eq!(view.entities().count(), view.len());
+                if view.archetype().column_len() == 2 {
+                    // 逐行记录，只修改偶数
+                    for mut v in view.iter_mut() {
+                        if v.0 % 2 == 0 {
+                            v.0 += 10;
+                        }
+                    }
+                } else {
+                    // 整体记录
+                    for (_, v) in view.iter_mut_all() {
+                        v.0 += 100;
+                    }
+                }
+            }
+        }
+        let mut r: Vec<usize> = q.get_param(&world).iter().map(|e| world.get_component::<Age0>(e).unwrap().0).collect();
+        r.sort();
+        assert_eq!(r, vec![10, 12, 100, 101, 102]);
+    }
+
+    #[test]
+    fn test_added_changed_tick() {
+        let mut world = World::new();
+        let mut q = world.make_query::<(Entity, AddedTick<Age0>, ChangedTick<Age0>), ()>();
+        let mut qa = world.make_query::<Entity, Added<Age0>>();
+        let mut qc = world.make_query::<Entity, Changed<Age0>>();
+        let i = world.make_insert::<(Age0,)>();
+        let e0 = i.insert(&world, (Age0(0),));
+        let e1 = i.insert(&world, (Age0(1),));
+        world.settle();
+        q.align(&world);
+        qa.align(&world);
+        qc.align(&world);
+        let added = world.tick();
+        assert_eq!(qa.get_param(&world).iter().count(), 2);
+        assert_eq!(qc.get_param(&world).iter().count(), 2);
+
+        world.increment_tick();
+        let mut qm = world.make_query::<&mut Age0, ()>();
+        qm.get_param(&world).get_mut(e1).unwrap().0 = 10;
+        let changed = world.tick();
+        assert!(changed > added);
+        let mut r: Vec<_> = q.iter(&world).collect();
+        r.sort_by_key(|(e, _, _)| *e);
+        assert_eq!(r, vec![(e0, added, added), (e1, added, changed)]);
+        assert_eq!(qa.get_param(&world).iter().count(), 0);
+        assert_eq!(qc.get_param(&world).iter().collect::<Vec<_>>(), vec![e1]);
+    }
+
+    #[test]
+    fn test_next_candidate() {
+        use crate::system_params::SystemParam;
+        // 各种行状态在迭代、按实体查询和列视图下的判断必须一致
+        let mut world = World::new();
+        let mut q = world.make_query::<Entity, Changed<Age0>>();
+        let mut qm = world.make_query::<&mut Age0, ()>();
+        let i = world.make_insert::<(Age0,)>();
+        let hole = i.insert(&world, (Age0(0),));
+        let moved = i.insert(&world, (Age0(1),));
+        let stale = i.insert(&world, (Age0(2),));
+        let changed = i.insert(&world, (Age0(3),));
+        world.settle();
+        q.align(&world);
+        qm.align(&world);
+        assert_eq!(q.get_param(&world).iter().count(), 4);
+        world.increment_tick();
+        qm.get_param(&world).get_mut(changed).unwrap().0 = 30;
+        qm.get_param(&world).get_mut(moved).unwrap().0 = 10;
+        // 空位，未整理
+        world.destroy_entity(hole).unwrap();
+        // 原行被标记移除，新行在新原型上，只能出现1次
+        world.make_entity_editor().add_components(moved, (Age1(1),)).unwrap();
+        q.align(&world);
+
+        let mut meta = SystemMeta::new(TypeInfo::of::<ColumnsMut<'static, Age0>>());
+        let mut state = ColumnsMut::<Age0>::init_state(&mut world, &mut meta);
+        ColumnsMut::<Age0>::align(&world, &meta, &mut state);
+        let mut view = vec![];
+        for mut v in ColumnsMut::<Age0>::get_param(&world, &meta, &mut state, world.tick()).iter_mut() {
+            view.extend(v.iter_mut().map(|m| m.entity()));
+        }
+        let query = q.get_param(&world);
+        let iter: Vec<Entity> = query.iter().collect();
+
+        // (实体, 迭代和查询是否接受, 列视图中是否存在)
+        let cases = [
+            (hole, false, false),
+            (moved, true, true),
+            (stale, false, true),
+            (changed, true, true),
+        ];
+        for (e, accept, live) in cases {
+            assert_eq!(iter.iter().filter(|x| **x == e).count(), accept as usize, "{:?}", e);
+            assert_eq!(query.get(e).is_ok(), accept, "{:?}", e);
+            assert_eq!(view.iter().filter(|x| **x == e).count(), live as usize, "{:?}", e);
+        }
+    }
+
+    #[test]
+    fn test_component_usage_stats() {
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0, Age1)>();
+        for n in 0..3 {
+            i.insert(&world, (Age0(n), Age1(n)));
+        }
+        let i = world.make_insert::<(Age0,)>();
+        let e = i.insert(&world, (Age0(0),));
+        i.insert(&world, (Age0(1),));
+        i.insert(&world, (Age0(2),));
+        world.make_insert::<(Age2,)>().insert(&world, (Age2(0),));
+        world.destroy_entity(e).unwrap();
+        let (a0, a1, a2) = (
+            world.init_component::<Age0>(),
+            world.init_component::<Age1>(),
+            world.init_component::<Age2>(),
+        );
+        let stats = world.component_usage_stats();
+        assert_eq!(&stats[0..3], &[(a0, 5), (a1, 3), (a2, 1)]);
+        world.settle();
+        assert_eq!(world.component_usage_stats()[0], (a0, 5));
+    }
+
+    #[test]
+    fn test_graph_fingerprint() {
+        use crate::fingerprint::{graph_fingerprints, validate_graph_compat, GraphMismatch};
+        use crate::query::QueryState;
+        fn build(world: &mut World) -> SystemMeta {
+            let mut meta = SystemMeta::new(TypeInfo::of::<Age3>());
+            QueryState::<(&Age0, &mut Age1), Without<Age2>>::create(world, &mut meta);
+            meta
+        }
+        let mut w1 = World::new();
+        w1.init_component::<Age0>();
+        w1.init_component::<Age1>();
+        let m1 = build(&mut w1);
+        let saved = graph_fingerprints(&w1, &[&m1]);
+
+        // 相同的注册顺序，指纹相同
+        let mut w2 = World::new();
+        w2.init_component::<Age0>();
+        w2.init_component::<Age1>();
+        let m2 = build(&mut w2);
+        assert_eq!(graph_fingerprints(&w2, &[&m2]), saved);
+        assert_eq!(validate_graph_compat(&saved, &w2, &[&m2]), Ok(()));
+        assert_eq!(
+            validate_graph_compat(&saved, &w2, &[&m2, &m2]),
+            Err(GraphMismatch::SystemCount { saved: 1, current: 2 })
+        );
+
+        // 调换注册顺序，注册表不兼容，但system的读写指纹不变
+        let mut w3 = World::new();
+        w3.init_component::<Age1>();
+        w3.init_component::<Age0>();
+        let m3 = build(&mut w3);
+        assert_ne!(w3.registry_fingerprint(), w1.registry_fingerprint());
+        assert_eq!(m3.access_fingerprint(&w3), m1.access_fingerprint(&w1));
+        assert_eq!(validate_graph_compat(&saved, &w3, &[&m3]), Err(GraphMismatch::Registry));
+
+        // 注册了稳定名称后，注册表指纹由稳定名称决定，和类型名无关
+        let mut w4 = World::new();
+        w4.register_stable_name::<Age0>("age.a");
+        w4.register_stable_name::<Age1>("age.b");
+        let mut w5 = World::new();
+        w5.register_stable_name::<Age2>("age.a");
+        w5.register_stable_name::<Age3>("age.b");
+        assert_eq!(w4.registry_fingerprint(), w5.registry_fingerprint());
+        assert_ne!(w4.registry_fingerprint(), w1.registry_fingerprint());
+    }
+
+    #[test]
+    fn test_archetype_memory() {
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0, Age1)>();
+        let es: Vec<Entity> = (0..10).map(|n| i.insert(&world, (Age0(n), Age1(n)))).collect();
+        world.settle();
+        let ar = world.get_archetype(world.get_entity_prototype(es[0]).unwrap().1).unwrap().clone();
+        let row = mem::size_of::<Entity>() + 2 * mem::size_of::<usize>();
+        assert_eq!(ar.live_memory(), 10 * row);
+        assert!(ar.total_memory() >= ar.live_memory());
+        for e in &es[0..5] {
+            world.destroy_entity(*e).unwrap();
+        }
+        assert_eq!(ar.live_memory(), 5 * row);
+        // 按各列实际分配的内存计算，记录tick后包括tick数组
+        let mut qc = world.make_query::<Entity, Changed<Age0>>();
+        qc.align(&world);
+        for n in 0..10 {
+            i.insert(&world, (Age0(n), Age1(n)));
+        }
+        world.settle();
+        let len = ar.len().index();
+        let column_memory = |t: TypeId| {
+            let c = world.get_column_by_id(&t).unwrap();
+            c.blob_ref_unchecked(ar.index()).blob.data_memsize(len, c.info().mem_size as usize)
+        };
+        let ticks = world.get_column_by_id(&TypeId::of::<Age0>()).unwrap().blob_ref_unchecked(ar.index()).blob.ticks_memsize();
+        assert!(ticks > 0);
+        let columns = column_memory(TypeId::of::<Age0>()) + column_memory(TypeId::of::<Age1>());
+        assert!(columns >= 15 * 2 * mem::size_of::<usize>() + ticks);
+        assert!(ar.total_memory() >= columns + 15 * mem::size_of::<Entity>());
+        assert_eq!((ar.total_memory() - columns) % mem::size_of::<Entity>(), 0);
+    }
+
+    #[test]
+    fn test_get_extra() {
+        let mut world = World::new();
+        let mut q = world.make_query::<(Entity, &Age0), ExtraRead<Age1>>();
+        let mut q1 = world.make_query::<(Entity, &Age0), ()>();
+        let i = world.make_insert::<(Age0, Age1)>();
+        for n in 0..1000 {
+            i.insert(&world, (Age0(n), Age1(n + 1)));
+        }
+        world.settle();
+        q.align(&world);
+        q1.align(&world);
+        let index = world.init_component::<Age1>();
+        let query = q.get_param(&world);
+        let mut count = 0;
+        for (e, a) in query.iter() {
+            if a.0 % 100 == 0 {
+                assert_eq!(query.get_extra::<Age1>(e).unwrap().0, a.0 + 1);
+                count += 1;
+            }
+        }
+        assert_eq!(count, 10);
+        let query = q1.get_param(&world);
+        let (e, _) = query.iter().next().unwrap();
+        assert_eq!(query.get_extra::<Age1>(e).err(), Some(QueryError::MissingReadAccess(index)));
+    }
+
+    #[test]
+    fn test_bulk_init_rows() {
+        let mut world = World::new();
+        let mut q = world.make_query::<(Entity, &Age0, &Age1), Changed<Age1>>();
+        let indexs = [world.init_component::<Age0>(), world.init_component::<Age1>()];
+        let es = world.make_entity_editor().insert_entities_by_index(&indexs, 100).unwrap();
+        assert_eq!(es.len(), 100);
+        world.settle();
+        q.align(&world);
+        let r: Vec<_> = q.get_param(&world).iter().map(|(e, a0, a1)| (e, *a0, *a1)).collect();
+        assert_eq!(r.len(), 100);
+        for (e, a0, a1) in r {
+            assert!(es.contains(&e));
+            assert_eq!((a0, a1), (Age0(0), Age1(0)));
+        }
+    }
+
+    // 模拟查询监听原型创建，检查原型是否和查询相关
+    struct ArNotify<'a>(
+        Share<crate::system::Related<ComponentIndex>>,
+        Share<pi_share::ShareUsize>,
+        std::marker::PhantomData<&'a ()>,
+    );
+    impl<'a> Listener for ArNotify<'a> {
+        type Event = crate::world::ArchetypeInit<'a>;
+        fn listen(&self, ar: Self::Event) {
+            if relate(&self.0, ar.0, 0) {
+                self.1.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            }
+        }
+    }
+    fn add_ar_notify(world: &mut World, components: &[ComponentIndex], count: &Share<pi_share::ShareUsize>) {
+        let related = random_qstate(components).related.clone();
+        world
+            .listener_mgr
+            .register_event(Share::new(ArNotify(related, count.clone(), std::marker::PhantomData)));
+    }
+    #[test]
+    fn test_deferred_archetype_notify() {
+        use std::sync::atomic::Ordering;
+        // 记录通知时原型是否已就绪
+        struct ReadyNotify<'a>(Share<pi_share::ShareBool>, std::marker::PhantomData<&'a ()>);
+        impl<'a> Listener for ReadyNotify<'a> {
+            type Event = crate::world::ArchetypeInit<'a>;
+            fn listen(&self, ar: Self::Event) {
+                self.0.store(ar.0.ready(), Ordering::Relaxed);
+            }
+        }
+        let mut world = World::new();
+        let count = Share::new(pi_share::ShareUsize::new(0));
+        let mut meta = SystemMeta::new(TypeInfo::of::<Age0>());
+        meta.relate(Relation::Read(world.init_component::<Age0>()));
+        world.listener_mgr.register_event(Share::new(ArNotify(
+            meta.related_ok(),
+            count.clone(),
+            std::marker::PhantomData,
+        )));
+        let ready = Share::new(pi_share::ShareBool::new(true));
+        world
+            .listener_mgr
+            .register_event(Share::new(ReadyNotify(ready.clone(), std::marker::PhantomData)));
+        // 默认同步通知，监听器在原型就绪前被调用
+        world.make_insert::<(Age0,)>().insert(&world, (Age0(0),));
+        assert_eq!(world.pending_archetype_notify_len(), 0);
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+        assert!(!ready.load(Ordering::Relaxed));
+
+        // 延迟通知
+        world.set_sync_archetype_notify(false);
+        let mut q = world.make_query::<&Age1, Changed<Age1>>();
+        world.make_insert::<(Age0, Age1)>().insert(&world, (Age0(1), Age1(1)));
+        // 新原型还未通知监听器，但查询对齐时已经可以看到，创建和通知之间的修改不会丢失
+        assert_eq!(world.pending_archetype_notify_len(), 1);
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+        assert_eq!(q.iter(&world).map(|a| a.0).collect::<Vec<_>>(), vec![1]);
+        world.settle();
+        assert_eq!(world.pending_archetype_notify_len(), 0);
+        assert_eq!(count.load(Ordering::Relaxed), 2);
+        // 延迟通知时，原型在通知前已就绪
+        assert!(ready.load(Ordering::Relaxed));
+
+        // 恢复同步通知
+        world.set_sync_archetype_notify(true);
+        world.make_insert::<(Age0, Age2)>().insert(&world, (Age0(2), Age2(2)));
+        assert_eq!(world.pending_archetype_notify_len(), 0);
+        assert_eq!(count.load(Ordering::Relaxed), 3);
+        assert!(!ready.load(Ordering::Relaxed));
+    }
+    fn archetype_notify(sync: bool) {
+        let mut world = World::new();
+        world.set_sync_archetype_notify(sync);
+        let components = init_ages(&mut world);
+        let count = Share::new(pi_share::ShareUsize::new(0));
+        for _ in 0..400 {
+            add_ar_notify(&mut world, &components, &count);
+        }
+        random_archetypes(&mut world, &components, 100);
+        world.settle();
+    }
+    #[bench]
+    fn bench_archetype_notify_sync(b: &mut Bencher) {
+        b.iter(|| archetype_notify(true));
+    }
+    #[bench]
+    fn bench_archetype_notify_deferred(b: &mut Bencher) {
+        b.iter(|| archetype_notify(false));
+    }
+
+    #[test]
+    fn test_batch_changed() {
+        use crate::system_params::SystemParam;
+        let mut world = World::new();
+        let mut meta = SystemMeta::new(TypeInfo::of::<ComponentChanged<'static, Age0>>());
+        let mut changed_state = ComponentChanged::<Age0>::init_state(&mut world, &mut meta);
+        let i = world.make_insert::<(Age0,)>();
+        let es: Vec<Entity> = (0..5).map(|n| i.insert(&world, (Age0(n),))).collect();
+        world.settle();
+        world.increment_tick();
+
+        let mut state = ColumnsMut::<Age0>::init_state(&mut world, &mut meta);
+        ColumnsMut::<Age0>::align(&world, &meta, &mut state);
+        for mut view in ColumnsMut::<Age0>::get_param(&world, &meta, &mut state, world.tick()).iter_mut() {
+            for (_, v) in view.iter_mut_all() {
+                v.0 += 1;
+            }
+        }
+        let changed = ComponentChanged::<Age0>::get_param(&world, &meta, &mut changed_state, world.tick());
+        let mut r: Vec<Entity> = changed.iter().map(|e| *e).collect();
+        r.sort();
+        let mut es = es;
+        es.sort();
+        assert_eq!(r, es);
+    }
+    #[test]
+    fn test_world_extension() {
+        use std::collections::HashMap;
+        // 模拟空间网格，按Age0计算格子
+        #[derive(Default)]
+        struct Grid {
+            cells: HashMap<Entity, usize>,
+            created: usize,
+            settles: usize,
+        }
+        impl WorldExtension for Grid {
+            fn on_archetype_created(&mut self, _ar: &Archetype) {
+                self.created += 1;
+            }
+            fn on_settle(&mut self, _report: &SettleReport) {
+                self.settles += 1;
+            }
+            fn on_frame_end(&mut self, world: &World) {
+                for (e, cell) in self.cells.iter_mut() {
+                    *cell = world.get_component::<Age0>(*e).unwrap().0 / 10;
+                }
+            }
+            fn on_entity_destroyed(&mut self, e: Entity) {
+                self.cells.remove(&e);
+            }
+        }
+        let mut world = World::new();
+        assert!(world.insert_extension(Grid::default()).is_none());
+        let i = world.make_insert::<(Age0, Age1)>();
+        let e1 = i.insert(&world, (Age0(5), Age1(0)));
+        let e2 = i.insert(&world, (Age0(25), Age1(0)));
+        let grid = world.extension_mut::<Grid>().unwrap();
+        grid.cells.insert(e1, 0);
+        grid.cells.insert(e2, 0);
+        world.settle();
+        let grid = world.extension::<Grid>().unwrap();
+        assert_eq!(grid.created, 1);
+        assert_eq!(grid.settles, 1);
+        assert_eq!(grid.cells[&e1], 0);
+        assert_eq!(grid.cells[&e2], 2);
+
+        world.get_component_mut::<Age0>(e1).unwrap().0 = 35;
+        world.destroy_entity(e2).unwrap();
+        world.settle();
+        let grid = world.extension::<Grid>().unwrap();
+        assert_eq!(grid.created, 1);
+        assert_eq!(grid.settles, 2);
+        assert_eq!(grid.cells.len(), 1);
+        assert_eq!(grid.cells[&e1], 3);
+
+        // Alter销毁的实体在整理时回调
+        let mut alter = world.make_alter::<(), (), (), ()>();
+        alter.get_param(&world).destroy(e1).unwrap();
+        assert_eq!(world.extension::<Grid>().unwrap().cells.len(), 1);
+        world.settle();
+        assert!(world.extension::<Grid>().unwrap().cells.is_empty());
+
+        let grid = world.remove_extension::<Grid>().unwrap();
+        assert_eq!(grid.settles, 3);
+        assert!(world.extension::<Grid>().is_none());
+    }
+    #[test]
+    fn test_validate_location() {
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0,)>();
+        let es: Vec<Entity> = (0..4).map(|n| i.insert(&world, (Age0(n),))).collect();
+        world.settle();
+        let locs: Vec<_> = es.iter().map(|e| world.get_entity_location(*e).unwrap()).collect();
+        let g = locs[0].generation;
+        for (e, loc) in es.iter().zip(locs.iter()) {
+            assert!(world.validate_location(*e, loc));
+        }
+        // 实体不同
+        assert!(!world.validate_location(es[1], &locs[0]));
+        // 代数不同
+        let mut stale = locs[0];
+        stale.generation = g.wrapping_add(1);
+        assert!(!world.validate_location(es[0], &stale));
+
+        // 删除最后一行，整理时没有移动行，代数不变
+        world.destroy_entity(es[3]).unwrap();
+        assert!(!world.validate_location(es[3], &locs[3]));
+        world.settle();
+        let ar = world.get_archetype(locs[0].archetype_index).unwrap();
+        assert_eq!(ar.row_generation(), g);
+        assert_eq!(ar.high_water_row(), Row(4));
+        for j in 0..3 {
+            assert!(world.validate_location(es[j], &locs[j]));
+        }
+        assert!(!world.validate_location(es[3], &locs[3]));
+
+        // 删除第一行，整理时最后一行移动到第一行，代数加1，缓存的位置全部失效
+        world.destroy_entity(es[0]).unwrap();
+        world.settle();
+        let ar = world.get_archetype(locs[0].archetype_index).unwrap();
+        assert_eq!(ar.row_generation(), g.wrapping_add(1));
+        assert_eq!(ar.len(), Row(2));
+        assert_eq!(ar.high_water_row(), Row(4));
+        for j in 0..4 {
+            assert!(!world.validate_location(es[j], &locs[j]));
+        }
+        for e in &es[1..3] {
+            let loc = world.get_entity_location(*e).unwrap();
+            assert!(world.validate_location(*e, &loc));
+        }
+        assert_eq!(world.get_entity_location(es[2]).unwrap().row, Row(0));
+    }
+    #[test]
+    fn test_snapshot_archetype() {
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0, Age1)>();
+        let es: Vec<Entity> = (0..3).map(|n| i.insert(&world, (Age0(n), Age1(n + 10)))).collect();
+        world.settle();
+        let index = world.get_entity_location(es[0]).unwrap().archetype_index;
+        let id = world.get_archetype(index).unwrap().id();
+        let snapshot = world.snapshot_archetype(id).unwrap();
+        assert_eq!(snapshot.id(), id);
+        assert_eq!(snapshot.entities.len(), 3);
+        assert_eq!(snapshot.entities[0].1.len(), 2);
+
+        for e in es.iter() {
+            world.get_component_mut::<Age0>(*e).unwrap().0 += 100;
+            world.get_component_mut::<Age1>(*e).unwrap().0 += 100;
+        }
+        world.destroy_entity(es[2]).unwrap();
+        world.settle();
+        assert_eq!(world.restore_archetype(snapshot), 2);
+        for n in 0..2 {
+            assert_eq!(world.get_component::<Age0>(es[n]).unwrap().0, n);
+            assert_eq!(world.get_component::<Age1>(es[n]).unwrap().0, n + 10);
+        }
+        assert!(!world.contains_entity(es[2]));
+
+        // 有需要释放的组件，不能快照
+        let i = world.make_insert::<(Age21,)>();
+        let e = i.insert(&world, (Age21(vec![1]),));
+        world.settle();
+        let index = world.get_entity_location(e).unwrap().archetype_index;
+        let id = world.get_archetype(index).unwrap().id();
+        assert!(world.snapshot_archetype(id).is_none());
+    }
+    #[test]
+    fn test_changed_window() {
+        let mut world = World::new();
+        let mut q = world.make_query::<Entity, Changed<Age0, 2>>();
+        let mut qm = world.make_query::<&mut Age0, ()>();
+        let i = world.make_insert::<(Age0,)>();
+        let e0 = i.insert(&world, (Age0(0),));
+        let e1 = i.insert(&world, (Age0(1),));
+        world.settle();
+        q.align(&world);
+        qm.align(&world);
+        let mut r: Vec<_> = q.get_param(&world).iter().collect();
+        r.sort();
+        assert_eq!(r, vec![e0, e1]);
+
+        world.increment_tick();
+        world.increment_tick();
+        qm.get_param(&world).get_mut(e1).unwrap().0 = 10;
+        world.increment_tick();
+        // e0的修改已超出窗口，e1在窗口内，和上次运行无关，多次查询结果相同
+        assert_eq!(q.get_param(&world).iter().collect::<Vec<_>>(), vec![e1]);
+        world.increment_tick();
+        assert_eq!(q.get_param(&world).iter().collect::<Vec<_>>(), vec![e1]);
+        assert_eq!(q.get_param(&world).iter().collect::<Vec<_>>(), vec![e1]);
+        // 超出窗口
+        world.increment_tick();
+        assert_eq!(q.get_param(&world).iter().count(), 0);
+    }
+    #[test]
+    fn test_filter_archetype_match() {
+        let mut world = World::new();
+        let a = world.make_insert::<(Age0,)>().insert(&world, (Age0(0),));
+        let b = world.make_insert::<(Age0, Age1)>().insert(&world, (Age0(0), Age1(1)));
+        let c = world.make_insert::<(Age0, Age2)>().insert(&world, (Age0(0), Age2(2)));
+        let d = world.make_insert::<(Age1,)>().insert(&world, (Age1(1),));
+        world.settle();
+        let index = |e: Entity| world.get_entity_location(e).unwrap().archetype_index;
+        let (a, b, c, d) = (index(a), index(b), index(c), index(d));
+        let indexs = |archetypes: &Vec<crate::archetype::ShareArchetype>| {
+            let mut vec = archetypes.iter().map(|ar| ar.index()).collect::<Vec<_>>();
+            vec.sort();
+            vec
+        };
+        let q = world.make_query::<&Age0, ()>();
+        assert_eq!(indexs(&q.archetypes), vec![a, b, c]);
+        let q = world.make_query::<&Age0, With<Age1>>();
+        assert_eq!(indexs(&q.archetypes), vec![b]);
+        let q = world.make_query::<&Age0, Without<Age1>>();
+        assert_eq!(indexs(&q.archetypes), vec![a, c]);
+        let q = world.make_query::<Entity, Or<(With<Age1>, With<Age2>)>>();
+        assert_eq!(indexs(&q.archetypes), vec![b, c, d]);
+        let q = world.make_query::<(&Age0, Option<&Age1>), ()>();
+        assert_eq!(indexs(&q.archetypes), vec![a, b, c]);
+        let q = world.make_query::<&mut Age1, Without<Age0>>();
+        assert_eq!(indexs(&q.archetypes), vec![d]);
+        let q = world.make_query::<Entity, (With<Age0>, Without<Age1>, Without<Age2>)>();
+        assert_eq!(indexs(&q.archetypes), vec![a]);
+    }
+    #[test]
+    fn test_raw_ptr_fetch() {
+        let mut world = World::new();
+        let mut qc = world.make_query::<Entity, Changed<Age0>>();
+        let mut qm = world.make_query::<(Entity, *mut Age0), ()>();
+        let mut qr = world.make_query::<*const Age0, ()>();
+        let i = world.make_insert::<(Age0,)>();
+        let e0 = i.insert(&world, (Age0(0),));
+        let e1 = i.insert(&world, (Age0(1),));
+        world.settle();
+        qc.align(&world);
+        qm.align(&world);
+        qr.align(&world);
+        assert_eq!(qc.get_param(&world).iter().count(), 2);
+
+        world.increment_tick();
+        for (e, ptr) in qm.get_param(&world).iter_mut() {
+            if e == e1 {
+                unsafe { (*ptr).0 = 10 };
+            }
+        }
+        let mut r: Vec<usize> = qr.iter(&world).map(|ptr| unsafe { (*ptr).0 }).collect();
+        r.sort();
+        assert_eq!(r, vec![0, 10]);
+        // 取可变指针时就记录了修改
+        let mut r: Vec<Entity> = qc.get_param(&world).iter().collect();
+        r.sort();
+        let mut es = vec![e0, e1];
+        es.sort();
+        assert_eq!(r, es);
+    }
+    #[test]
+    fn test_column_iter() {
+        let mut world = World::new();
+        let e0 = world.make_insert::<(Age0,)>().insert(&world, (Age0(0),));
+        let e1 = world.make_insert::<(Age0, Age1)>().insert(&world, (Age0(1), Age1(1)));
+        let e2 = world.make_insert::<(Age0, Age2)>().insert(&world, (Age0(2), Age2(2)));
+        let e3 = world.make_insert::<(Age0, Age2)>().insert(&world, (Age0(3), Age2(3)));
+        world.make_insert::<(Age1,)>().insert(&world, (Age1(4),));
+        world.destroy_entity(e2).unwrap();
+        let index = world.init_component::<Age0>();
+        let column = world.get_column(index).unwrap();
+        assert_eq!(column.archetypes_len(), 3);
+        let mut r: Vec<(Entity, usize)> = column.iter::<Age0>(&world).map(|(e, v)| (e, v.0)).collect();
+        r.sort();
+        let mut es = vec![(e0, 0), (e1, 1), (e3, 3)];
+        es.sort();
+        assert_eq!(r, es);
+    }
+    #[test]
+    fn test_stalled_consumers() {
+        use crate::system_params::SystemParam;
+        struct Ping;
+        struct Active;
+        struct Forgotten;
+        let mut world = World::new();
+        let mut meta = SystemMeta::new(TypeInfo::of::<Ping>());
+        let mut sender = EventSender::<u32>::init_state(&mut world, &mut meta);
+        let mut meta_active = SystemMeta::new(TypeInfo::of::<Active>());
+        let mut active = Event::<u32>::init_state(&mut world, &mut meta_active);
+        let mut meta_forgotten = SystemMeta::new(TypeInfo::of::<Forgotten>());
+        let _forgotten = Event::<u32>::init_state(&mut world, &mut meta_forgotten);
+        for i in 0..3 {
+            EventSender::<u32>::get_param(&world, &meta, &mut sender, world.tick()).send(i);
+            assert_eq!(Event::<u32>::get_param(&world, &meta_active, &mut active, world.tick()).iter().count(), 1);
+            world.settle();
+            assert!(world.stalled_consumers(4).is_empty());
+        }
+        EventSender::<u32>::get_param(&world, &meta, &mut sender, world.tick()).send(3);
+        Event::<u32>::get_param(&world, &meta_active, &mut active, world.tick()).mark_read();
+        world.settle();
+        let r = world.stalled_consumers(4);
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].owner, std::any::type_name::<Forgotten>());
+        assert_eq!(r[0].frames, 4);
+        assert_eq!(r[0].unread, 4);
+        assert_eq!(world.stalled_consumers(5).len(), 0);
+    }
+    #[test]
+    fn test_query_materialize() {
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0, Age1)>();
+        let es: Vec<Entity> = (0..4).map(|n| i.insert(&world, (Age0(n), Age1(n)))).collect();
+        world.settle();
+        let mut q = world.make_query::<&Age0, ()>();
+        let mut snapshot = q.get_param(&world).materialize_cloned();
+        snapshot.sort_by_key(|(e, _)| *e);
+        let mut qa = world.make_query::<(&Age0, &Age1), ()>();
+        let mut buf = Vec::with_capacity(8);
+        qa.get_param(&world).materialize_into(&mut buf, |e, (a, b)| (e, a.0 + b.0));
+        buf.sort();
+
+        // 修改world，快照不受影响
+        for e in es.iter() {
+            world.get_component_mut::<Age0>(*e).unwrap().0 += 100;
+        }
+        world.destroy_entity(es[0]).unwrap();
+        world.settle();
+        let mut expect: Vec<(Entity, usize)> = es.iter().enumerate().map(|(n, e)| (*e, n)).collect();
+        expect.sort();
+        assert_eq!(snapshot.iter().map(|(e, v)| (*e, v.0)).collect::<Vec<_>>(), expect);
+        assert_eq!(buf, expect.iter().map(|(e, n)| (*e, n * 2)).collect::<Vec<_>>());
+
+        let r = q.get_param(&world).materialize(|e, v| (e, v.0));
+        assert_eq!(r.len(), 3);
+        assert!(r.iter().all(|(_, v)| *v >= 100));
+    }
+    #[test]
+    fn test_flush_commands_now() {
+        let mut world = World::new();
+        let es = std::cell::RefCell::new(Vec::new());
+        world.flush_commands_now((0..3).map(|n| {
+            let es = &es;
+            move |w: &mut World| {
+                let i = w.make_insert::<(Age0,)>();
+                es.borrow_mut().push(i.insert(w, (Age0(n),)));
+            }
+        }));
+        let es = es.into_inner();
+        assert_eq!(es.len(), 3);
+        for (n, e) in es.iter().enumerate() {
+            assert_eq!(world.get_component::<Age0>(*e).unwrap().0, n);
+        }
+        let e = es[1];
+        let cmds: Vec<Box<dyn FnOnce(&mut World)>> = vec![
+            Box::new(move |w| w.get_component_mut::<Age0>(e).unwrap().0 = 10),
+            Box::new(move |w| assert_eq!(w.get_component::<Age0>(e).unwrap().0, 10)),
+        ];
+        world.flush_commands_now(cmds);
+        assert_eq!(world.get_component::<Age0>(e).unwrap().0, 10);
+    }
+    #[cfg(feature = "strict_iter")]
+    #[test]
+    #[should_panic(expected = "while it is being iterated by this thread")]
+    fn test_strict_iter_insert() {
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0,)>();
+        i.insert(&world, (Age0(0),));
+        world.settle();
+        let mut q = world.make_query::<&Age0, ()>();
+        for _ in q.iter(&world) {
+            // 迭代时在同一原型上插入
+            i.insert(&world, (Age0(1),));
+        }
+    }
+    #[cfg(feature = "strict_iter")]
+    #[test]
+    fn test_strict_iter_alter() {
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0,)>();
+        for n in 0..3 {
+            i.insert(&world, (Age0(n),));
+        }
+        world.settle();
+        let mut q = world.make_query::<&Age0, ()>();
+        assert_eq!(q.iter(&world).count(), 3);
+        // Alter的修改是允许的
+        let mut alter = world.make_alter::<&Age0, (), (Age1,), ()>();
+        {
+            let mut a = alter.get_param(&world);
+            let mut it = a.iter_mut();
+            while let Some(v) = it.next() {
+                if v.0 == 0 {
+                    it.destroy().unwrap();
+                } else {
+                    it.alter((Age1(v.0),)).unwrap();
+                }
+            }
+        }
+        world.settle();
+        assert_eq!(world.make_query::<&Age1, ()>().iter(&world).count(), 2);
+    }
+    #[test]
+    fn test_warm_archetype() {
+        let mut world = World::new();
+        let src = world.warm_spawn::<(Age0, Age1)>();
+        let dst = world.warm_alter::<(Age2,), (Age1,)>(src).unwrap();
+        assert_ne!(src, dst);
+        assert_eq!(world.pending_archetype_notify_len(), 0);
+        let len = world.archetype_arr.len();
+
+        let i = world.make_insert::<(Age0, Age1)>();
+        let e = i.insert(&world, (Age0(0), Age1(1)));
+        assert_eq!(world.get_entity_location(e).unwrap().archetype_index, src);
+        let mut alter = world.make_alter::<(), (), (Age2,), (Age1,)>();
+        alter.get_param(&world).alter(e, (Age2(2),)).unwrap();
+        world.settle();
+        // 预热后，插入和变更都不再创建原型
+        assert_eq!(world.archetype_arr.len(), len);
+        assert_eq!(world.get_entity_location(e).unwrap().archetype_index, dst);
+        assert_eq!(world.get_component::<Age2>(e).unwrap().0, 2);
+        assert!(world.get_component::<Age1>(e).is_err());
+    }
+    #[test]
+    fn test_tick_compressed_memory() {
+        let mut world = World::new();
+        assert!(world.set_tick_policy::<Age1>(crate::column::TickPolicy::compressed()));
+        let _q0 = world.make_query::<Entity, Changed<Age0>>();
+        let _q1 = world.make_query::<Entity, Changed<Age1>>();
+        let i = world.make_insert::<(Age0, Age1)>();
+        for n in 0..1000 {
+            i.insert(&world, (Age0(n), Age1(n)));
+        }
+        world.settle();
+        // 已放入原型后不能再修改策略
+        assert!(!world.set_tick_policy::<Age1>(crate::column::TickPolicy::full()));
+        let full = world.get_column_by_id(&std::any::TypeId::of::<Age0>()).unwrap().ticks_memsize();
+        let compressed = world.get_column_by_id(&std::any::TypeId::of::<Age1>()).unwrap().ticks_memsize();
+        assert!(compressed >= 2000);
+        assert!(full >= compressed * 4);
+    }
+    #[test]
+    fn test_tick_compressed_rebase() {
+        let mut world = World::new();
+        world.set_tick_policy::<Age1>(crate::column::TickPolicy::compressed());
+        let mut q = world.make_query::<Entity, Changed<Age1>>();
+        let mut qm = world.make_query::<&mut Age1, ()>();
+        let i = world.make_insert::<(Age1,)>();
+        let e0 = i.insert(&world, (Age1(0),));
+        let e1 = i.insert(&world, (Age1(1),));
+        world.settle();
+        q.align(&world);
+        qm.align(&world);
+        let mut r: Vec<_> = q.get_param(&world).iter().collect();
+        r.sort();
+        assert_eq!(r, vec![e0, e1]);
+        assert_eq!(q.get_param(&world).iter().count(), 0);
+
+        // 差值饱和，修改不会漏掉，但在整理前会被重复报告
+        for _ in 0..300 {
+            world.increment_tick();
+        }
+        qm.get_param(&world).get_mut(e1).unwrap().0 = 10;
+        world.increment_tick();
+        assert_eq!(q.get_param(&world).iter().collect::<Vec<_>>(), vec![e1]);
+        assert_eq!(q.get_param(&world).iter().collect::<Vec<_>>(), vec![e1]);
+
+        // 整理时推进base，饱和的差值被重写为整理时的tick，旧的修改被限制为base
+        world.settle();
+        let now = world.tick();
+        let column = world.get_column_by_id(&std::any::TypeId::of::<Age1>()).unwrap();
+        let loc0 = world.get_entity_location(e0).unwrap();
+        let loc1 = world.get_entity_location(e1).unwrap();
+        let blob = column.blob_ref(loc0.archetype_index).unwrap();
+        assert_eq!(blob.get_tick_unchecked(loc1.row), now);
+        assert_eq!(*blob.get_tick_unchecked(loc0.row), *now - 64);
+        assert_eq!(q.get_param(&world).iter().count(), 0);
+
+        world.increment_tick();
+        qm.get_param(&world).get_mut(e0).unwrap().0 = 20;
+        world.increment_tick();
+        assert_eq!(q.get_param(&world).iter().collect::<Vec<_>>(), vec![e0]);
+        assert_eq!(q.get_param(&world).iter().count(), 0);
+    }
+    #[test]
+    fn test_insert_batch_systems() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Mutex;
+        static INSERTED: Mutex<Vec<(crate::archetype::ArchetypeIndex, Entity)>> = Mutex::new(Vec::new());
+        static SEEN: AtomicUsize = AtomicUsize::new(0);
+        fn insert_a(i: Insert<(Age3, Age4)>) {
+            let e = i.insert((Age3(0), Age4(0)));
+            INSERTED.lock().unwrap().push((i.archetype_index(), e));
+        }
+        fn insert_b(i: Insert<(Age3, Age4)>) {
+            let r = i.insert_batch((1..4).map(|n| (Age3(n), Age4(n))));
+            assert_eq!(r.len(), 3);
+            let index = r.archetype_index();
+            let mut vec = INSERTED.lock().unwrap();
+            for e in Vec::from(r) {
+                vec.push((index, e));
+            }
+        }
+        fn reader(q: Query<(&Age3, &Age4)>) {
+            SEEN.store(q.iter().count(), Ordering::Relaxed);
+        }
+        let mut app = crate::prelude::App::new();
+        app.add_system(Update, insert_a);
+        app.add_system(Update, insert_b);
+        app.add_system(Update, reader);
+        app.run();
+        // 读取的system在插入的system后执行，能看到同一帧的全部插入
+        assert_eq!(SEEN.load(Ordering::Relaxed), 4);
+        let vec = INSERTED.lock().unwrap().clone();
+        assert_eq!(vec.len(), 4);
+        // 两个system的插入共享同一个原型
+        for (index, e) in vec.iter() {
+            assert_eq!(*index, vec[0].0);
+            assert_eq!(app.world.get_entity_location(*e).unwrap().archetype_index, *index);
+        }
+    }
+    #[bench]
+    fn bench_insert_cached(b: &mut Bencher) {
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0, Age1)>();
+        b.iter(|| {
+            for n in 0..1000 {
+                i.insert(&world, (Age0(n), Age1(n)));
+            }
+        });
+    }
+    #[bench]
+    fn bench_insert_uncached(b: &mut Bencher) {
+        let mut world = World::new();
+        b.iter(|| {
+            for n in 0..1000 {
+                // 每次插入都重新查找原型和列
+                let i = world.make_insert::<(Age0, Age1)>();
+                i.insert(&world, (Age0(n), Age1(n)));
+            }
+        });
+    }
+    #[test]
+    fn test_coarse_mut() {
+        use crate::system_params::SystemParam;
+        let mut world = World::new();
+        let mut meta = SystemMeta::new(TypeInfo::of::<ComponentChanged<'static, Age0>>());
+        let mut changed_state = ComponentChanged::<Age0>::init_state(&mut world, &mut meta);
+        let column = world.get_column_by_id(&TypeId::of::<Age0>()).unwrap().clone();
+        assert_eq!(column.changed_listener_count(), 1);
+        let mut q = world.make_query::<(Entity, CoarseMut<Age0>), ()>();
+        let mut qc = world.make_query::<Entity, Changed<Age0>>();
+        let i = world.make_insert::<(Age0,)>();
+        let i1 = world.make_insert::<(Age0, Age1)>();
+        let mut es: Vec<Entity> = (0..5).map(|n| i.insert(&world, (Age0(n),))).collect();
+        let other = i1.insert(&world, (Age0(0), Age1(0)));
+        world.settle();
+        q.align(&world);
+        qc.align(&world);
+        assert_eq!(qc.get_param(&world).iter().count(), 6);
+        ComponentChanged::<Age0>::get_param(&world, &meta, &mut changed_state, world.tick()).mark_read();
+        world.increment_tick();
+
+        // 只修改第一个原型中的两个实体
+        for (e, mut v) in q.get_param(&world).iter_mut() {
+            if e == es[1] || e == es[3] {
+                v.0 += 1;
+            }
+        }
+        // Changed过滤是精确的
+        let mut r: Vec<Entity> = qc.get_param(&world).iter().collect();
+        r.sort();
+        assert_eq!(r, vec![es[1], es[3]]);
+        // 修改事件是原型级别的近似，包含该原型的全部实体，但不包含其他原型的实体
+        let changed = ComponentChanged::<Age0>::get_param(&world, &meta, &mut changed_state, world.tick());
+        let mut r: Vec<Entity> = changed.iter().map(|e| *e).collect();
+        r.sort();
+        es.sort();
+        assert_eq!(r, es);
+        assert!(!r.contains(&other));
+    }
+    #[bench]
+    fn bench_mut_write(b: &mut Bencher) {
+        use crate::system_params::SystemParam;
+        let mut world = World::new();
+        let mut meta = SystemMeta::new(TypeInfo::of::<ComponentChanged<'static, Age0>>());
+        let _changed_state = ComponentChanged::<Age0>::init_state(&mut world, &mut meta);
+        let i = world.make_insert::<(Age0,)>();
+        for n in 0..10_000 {
+            i.insert(&world, (Age0(n),));
+        }
+        world.settle();
+        let mut q = world.make_query::<&mut Age0, ()>();
+        b.iter(|| {
+            world.increment_tick();
+            for mut v in q.get_param(&world).iter_mut() {
+                v.0 += 1;
+            }
+            world.settle();
+        });
+    }
+    #[bench]
+    fn bench_coarse_mut_write(b: &mut Bencher) {
+        use crate::system_params::SystemParam;
+        let mut world = World::new();
+        let mut meta = SystemMeta::new(TypeInfo::of::<ComponentChanged<'static, Age0>>());
+        let _changed_state = ComponentChanged::<Age0>::init_state(&mut world, &mut meta);
+        let i = world.make_insert::<(Age0,)>();
+        for n in 0..10_000 {
+            i.insert(&world, (Age0(n),));
+        }
+        world.settle();
+        let mut q = world.make_query::<CoarseMut<Age0>, ()>();
+        b.iter(|| {
+            world.increment_tick();
+            for mut v in q.get_param(&world).iter_mut() {
+                v.0 += 1;
+            }
+            world.settle();
+        });
+    }
+    #[test]
+    fn test_reference_policy() {
+        #[derive(Component)]
+        struct Follow(Entity);
+        impl MapEntities for Follow {
+            fn map_entities(&mut self, f: &mut dyn FnMut(Entity) -> Entity) {
+                self.0 = f(self.0);
+            }
+        }
+        #[derive(Component)]
+        struct Attach(Entity);
+        impl MapEntities for Attach {
+            fn map_entities(&mut self, f: &mut dyn FnMut(Entity) -> Entity) {
+                self.0 = f(self.0);
+            }
+        }
+        #[derive(Component)]
+        struct Owner(Entity);
+        impl MapEntities for Owner {
+            fn map_entities(&mut self, f: &mut dyn FnMut(Entity) -> Entity) {
+                self.0 = f(self.0);
+            }
+        }
+        let mut world = World::new();
+        world.register_reference_policy::<Follow>(ReferencePolicy::Nullify);
+        world.register_reference_policy::<Attach>(ReferencePolicy::RemoveComponent);
+        world.register_reference_policy::<Owner>(ReferencePolicy::DespawnHolder);
+        let target = world.make_insert::<(Age0,)>();
+        let t1 = target.insert(&world, (Age0(1),));
+        let t2 = target.insert(&world, (Age0(2),));
+        let x0 = target.insert(&world, (Age0(0),));
+        let follow = world.make_insert::<(Follow,)>();
+        let a = follow.insert(&world, (Follow(t1),));
+        let b = follow.insert(&world, (Follow(t1),));
+        let c = follow.insert(&world, (Follow(t2),));
+        let d = world.make_insert::<(Attach, Age1)>().insert(&world, (Attach(t1), Age1(0)));
+        // 引用链 x3 -> x2 -> x1 -> x0
+        let owner = world.make_insert::<(Owner,)>();
+        let x1 = owner.insert(&world, (Owner(x0),));
+        let x2 = owner.insert(&world, (Owner(x1),));
+        let x3 = owner.insert(&world, (Owner(x2),));
+        let other = owner.insert(&world, (Owner(t2),));
+        world.settle();
+        assert_eq!(world.reference_stats(), ReferenceStats::default());
+
+        world.destroy_entity(t1).unwrap();
+        world.destroy_entity(x0).unwrap();
+        world.settle();
+        assert!(world.get_component::<Follow>(a).unwrap().0.is_null());
+        assert!(world.get_component::<Follow>(b).unwrap().0.is_null());
+        assert_eq!(world.get_component::<Follow>(c).unwrap().0, t2);
+        assert!(world.get_component::<Attach>(d).is_err());
+        assert_eq!(world.get_component::<Age1>(d).unwrap().0, 0);
+        assert!(!world.contains(x1));
+        assert!(!world.contains(x2));
+        assert!(!world.contains(x3));
+        assert!(world.contains(other));
+        // 只访问了引用已销毁实体的持有者，c和other没有被访问
+        assert_eq!(
+            world.reference_stats(),
+            ReferenceStats {
+                holders_visited: 6,
+                references_fixed: 6,
+                components_removed: 1,
+                holders_despawned: 3,
+            }
+        );
+        // 修改后的引用重新建立索引
+        let t3 = target.insert(&world, (Age0(3),));
+        world.increment_tick();
+        world.get_component_mut::<Follow>(a).unwrap().0 = t3;
+        world.settle();
+        world.destroy_entity(t3).unwrap();
+        world.settle();
+        assert!(world.get_component::<Follow>(a).unwrap().0.is_null());
+        assert_eq!(world.reference_stats().holders_visited, 1);
+
+        let t4 = target.insert(&world, (Age0(4),));
+        let e1 = follow.insert(&world, (Follow(t4),));
+        let e2 = follow.insert(&world, (Follow(t4),));
+        let e3 = follow.insert(&world, (Follow(t4),));
+        world.settle();
+        // e1改为引用c，从t4的反向索引中交换移除，e3被移到e1的位置
+        world.increment_tick();
+        world.get_component_mut::<Follow>(e1).unwrap().0 = c;
+        world.settle();
+        // 归档的实体同样修正引用
+        world.archive_entities(&[t4]).unwrap();
+        world.settle();
+        assert_eq!(world.get_component::<Follow>(e1).unwrap().0, c);
+        assert!(world.get_component::<Follow>(e2).unwrap().0.is_null());
+        assert!(world.get_component::<Follow>(e3).unwrap().0.is_null());
+        assert_eq!(world.reference_stats().holders_visited, 2);
+    }
+    #[test]
+    fn test_iter_entities() {
+        use std::collections::HashSet;
+        let mut world = World::new();
+        let mut es = HashSet::new();
+        let i0 = world.make_insert::<(Age0,)>();
+        let i1 = world.make_insert::<(Age0, Age1)>();
+        let i2 = world.make_insert::<(Age2,)>();
+        for n in 0..5 {
+            es.insert(i0.insert(&world, (Age0(n),)));
+            es.insert(i1.insert(&world, (Age0(n), Age1(n))));
+            es.insert(i2.insert(&world, (Age2(n),)));
+            es.insert(world.spawn_empty());
+        }
+        let check = |world: &World, es: &HashSet<Entity>| {
+            let r: HashSet<Entity> = world.iter_entities().map(|(e, _)| e).collect();
+            assert_eq!(&r, es);
+            assert_eq!(world.entity_count(), es.len());
+            for (e, loc) in world.iter_entities() {
+                assert!(loc.row.is_null() || world.validate_location(e, &loc));
+            }
+        };
+        check(&world, &es);
+        let dead: Vec<Entity> = es.iter().copied().step_by(3).collect();
+        for e in dead {
+            world.destroy_entity(e).unwrap();
+            es.remove(&e);
+        }
+        check(&world, &es);
+        world.settle();
+        check(&world, &es);
+    }
+    #[test]
+    fn test_diff_round_trip() {
+        use crate::diff::{DiffBaseline, DiffValue, WorldDiff};
+        use std::collections::HashMap;
+        impl DiffValue for Age0 {
+            fn encode(&self, out: &mut Vec<u8>) {
+                self.0.encode(out)
+            }
+            fn decode(patch: &[u8]) -> Option<Self> {
+                usize::decode(patch).map(Age0)
+            }
+        }
+        impl DiffValue for Age1 {
+            fn encode(&self, out: &mut Vec<u8>) {
+                self.0.encode(out)
+            }
+            fn decode(patch: &[u8]) -> Option<Self> {
+                usize::decode(patch).map(Age1)
+            }
+        }
+        let mut rng = rand::thread_rng();
+        let mut a = World::new();
+        let mut b = World::new();
+        // 两端组件的注册顺序不同，按稳定名称对应
+        a.register_diffable::<Age0>("age0");
+        a.register_diffable::<Age1>("age1");
+        b.register_diffable::<Age1>("age1");
+        b.register_diffable::<Age0>("age0");
+        let i0 = a.make_insert::<(Age0,)>();
+        let i1 = a.make_insert::<(Age0, Age1)>();
+        let mut live: Vec<Entity> = Vec::new();
+        for n in 0..100 {
+            if n % 2 == 0 {
+                live.push(i0.insert(&a, (Age0(n),)));
+            } else {
+                live.push(i1.insert(&a, (Age0(n), Age1(n))));
+            }
+        }
+        a.settle();
+        let mut baseline = DiffBaseline::default();
+        let mut diff = WorldDiff::default();
+        let mut mapper = HashMap::new();
+        let mut last = WorldDiff::default();
+        for frame in 0..20 {
+            a.increment_tick();
+            // 每帧5%的实体变化：修改、创建和销毁
+            for _ in 0..3 {
+                let e = live[rng.gen_range(0..live.len())];
+                a.get_component_mut::<Age0>(e).unwrap().0 += 1000;
+            }
+            let e = live.swap_remove(rng.gen_range(0..live.len()));
+            a.destroy_entity(e).unwrap();
+            live.push(i1.insert(&a, (Age0(frame), Age1(frame))));
+            a.settle();
+            a.diff_since(&mut baseline, &mut diff);
+            if frame > 0 {
+                assert_eq!(diff.spawned.len(), 1);
+                assert_eq!(diff.despawned, vec![e]);
+                // 只输出修改的组件
+                assert!(diff.patches.len() <= 3 + 2);
+            }
+            assert!(diff.patches.windows(2).all(|w| (w[0].0, w[0].1) < (w[1].0, w[1].1)));
+            b.apply_diff(&diff, &mut mapper);
+            b.settle();
+            b.increment_tick();
+            // 收敛：b和a的组件相同
+            assert_eq!(mapper.len(), live.len());
+            for e in live.iter() {
+                let local = mapper[e];
+                assert_eq!(a.get_component::<Age0>(*e).unwrap(), b.get_component::<Age0>(local).unwrap());
+                assert_eq!(a.get_component::<Age1>(*e).ok(), b.get_component::<Age1>(local).ok());
+            }
+            last = diff.clone();
+        }
+        // 没有变化时差异为空
+        a.increment_tick();
+        a.diff_since(&mut baseline, &mut diff);
+        assert!(diff.is_empty());
+        assert!(!last.is_empty());
+        // 比较后没有递增tick的修改不会丢失
+        let e = live[0];
+        a.get_component_mut::<Age0>(e).unwrap().0 += 1;
+        a.diff_since(&mut baseline, &mut diff);
+        assert_eq!(diff.patches.len(), 1);
+        assert_eq!(diff.patches[0].0, e);
+        a.diff_since(&mut baseline, &mut diff);
+        assert!(diff.is_empty());
+        // 没有可比较组件的实体也输出创建和销毁
+        let e = a.make_insert::<(Age2,)>().insert(&a, (Age2(1),));
+        a.settle();
+        a.diff_since(&mut baseline, &mut diff);
+        assert_eq!(diff.spawned, vec![e]);
+        assert!(diff.patches.is_empty());
+        b.apply_diff(&diff, &mut mapper);
+        assert!(b.contains_entity(mapper[&e]));
+        a.destroy_entity(e).unwrap();
+        a.settle();
+        a.diff_since(&mut baseline, &mut diff);
+        assert_eq!(diff.despawned, vec![e]);
+        b.apply_diff(&diff, &mut mapper);
+        assert!(!mapper.contains_key(&e));
+    }
+    #[test]
+    fn test_nested_tuple_query() {
+        let mut world = World::new();
+        let e = world.make_insert::<(Age0, Age1, Age2, Age3, Age4, Age6, Age9, Age10, Age12, Age13, Age14, Age15, Age16, Age18, Age19, Age20, Age17)>()
+            .insert(&world, (Age0(0), Age1(1), Age2(2), Age3(3), Age4(4), Age6(6), Age9(9), Age10(10), Age12::default(), Age13::default(), Age14::default(), Age15::default(), Age16::default(), Age18::default(), Age19::default(), Age20::default(), Age17::default()));
+        // 超过15项时嵌套元组
+        let mut q = world.make_query::<(
+            (Entity, &Age0, &Age1, &Age2, &Age3, &Age4, &Age6, &Age9, &Age10),
+            (&Age12, &Age13, &Age14, &Age15, &Age16, &Age18, &Age19, &Age20, &Age17),
+        ), (With<Age0>, With<Age17>)>();
+        let r: Vec<_> = q.iter(&world).map(|((e, a0, _, _, a3, ..), _)| (e, a0.0, a3.0)).collect();
+        assert_eq!(r, vec![(e, 0, 3)]);
+    }
+    #[cfg(feature = "large-tuples")]
+    #[test]
+    fn test_large_tuple_query() {
+        let mut world = World::new();
+        let e = world.make_insert::<(Age0, Age1, Age2, Age3, Age4, Age6, Age9, Age10, Age12, Age13, Age14, Age15, Age16, Age18, Age19, Age20, Age17)>()
+            .insert(&world, (Age0(0), Age1(1), Age2(2), Age3(3), Age4(4), Age6(6), Age9(9), Age10(10), Age12::default(), Age13::default(), Age14::default(), Age15::default(), Age16::default(), Age18::default(), Age19::default(), Age20::default(), Age17::default()));
+        let mut q = world.make_query::<(
+            Entity, &Age0, &Age1, &Age2, &Age3, &Age4, &Age6, &Age9, &Age10,
+            &Age12, &Age13, &Age14, &Age15, &Age16, &Age18, &Age19, &Age20, &Age17, Has<Age0>, Has<Age5>,
+        ), ()>();
+        let r: Vec<_> = q.iter(&world).map(|(e, a0, .., h0, h5)| (e, a0.0, h0, h5)).collect();
+        assert_eq!(r, vec![(e, 0, true, false)]);
+    }
+    #[test]
+    fn test_cached_query() {
+        use crate::cached_query::CachedQuery;
+        type CQ<'a> = CachedQuery<'a, &'static Age0, With<Age1>>;
+        let mut world = World::new();
+        let mut meta = SystemMeta::new(TypeInfo::of::<()>());
+        let mut state = CQ::init_state(&mut world, &mut meta);
+        let i = world.make_insert::<(Age0, Age1)>();
+        let e1 = i.insert(&world, (Age0(1), Age1(1)));
+        let _e2 = i.insert(&world, (Age0(2), Age1(2)));
+        let e3 = world.make_insert::<(Age0, Age1, Age2)>().insert(&world, (Age0(3), Age1(3), Age2(3)));
+        let e4 = world.make_insert::<(Age0,)>().insert(&world, (Age0(4),));
+        world.settle();
+        let ar1 = world.get_entity_location(e1).unwrap().archetype_index;
+        let ar3 = world.get_entity_location(e3).unwrap().archetype_index;
+        // 第一次运行，全部匹配的原型都是脏的
+        CQ::align(&world, &meta, &mut state);
+        let q = CQ::get_param(&world, &meta, &mut state, world.increment_tick());
+        assert!(q.is_dirty());
+        assert_eq!(q.changed_archetypes(), &[ar1, ar3]);
+        assert_eq!(q.iter().count(), 3);
+        drop(q);
+        // 没有修改
+        world.increment_tick();
+        CQ::align(&world, &meta, &mut state);
+        let q = CQ::get_param(&world, &meta, &mut state, world.increment_tick());
+        assert!(!q.is_dirty());
+        drop(q);
+        // 修改不匹配的实体
+        world.increment_tick();
+        let mut m = world.make_query::<&mut Age0, ()>();
+        m.get_mut(&mut world, e4).unwrap().0 = 40;
+        CQ::align(&world, &meta, &mut state);
+        let q = CQ::get_param(&world, &meta, &mut state, world.increment_tick());
+        assert!(!q.is_dirty());
+        drop(q);
+        // 修改一个实体，只有它的原型变脏
+        world.increment_tick();
+        m.get_mut(&mut world, e1).unwrap().0 = 10;
+        CQ::align(&world, &meta, &mut state);
+        let q = CQ::get_param(&world, &meta, &mut state, world.increment_tick());
+        assert_eq!(q.changed_archetypes(), &[ar1]);
+        assert!(q.is_archetype_dirty(ar1));
+        assert!(!q.is_archetype_dirty(ar3));
+        drop(q);
+        // 新的匹配原型
+        let e5 = world.make_insert::<(Age0, Age1, Age3)>().insert(&world, (Age0(5), Age1(5), Age3(5)));
+        let ar5 = world.get_entity_location(e5).unwrap().archetype_index;
+        CQ::align(&world, &meta, &mut state);
+        let q = CQ::get_param(&world, &meta, &mut state, world.increment_tick());
+        assert_eq!(q.changed_archetypes(), &[ar5]);
+        assert_eq!(q.iter().count(), 4);
+        drop(q);
+        // 销毁实体，原型的结构变化
+        world.destroy_entity(e3).unwrap();
+        CQ::align(&world, &meta, &mut state);
+        let q = CQ::get_param(&world, &meta, &mut state, world.increment_tick());
+        assert_eq!(q.changed_archetypes(), &[ar3]);
+        drop(q);
+        CQ::align(&world, &meta, &mut state);
+        let q = CQ::get_param(&world, &meta, &mut state, world.increment_tick());
+        assert!(!q.is_dirty());
+    }
+    #[test]
+    fn test_prefetch_hint() {
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0, Age1, Age2)>();
+        for n in 0..1000 {
+            i.insert(&world, (Age0(n), Age1(n * 2), Age2(n * 3)));
+        }
+        let i = world.make_insert::<(Age0, Age1)>();
+        for n in 1000..1100 {
+            i.insert(&world, (Age0(n), Age1(n * 2)));
+        }
+        world.settle();
+        let mut q = world.make_query::<(Entity, &Age0, Option<&Age2>), With<Age1>>();
+        let expect: Vec<_> = q.iter(&world).map(|(e, a, c)| (e, a.0, c.map(|c| c.0))).collect();
+        assert_eq!(expect.len(), 1100);
+        for distance in [1, 4, 16, 5000] {
+            q.set_prefetch_hint(&world, distance);
+            let r: Vec<_> = q.iter(&world).map(|(e, a, c)| (e, a.0, c.map(|c| c.0))).collect();
+            assert_eq!(r, expect);
+        }
+        // 通过Query设置，并迭代修改
+        let mut m = world.make_query::<(&mut Age0, &Age1), ()>();
+        let mut p = m.get_param(&world);
+        p.prefetch_hint(8);
+        for (mut a, b) in p.iter_mut() {
+            a.0 += b.0;
+        }
+        drop(p);
+        let r: Vec<_> = q.iter(&world).map(|(_, a, _)| a.0).collect();
+        let e: Vec<_> = expect.iter().map(|(_, a, _)| a * 3).collect();
+        assert_eq!(r, e);
+    }
+    fn bench_prefetch(b: &mut Bencher, distance: usize) {
+        let mut world = World::new();
+        let i = world.make_insert::<(Age12, Age13, Age14, Age15)>();
+        for _ in 0..1_000_000 {
+            i.insert(&world, (Age12::default(), Age13::default(), Age14::default(), Age15::default()));
+        }
+        world.settle();
+        let mut q = world.make_query::<(&Age12, &Age13, &Age14, &mut Age15), ()>();
+        q.set_prefetch_hint(&world, distance);
+        b.iter(|| {
+            for (x, y, z, mut w) in q.get_param(&world).iter_mut() {
+                w.0[0] += x.0[0] + y.0[1] + z.0[2];
+            }
+        });
+    }
+    #[bench]
+    fn bench_prefetch_off(b: &mut Bencher) {
+        bench_prefetch(b, 0);
+    }
+    #[bench]
+    fn bench_prefetch_4(b: &mut Bencher) {
+        bench_prefetch(b, 4);
+    }
+    #[bench]
+    fn bench_prefetch_16(b: &mut Bencher) {
+        bench_prefetch(b, 16);
+    }
+    #[bench]
+    fn bench_prefetch_64(b: &mut Bencher) {
+        bench_prefetch(b, 64);
+    }
+    #[test]
+    fn test_required_components() {
+        #[derive(Component, Debug, Default, Clone, Copy, PartialEq)]
+        struct Mesh(u32);
+        #[derive(Component, Debug, Default, Clone, Copy, PartialEq)]
+        struct Aabb(u32);
+        #[derive(Component, Debug, Default, Clone, Copy, PartialEq)]
+        struct Visibility(bool);
+        #[derive(Component, Debug, Default, Clone, Copy, PartialEq)]
+        struct Bounds(u32);
+        #[derive(Component, Debug, Default, Clone, Copy, PartialEq)]
+        struct Transform(u32);
+        let mut world = World::new();
+        world.register_required_components::<Mesh, (Aabb, Visibility)>();
+        world.register_required_components::<Aabb, (Bounds,)>();
+        assert_eq!(world.required_components::<Mesh>().len(), 2);
+        let mut q = world.make_query::<(Entity, &Mesh), (Added<Aabb>, Added<Bounds>)>();
+        world.increment_tick();
+        // 插入时传递补上依赖
+        let i = world.make_insert::<(Mesh,)>();
+        let e1 = i.insert(&world, (Mesh(1),));
+        // 显式提供的依赖组件使用提供的值
+        let i = world.make_insert::<(Mesh, Aabb)>();
+        let e2 = i.insert(&world, (Mesh(2), Aabb(5)));
+        assert_eq!(world.get_entity_location(e1).unwrap().archetype_index, world.get_entity_location(e2).unwrap().archetype_index);
+        world.settle();
+        assert_eq!(world.get_component::<Aabb>(e1), Ok(&Aabb(0)));
+        assert_eq!(world.get_component::<Visibility>(e1), Ok(&Visibility(false)));
+        assert_eq!(world.get_component::<Bounds>(e1), Ok(&Bounds(0)));
+        assert_eq!(world.get_component::<Aabb>(e2), Ok(&Aabb(5)));
+        assert_eq!(world.get_component::<Bounds>(e2), Ok(&Bounds(0)));
+        // 补上的依赖组件记录了添加tick
+        assert_eq!(q.iter(&world).count(), 2);
+        // Alter添加Mesh时补上依赖
+        let e3 = world.make_insert::<(Transform,)>().insert(&world, (Transform(3),));
+        world.settle();
+        let mut alter = world.make_alter::<(), With<Transform>, (Mesh,), ()>();
+        world.increment_tick();
+        alter.get_param(&world).alter(e3, (Mesh(3),)).unwrap();
+        world.settle();
+        assert_eq!(world.get_component::<Transform>(e3), Ok(&Transform(3)));
+        assert_eq!(world.get_component::<Mesh>(e3), Ok(&Mesh(3)));
+        assert_eq!(world.get_component::<Visibility>(e3), Ok(&Visibility(false)));
+        assert_eq!(world.get_component::<Bounds>(e3), Ok(&Bounds(0)));
+        // 移除Mesh不会移除依赖
+        let mut alter = world.make_alter::<(), With<Mesh>, (), (Mesh,)>();
+        alter.get_param(&world).alter(e3, ()).unwrap();
+        world.settle();
+        assert!(world.get_component::<Mesh>(e3).is_err());
+        assert_eq!(world.get_component::<Aabb>(e3), Ok(&Aabb(0)));
+        assert_eq!(world.get_component::<Visibility>(e3), Ok(&Visibility(false)));
+    }
+    #[test]
+    #[should_panic(expected = "required components cycle")]
+    fn test_required_components_cycle() {
+        #[derive(Component, Default)]
+        struct A0;
+        #[derive(Component, Default)]
+        struct A1;
+        #[derive(Component, Default)]
+        struct A2;
+        let mut world = World::new();
+        world.register_required_components::<A0, (A1,)>();
+        world.register_required_components::<A1, (A2,)>();
+        world.register_required_components::<A2, (A0,)>();
+    }
+    #[test]
+    fn test_frame_scoped() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use crate::system_params::SystemParam;
+        use crate::transient::TransientColumn;
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        static SEEN: AtomicUsize = AtomicUsize::new(0);
+        struct Damage(usize);
+        impl Drop for Damage {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        // 伤害system添加帧内组件
+        fn damage(t: Transient<Damage>, q: Query<(Entity, &Age0)>) {
+            for (e, a) in q.iter() {
+                match a.0 {
+                    1 => t.insert(e, Damage(10)).unwrap(),
+                    3 => {
+                        t.insert(e, Damage(5)).unwrap();
+                        t.insert(e, Damage(7)).unwrap();
+                        assert_eq!(t.get(e).unwrap().0, 7);
+                    }
+                    _ => assert!(!t.contains(e)),
+                }
+            }
+            assert!(t.insert(Entity::null(), Damage(1)).is_err());
+            assert_eq!(t.iter().count(), 2);
+        }
+        // 反应system读取本帧的帧内组件
+        fn react(q: Query<(Entity, FrameScoped<Damage>), WithFrameScoped<Damage>>) {
+            for (_, d) in q.iter() {
+                SEEN.fetch_add(d.unwrap().0, Ordering::Relaxed);
+            }
+        }
+        let mut app = crate::prelude::App::new();
+        assert!(app.world.set_tick_policy::<Damage>(crate::column::TickPolicy::frame_scoped()));
+        let i = app.world.make_insert::<(Age0,)>();
+        let e1 = i.insert(&app.world, (Age0(1),));
+        let e2 = i.insert(&app.world, (Age0(2),));
+        i.insert(&app.world, (Age0(3),));
+        app.world.settle();
+        let ar_len = app.world.archetype_arr.len();
+        app.add_system(Update, damage);
+        app.add_system(Update, react.after(damage));
+        app.run();
+        assert_eq!(SEEN.load(Ordering::Relaxed), 17);
+        // 不产生结构变化
+        assert_eq!(app.world.archetype_arr.len(), ar_len);
+        assert_eq!(
+            app.world.get_entity_location(e1).unwrap().archetype_index,
+            app.world.get_entity_location(e2).unwrap().archetype_index
+        );
+        // 帧末整理后全部清除，Entity::null()插入失败的值被丢弃
+        assert_eq!(app.world.transients_cleared(), 3);
+        assert_eq!(DROPS.load(Ordering::Relaxed), 4);
+        assert!(app.world.get_transient::<Damage>().unwrap().is_empty());
+        assert_eq!(app.world.get_component::<Age0>(e1).unwrap().0, 1);
+        // 下一帧重新产生
+        app.run();
+        assert_eq!(SEEN.load(Ordering::Relaxed), 34);
+        assert_eq!(DROPS.load(Ordering::Relaxed), 8);
+
+        // 写帧内组件和读帧内组件的system有冲突
+        let world = &mut app.world;
+        let mut m1 = SystemMeta::new(TypeInfo::of::<u8>());
+        let _ = <Transient<Damage> as SystemParam>::init_state(world, &mut m1);
+        let mut m2 = SystemMeta::new(TypeInfo::of::<u16>());
+        let _ = <Query<FrameScoped<Damage>> as SystemParam>::init_state(world, &mut m2);
+        let mut m3 = SystemMeta::new(TypeInfo::of::<u32>());
+        let _ = <Query<Entity, WithFrameScoped<Damage>> as SystemParam>::init_state(world, &mut m3);
+        let id = TypeId::of::<TransientColumn<Damage>>();
+        assert_eq!(m1.res_related.check_rw(&m2.res_related), Some(id));
+        assert_eq!(m1.res_related.check_rw(&m3.res_related), Some(id));
+        assert_eq!(m2.res_related.check_rw(&m3.res_related), None);
+    }
+    #[test]
+    fn test_align_snapshot() {
+        type Q1<'a> = Query<'a, &'static Age0>;
+        type Q2<'a> = Query<'a, (&'static Age0, &'static Age2)>;
+        let mut world = World::new();
+        let mut meta = SystemMeta::new(TypeInfo::of::<()>());
+        let mut s1 = Q1::init_state(&mut world, &mut meta);
+        let mut s2 = Q2::init_state(&mut world, &mut meta);
+        let e1 = world.make_insert::<(Age0, Age2)>().insert(&world, (Age0(1), Age2(1)));
+        // 本次运行开始时记录原型数量
+        meta.snapshot_archetypes(&world);
+        Q1::align(&world, &meta, &mut s1);
+        // 两个查询对齐之间创建了新原型，比如监听器的副作用
+        let e2 = world
+            .make_insert::<(Age0, Age1, Age2)>()
+            .insert(&world, (Age0(2), Age1(2), Age2(2)));
+        Q2::align(&world, &meta, &mut s2);
+        {
+            let tick = world.increment_tick();
+            let q1 = Q1::get_param(&world, &meta, &mut s1, tick);
+            let q2 = Q2::get_param(&world, &meta, &mut s2, tick);
+            // 两个查询都看不到新原型，第一个查询遍历出的实体都可以从第二个查询获得
+            assert_eq!(q1.iter().count(), 1);
+            for a in q1.iter() {
+                assert_eq!(a.0, 1);
+            }
+            assert!(q2.get(e1).is_ok());
+            assert!(q1.get(e2).is_err());
+            assert!(q2.get(e2).is_err());
+        }
+        // 下次运行两个查询都看到新原型
+        meta.snapshot_archetypes(&world);
+        Q1::align(&world, &meta, &mut s1);
+        Q2::align(&world, &meta, &mut s2);
+        let tick = world.increment_tick();
+        let q1 = Q1::get_param(&world, &meta, &mut s1, tick);
+        let q2 = Q2::get_param(&world, &meta, &mut s2, tick);
+        assert_eq!(q1.iter().count(), 2);
+        assert_eq!(q2.get(e2).unwrap().1 .0, 2);
+        assert_eq!(q1.get(e2).unwrap().0, 2);
+    }
+    #[test]
+    fn test_structural_changes() {
+        use crate::archetype::ArchetypeIndex;
+        use crate::table::{StructuralChange, StructuralCursor};
+        // 按结构变化重建行 -> 实体
+        fn replay(world: &World, index: ArchetypeIndex, cursor: &mut StructuralCursor, layout: &mut Vec<Entity>) {
+            let ar = world.get_archetype(index).unwrap();
+            for c in ar.structural_changes(cursor) {
+                match c {
+                    StructuralChange::RowAllocated(row, e) => {
+                        if layout.len() <= row.index() {
+                            layout.resize(row.index() + 1, Entity::null());
+                        }
+                        layout[row.index()] = e;
+                    }
+                    StructuralChange::RowDestroyed(row, e) => {
+                        assert_eq!(layout[row.index()], e);
+                        layout[row.index()] = Entity::null();
+                    }
+                    StructuralChange::RowMoved { from, to, entity } => {
+                        assert_eq!(layout[from.index()], entity);
+                        layout[from.index()] = Entity::null();
+                        layout[to.index()] = entity;
+                    }
+                    StructuralChange::RowSwapped { a, b } => layout.swap(a.index(), b.index()),
+                }
+            }
+            while layout.last().map_or(false, |e| e.is_null()) {
+                layout.pop();
+            }
+        }
+        fn table_layout(world: &World, index: ArchetypeIndex) -> Vec<Entity> {
+            let ar = world.get_archetype(index).unwrap();
+            (0..ar.len().index()).map(|r| ar.get_unchecked(Row(r as u32))).collect()
+        }
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0, Age1)>();
+        let e0 = i.insert(&world, (Age0(0), Age1(0)));
+        world.settle();
+        let index = world.get_entity_location(e0).unwrap().archetype_index;
+        let mut cursor = world.structural_cursor(index, "render").unwrap();
+        let mut layout = vec![e0];
+        let mut entities = vec![e0];
+        for n in 1..10 {
+            entities.push(i.insert(&world, (Age0(n), Age1(n))));
+        }
+        for n in [2, 5, 9] {
+            world.destroy_entity(entities[n]).unwrap();
+        }
+        replay(&world, index, &mut cursor, &mut layout);
+        assert_eq!(layout.len(), 9);
+        assert!(layout[2].is_null() && layout[5].is_null());
+        // 整理移动了行
+        world.settle();
+        replay(&world, index, &mut cursor, &mut layout);
+        assert_eq!(layout, table_layout(&world, index));
+        // 大量移除集中在前部
+        for n in 10..20 {
+            entities.push(i.insert(&world, (Age0(n), Age1(n))));
+        }
+        for n in [0, 1, 3, 4, 6, 7, 8, 10, 11] {
+            world.destroy_entity(entities[n]).unwrap();
+        }
+        world.settle();
+        replay(&world, index, &mut cursor, &mut layout);
+        assert_eq!(layout, table_layout(&world, index));
+        assert_eq!(layout.len(), 8);
+        // 交换两个实体的行
+        world.swap_entities(layout[1], layout[6]).unwrap();
+        replay(&world, index, &mut cursor, &mut layout);
+        assert_eq!(layout, table_layout(&world, index));
+        // 没有新的变化
+        world.settle();
+        let ar = world.get_archetype(index).unwrap();
+        assert_eq!(ar.structural_changes(&mut cursor).count(), 0);
+    }
+    // 检查移动对：源是尾部存活的行，目标是前面的空位，互不重复，移动后没有空位
+    fn check_removes_action(removes: &[usize], entity_len: usize, len: usize, action: &Vec<(Row, Row)>) {
+        assert_eq!(len, entity_len - removes.len().min(entity_len));
+        let holes = removes.iter().filter(|r| **r < len).count();
+        assert_eq!(action.len(), holes, "{:?}", action);
+        let mut layout: Vec<bool> = (0..entity_len).map(|r| !removes.contains(&r)).collect();
+        for (src, dst) in action.iter() {
+            assert!(src.index() >= len && layout[src.index()], "{:?}", action);
+            assert!(dst.index() < len && !layout[dst.index()], "{:?}", action);
+            layout[src.index()] = false;
+            layout[dst.index()] = true;
+        }
+        assert!(layout[0..len].iter().all(|b| *b));
+    }
+    #[test]
+    fn test_removes_strategy() {
+        use crate::table::RemovesStrategy;
+        let mut action = Default::default();
+        let mut set: FixedBitSet = Default::default();
+        let mut rng = rand::thread_rng();
+        let strategies = [
+            RemovesStrategy::Auto,
+            RemovesStrategy::SwapTail,
+            RemovesStrategy::Sorted,
+            RemovesStrategy::Bitset,
+        ];
+        for _ in 0..500 {
+            let size = rng.gen_range(1..200);
+            let mut vec = Vec::new();
+            // 均匀分布、集中在前部、集中在尾部
+            let (lo, hi) = match rng.gen_range(0..3) {
+                0 => (0, size),
+                1 => (0, (size / 4).max(1)),
+                _ => (size - (size / 4).max(1), size),
+            };
+            for _ in 0..rng.gen_range(0..size) {
+                let x = rng.gen_range(lo..hi);
+                if !vec.contains(&x) {
+                    vec.push(x);
+                }
+            }
+            let mut removes: AppendVec<Row> = Default::default();
+            for x in vec.iter() {
+                removes.insert((*x).into());
+            }
+            for s in strategies {
+                let len = Table::removes_action_with(&removes, removes.len(), size, &mut action, &mut set, s);
+                check_removes_action(&vec, size, len, &action);
+            }
+        }
+    }
+    // 在1百万行的table上计算移除的移动对，front为true时移除集中在前部
+    fn bench_removes(b: &mut Bencher, strategy: crate::table::RemovesStrategy, front: bool) {
+        let size = 1_000_000;
+        let mut action = Vec::new();
+        let mut set: FixedBitSet = Default::default();
+        let mut removes: AppendVec<Row> = Default::default();
+        let mut rng = rand::thread_rng();
+        let mut marks = FixedBitSet::with_capacity(size);
+        while removes.len() < 10_000 {
+            let x = if front { rng.gen_range(0..20_000) } else { rng.gen_range(0..size) };
+            if !marks.contains(x) {
+                marks.set(x, true);
+                removes.insert(x.into());
+            }
+        }
+        b.iter(|| {
+            Table::removes_action_with(&removes, removes.len(), size, &mut action, &mut set, strategy)
+        });
+    }
+    #[bench]
+    fn bench_removes_front_auto(b: &mut Bencher) {
+        bench_removes(b, crate::table::RemovesStrategy::Auto, true);
+    }
+    #[bench]
+    fn bench_removes_front_swap_tail(b: &mut Bencher) {
+        bench_removes(b, crate::table::RemovesStrategy::SwapTail, true);
+    }
+    #[bench]
+    fn bench_removes_front_sorted(b: &mut Bencher) {
+        bench_removes(b, crate::table::RemovesStrategy::Sorted, true);
+    }
+    #[bench]
+    fn bench_removes_front_bitset(b: &mut Bencher) {
+        bench_removes(b, crate::table::RemovesStrategy::Bitset, true);
+    }
+    #[bench]
+    fn bench_removes_random_swap_tail(b: &mut Bencher) {
+        bench_removes(b, crate::table::RemovesStrategy::SwapTail, false);
+    }
+    #[bench]
+    fn bench_removes_random_sorted(b: &mut Bencher) {
+        bench_removes(b, crate::table::RemovesStrategy::Sorted, false);
+    }
+    #[test]
+    fn test_freeze_ticks() {
+        fn inspect(q: Query<&Age0>) -> usize {
+            q.iter().count()
+        }
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0,)>();
+        let e1 = i.insert(&world, (Age0(1),));
+        let e2 = i.insert(&world, (Age0(2),));
+        let mut changed = world.make_query::<Entity, Changed<Age0>>();
+        let mut w = world.make_query::<&mut Age0, ()>();
+        world.increment_tick();
+        changed.get_param(&world).iter().count();
+        assert_eq!(changed.last_run(), world.tick());
+        let last_run = changed.last_run();
+        world.increment_tick();
+        // 冻结后tick不递增
+        world.freeze_ticks();
+        let tick = world.tick();
+        for _ in 0..3 {
+            assert_eq!(world.increment_tick(), tick);
+        }
+        assert_eq!(world.tick(), tick);
+        // 检查用的system
+        assert_eq!(world.run_frozen(inspect), 2);
+        assert!(world.is_ticks_frozen());
+        // 冻结期间消费者的last_run不推进
+        assert_eq!(changed.get_param(&world).iter().count(), 0);
+        assert_eq!(changed.last_run(), last_run);
+        world.unfreeze_ticks();
+        // 只有解冻后的修改被检测到
+        world.increment_tick();
+        w.get_mut(&mut world, e2).unwrap().0 = 20;
+        let r: Vec<Entity> = changed.get_param(&world).iter().collect();
+        assert_eq!(r, vec![e2]);
+        assert_eq!(changed.get_param(&world).iter().count(), 0);
+        // run_frozen在未冻结时运行，运行后恢复
+        assert_eq!(world.run_frozen(inspect), 2);
+        assert!(!world.is_ticks_frozen());
+        assert_eq!(world.get_component::<Age0>(e1).unwrap().0, 1);
+    }
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn test_ffi() {
+        use crate::ffi::*;
+        use std::ffi::c_void;
+        // 读Age0，写Age1，把Age0加到Age1上
+        extern "C" fn add(_e: u64, ptrs: *const *mut u8, user_data: *mut c_void) {
+            unsafe {
+                let a0 = &*(*ptrs as *const Age0);
+                let a1 = &mut *(*ptrs.add(1) as *mut Age1);
+                a1.0 += a0.0;
+                *(user_data as *mut usize) += 1;
+            }
+        }
+        let mut world = World::new();
+        // C侧只能看到注册了稳定名称的组件
+        let id = |world: &World, index: ComponentIndex| world.get_column(index).unwrap().info().stable_id();
+        let index = world.init_component::<Age4>();
+        let id4 = id(&world, index);
+        let index = world.register_stable_name::<Age0>("ffi.age0");
+        let id0 = id(&world, index);
+        let index = world.register_stable_name::<Age1>("ffi.age1");
+        let id1 = id(&world, index);
+        let index = world.register_stable_name::<Age2>("ffi.age2");
+        let id2 = id(&world, index);
+        // Age2依赖Age3，C侧创建时也会补上
+        world.register_required_components::<Age2, (Age3,)>();
+        let h = WorldHandle::into_raw(world);
+        unsafe {
+            let (a0, a1, a2) = (Age0(10), Age1(1), Age2(0));
+            let values = [&a0 as *const Age0 as *const u8, &a1 as *const Age1 as *const u8, &a2 as *const Age2 as *const u8];
+            let e1 = pi_world_spawn(h, [id0, id1].as_ptr(), values.as_ptr(), 2);
+            let e2 = pi_world_spawn(h, [id0, id1, id2].as_ptr(), values.as_ptr(), 3);
+            assert_ne!(e1, pi_entity_null());
+            assert!(pi_world_contains(h, e2));
+            let world = WorldHandle::world(h);
+            let mut q3 = world.make_query::<(&Age2, &Age3), ()>();
+            assert_eq!(q3.iter(world).map(|(_, a)| a.0).collect::<Vec<_>>(), vec![0]);
+            // 未注册的组件、没有稳定名称的组件和重复的组件
+            assert_eq!(pi_world_spawn(h, [12345].as_ptr(), values.as_ptr(), 1), pi_entity_null());
+            assert_eq!(pi_world_spawn(h, [id4].as_ptr(), values.as_ptr(), 1), pi_entity_null());
+            assert_eq!(pi_world_spawn(h, [id0, id0].as_ptr(), values.as_ptr(), 2), pi_entity_null());
+            let mut q = std::ptr::null_mut();
+            let r = pi_query_create(h, [id0].as_ptr(), 1, [id1].as_ptr(), 1, std::ptr::null(), 0, [id2].as_ptr(), 1, &mut q);
+            assert_eq!(r, 0);
+            let mut count = 0usize;
+            assert_eq!(pi_query_iter(h, q, add, &mut count as *mut usize as *mut c_void), 0);
+            assert_eq!(count, 1);
+            let world = WorldHandle::world(h);
+            let mut q1 = world.make_query::<&Age1, Without<Age2>>();
+            assert_eq!(q1.iter(world).map(|a| a.0).collect::<Vec<_>>(), vec![11]);
+            assert!(pi_world_contains(h, e1));
+            assert_eq!(pi_world_despawn(h, e2), 0);
+            assert_eq!(pi_world_despawn(h, e2), PiWorldError::NoSuchEntity as i32);
+            assert!(!pi_world_contains(h, e2));
+            pi_world_settle(h);
+            pi_query_destroy(q);
+            pi_world_destroy(h);
+        }
+    }
+    #[cfg(feature = "ffi")]
+    #[test]
+    fn test_ffi_c() {
+        use crate::ffi::*;
+        // build.rs编译的tests/c/ffi_smoke.c，按include/pi_world.h调用C接口
+        extern "C" {
+            fn pi_world_ffi_smoke(handle: *mut WorldHandle, pos_id: u64, vel_id: u64) -> i32;
+        }
+        let mut world = World::new();
+        let pos = world.register_stable_name::<Age0>("smoke.pos");
+        let vel = world.register_stable_name::<Age1>("smoke.vel");
+        let pos_id = world.get_column(pos).unwrap().info().stable_id();
+        let vel_id = world.get_column(vel).unwrap().info().stable_id();
+        let h = WorldHandle::into_raw(world);
+        unsafe {
+            // 失败时返回C文件中出错的行号
+            assert_eq!(pi_world_ffi_smoke(h, pos_id, vel_id), 0);
+            let world = WorldHandle::world(h);
+            let mut q = world.make_query::<&Age0, ()>();
+            let mut v: Vec<usize> = q.iter(world).map(|a| a.0).collect();
+            v.sort();
+            assert_eq!(v, vec![2, 26]);
+            pi_world_destroy(h);
+        }
+    }
+    // 64个原型，每个原型rows行
+    fn multi_archetype_world(rows: usize) -> World {
+        let mut world = World::new();
+        let base = world.init_component::<Age0>();
+        let markers = [
+            world.init_component::<Age1>(), world.init_component::<Age2>(), world.init_component::<Age3>(),
+            world.init_component::<Age4>(), world.init_component::<Age6>(), world.init_component::<Age9>(),
+        ];
+        let mut editor = world.make_entity_editor();
+        for mask in 0..64usize {
+            let mut vec = vec![base];
+            vec.extend(markers.iter().enumerate().filter(|(i, _)| mask & (1 << i) != 0).map(|(_, c)| *c));
+            editor.insert_entities_by_index(&vec, rows).unwrap();
+        }
+        world.settle();
+        world
+    }
+    #[test]
+    fn test_multi_archetype_iter() {
+        let mut world = multi_archetype_world(100);
+        let mut q = world.make_query::<Entity, ()>();
+        let mut qw = world.make_query::<Entity, With<Age9>>();
+        assert_eq!(q.iter(&world).count(), 6400);
+        assert_eq!(qw.iter(&world).count(), 3200);
+        // 中间的原型被清空后，迭代跳过空原型
+        let vec: Vec<Entity> = qw.iter(&world).collect();
+        for e in vec {
+            world.destroy_entity(e).unwrap();
+        }
+        world.settle();
+        assert_eq!(q.iter(&world).count(), 3200);
+        assert_eq!(qw.iter(&world).count(), 0);
+    }
+    #[bench]
+    fn bench_multi_archetype_iter(b: &mut Bencher) {
+        let mut world = multi_archetype_world(10_000);
+        let mut q = world.make_query::<&mut Age0, ()>();
+        b.iter(|| {
+            for mut a in q.get_param(&world).iter_mut() {
+                a.0 += 1;
+            }
+        });
+    }
+    #[cfg(feature = "debug-names")]
+    #[test]
+    fn test_debug_names() {
+        use crate::debug_name::NameOf;
+        let mut world = World::new();
+        let mut q = world.make_query::<(Entity, NameOf), ()>();
+        let mut qc = world.make_query::<Entity, Changed<Age0>>();
+        let i = world.make_insert::<(Age0,)>();
+        let e1 = i.insert(&world, (Age0(1),));
+        let e2 = i.insert(&world, (Age0(2),));
+        let e3 = i.insert(&world, (Age0(3),));
+        world.settle();
+        world.set_entity_name(e1, "player".into()).unwrap();
+        world.set_entity_name(e2, format!("enemy{}", 2).into()).unwrap();
+        assert_eq!(world.entity_name(e1), Some("player"));
+        assert_eq!(world.entity_name(e3), None);
+        assert_eq!(world.entity_names_len(), 2);
+        let mut names: Vec<_> = q.iter(&world).map(|(e, n)| (e, n.map(|n| n.to_string()))).collect();
+        names.sort_by_key(|(e, _)| world.get_component::<Age0>(*e).unwrap().0);
+        assert_eq!(names, vec![(e1, Some("player".to_string())), (e2, Some("enemy2".to_string())), (e3, None)]);
+        // 名称按实体保存，改变原型不影响
+        world.make_entity_editor().add_components(e1, (Age1(1),)).unwrap();
+        world.settle();
+        assert_eq!(world.entity_name(e1), Some("player"));
+        // 错误信息带上名称
+        world.increment_tick();
+        drop(qc.get_param(&world));
+        world.increment_tick();
+        let err = qc.get(&world, e2).unwrap_err();
+        assert!(err.describe(&world).contains("enemy2"));
+        assert_eq!(world.entity_label(e3), format!("{:?}", e3));
+        // 直接销毁立即回收
+        world.destroy_entity(e1).unwrap();
+        assert_eq!(world.entity_names_len(), 1);
+        // 通过Alter销毁，整理时回收
+        let mut alter = world.make_alter::<(), (), (), ()>();
+        alter.get_param(&world).destroy(e2).unwrap();
+        assert_eq!(world.entity_name(e2), None);
+        assert_eq!(world.entity_names_len(), 1);
+        world.settle();
+        assert_eq!(world.entity_names_len(), 0);
+        assert!(world.set_entity_name(e2, "dead".into()).is_err());
+    }
+    #[test]
+    fn test_cleanup_policy() {
+        use std::sync::{Arc, Mutex};
+        #[derive(Component)]
+        struct Tracked(usize, Arc<Mutex<Vec<usize>>>);
+        impl Drop for Tracked {
+            fn drop(&mut self) {
+                self.1.lock().unwrap().push(self.0);
+            }
+        }
+        for policy in [CleanupPolicy::PerSystemAfter, CleanupPolicy::EndOfFrame] {
+            let log = Arc::new(Mutex::new(Vec::new()));
+            let mut world = World::new();
+            world.set_cleanup_policy(policy);
+            let i = world.make_insert::<(Age0, Tracked)>();
+            let entities: Vec<_> = (0..4).map(|n| i.insert(&world, (Age0(n), Tracked(n, log.clone())))).collect();
+            world.settle();
+            let mut alter = world.make_alter::<(), (), (), (Tracked,)>();
+            let mut a = alter.get_param(&world);
+            a.alter(entities[2], ()).unwrap();
+            a.alter(entities[0], ()).unwrap();
+            assert!(log.lock().unwrap().is_empty());
+            drop(a);
+            if policy == CleanupPolicy::PerSystemAfter {
+                // system结束时按修改的顺序释放
+                assert_eq!(*log.lock().unwrap(), vec![2, 0]);
+                assert_eq!(world.pending_cleanup_len(), 0);
+            } else {
+                // 帧末才释放，按行的顺序
+                assert!(log.lock().unwrap().is_empty());
+                assert_eq!(world.pending_cleanup_len(), 2);
+                assert_eq!(world.cleanup(), 2);
+                assert_eq!(*log.lock().unwrap(), vec![0, 2]);
+            }
+            world.settle();
+            assert_eq!(world.pending_cleanup_len(), 0);
+            assert_eq!(log.lock().unwrap().len(), 2);
+            assert_eq!(world.get_component::<Age0>(entities[0]).unwrap().0, 0);
+            assert!(world.get_component::<Tracked>(entities[0]).is_err());
+            assert_eq!(world.get_component::<Tracked>(entities[3]).unwrap().0, 3);
+            // 销毁实体的组件也按释放策略释放
+            world.destroy_entity(entities[3]).unwrap();
+            if policy == CleanupPolicy::PerSystemAfter {
+                assert_eq!(log.lock().unwrap().len(), 3);
+            } else {
+                assert_eq!(log.lock().unwrap().len(), 2);
+                assert_eq!(world.pending_cleanup_len(), 1);
+            }
+            // 未整理就通过shutdown销毁world，队列中的组件也会释放
+            alter.get_param(&world).alter(entities[1], ()).unwrap();
+            drop(alter);
+            world.shutdown();
+            let mut v = log.lock().unwrap().clone();
+            v.sort();
+            assert_eq!(v, vec![0, 1, 2, 3]);
+        }
+    }
+    #[test]
+    fn test_extract_view() {
+        let mut world = World::new();
+        // Changed让Age0记录修改tick
+        let mut qc = world.make_query::<Entity, Changed<Age0>>();
+        let mut qm = world.make_query::<&mut Age0, ()>();
+        let a0 = world.init_component::<Age0>();
+        let a1 = world.init_component::<Age1>();
+        let a8 = world.init_component::<Age8>();
+        let i = world.make_insert::<(Age0, Age1)>();
+        let e1 = i.insert(&world, (Age0(1), Age1(10)));
+        let e2 = i.insert(&world, (Age0(2), Age1(20)));
+        let e3 = i.insert(&world, (Age0(3), Age1(30)));
+        let e4 = world.make_insert::<(Age0, Age2)>().insert(&world, (Age0(4), Age2(40)));
+        let e5 = world.make_insert::<(Age0, Age1, Age2)>().insert(&world, (Age0(5), Age1(50), Age2(50)));
+        world.settle();
+        qc.align(&world);
+        qm.align(&world);
+        let since = world.tick();
+        world.increment_tick();
+        qm.get_param(&world).get_mut(e2).unwrap().0 = 22;
+        let view = world.extract_view(&[a0, a1]).unwrap();
+        assert_eq!(view.len(), 4);
+        assert_eq!(view.archetypes().len(), 2);
+        let mut r: Vec<_> = view.iter::<Age0>().map(|(e, a)| (e, a.0)).collect();
+        r.sort();
+        let mut expect = vec![(e1, 1), (e2, 22), (e3, 3), (e5, 5)];
+        expect.sort();
+        assert_eq!(r, expect);
+        assert_eq!(view.iter::<Age1>().count(), 4);
+        assert_eq!(view.iter::<Age2>().count(), 0);
+        // 修改记录和复制前一致，不记录tick的组件返回全部实体
+        assert_eq!(view.changed_since(a0, since), vec![e2]);
+        assert_eq!(view.changed_since(a1, since).len(), 4);
+        // 修改world不影响视图
+        qm.get_param(&world).get_mut(e1).unwrap().0 = 100;
+        world.destroy_entity(e3).unwrap();
+        world.settle();
+        let mut r2: Vec<_> = view.iter::<Age0>().map(|(e, a)| (e, a.0)).collect();
+        r2.sort();
+        assert_eq!(r2, r);
+        // 可以在其他线程读取
+        let handle = std::thread::spawn(move || view.iter::<Age1>().map(|(_, a)| a.0).sum::<usize>());
+        assert_eq!(handle.join().unwrap(), 110);
+        // 并行复制的结果一致
+        let view = world.extract_view(&[a0, a1]).unwrap();
+        let par = world.extract_view_par(&[a0, a1], 4).unwrap();
+        let a: Vec<_> = view.iter::<Age0>().map(|(e, a)| (e, a.0)).collect();
+        let b: Vec<_> = par.iter::<Age0>().map(|(e, a)| (e, a.0)).collect();
+        assert_eq!(a, b);
+        assert_eq!(a.len(), 3);
+        assert!(!a.contains(&(e4, 4)));
+        // 组件比线程多时，线程间分配各列，列的顺序不变
+        let a2 = world.init_component::<Age2>();
+        let par = world.extract_view_par(&[a2, a0, a1], 2).unwrap();
+        assert_eq!(par.components(), &[a2, a0, a1]);
+        assert_eq!(par.iter::<Age2>().map(|(_, a)| a.0).collect::<Vec<_>>(), vec![50]);
+        assert_eq!(par.iter::<Age1>().map(|(_, a)| a.0).collect::<Vec<_>>(), vec![50]);
+        // 需要释放的组件和对齐超过16字节的组件不支持
+        assert_eq!(world.extract_view(&[a0, a8]).err(), Some(ExtractError::NeedsDrop(a8)));
+        #[derive(Component, Clone, Copy)]
+        #[repr(align(32))]
+        struct Aligned32(u8);
+        let a32 = world.init_component::<Aligned32>();
+        assert_eq!(world.extract_view(&[a32]).err(), Some(ExtractError::Overaligned(a32)));
+        assert_eq!(world.extract_view_par(&[a0, a32], 2).err(), Some(ExtractError::Overaligned(a32)));
+    }
+    #[test]
+    fn test_fetch_init_error() {
+        use crate::archetype::ArchetypeIndex;
+        use crate::fetch::FetchComponents;
+        // 拒绝包含Age2的原型
+        struct RejectAge2;
+        impl FetchComponents for RejectAge2 {
+            type Fetch<'w> = ();
+            type Item<'w> = ();
+            type ReadOnly = Self;
+            type State = ComponentIndex;
+
+            fn init_state(world: &mut World, _meta: &mut SystemMeta) -> Self::State {
+                world.init_component::<Age2>()
+            }
+            fn init_fetch<'w>(
+                _world: &'w World,
+                _state: &'w Self::State,
+                _index: ArchetypeIndex,
+                _tick: Tick,
+                _last_run: Tick,
+            ) -> Self::Fetch<'w> {
+            }
+            fn fetch<'w>(_fetch: &Self::Fetch<'w>, _row: Row, _e: Entity) -> Self::Item<'w> {}
+            fn try_init_archetype(
+                _world: &World,
+                state: &Self::State,
+                ar: &Archetype,
+            ) -> Result<(), FetchInitError> {
+                if ar.contains(*state) {
+                    return Err(FetchInitError::Unavailable("age2".into()));
+                }
+                Ok(())
+            }
+        }
+        let mut world = World::new();
+        let mut q = world.make_query::<(Entity, &Age0, RejectAge2), ()>();
+        let e1 = world.make_insert::<(Age0,)>().insert(&world, (Age0(1),));
+        let e2 = world.make_insert::<(Age0, Age1)>().insert(&world, (Age0(2), Age1(2)));
+        let e3 = world.make_insert::<(Age0, Age2)>().insert(&world, (Age0(3), Age2(3)));
+        world.settle();
+        let mut r: Vec<_> = q.iter(&world).map(|(e, a, _)| (e, a.0)).collect();
+        r.sort();
+        let mut expect = vec![(e1, 1), (e2, 2)];
+        expect.sort();
+        assert_eq!(r, expect);
+        assert_eq!(q.init_warnings().len(), 1);
+        let ar_index = world.get_entity_location(e3).unwrap().archetype_index;
+        assert_eq!(q.init_warnings()[0], (ar_index, FetchInitError::Unavailable("age2".into())));
+        assert_eq!(q.get(&world, e3).err(), Some(QueryError::NoMatchArchetype));
+        // 之后新增的原型同样检查
+        world.make_insert::<(Age0, Age2, Age3)>().insert(&world, (Age0(4), Age2(4), Age3(4)));
+        world.make_insert::<(Age0, Age3)>().insert(&world, (Age0(5), Age3(5)));
+        world.settle();
+        assert_eq!(q.iter(&world).count(), 3);
+        assert_eq!(q.init_warnings().len(), 2);
+    }
+    #[test]
+    fn test_system_builder() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static INITS: AtomicUsize = AtomicUsize::new(0);
+        // 记录init_state调用次数的参数
+        struct InitCounter;
+        impl SystemParam for InitCounter {
+            type State = ();
+            type Item<'w> = InitCounter;
+
+            fn init_state(_world: &mut World, _meta: &mut SystemMeta) -> Self::State {
+                INITS.fetch_add(1, Ordering::Relaxed);
+            }
+            fn get_param<'w>(
+                _world: &'w World,
+                _meta: &'w SystemMeta,
+                _state: &'w mut Self::State,
+                _tick: Tick,
+            ) -> Self::Item<'w> {
+                InitCounter
+            }
+            fn get_self<'w>(
+                _world: &'w World,
+                _meta: &'w SystemMeta,
+                _state: &'w mut Self::State,
+                _tick: Tick,
+            ) -> Self {
+                InitCounter
+            }
+        }
+        struct Score(u32);
+
+        fn conflict(_c: InitCounter, _q1: Query<&mut Age0>, _q2: Query<&Age0>) {}
+        fn disjoint(_q1: Query<&mut Age0, With<Age1>>, _q2: Query<&Age0, Without<Age1>>) {}
+        fn need_score(_c: InitCounter, _q: Query<&Age0>, _s: SingleRes<Score>) {}
+        fn add_score(_c: InitCounter, q: Query<&Age0>, mut s: SingleResMut<Score>) {
+            for a in q.iter() {
+                s.0 += a.0 as u32;
+            }
+        }
+
+        let mut world = World::new();
+        match SystemBuilder::new(conflict).build(&mut world) {
+            Err(SystemBuildError::ComponentConflict { params, component, .. }) => {
+                assert_eq!(params.0.index, 1);
+                assert_eq!(params.1.index, 2);
+                assert!(params.0.type_name.contains("Query"));
+                assert_eq!(component, std::any::type_name::<Age0>());
+            }
+            _ => panic!("expect conflict"),
+        }
+        assert!(SystemBuilder::new(disjoint).build(&mut world).is_ok());
+        match SystemBuilder::new(need_score).build(&mut world) {
+            Err(SystemBuildError::MissingRes { param, res, .. }) => {
+                assert_eq!(param.index, 2);
+                assert!(param.type_name.contains("SingleRes"));
+                assert_eq!(res, std::any::type_name::<Score>());
+            }
+            _ => panic!("expect missing res"),
+        }
+
+        world.insert_single_res(Score(0));
+        world.make_insert::<(Age0,)>().insert(&world, (Age0(3),));
+        world.make_insert::<(Age0,)>().insert(&world, (Age0(4),));
+        world.settle();
+        let inits = INITS.load(Ordering::Relaxed);
+        let mut s = SystemBuilder::new(add_score).build(&mut world).unwrap();
+        assert_eq!(INITS.load(Ordering::Relaxed), inits + 1);
+        s.run(&world);
+        s.run(&world);
+        // 运行时不再初始化
+        assert_eq!(INITS.load(Ordering::Relaxed), inits + 1);
+        assert_eq!(world.get_single_res::<Score>().unwrap().0, 14);
+    }
+    #[test]
+    fn test_change_span() {
+        use crate::column::CHANGE_SPAN_MAX;
+        let mut world = World::new();
+        world.set_change_span::<Age1>(SpanDedup::PerTick);
+        world.set_change_span::<Age2>(SpanDedup::PerWrite);
+        let i = world.make_insert::<(Age1, Age2)>();
+        let es: Vec<Entity> = (0..3).map(|n| i.insert(&world, (Age1(n), Age2(n)))).collect();
+        let add = world.tick();
+        world.settle();
+        let mut q = world.make_query::<(ChangeSpan<Age1>, ChangeSpan<Age2>), ()>();
+        assert_eq!(q.iter(&world).count(), 3);
+        // 插入也算修改，上一帧的修改不计数
+        let (s1, _) = q.get(&world, es[0]).unwrap();
+        assert_eq!(s1, ChangeSpanItem { first_changed: add, last_changed: add, change_count_this_frame: 0 });
+
+        let mut m1 = world.make_query::<&mut Age1, ()>();
+        let mut m2 = world.make_query::<&mut Age2, ()>();
+        for (e, n) in es.iter().zip([1u32, 2, 5]) {
+            // 每个tick修改两次，PerTick只计一次
+            let mut ticks = Vec::new();
+            for _ in 0..n {
+                world.increment_tick();
+                ticks.push(world.tick());
+                m1.get_mut(&mut world, *e).unwrap().0 += 1;
+                m1.get_mut(&mut world, *e).unwrap().0 += 1;
+            }
+            // 同一tick内修改n次，PerWrite每次都计数
+            world.increment_tick();
+            let t = world.tick();
+            for _ in 0..n {
+                m2.get_mut(&mut world, *e).unwrap().0 += 1;
+            }
+            let (s1, s2) = q.get(&world, *e).unwrap();
+            assert_eq!(
+                s1,
+                ChangeSpanItem {
+                    first_changed: ticks[0],
+                    last_changed: *ticks.last().unwrap(),
+                    change_count_this_frame: n,
+                }
+            );
+            assert_eq!(s2, ChangeSpanItem { first_changed: t, last_changed: t, change_count_this_frame: n });
+        }
+        // 新的一帧重新计数
+        world.settle();
+        let (s1, s2) = q.get(&world, es[2]).unwrap();
+        assert_eq!(s1.change_count_this_frame, 0);
+        assert_eq!(s2.change_count_this_frame, 0);
+        world.increment_tick();
+        let t = world.tick();
+        m1.get_mut(&mut world, es[2]).unwrap().0 += 1;
+        let (s1, _) = q.get(&world, es[2]).unwrap();
+        assert_eq!(s1, ChangeSpanItem { first_changed: t, last_changed: t, change_count_this_frame: 1 });
+        // 次数饱和
+        for _ in 0..300 {
+            m2.get_mut(&mut world, es[1]).unwrap().0 += 1;
+        }
+        let (_, s2) = q.get(&world, es[1]).unwrap();
+        assert_eq!(s2.change_count_this_frame, CHANGE_SPAN_MAX);
+        // 和Changed一起使用
+        let mut c = world.make_query::<(Entity, ChangeSpan<Age1>), Changed<Age1>>();
+        let r: Vec<_> = c.iter(&world).map(|(e, s)| (e, s.change_count_this_frame)).collect();
+        assert!(r.contains(&(es[2], 1)));
+    }
+    #[test]
+    fn test_column_guard() {
+        use std::sync::Barrier;
+        let mut world = World::new();
+        let e = world.make_insert::<(Age0,)>().insert(&world, (Age0(1),));
+        world.settle();
+        let index = world.init_component::<Age0>();
+        let ar_index = world.get_entity_location(e).unwrap().archetype_index;
+        let column = world.get_column(index).unwrap().clone();
+        let barrier = Barrier::new(2);
+        let r = std::thread::scope(|s| {
+            let reader = s.spawn(|| {
+                let r = column.blob_ref(ar_index).unwrap();
+                assert_eq!(r.get::<Age0>(Row(0), e).0, 1);
+                // 持有BlobRef，等待另一个线程整理该列
+                barrier.wait();
+                barrier.wait();
+                r.get::<Age0>(Row(0), e).0
+            });
+            barrier.wait();
+            let mut c = column.clone();
+            unsafe { Share::get_mut_unchecked(&mut c) }.settle_by_index(ar_index, 1, 0, &Vec::new());
+            barrier.wait();
+            reader.join()
+        });
+        #[cfg(not(feature = "unchecked-sync"))]
+        {
+            let err = r.unwrap_err();
+            let msg = err.downcast_ref::<String>().unwrap();
+            assert!(msg.contains(std::any::type_name::<Age0>()));
+        }
+        // 没有检查时，跨过整理的访问不会被发现
+        #[cfg(feature = "unchecked-sync")]
+        assert_eq!(r.unwrap(), 1);
+        // 整理后重新获得的BlobRef正常使用
+        let r = column.blob_ref(ar_index).unwrap();
+        assert_eq!(r.get::<Age0>(Row(0), e).0, 1);
+    }
+    #[test]
+    fn test_archetype_user_data() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static SUM: AtomicUsize = AtomicUsize::new(0);
+        // 原型创建时计算的布局
+        #[derive(Debug, PartialEq)]
+        struct Layout(usize);
+        fn read_layout(q: Query<(ArchetypeRef, &Age0)>) {
+            for (ar, a) in q.iter() {
+                let layout = ar.get_user_data::<Layout>().unwrap();
+                SUM.fetch_add(layout.0 * a.0, Ordering::Relaxed);
+            }
+            for ar in q.archetypes() {
+                assert!(ar.get_user_data::<Layout>().is_some());
+            }
+        }
+        let mut app = crate::prelude::App::new();
+        app.world.on_archetype_created(|ar| {
+            ar.set_user_data(Layout(ar.column_len()));
+        });
+        // 已有的空原型也设置
+        assert_eq!(app.world.get_archetype(0usize.into()).unwrap().get_user_data::<Layout>().as_deref(), Some(&Layout(0)));
+        let i = app.world.make_insert::<(Age0, Age1)>();
+        let e1 = i.insert(&app.world, (Age0(2), Age1(0)));
+        app.world.make_insert::<(Age0,)>().insert(&app.world, (Age0(3),));
+        app.add_system(Update, read_layout);
+        app.run();
+        assert_eq!(SUM.load(Ordering::Relaxed), 2 * 2 + 3);
+
+        // 覆盖后，之前取出的值仍然有效
+        let index = app.world.get_entity_location(e1).unwrap().archetype_index;
+        let ar = app.world.get_archetype(index).unwrap().clone();
+        let old = ar.get_user_data::<Layout>().unwrap();
+        assert!(app.world.set_archetype_user_data(index, Layout(9)));
+        assert_eq!(*old, Layout(2));
+        assert_eq!(ar.get_user_data::<Layout>().as_deref(), Some(&Layout(9)));
+        ar.set_user_data(Layout(10));
+        assert_eq!(ar.get_user_data::<Layout>().as_deref(), Some(&Layout(10)));
+        assert!(ar.get_user_data::<u32>().is_none());
+    }
+
+    #[test]
+    fn test_stable_entity_allocation() {
+        use pi_key_alloter::Key;
+        fn stable() -> World {
+            World::with_options(WorldOptions {
+                entity_allocation: EntityAllocation::Stable,
+            })
+        }
+        // 相同的创建和销毁序列，得到相同的实体
+        fn script(mut world: World) -> (Vec<u64>, Option<u32>) {
+            let i = world.make_insert::<(Age0,)>();
+            let mut vec: Vec<Entity> = (0..8).map(|n| i.insert(&world, (Age0(n),))).collect();
+            world.destroy_entity(vec[5]).unwrap();
+            world.destroy_entity(vec[2]).unwrap();
+            world.settle();
+            vec.push(i.insert(&world, (Age0(8),)));
+            vec.push(i.insert(&world, (Age0(9),)));
+            world.settle();
+            (vec.iter().map(|e| e.data().as_ffi()).collect(), world.max_entity_index())
+        }
+        let (a, max) = script(stable());
+        assert_eq!(script(stable()), (a.clone(), max));
+        assert_eq!(max, Some(7));
+        // 优先复用最小的空闲索引，与销毁的先后无关
+        assert_eq!(a[8] as u32, 2);
+        assert_eq!(a[9] as u32, 5);
+        // 在其他线程上分配，得到相同的实体
+        let r = std::thread::scope(|s| s.spawn(|| script(stable())).join().unwrap());
+        assert_eq!(r, (a.clone(), max));
+        // 默认的分配按销毁的先后复用，实体不同
+        let (b, _) = script(World::new());
+        assert_eq!(b[8] as u32, 5);
+        assert_ne!(a, b);
+
+        // 分配的先后与system的顺序不同时，整理时按system的顺序交换实体
+        let mut world = stable();
+        assert_eq!(world.entity_allocation(), EntityAllocation::Stable);
+        assert_eq!(world.max_entity_index(), None);
+        let i = world.make_insert::<(Age0,)>();
+        world.enter_system_alloc(1, 1);
+        let e1 = i.insert(&world, (Age0(1),));
+        world.enter_system_alloc(1, 0);
+        let e0 = i.insert(&world, (Age0(0),));
+        world.leave_system_alloc();
+        world.settle();
+        assert_eq!(world.stable_remap().get(e0), Some(e1));
+        assert_eq!(world.stable_remap().get(e1), Some(e0));
+        assert_eq!(world.get_component::<Age0>(e1).unwrap().0, 0);
+        assert_eq!(world.get_component::<Age0>(e0).unwrap().0, 1);
+        // 顺序一致时不交换
+        world.enter_system_alloc(1, 0);
+        i.insert(&world, (Age0(2),));
+        world.enter_system_alloc(1, 1);
+        i.insert(&world, (Age0(3),));
+        world.leave_system_alloc();
+        world.settle();
+        assert!(world.stable_remap().is_empty());
+    }
+
+    #[test]
+    fn test_raw_dump() {
+        let fnv = |bytes: &[u8]| {
+            let mut hash: u64 = 0xcbf29ce484222325;
+            for b in bytes {
+                hash ^= *b as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+            hash
+        };
+        // 单个usize字段，没有填充
+        unsafe impl crate::raw_dump::RawPod for Age0 {}
+        unsafe impl crate::raw_dump::RawPod for Age1 {}
+        let mut world = World::new();
+        world.register_raw_pod::<Age0>();
+        world.register_raw_pod::<Age1>();
+        let i = world.make_insert::<(Age0, Age1)>();
+        let es: Vec<Entity> = (0..4).map(|n| i.insert(&world, (Age0(n), Age1(n * 10)))).collect();
+        let e11 = world
+            .make_insert::<(Age0, Age11)>()
+            .insert(&world, (Age0(7), Age11(vec![1, 2], vec![], 3, 4)));
+        world.destroy_entity(es[1]).unwrap();
+        let id = |world: &World, t: TypeId| {
+            let index = world.get_component_index(&t);
+            world.get_column(index).unwrap().info().stable_id()
+        };
+        let (id0, id1, id11) = (
+            id(&world, TypeId::of::<Age0>()),
+            id(&world, TypeId::of::<Age1>()),
+            id(&world, TypeId::of::<Age11>()),
+        );
+
+        let mut buf: Vec<u8> = Vec::new();
+        let manifest = world.dump_raw(&mut buf).unwrap();
+        let view = World::load_raw_readonly(&mut &buf[..]).unwrap();
+
+        // 实体数量和POD组件的值
+        assert_eq!(view.len(), 4);
+        assert!(!view.contains(es[1]));
+        for n in [0, 2, 3] {
+            assert_eq!(view.get_pod::<Age0>(es[n], id0), Some(Age0(n)));
+            assert_eq!(view.get_pod::<Age1>(es[n], id1), Some(Age1(n * 10)));
+        }
+        assert_eq!(view.get_pod::<Age0>(e11, id0), Some(Age0(7)));
+        // 没有声明RawPod的组件不导出字节
+        assert_eq!(view.get_pod::<usize>(e11, id11), None);
+        assert_eq!(view.get_bytes(e11, id11), None);
+        let (ar, _) = view.location(e11).unwrap();
+        assert!(!ar.column(id11).unwrap().is_pod());
+        assert!(ar.column(id0).unwrap().is_pod());
+
+        // 清单的长度和校验和
+        assert_eq!(manifest.len, buf.len() as u64);
+        assert_eq!(manifest.checksum, fnv(&buf));
+        assert_eq!(view.checksum(), manifest.checksum);
+        let section = |s: &crate::raw_dump::DumpSection| fnv(&buf[s.offset as usize..(s.offset + s.len) as usize]);
+        for am in manifest.archetypes.iter() {
+            assert_eq!(am.entities.len, am.rows as u64 * 8);
+            assert_eq!(section(&am.entities), am.entities.checksum);
+            for cm in am.columns.iter() {
+                let size = if cm.pod { cm.size } else { 0 };
+                assert_eq!(cm.data.len, (am.rows * size) as u64);
+                assert_eq!(section(&cm.data), cm.data.checksum);
+                if let Some(t) = &cm.ticks {
+                    assert_eq!(t.len, am.rows as u64 * 8);
+                    assert_eq!(section(t), t.checksum);
+                }
+            }
+        }
+        // 截断的数据
+        assert!(World::load_raw_readonly(&mut &buf[..buf.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_iter_budgeted() {
+        use crate::budget::Budget;
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::OnceLock;
+        use std::time::{Duration, Instant};
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0,)>();
+        for n in 0..5 {
+            i.insert(&world, (Age0(n),));
+        }
+        let i = world.make_insert::<(Age0, Age1)>();
+        for n in 5..10 {
+            i.insert(&world, (Age0(n), Age1(n)));
+        }
+        {
+            let mut state = world.query::<&Age0, ()>();
+            let q = Query::new(&world, &mut state, world.tick());
+
+            // 按数量切分，每次恰好3项，继续时不遗漏不重复
+            let mut it = q.iter_budgeted(Budget::items(3));
+            let mut all: Vec<usize> = it.by_ref().map(|a| a.0).collect();
+            assert_eq!(all.len(), 3);
+            assert!(it.was_exhausted());
+            assert_eq!(it.next(), None);
+            let mut cursor = it.cursor();
+            for _ in 0..2 {
+                let mut it = q.resume_budgeted(cursor, Budget::items(3));
+                all.extend(it.by_ref().map(|a| a.0));
+                assert!(it.was_exhausted());
+                cursor = it.cursor();
+            }
+            assert_eq!(all.len(), 9);
+            let mut it = q.resume_budgeted(cursor, Budget::items(3));
+            all.extend(it.by_ref().map(|a| a.0));
+            assert!(!it.was_exhausted());
+            assert!(it.cursor().is_finished());
+            all.sort();
+            assert_eq!(all, (0..10).collect::<Vec<usize>>());
+        }
+
+        // 可写的迭代
+        {
+            let mut mstate = world.query::<&mut Age0, ()>();
+            let mut q = Query::new(&world, &mut mstate, world.tick());
+            let mut it = q.iter_budgeted_mut(Budget::items(4));
+            it.by_ref().for_each(|mut a| a.0 += 100);
+            let cursor = it.cursor();
+            assert_eq!(it.yielded(), 4);
+            drop(it);
+            q.resume_budgeted_mut(cursor, Budget::items(100)).for_each(|mut a| a.0 += 100);
+        }
+        let mut state = world.query::<&Age0, ()>();
+        let q = Query::new(&world, &mut state, world.tick());
+        assert!(q.iter().all(|a| a.0 >= 100));
+
+        // 时间预算，用假的时钟，每次读取前进0.2ms
+        static NANOS: AtomicU64 = AtomicU64::new(0);
+        static BASE: OnceLock<Instant> = OnceLock::new();
+        fn clock() -> Instant {
+            *BASE.get_or_init(Instant::now) + Duration::from_nanos(NANOS.fetch_add(200_000, Ordering::Relaxed))
+        }
+        let budget = Budget::time(Duration::from_micros(500)).check_every(2).with_clock(clock);
+        let mut it = q.iter_budgeted(budget);
+        assert_eq!(it.by_ref().count(), 6);
+        assert!(it.was_exhausted());
+        assert_eq!(q.resume_budgeted(it.cursor(), Budget::items(100)).count(), 4);
+    }
+
+    #[test]
+    fn test_not_defaultable() {
+        let mut world = World::new();
+        // Bundle提供了值，不需要默认值
+        let e = world.make_insert::<(Age0, Position)>().insert(&world, (Age0(1), Position([1.0, 2.0, 3.0])));
+        assert_eq!(world.get_component::<Position>(e).unwrap().0, [1.0, 2.0, 3.0]);
+
+        // 不提供值添加，提前返回错误，实体不变
+        let e = world.make_insert::<(Age0,)>().insert(&world, (Age0(2),));
+        let rot = world.init_component::<Rotation>();
+        let age1 = world.init_component::<Age1>();
+        let r = world.make_entity_editor().add_components_by_index(e, &[age1, rot]);
+        match r {
+            Err(QueryError::NotDefaultable(index, ar, op)) => {
+                assert_eq!(index, rot);
+                assert_eq!(op, "EntityEditor::alter_components");
+                let msg = QueryError::NotDefaultable(index, ar, op).describe(&world);
+                assert!(msg.contains("Rotation"), "{}", msg);
+                assert!(msg.contains("Age1"), "{}", msg);
+            }
+            r => panic!("{:?}", r),
+        }
+        assert!(world.get_component::<Age1>(e).is_err());
+        assert_eq!(world.get_component::<Age0>(e).unwrap().0, 2);
+        let r = world.make_entity_editor().insert_entity_by_index(&[rot]);
+        assert!(matches!(r, Err(QueryError::NotDefaultable(i, _, _)) if i == rot));
+
+        // 注册时提前检查
+        world.assert_defaultable::<Age2>();
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            world.assert_defaultable::<Rotation>();
+        }));
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn test_group_by() {
+        use std::collections::BTreeMap;
+        #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Component)]
+        struct Material(u32);
+        let mut world = World::new();
+        let i0 = world.make_insert::<(Material, Age0)>();
+        let i1 = world.make_insert::<(Material, Age0, Age1)>();
+        let i2 = world.make_insert::<(Material, Age0, Age2)>();
+        let mut brute: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+        for n in 0..10000usize {
+            let m = Material((n * 7 % 16) as u32);
+            match n % 3 {
+                0 => i0.insert(&world, (m, Age0(n))),
+                1 => i1.insert(&world, (m, Age0(n), Age1(n))),
+                _ => i2.insert(&world, (m, Age0(n), Age2(n))),
+            };
+            brute.entry(m.0).or_default().push(n);
+        }
+        let mut state = world.query::<(&Material, &Age0), ()>();
+        let q = Query::new(&world, &mut state, world.tick());
+
+        // 用闭包计算键
+        let mut groups: Vec<(u32, Vec<usize>)> = Vec::new();
+        q.group_by(|_, (m, _)| m.0, |k, items| {
+            assert_eq!(items.len(), brute[&k].len());
+            let mut vec: Vec<usize> = items
+                .map(|(_, (m, a))| {
+                    assert_eq!(m.0, k);
+                    a.0
+                })
+                .collect();
+            vec.sort();
+            groups.push((k, vec));
+        });
+        assert_eq!(groups.len(), 16);
+        assert_eq!(groups.iter().map(|(_, v)| v.len()).sum::<usize>(), 10000);
+        assert_eq!(groups, brute.clone().into_iter().collect::<Vec<_>>());
+
+        // 键为组件，直接读取列，组内按实体有序，结果确定
+        let mut buf = Vec::new();
+        let mut fast: Vec<(u32, Vec<Entity>)> = Vec::new();
+        q.group_by_component::<Material>(&mut buf, |k, items| {
+            fast.push((k.0, items.entities().collect()));
+        })
+        .unwrap();
+        assert_eq!(fast.len(), 16);
+        let mut again: Vec<(u32, Vec<Entity>)> = Vec::new();
+        q.group_by_with(&mut buf, |_, (m, _)| **m, |k, items| {
+            again.push((k.0, items.map(|(e, _)| e).collect()));
+        });
+        assert_eq!(fast, again);
+        for ((k, es), (bk, bv)) in fast.iter().zip(brute.iter()) {
+            assert_eq!(k, bk);
+            let mut vec: Vec<usize> = es.iter().map(|e| world.get_component::<Age0>(*e).unwrap().0).collect();
+            vec.sort();
+            assert_eq!(&vec, bv);
+        }
+        // 没有声明对键组件的读，不能按该组件分组
+        drop(q);
+        let material = world.init_component::<Material>();
+        let mut state = world.query::<&Age0, ()>();
+        let q = Query::new(&world, &mut state, world.tick());
+        let r = q.group_by_component::<Material>(&mut buf, |_, _| unreachable!());
+        assert_eq!(r, Err(QueryError::MissingReadAccess(material)));
+        drop(q);
+        // 用ExtraRead声明后可以
+        let mut state = world.query::<&Age0, ExtraRead<Material>>();
+        let q = Query::new(&world, &mut state, world.tick());
+        let mut n = 0;
+        q.group_by_component::<Material>(&mut buf, |_, items| n += items.len()).unwrap();
+        assert_eq!(n, 10000);
+    }
+
+    #[test]
+    fn test_column_sharing_invariant() {
+        use crate::invariant::InvariantViolation;
+        let mut world = World::new();
+        let e = world.make_insert::<(Age0,)>().insert(&world, (Age0(1),));
+        let age0 = world.init_component::<Age0>();
+        let age1 = world.init_component::<Age1>();
+        assert_eq!(world.check_invariants(), Ok(()));
+
+        // 重新注册的列和已有原型上的列不是同一个
+        world.fork_column(age0, 16);
+        match world.check_invariants() {
+            Err(InvariantViolation::ColumnMismatch { component, size, drop_differs, .. }) => {
+                assert_eq!(component, age0);
+                assert_eq!(size, (16, mem::size_of::<Age0>()));
+                assert!(!drop_differs);
+            }
+            r => panic!("{:?}", r),
+        }
+        // 目标原型用新的列句柄创建，建立alter边时就发现，不等到移动数据
+        world.make_insert::<(Age0, Age1)>();
+        if cfg!(debug_assertions) {
+            let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                let _ = world.make_entity_editor().add_components_by_index(e, &[age1]);
+            }));
+            let msg = r.unwrap_err().downcast::<String>().unwrap();
+            assert!(msg.contains("alter edge"), "{}", msg);
+            assert!(msg.contains("Age0"), "{}", msg);
+        }
+    }
+
+    #[test]
+    fn test_world_builder() {
+        use crate::cleanup::CleanupPolicy;
+        use crate::column::TickPolicy;
+        use crate::world::EntityAllocation;
+        use crate::world_builder::{WorldBuildError, WorldBuilder};
+        let (mut world, report) = WorldBuilder::new()
+            .register::<Age1>(TickPolicy::compressed())
+            .register::<Age0>(TickPolicy::full())
+            .register_bundle::<(Age0, Age1)>()
+            .register_bundle::<(Age0,)>()
+            .reserve_entities(100)
+            .reserve_component::<Age1>(1000)
+            .cleanup_policy(CleanupPolicy::EndOfFrame)
+            .deterministic(true)
+            .build()
+            .unwrap();
+        assert_eq!(report.cleanup, CleanupPolicy::EndOfFrame);
+        assert_eq!(report.entity_allocation, EntityAllocation::Stable);
+        assert_eq!(report.components.len(), 2);
+        assert_eq!(report.components[1].policy, TickPolicy::compressed());
+        let mut reserved: Vec<usize> = report.archetypes.iter().map(|a| a.reserved).collect();
+        reserved.sort();
+        assert_eq!(reserved, vec![100, 1000]);
+        assert!(report.to_string().contains("Age1"));
+
+        // 注册顺序无关
+        let (_, other) = WorldBuilder::new()
+            .register_bundle::<(Age0,)>()
+            .register_bundle::<(Age0, Age1)>()
+            .register::<Age0>(TickPolicy::full())
+            .reserve_component::<Age1>(1000)
+            .register::<Age1>(TickPolicy::compressed())
+            .reserve_entities(100)
+            .cleanup_policy(CleanupPolicy::EndOfFrame)
+            .deterministic(true)
+            .build()
+            .unwrap();
+        assert_eq!(other, report);
+
+        // 第一帧不创建原型和列，列不扩容
+        let archetypes = world.archetype_list().count();
+        let memsize = |world: &World| {
+            report
+                .components
+                .iter()
+                .map(|c| world.get_column(c.index).unwrap().memsize())
+                .sum::<usize>()
+        };
+        let before = memsize(&world);
+        let i = world.make_insert::<(Age0, Age1)>();
+        for n in 0..1000 {
+            i.insert(&world, (Age0(n), Age1(n)));
+        }
+        let i = world.make_insert::<(Age0,)>();
+        for n in 0..100 {
+            i.insert(&world, (Age0(n),));
+        }
+        assert_eq!(world.archetype_list().count(), archetypes);
+        assert_eq!(world.component_arr.len(), report.components.len());
+        assert_eq!(memsize(&world), before);
+
+        // 重复注册在构建时发现
+        let r = WorldBuilder::new()
+            .register::<Age0>(TickPolicy::full())
+            .register::<Age2>(TickPolicy::full())
+            .register::<Age0>(TickPolicy::compressed())
+            .build();
+        match r {
+            Err(WorldBuildError::DuplicateComponent(name)) => assert!(name.contains("Age0")),
+            r => panic!("{:?}", r.map(|(_, report)| report)),
+        }
+        let r = WorldBuilder::new()
+            .register_bundle::<(Age0,)>()
+            .register_bundle::<(Age0,)>()
+            .build();
+        assert!(matches!(r, Err(WorldBuildError::DuplicateBundle(_))));
+    }
+
+    #[test]
+    fn test_iter_many() {
+        let mut world = World::new();
+        let i0 = world.make_insert::<(Age0,)>();
+        let i1 = world.make_insert::<(Age0, Age1)>();
+        let i2 = world.make_insert::<(Age1,)>();
+        // 子节点列表，跨两个原型交错
+        let children: Vec<Entity> = (0..20usize)
+            .map(|n| if n % 2 == 0 { i0.insert(&world, (Age0(n),)) } else { i1.insert(&world, (Age0(n), Age1(n))) })
+            .collect();
+        let other = i2.insert(&world, (Age1(100),));
+        let dead = i0.insert(&world, (Age0(200),));
+        world.destroy_entity(dead).unwrap();
+        let mut list = children.clone();
+        list.reverse();
+        list.insert(3, other);
+        list.insert(7, dead);
+
+        {
+            let mut state = world.query::<&Age0, ()>();
+            let q = Query::new(&world, &mut state, world.tick());
+            // 保持列表的顺序，跳过不匹配和已销毁的实体
+            let r: Vec<usize> = q.iter_many(list.iter().copied()).map(|a| a.0).collect();
+            assert_eq!(r, (0..20usize).rev().collect::<Vec<_>>());
+            // 严格模式对每个实体返回结果
+            let r: Vec<_> = q.iter_many_strict(list.iter().copied()).collect();
+            assert_eq!(r.len(), 22);
+            assert!(r[3].is_err());
+            assert!(r[7].is_err());
+            assert_eq!(r.iter().filter(|r| r.is_ok()).count(), 20);
+            assert_eq!(*r[0].as_ref().unwrap(), &Age0(19));
+        }
+
+        // 可写的借出迭代器，重复实体返回错误，不会写两次
+        let mut state = world.query::<&mut Age0, ()>();
+        let mut q = Query::new(&world, &mut state, world.tick());
+        let mut list: Vec<Entity> = children.clone();
+        list.extend(children.iter().take(5).copied());
+        list.push(other);
+        let mut it = q.iter_many_mut(list.iter().copied());
+        let (mut ok, mut dup, mut miss) = (0, 0, 0);
+        while let Some(r) = it.fetch_next() {
+            match r {
+                Ok(mut a) => {
+                    a.0 += 1000;
+                    ok += 1;
+                }
+                Err(QueryError::DuplicateEntity(_)) => dup += 1,
+                Err(_) => miss += 1,
+            }
+        }
+        assert_eq!((ok, dup, miss), (20, 5, 1));
+        for (n, e) in children.iter().enumerate() {
+            assert_eq!(world.get_component::<Age0>(*e).unwrap().0, n + 1000);
+        }
+    }
+
+    #[test]
+    fn test_deferred_drop() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Mutex;
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        static ORDER: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+        #[derive(Component)]
+        struct Heavy(Vec<usize>);
+        impl Drop for Heavy {
+            fn drop(&mut self) {
+                DROPS.fetch_add(self.0.len(), Ordering::Relaxed);
+                ORDER.lock().unwrap().push(self.0.first().copied().unwrap_or(0));
+            }
+        }
+        // 按1..=n的顺序销毁一批实体
+        fn despawn(world: &mut World, n: usize) {
+            let i = world.make_insert::<(Heavy, Age0)>();
+            let vec: Vec<Entity> = (1..=n).map(|k| i.insert(world, (Heavy(vec![k]), Age0(k)))).collect();
+            for e in vec {
+                world.destroy_entity(e).unwrap();
+            }
+        }
+        let mut world = World::new();
+        despawn(&mut world, 20);
+        assert_eq!(DROPS.swap(0, Ordering::Relaxed), 20);
+        assert_eq!(std::mem::take(&mut *ORDER.lock().unwrap()), (1..=20).collect::<Vec<_>>());
+
+        let mut world = World::new();
+        assert!(world.register_deferred_drop::<Heavy>());
+        assert!(!world.register_deferred_drop::<Age0>());
+        despawn(&mut world, 20);
+        assert_eq!(DROPS.load(Ordering::Relaxed), 0);
+        assert!(ORDER.lock().unwrap().is_empty());
+        let index = world.init_component::<Heavy>();
+        assert_eq!(world.pending_deferred_drops(index), 20);
+        assert_eq!(world.deferred_drop_stats(), DeferredDropStats { components: 1, pending: 20, dropped: 0 });
+
+        // 行被复用后，待释放的组件不受影响
+        let i = world.make_insert::<(Heavy, Age0)>();
+        let e = i.insert(&world, (Heavy(vec![0; 100]), Age0(0)));
+        // 移除组件也延迟释放
+        world.make_entity_editor().remove_components_by_index(e, &[index]).unwrap();
+        assert_eq!(world.deferred_drop_stats().pending, 21);
+
+        // 在工作线程上按预算释放
+        let runner = world.deferred_drop_runner();
+        let handle = std::thread::spawn(move || {
+            let first = runner.run(5);
+            let rest = runner.run(usize::MAX);
+            (first, rest, runner.pending())
+        });
+        assert_eq!(handle.join().unwrap(), (5, 16, 0));
+        assert_eq!(DROPS.load(Ordering::Relaxed), 20 + 100);
+        // 按进入缓冲的顺序释放
+        let mut order: Vec<usize> = (1..=20).collect();
+        order.push(0);
+        assert_eq!(*ORDER.lock().unwrap(), order);
+        assert_eq!(world.deferred_drop_stats(), DeferredDropStats { components: 1, pending: 0, dropped: 21 });
+
+        // world释放时释放剩余的组件，每个只释放一次
+        let e = i.insert(&world, (Heavy(vec![0; 7]), Age0(0)));
+        world.destroy_entity(e).unwrap();
+        i.insert(&world, (Heavy(vec![0; 3]), Age0(0)));
+        // 插入的状态持有原型和列，先释放它，world释放时原型和列才会释放
+        drop(i);
+        drop(world);
+        assert_eq!(DROPS.load(Ordering::Relaxed), 20 + 100 + 7 + 3);
+    }
+    // 销毁持有慢Drop组件的实体，延迟释放时销毁本身不等待Drop
+    fn despawn_slow_drop(b: &mut Bencher, deferred: bool) {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        // 计时结束后缓冲中剩余的组件随world释放，不再等待
+        static SLOW: AtomicBool = AtomicBool::new(false);
+        #[derive(Component)]
+        struct Heavy(usize);
+        impl Drop for Heavy {
+            fn drop(&mut self) {
+                if SLOW.load(Ordering::Relaxed) {
+                    std::thread::sleep(std::time::Duration::from_micros(50));
+                }
+            }
+        }
+        let mut world = World::new();
+        if deferred {
+            world.register_deferred_drop::<Heavy>();
+        }
+        let i = world.make_insert::<(Heavy, Age0)>();
+        SLOW.store(true, Ordering::Relaxed);
+        b.iter(|| {
+            let vec: Vec<Entity> = (0..20).map(|k| i.insert(&world, (Heavy(k), Age0(k)))).collect();
+            for e in vec {
+                world.destroy_entity(e).unwrap();
+            }
+        });
+        SLOW.store(false, Ordering::Relaxed);
+    }
+    #[bench]
+    fn bench_despawn_inline_drop(b: &mut Bencher) {
+        despawn_slow_drop(b, false);
+    }
+    #[bench]
+    fn bench_despawn_deferred_drop(b: &mut Bencher) {
+        despawn_slow_drop(b, true);
+    }
+
+    #[test]
+    fn test_iter_changed_recent() {
+        use crate::system_params::SystemParam;
+        let mut world = World::new();
+        let mut meta = SystemMeta::new(TypeInfo::of::<ComponentChanged<'static, Age0>>());
+        let mut changed_state = ComponentChanged::<Age0>::init_state(&mut world, &mut meta);
+        let i = world.make_insert::<(Age0,)>();
+        let i1 = world.make_insert::<(Age0, Age1)>();
+        let es: Vec<Entity> = (0..10).map(|n| if n < 5 { i.insert(&world, (Age0(n),)) } else { i1.insert(&world, (Age0(n), Age1(n))) }).collect();
+        world.settle();
+        ComponentChanged::<Age0>::get_param(&world, &meta, &mut changed_state, world.tick()).mark_read();
+        let mut wq = world.make_query::<&mut Age0, ()>();
+        // 按已知的tick依次修改
+        for batch in [&[0usize, 1, 2][..], &[3, 1], &[5, 0]] {
+            world.increment_tick();
+            let mut q = wq.get_param(&world);
+            for n in batch {
+                q.get_mut(es[*n]).unwrap().0 += 100;
+            }
+        }
+        let mut state = world.query::<&Age0, ()>();
+        let q = Query::new(&world, &mut state, world.tick());
+        let changed = ComponentChanged::<Age0>::get_param(&world, &meta, &mut changed_state, world.tick());
+        let mut it = q.iter_changed_recent(&changed, 3);
+        let r: Vec<(Entity, usize)> = (&mut it).map(|(e, a)| (e, a.0)).collect();
+        assert_eq!(r, vec![(es[0], 200), (es[5], 105), (es[1], 201)]);
+        assert_eq!(it.yielded(), 3);
+        // 更早的修改被确认，不再报告
+        assert_eq!(it.skipped(), 4);
+        assert_eq!(changed.len(), 0);
+
+        // 下一帧只报告新的修改
+        world.increment_tick();
+        wq.get_param(&world).get_mut(es[7]).unwrap().0 += 100;
+        let changed = ComponentChanged::<Age0>::get_param(&world, &meta, &mut changed_state, world.tick());
+        let r: Vec<Entity> = q.iter_changed_recent(&changed, 3).map(|(e, _)| e).collect();
+        assert_eq!(r, vec![es[7]]);
+    }
+
+    #[test]
+    fn test_update_mask() {
+        use crate::system_params::SystemParam;
+        let mut world = World::new();
+        let mut meta = SystemMeta::new(TypeInfo::of::<ComponentChanged<'static, Age0>>());
+        let mut c0 = ComponentChanged::<Age0>::init_state(&mut world, &mut meta);
+        let mut c1 = ComponentChanged::<Age1>::init_state(&mut world, &mut meta);
+        let mut c2 = ComponentChanged::<Age2>::init_state(&mut world, &mut meta);
+        let i = world.make_insert::<(Age0, Age1, Age2)>();
+        let es: Vec<Entity> = (0..4).map(|n| i.insert(&world, (Age0(n), Age1(n), Age2(n)))).collect();
+        world.settle();
+        ComponentChanged::<Age0>::get_param(&world, &meta, &mut c0, world.tick()).mark_read();
+        ComponentChanged::<Age1>::get_param(&world, &meta, &mut c1, world.tick()).mark_read();
+        ComponentChanged::<Age2>::get_param(&world, &meta, &mut c2, world.tick()).mark_read();
+        world.increment_tick();
+
+        let mut state = world.query::<(&mut Age0, &mut Age1, &mut Age2), ()>();
+        let mut q = Query::new(&world, &mut state, world.tick());
+        // 三个都写了，只声明修改了Age1
+        let mask = q
+            .update(es[0], |(a, b, c)| {
+                a.0 = std::hint::black_box(a.0);
+                b.0 += 10;
+                c.0 = std::hint::black_box(c.0);
+                ChangeMask::bit(1)
+            })
+            .unwrap();
+        assert_eq!(mask, ChangeMask::bit(1));
+        // 读后没有修改
+        q.update(es[1], |(a, _, _)| {
+            if a.0 > 100 {
+                a.0 = 0;
+                return ChangeMask::bit(0);
+            }
+            ChangeMask::NONE
+        })
+        .unwrap();
+        // 迭代中对每项决定
+        for item in q.iter_mut() {
+            item.update(|(_, _, c)| {
+                if c.0 == 3 {
+                    c.0 = 30;
+                    ChangeMask::bit(2)
+                } else {
+                    ChangeMask::NONE
+                }
+            });
+        }
+        assert_eq!(world.get_component::<Age1>(es[0]).unwrap().0, 10);
+        assert_eq!(world.get_component::<Age2>(es[3]).unwrap().0, 30);
+        let r0: Vec<Entity> = ComponentChanged::<Age0>::get_param(&world, &meta, &mut c0, world.tick()).iter().map(|e| *e).collect();
+        let r1: Vec<Entity> = ComponentChanged::<Age1>::get_param(&world, &meta, &mut c1, world.tick()).iter().map(|e| *e).collect();
+        let r2: Vec<Entity> = ComponentChanged::<Age2>::get_param(&world, &meta, &mut c2, world.tick()).iter().map(|e| *e).collect();
+        assert!(r0.is_empty());
+        assert_eq!(r1, vec![es[0]]);
+        assert_eq!(r2, vec![es[3]]);
+    }
+
+    #[test]
+    fn test_component_id() {
+        use crate::archetype::{ArchetypeInfo, ComponentInfo, COMPONENT_TICK};
+        use crate::component_id::{next_archetype_id, ComponentId, IdBits};
+        let a = ComponentInfo::of::<Age0>(0);
+        let b = ComponentInfo::of::<Age1>(COMPONENT_TICK);
+        // 同一进程内稳定，和tick信息无关
+        assert_eq!(a.id(), ComponentInfo::of::<Age0>(COMPONENT_TICK).id());
+        assert_eq!(a.id(), ComponentId::of(&TypeId::of::<Age0>()));
+        assert_ne!(a.id(), b.id());
+        // 组合和顺序无关
+        assert_eq!(
+            ComponentInfo::calc_id(&vec![a.clone(), b.clone()]),
+            ComponentInfo::calc_id(&vec![b.clone(), a.clone()])
+        );
+        assert_eq!(ComponentInfo::calc_id(&vec![a.clone(), a.clone()]), ComponentId::default());
+        let wide = cfg!(all(feature = "wide-ids", not(target_arch = "wasm32")));
+        assert_eq!(std::mem::size_of::<IdBits>(), if wide { 16 } else { 8 });
+
+        // 原型表中id相同但组件不同时，探测下一个id
+        let mut world = World::new();
+        let i0 = world.make_insert::<(Age0,)>();
+        let _ = world.make_insert::<(Age1,)>();
+        let e0 = i0.insert(&world, (Age0(0),));
+        let ar0 = world.get_archetype(world.get_entity_prototype(e0).unwrap().1).unwrap().clone();
+        let info = ArchetypeInfo::sort(vec![
+            world.get_column_by_id(&TypeId::of::<Age0>()).unwrap().clone(),
+            world.get_column_by_id(&TypeId::of::<Age1>()).unwrap().clone(),
+        ]);
+        // 让(Age0, Age1)的id被其他原型占用
+        world.archetype_map.insert(info.id, ar0.clone());
+        let i = world.make_insert::<(Age0, Age1)>();
+        let e = i.insert(&world, (Age0(1), Age1(1)));
+        let ar = world.get_archetype(world.get_entity_prototype(e).unwrap().1).unwrap().clone();
+        assert_eq!(ar.get_columns().len(), 2);
+        assert_eq!(ar.id(), next_archetype_id(info.id));
+        assert_eq!(world.get_archetype_by_info(&info).unwrap().index(), ar.index());
+        assert_eq!(world.get_component::<Age1>(e).unwrap().0, 1);
+        assert_eq!(world.get_component::<Age0>(e0).unwrap().0, 0);
+    }
+
+    #[test]
+    fn test_remove_hook() {
+        use std::sync::{Arc, Mutex};
+        #[derive(Component)]
+        struct Body(u32);
+        #[derive(Component)]
+        struct Handle(u32);
+        let log: Arc<Mutex<Vec<(&'static str, Entity, Option<u32>)>>> = Arc::default();
+        let mut world = World::new();
+        let l = log.clone();
+        world.on_remove::<Body>(move |ctx| {
+            // 读取同一实体上的其他组件，以及自身
+            assert!(ctx.get::<Body>().is_some());
+            l.lock().unwrap().push(("body", ctx.entity(), ctx.get::<Handle>().map(|h| h.0)));
+        });
+        let l = log.clone();
+        world.on_remove::<Handle>(move |ctx| {
+            l.lock().unwrap().push(("handle", ctx.entity(), ctx.get::<Body>().map(|b| b.0)));
+        });
+        let body = world.init_component::<Body>();
+        let handle = world.init_component::<Handle>();
+        let i = world.make_insert::<(Body, Handle, Age0)>();
+
+        // 销毁实体，两个钩子都在释放前调用，按列顺序
+        let e = i.insert(&world, (Body(1), Handle(7), Age0(0)));
+        world.destroy_entity(e).unwrap();
+        assert_eq!(*log.lock().unwrap(), vec![("body", e, Some(7)), ("handle", e, Some(1))]);
+        log.lock().unwrap().clear();
+
+        // 只移除Body，Handle还在实体上
+        let e = i.insert(&world, (Body(2), Handle(8), Age0(0)));
+        world.make_entity_editor().remove_components_by_index(e, &[body]).unwrap();
+        assert_eq!(*log.lock().unwrap(), vec![("body", e, Some(8))]);
+        log.lock().unwrap().clear();
+
+        // 同一次alter中一起移除，钩子仍能读取对方
+        let e = i.insert(&world, (Body(3), Handle(9), Age0(0)));
+        world.make_entity_editor().remove_components_by_index(e, &[handle, body]).unwrap();
+        assert_eq!(*log.lock().unwrap(), vec![("body", e, Some(9)), ("handle", e, Some(3))]);
+        assert!(world.get_component::<Age0>(e).is_ok());
+    }
+
+    #[test]
+    fn test_query_single() {
+        let mut world = World::new();
+        let mut state = world.make_query::<&mut Age1, With<Age2>>();
+        // 没有匹配
+        assert!(matches!(state.single(&world), Err(QueryError::NoSuchEntity(e)) if e.is_null()));
+        let i = world.make_insert::<(Age1, Age2)>();
+        let e = i.insert(&world, (Age1(1), Age2(0)));
+        world.make_insert::<(Age1,)>().insert(&world, (Age1(5),));
+        // 只有一个
+        assert_eq!(state.single(&world).unwrap().0, 1);
+        state.single_mut(&mut world).unwrap().0 = 2;
+        {
+            let mut q = state.get_param(&world);
+            assert_eq!(q.single().unwrap().0, 2);
+            q.single_mut().unwrap().0 += 1;
+        }
+        assert_eq!(world.get_component::<Age1>(e).unwrap().0, 3);
+        // 多于一个
+        i.insert(&world, (Age1(7), Age2(0)));
+        assert_eq!(state.single(&world).err(), Some(QueryError::TooManyEntities));
+        let mut q = state.get_param(&world);
+        assert!(matches!(q.single_mut(), Err(QueryError::TooManyEntities)));
+    }
+
+    #[test]
+    fn test_get_many() {
+        let mut world = World::new();
+        let parent = world.make_insert::<(Age0, Age1)>().insert(&world, (Age0(1), Age1(0)));
+        let child = world.make_insert::<(Age0,)>().insert(&world, (Age0(2),));
+        let other = world.make_insert::<(Age1,)>().insert(&world, (Age1(3),));
+        let mut state = world.make_query::<&mut Age0, ()>();
+        state.align(&world);
+        let mut q = state.get_param(&world);
+        // 两个原型的实体
+        let [a, b] = q.get_many([parent, child]).unwrap();
+        assert_eq!((a.0, b.0), (1, 2));
+        assert_eq!(q.get_many([child, other]).err(), Some(QueryError::NoMatchArchetype));
+        // 交换父子的数据
+        let [mut a, mut b] = q.get_many_mut([parent, child]).unwrap();
+        std::mem::swap(&mut a.0, &mut b.0);
+        // 重复的实体被拒绝
+        assert!(matches!(q.get_many_mut([parent, child, parent]), Err(QueryError::DuplicateEntity(e)) if e == parent));
+        // 只读时允许重复
+        assert!(q.get_many([child, child]).is_ok());
+        drop(q);
+        assert_eq!(world.get_component::<Age0>(parent).unwrap().0, 2);
+        assert_eq!(world.get_component::<Age0>(child).unwrap().0, 1);
+    }
+    #[test]
+    fn test_conflict_prefilter() {
+        // 读写不相交的关系表跳过检查，相交的仍然报告冲突
+        let mut meta: SystemMeta = SystemMeta::new(TypeInfo::of::<SystemMeta>());
+        for i in 0..8usize {
+            meta.relate(Relation::Write(i.into()));
+            meta.related_ok();
+        }
+        assert_eq!(meta.find_conflict(), None);
+        meta.relate(Relation::Read(5usize.into()));
+        meta.related_ok();
+        assert_eq!(meta.find_conflict(), Some((5, 8, 5usize.into())));
+        // ReadAll不能被快速排除
+        let mut meta: SystemMeta = SystemMeta::new(TypeInfo::of::<SystemMeta>());
+        meta.relate(Relation::Write(1usize.into()));
+        meta.related_ok();
+        meta.relate(Relation::ReadAll);
+        meta.related_ok();
+        assert_eq!(meta.find_conflict(), Some((0, 1, 1usize.into())));
+    }
+    #[bench]
+    fn bench_find_conflict(b: &mut Bencher) {
+        // 大量读写不相交的查询
+        let mut meta: SystemMeta = SystemMeta::new(TypeInfo::of::<SystemMeta>());
+        for i in 0..256usize {
+            meta.relate(Relation::Read((i * 2).into()));
+            meta.relate(Relation::Write((i * 2 + 1).into()));
+            meta.related_ok();
+        }
+        b.iter(|| assert_eq!(meta.find_conflict(), None));
+    }
+
+    #[test]
+    fn test_iter_indexed() {
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0, Age1)>();
+        let es: Vec<_> = (0..5).map(|n| i.insert(&world, (Age0(n), Age1(0)))).collect();
+        world.make_insert::<(Age0,)>().insert(&world, (Age0(9),));
+        // 留下空位
+        world.destroy_entity(es[2]).unwrap();
+        let mut state = world.make_query::<(Entity, &mut Age0), ()>();
+        state.align(&world);
+        let mut q = state.get_param(&world);
+        let len = q.dense_len();
+        assert_eq!(len, 5);
+        let first: Vec<_> = q.iter_indexed().map(|(i, (e, _))| (i, e)).collect();
+        let second: Vec<_> = q.iter_indexed_mut().map(|(i, (e, _))| (i, e)).collect();
+        assert_eq!(first, second);
+        assert_eq!(first.iter().map(|(i, _)| *i).collect::<Vec<_>>(), (0..len).collect::<Vec<_>>());
+        let mut buf = vec![0; len];
+        for (i, (_, a)) in q.iter_indexed() {
+            buf[i] = a.0;
+        }
+        assert_eq!(buf.iter().sum::<usize>(), 17);
+    }
+
+    #[test]
+    #[should_panic(expected = "row filtered")]
+    fn test_iter_indexed_listener() {
+        let mut world = World::new();
+        let mut state = world.make_query::<&Age0, Changed<Age0>>();
+        state.align(&world);
+        let q = state.get_param(&world);
+        q.dense_len();
+    }
+
+    #[test]
+    fn test_query_iter_mut() {
+        let mut world = World::new();
+        let mut changed = world.make_query::<Entity, Changed<Age0>>();
+        let i = world.make_insert::<(Age0,)>();
+        for n in 0..4 {
+            i.insert(&world, (Age0(n),));
+        }
+        changed.align(&world);
+        assert_eq!(changed.get_param(&world).iter().count(), 4);
+        world.increment_tick();
+        // 只有实际写入的组件记录修改
+        let mut state = world.make_query::<&mut Age0, ()>();
+        let it: QueryIterMut<'_, &mut Age0> = state.iter_mut(&mut world);
+        for mut a in it {
+            if a.0 % 2 == 0 {
+                a.0 += 10;
+            }
+        }
+        assert_eq!(changed.get_param(&world).iter().count(), 2);
+        world.increment_tick();
+        {
+            let mut q = state.get_param(&world);
+            for mut a in q.iter_mut() {
+                a.0 += 1;
+            }
+        }
+        assert_eq!(changed.get_param(&world).iter().count(), 4);
+        let sum: usize = state.iter(&world).map(|a| a.0).sum();
+        assert_eq!(sum, 11 + 2 + 13 + 4);
+    }
+
+    #[test]
+    fn test_par_iter() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0, Age1)>();
+        for n in 0..60_000 {
+            i.insert(&world, (Age0(n), Age1(0)));
+        }
+        let i = world.make_insert::<(Age0,)>();
+        for n in 0..40_000 {
+            i.insert(&world, (Age0(n),));
+        }
+        let mut state = world.make_query::<&mut Age0, ()>();
+        let seq: usize = state.iter(&world).map(|a| a.0).sum();
+        let mut q = state.get_param(&world);
+        // 多线程求和和单线程相同
+        let sum = AtomicUsize::new(0);
+        q.par_for_each(4, |a| {
+            sum.fetch_add(a.0, Ordering::Relaxed);
+        });
+        assert_eq!(sum.load(Ordering::Relaxed), seq);
+        // 块是单线程迭代的划分
+        let sum = AtomicUsize::new(0);
+        let count = AtomicUsize::new(0);
+        std::thread::scope(|s| {
+            for chunk in q.chunks(4096) {
+                let (sum, count) = (&sum, &count);
+                s.spawn(move || {
+                    for a in chunk {
+                        sum.fetch_add(a.0, Ordering::Relaxed);
+                        count.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+        assert_eq!(sum.load(Ordering::Relaxed), seq);
+        assert_eq!(count.load(Ordering::Relaxed), 100_000);
+        // 并行修改
+        q.par_for_each_mut(4, |mut a| a.0 += 1);
+        drop(q);
+        let after: usize = state.iter(&world).map(|a| a.0).sum();
+        assert_eq!(after, seq + 100_000);
+    }
+
+    #[test]
+    fn test_query_for_each() {
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0, Age1)>();
+        let es: Vec<_> = (0..4).map(|n| i.insert(&world, (Age0(n), Age1(n)))).collect();
+        let mut state = world.make_query::<(&mut Age0, &Age1), ()>();
+        let mut sum = 0;
+        state.for_each(&world, world.tick(), |(mut a, b)| {
+            a.0 += b.0;
+            sum += a.0;
+        });
+        assert_eq!(sum, 12);
+        let mut seen = Vec::new();
+        {
+            let mut q = state.get_param(&world);
+            q.for_each_with_entity(|e, (a, _)| seen.push((e, a.0)));
+            q.for_each(|(mut a, _)| a.0 = 0);
+        }
+        let mut expect: Vec<_> = es.iter().enumerate().map(|(n, e)| (*e, n * 2)).collect();
+        seen.sort();
+        expect.sort();
+        assert_eq!(seen, expect);
+        let mut count = 0;
+        state.for_each_with_entity(&world, world.tick(), |e, (a, _)| {
+            assert!(es.contains(&e));
+            assert_eq!(a.0, 0);
+            count += 1;
+        });
+        assert_eq!(count, 4);
+    }
+
+    #[test]
+    fn test_align_filter_once() {
+        let mut world = World::new();
+        world.make_insert::<(Age0, Age1)>().insert(&world, (Age0(0), Age1(0)));
+        world.make_insert::<(Age0,)>().insert(&world, (Age0(0),));
+        world.make_insert::<(Age0, Age2)>().insert(&world, (Age0(0), Age2(0)));
+        let mut q = world.make_query::<&Age0, With<Age1>>();
+        q.align(&world);
+        // 只判断包含Age1的原型
+        assert_eq!(q.statistics().archetypes_filtered, 1);
+        q.align(&world);
+        assert_eq!(q.iter(&world).count(), 1);
+        assert_eq!(q.statistics().archetypes_filtered, 1);
+        // 新增的原型只判断一次
+        world.make_insert::<(Age0, Age1, Age2)>().insert(&world, (Age0(0), Age1(0), Age2(0)));
+        assert_eq!(q.iter(&world).count(), 2);
+        q.align(&world);
+        assert_eq!(q.statistics().archetypes_filtered, 2);
+    }
+
+    #[test]
+    fn test_iter_mut_changed_systems() {
+        struct Seen(Vec<Entity>);
+        // 只修改偶数，修改后仍为偶数
+        fn write_even(mut q: Query<&mut Age0>) {
+            for mut a in q.iter_mut() {
+                if a.0 % 2 == 0 {
+                    a.0 += 2;
+                }
+            }
+        }
+        fn read_changed(q: Query<Entity, Changed<Age0>>, mut seen: SingleResMut<Seen>) {
+            seen.0 = q.iter().collect();
+        }
+        let mut app = crate::prelude::App::new();
+        app.world.insert_single_res(Seen(Vec::new()));
+        let i = app.world.make_insert::<(Age0,)>();
+        let es: Vec<_> = (0..6).map(|n| i.insert(&app.world, (Age0(n),))).collect();
+        app.add_system(Update, write_even);
+        app.add_system(Update, read_changed);
+        // 第一次运行，新增的实体都算修改
+        app.run();
+        assert_eq!(app.world.get_single_res::<Seen>().unwrap().0.len(), 6);
+        app.run();
+        let mut seen = app.world.get_single_res::<Seen>().unwrap().0.clone();
+        seen.sort();
+        let mut expect: Vec<_> = es.iter().copied().step_by(2).collect();
+        expect.sort();
+        assert_eq!(seen, expect);
+    }
+
+    #[test]
+    fn test_table_bitset() {
+        let mut world = World::new();
+        let a0 = world.init_component::<Age0>();
+        let a1 = world.init_component::<Age1>();
+        // 注册很多组件，原型的最大组件索引远小于world的组件数量
+        world.init_component::<Age10>();
+        world.init_component::<Age11>();
+        world.init_component::<Age12>();
+        let a19 = world.init_component::<Age19>();
+        let i = world.make_insert::<(Age0, Age1)>();
+        let e = i.insert(&world, (Age0(0), Age1(0)));
+        let index = world.get_entity_location(e).unwrap().archetype_index;
+        let ar = world.get_archetype(index).unwrap();
+        assert!(ar.contains(a0) && ar.contains(a1));
+        assert!(!ar.contains(a19));
+        assert!(!ar.contains(ComponentIndex::null()));
+        assert!(ar.component_bitset().len() <= a1.index() + 1);
+        // world大小的位图
+        let mut set = FixedBitSet::with_capacity(a19.index() + 1);
+        set.insert(a0.index());
+        assert!(ar.contains_all(&set) && ar.contains_any(&set));
+        set.insert(a19.index());
+        assert!(!ar.contains_all(&set) && ar.contains_any(&set));
+        set.clear();
+        set.insert(a19.index());
+        assert!(!ar.contains_all(&set) && !ar.contains_any(&set));
+        // 空集
+        assert!(ar.contains_all(&FixedBitSet::new()) && !ar.contains_any(&FixedBitSet::new()));
+    }
+
+    #[test]
+    fn test_query_iter_len() {
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0, Age1)>();
+        let es: Vec<_> = (0..5).map(|n| i.insert(&world, (Age0(n), Age1(n)))).collect();
+        world.make_insert::<(Age0,)>().insert(&world, (Age0(9),));
+        // 留下空位
+        world.destroy_entity(es[1]).unwrap();
+        let mut q = world.make_query::<&Age0, ()>();
+        let mut it = q.iter(&world);
+        assert_eq!(it.size_hint(), (5, Some(5)));
+        assert_eq!(it.exact_len(), Some(5));
+        for n in (0..5).rev() {
+            assert!(it.next().is_some());
+            assert_eq!(it.exact_len(), Some(n));
+        }
+        assert!(it.next().is_none());
+        assert_eq!(it.size_hint(), (0, Some(0)));
+        drop(it);
+        // 逐行过滤的查询只有上限
+        let mut q = world.make_query::<&Age0, Changed<Age1>>();
+        let it = q.iter(&world);
+        assert_eq!(it.size_hint(), (0, Some(4)));
+        assert_eq!(it.exact_len(), None);
+        assert!(it.count() <= 4);
+        // 逐行过滤的查询也可以反向枚举
+        let n = q.iter(&world).count();
+        assert_eq!(q.iter(&world).rev().enumerate().count(), n);
+        // 自定义的过滤器默认是逐行过滤
+        struct Odd;
+        impl FilterComponents for Odd {
+            type Filter<'w> = ();
+            type State = ();
+            fn init_state(_world: &mut World, _meta: &mut SystemMeta) -> Self::State {}
+            fn init_filter<'w>(
+                _world: &'w World,
+                _state: &'w Self::State,
+                _index: crate::archetype::ArchetypeIndex,
+                _tick: Tick,
+                _last_run: Tick,
+            ) -> Self::Filter<'w> {
+            }
+            fn filter<'w>(_filter: &Self::Filter<'w>, row: Row, _e: Entity) -> bool {
+                row.0 % 2 == 0
+            }
+        }
+        let mut q = world.make_query::<&Age0, Odd>();
+        let it = q.iter(&world);
+        assert_eq!(it.exact_len(), None);
+        assert_eq!(it.size_hint().0, 0);
+        drop(it);
+        let mut q = world.make_query::<&Age0, (With<Age1>, Without<Age2>)>();
+        assert_eq!(q.iter(&world).exact_len(), Some(4));
+    }
+
+    #[test]
+    fn test_ref_ticks() {
+        let mut world = World::new();
+        let mut q = world.make_query::<(Entity, Ref<Age0>), ()>();
+        let added = world.tick();
+        let e = world.make_insert::<(Age0,)>().insert(&world, (Age0(1),));
+        world.increment_tick();
+        world.increment_tick();
+        let changed = world.tick();
+        let mut m = world.make_query::<&mut Age0, ()>();
+        for mut a in m.iter_mut(&mut world) {
+            a.0 = 2;
+        }
+        let items: Vec<_> = q.iter(&world).collect();
+        assert_eq!(items.len(), 1);
+        let (e1, r) = &items[0];
+        assert_eq!(*e1, e);
+        assert_eq!(r.0, 2);
+        assert_eq!(r.added_tick(), added);
+        assert_eq!(r.last_changed(), changed);
+        assert!(added < changed);
+        assert!(r.is_changed_since(added) && !r.is_changed_since(changed));
+        assert!(!r.is_added_since(added));
+    }
+
+    #[test]
+    fn test_removed_filter() {
+        let mut world = World::new();
+        let mut q = world.make_query::<Entity, (With<Age0>, Removed<Age1>)>();
+        let i = world.make_insert::<(Age0, Age1)>();
+        let es: Vec<_> = (0..4).map(|n| i.insert(&world, (Age0(n), Age1(n)))).collect();
+        world.settle();
+        let mut alter = world.make_alter::<(), (), (), (Age1,)>();
+        {
+            let mut a = alter.get_param(&world);
+            a.alter(es[1], ()).unwrap();
+            a.alter(es[3], ()).unwrap();
+        }
+        world.increment_tick();
+        let mut removed: Vec<_> = q.iter(&world).collect();
+        removed.sort();
+        let mut expect = vec![es[1], es[3]];
+        expect.sort();
+        assert_eq!(removed, expect);
+        // 同一tick内再次迭代结果相同
+        assert_eq!(q.iter(&world).count(), 2);
+        // 下一次运行不再报告
+        world.increment_tick();
+        assert_eq!(q.iter(&world).count(), 0);
+    }
+
+    #[test]
+    fn test_unchanged_filter() {
+        let mut world = World::new();
+        let mut q = world.make_query::<(Entity, &mut Age0), Unchanged<Age0>>();
+        let mut na = world.make_query::<Entity, NotAdded<Age0>>();
+        let i = world.make_insert::<(Age0,)>();
+        let es: Vec<_> = (0..6).map(|n| i.insert(&world, (Age0(n),))).collect();
+        q.align(&world);
+        na.align(&world);
+        // 新增也算修改
+        assert_eq!(q.get_param(&world).iter().count(), 0);
+        assert_eq!(na.get_param(&world).iter().count(), 0);
+        world.increment_tick();
+        // 其他system修改一半
+        let mut m = world.make_query::<&mut Age0, ()>();
+        for mut a in m.iter_mut(&mut world) {
+            if a.0 % 2 == 0 {
+                a.0 += 10;
+            }
+        }
+        world.increment_tick();
+        let mut visited = Vec::new();
+        {
+            let mut p = q.get_param(&world);
+            for (e, mut a) in p.iter_mut() {
+                visited.push(e);
+                a.0 += 100;
+            }
+        }
+        visited.sort();
+        let mut expect: Vec<_> = es.iter().copied().skip(1).step_by(2).collect();
+        expect.sort();
+        assert_eq!(visited, expect);
+        // 自己的修改不影响下次的判断
+        world.increment_tick();
+        assert_eq!(q.get_param(&world).iter().count(), 6);
+        assert_eq!(na.get_param(&world).iter().count(), 6);
+    }
+
+    #[test]
+    fn test_or_default() {
+        let mut world = World::new();
+        // 覆盖默认值
+        world.insert_single_res(Age1(7));
+        let i = world.make_insert::<(Age0, Age1)>();
+        i.insert(&world, (Age0(1), Age1(5)));
+        i.insert(&world, (Age0(2), Age1(6)));
+        world.make_insert::<(Age0,)>().insert(&world, (Age0(3),));
+        world.make_insert::<(Age0, Age2)>().insert(&world, (Age0(4), Age2(0)));
+        // 没有Age1的原型不会被排除
+        let mut q = world.make_query::<(&Age0, OrDefault<Age1>), ()>();
+        let mut items: Vec<_> = q.iter(&world).map(|(a, b)| (a.0, b.0)).collect();
+        items.sort();
+        assert_eq!(items, vec![(1, 5), (2, 6), (3, 7), (4, 7)]);
+    }
+
+    #[test]
+    fn test_any_of() {
+        let mut world = World::new();
+        let a = world.make_insert::<(Age0,)>().insert(&world, (Age0(1),));
+        let b = world.make_insert::<(Age1,)>().insert(&world, (Age1(2),));
+        let ab = world
+            .make_insert::<(Age0, Age1)>()
+            .insert(&world, (Age0(3), Age1(4)));
+        world.make_insert::<(Age2,)>().insert(&world, (Age2(5),));
+        let mut q = world.make_query::<(Entity, AnyOf<(&Age0, &mut Age1)>), ()>();
+        let mut r: Vec<_> = q
+            .iter_mut(&mut world)
+            .map(|(e, (a0, a1))| {
+                let v1 = a1.map(|mut a1| {
+                    a1.0 += 10;
+                    a1.0
+                });
+                (e, a0.map(|a0| a0.0), v1)
+            })
+            .collect();
+        r.sort();
+        // 只有Age2的原型不匹配
+        let mut expect = vec![
+            (a, Some(1), None),
+            (b, None, Some(12)),
+            (ab, Some(3), Some(14)),
+        ];
+        expect.sort();
+        assert_eq!(r, expect);
+        let mut q1 = world.make_query::<&Age1, ()>();
+        let mut v: Vec<_> = q1.iter(&world).map(|a| a.0).collect();
+        v.sort();
+        assert_eq!(v, vec![12, 14]);
+    }
+
+    #[test]
+    fn test_changed_across_frames() {
+        struct Seen(Vec<Vec<Entity>>);
+        struct Target(Entity);
+        // 第一个system，读取修改
+        fn read_changed(q: Query<Entity, Changed<Age0>>, mut seen: SingleResMut<Seen>) {
+            let mut v: Vec<_> = q.iter().collect();
+            v.sort();
+            seen.0.push(v);
+        }
+        fn middle(mut q: Query<&mut Age1>) {
+            for mut a in q.iter_mut() {
+                a.0 += 1;
+            }
+        }
+        // 最后一个system，修改同一个实体
+        fn write_last(mut q: Query<(Entity, &mut Age0)>, target: SingleRes<Target>) {
+            for (e, mut a) in q.iter_mut() {
+                if e == target.0 {
+                    a.0 += 1;
+                }
+            }
+        }
+        let mut app = crate::prelude::App::new();
+        app.world.insert_single_res(Seen(Vec::new()));
+        let i = app.world.make_insert::<(Age0, Age1)>();
+        let es: Vec<_> = (0..4).map(|n| i.insert(&app.world, (Age0(n), Age1(n)))).collect();
+        app.world.insert_single_res(Target(es[2]));
+        app.add_system(Update, read_changed);
+        app.add_system(Update, middle);
+        app.add_system(Update, write_last);
+        for _ in 0..3 {
+            app.run();
+        }
+        let seen = &app.world.get_single_res::<Seen>().unwrap().0;
+        let mut all = es.clone();
+        all.sort();
+        // 第一帧新增的都算修改，之后每帧都能看到上一帧最后的修改
+        assert_eq!(seen, &vec![all, vec![es[2]], vec![es[2]]]);
+    }
+
+    #[test]
+    fn test_world_merge() {
+        #[derive(Component)]
+        struct Follow(Entity);
+        impl MapEntities for Follow {
+            fn map_entities(&mut self, f: &mut dyn FnMut(Entity) -> Entity) {
+                self.0 = f(self.0);
+            }
+        }
+        // 3个原型的子World，有内部引用
+        fn prefab() -> (World, Vec<Entity>) {
+            let mut w = World::new();
+            let root = w.make_insert::<(Age0,)>().insert(&w, (Age0(1),));
+            let child = w
+                .make_insert::<(Age0, Follow)>()
+                .insert(&w, (Age0(2), Follow(root)));
+            let leaf = w
+                .make_insert::<(Age1, Follow)>()
+                .insert(&w, (Age1(3), Follow(child)));
+            (w, vec![root, child, leaf])
+        }
+        let mut world = World::new();
+        world.register_reference_policy::<Follow>(ReferencePolicy::Nullify);
+        let mut added = world.make_query::<Entity, Added<Age0>>();
+        added.align(&world);
+        drop(added.get_param(&world));
+        world.increment_tick();
+
+        let mut copies = Vec::new();
+        for n in 0..2 {
+            let (sub, olds) = prefab();
+            let mut mapper = EntityMapper::default();
+            let report = world.merge(sub, &mut mapper).unwrap();
+            assert_eq!(report.entities, 3);
+            assert_eq!(report.archetypes, 3);
+            assert_eq!(report.archetypes_created, if n == 0 { 3 } else { 0 });
+            let news: Vec<Entity> = olds.iter().map(|e| mapper.get(*e).unwrap()).collect();
+            // 导入的实体都能被Added看到
+            added.align(&world);
+            let mut v: Vec<_> = added.get_param(&world).iter().collect();
+            v.sort();
+            let mut expect = vec![news[0], news[1]];
+            expect.sort();
+            assert_eq!(v, expect);
+            world.increment_tick();
+            copies.push(news);
+        }
+        for news in copies.iter() {
+            assert_eq!(world.get_component::<Age0>(news[0]).unwrap().0, 1);
+            assert_eq!(world.get_component::<Age0>(news[1]).unwrap().0, 2);
+            assert_eq!(world.get_component::<Age1>(news[2]).unwrap().0, 3);
+            // 内部引用指向本次导入的实体
+            assert_eq!(world.get_component::<Follow>(news[1]).unwrap().0, news[0]);
+            assert_eq!(world.get_component::<Follow>(news[2]).unwrap().0, news[1]);
+        }
+        // 两份互相独立
+        world.get_component_mut::<Age0>(copies[0][0]).unwrap().0 = 10;
+        assert_eq!(world.get_component::<Age0>(copies[1][0]).unwrap().0, 1);
+        world.settle();
+        world.destroy_entity(copies[0][0]).unwrap();
+        world.settle();
+        assert!(world.get_component::<Follow>(copies[0][1]).unwrap().0.is_null());
+        assert_eq!(world.get_component::<Follow>(copies[1][1]).unwrap().0, copies[1][0]);
+    }
+
+    #[test]
+    fn test_change_trackers() {
+        let mut world = World::new();
+        let mut q = world.make_query::<(Entity, ChangeTrackers<Age0>), ()>();
+        let i = world.make_insert::<(Age0,)>();
+        let e0 = i.insert(&world, (Age0(0),));
+        let e1 = i.insert(&world, (Age0(1),));
+        let added = world.tick();
+        for (_, t) in q.iter(&world) {
+            assert!(t.is_added(added));
+            assert!(t.is_changed(Tick::default(), added));
+        }
+        world.increment_tick();
+        let last_run = added;
+        let mut qm = world.make_query::<&mut Age0, ()>();
+        qm.get_param(&world).get_mut(e1).unwrap().0 = 10;
+        let now = world.tick();
+        let mut r: Vec<_> = q.iter(&world).collect();
+        r.sort_by_key(|(e, _)| *e);
+        assert_eq!(
+            r,
+            vec![
+                (e0, ComponentTicks { added, changed: added }),
+                (e1, ComponentTicks { added, changed: now })
+            ]
+        );
+        // 新添加和修改可以区分
+        assert!(!r[1].1.is_added(now) && r[1].1.is_changed(last_run, now));
+        assert!(!r[0].1.is_added(now) && !r[0].1.is_changed(last_run, now));
+    }
+
+    #[test]
+    fn test_fragmentation() {
+        #[derive(Default)]
+        struct Log(Vec<SettleReport>);
+        impl WorldExtension for Log {
+            fn on_settle(&mut self, report: &SettleReport) {
+                self.0.push(*report);
+            }
+        }
+        let mut world = World::new();
+        world.insert_extension(Log::default());
+        let i01 = world.make_insert::<(Age0, Age1)>();
+        let i2 = world.make_insert::<(Age2,)>();
+        let es01: Vec<_> = (0..10).map(|n| i01.insert(&world, (Age0(n), Age1(n)))).collect();
+        let es2: Vec<_> = (0..5).map(|n| i2.insert(&world, (Age2(n),))).collect();
+        world.settle();
+        assert_eq!(world.fragmentation_report().total.holes, 0);
+        for n in [1, 3, 5] {
+            world.destroy_entity(es01[n]).unwrap();
+        }
+        world.destroy_entity(es2[0]).unwrap();
+        let ar01 = world.get_entity_location(es01[0]).unwrap().archetype_index;
+        let ar2 = world.get_entity_location(es2[1]).unwrap().archetype_index;
+        let f01 = Fragmentation {
+            len: 10,
+            holes: 3,
+            pending_removes: 3,
+            wasted_bytes: 3 * 2 * mem::size_of::<usize>(),
+        };
+        let f2 = Fragmentation {
+            len: 5,
+            holes: 1,
+            pending_removes: 1,
+            wasted_bytes: mem::size_of::<usize>(),
+        };
+        assert_eq!(world.get_archetype(ar01).unwrap().fragmentation(), f01);
+        assert!((f01.hole_ratio() - 0.3).abs() < 1e-6);
+        let report = world.fragmentation_report();
+        // 浪费多的原型在前
+        assert_eq!(report.tables, vec![(ar01, f01), (ar2, f2)]);
+        let total = Fragmentation {
+            len: 15,
+            holes: 4,
+            pending_removes: 4,
+            wasted_bytes: 7 * mem::size_of::<usize>(),
+        };
+        assert_eq!(report.total, total);
+
+        world.settle();
+        let report = world.fragmentation_report();
+        assert_eq!(report.total.holes, 0);
+        assert_eq!(report.total.pending_removes, 0);
+        assert_eq!(report.total.wasted_bytes, 0);
+        assert!(report.tables.is_empty());
+        let log = &world.extension::<Log>().unwrap().0;
+        let last = log.last().unwrap();
+        assert_eq!(last.fragmentation_before, total);
+        assert_eq!(last.fragmentation_after.holes, 0);
+        assert_eq!(last.fragmentation_after.len, 11);
+    }
+
+    #[test]
+    fn test_defer_add() {
+        struct Seen(Vec<usize>);
+        fn mark(q: Query<(Entity, &Age0)>) {
+            for (e, a) in q.iter() {
+                if a.0 % 2 == 0 {
+                    // 同一组件添加两次，保留最后的值
+                    q.defer_add(e, Age1(1)).unwrap();
+                    q.defer_add(e, Age1(a.0)).unwrap();
+                }
+            }
+        }
+        fn read(q: Query<&Age1>, mut seen: SingleResMut<Seen>) {
+            let mut v: Vec<_> = q.iter().map(|a| a.0).collect();
+            v.sort();
+            seen.0 = v;
+        }
+        let mut app = crate::prelude::App::new();
+        app.world.insert_single_res(Seen(Vec::new()));
+        let i = app.world.make_insert::<(Age0,)>();
+        for n in 0..5 {
+            i.insert(&app.world, (Age0(n),));
+        }
+        app.add_system(Update, mark);
+        app.add_system(PostUpdate, read);
+        app.run();
+        // 之后阶段的system可以看到
+        assert_eq!(app.world.get_single_res::<Seen>().unwrap().0, vec![0, 2, 4]);
+
+        // 添加后实体被销毁，则忽略
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0,)>();
+        let e0 = i.insert(&world, (Age0(0),));
+        let e1 = i.insert(&world, (Age0(1),));
+        let mut q = world.make_query::<Entity, ()>();
+        q.align(&world);
+        {
+            let p = q.get_param(&world);
+            p.defer_add(e0, Age1(0)).unwrap();
+            p.defer_add(e1, Age1(1)).unwrap();
+        }
+        world.destroy_entity(e0).unwrap();
+        assert_eq!(
+            q.get_param(&world).defer_add(e0, Age1(0)),
+            Err(QueryError::NoSuchEntity(e0))
+        );
+        assert_eq!(world.flush_deferred_adds(), 1);
+        assert_eq!(world.get_component::<Age1>(e1).unwrap().0, 1);
+        assert_eq!(world.flush_deferred_adds(), 0);
+    }
+
+    #[test]
+    fn test_set_if_neq() {
+        let mut world = World::new();
+        let mut qc = world.make_query::<Entity, Changed<Age0>>();
+        let i = world.make_insert::<(Age0,)>();
+        let es: Vec<_> = (0..4).map(|n| i.insert(&world, (Age0(n),))).collect();
+        qc.align(&world);
+        assert_eq!(qc.get_param(&world).iter().count(), 4);
+        world.increment_tick();
+        let mut q = world.make_query::<&mut Age0, ()>();
+        let mut written = 0;
+        for mut a in q.iter_mut(&mut world) {
+            // 只有奇数的值变化
+            let v = a.0 - a.0 % 2;
+            if a.set_if_neq(Age0(v)) {
+                written += 1;
+            }
+            // 绕过修改记录
+            a.bypass_change_detection().0 += 0;
+        }
+        assert_eq!(written, 2);
+        world.increment_tick();
+        let mut r: Vec<_> = qc.get_param(&world).iter().collect();
+        r.sort();
+        let mut expect = vec![es[1], es[3]];
+        expect.sort();
+        assert_eq!(r, expect);
+        assert_eq!(world.get_component::<Age0>(es[3]).unwrap().0, 2);
+    }
+
+    #[test]
+    fn test_stamp() {
+        #[derive(Component, Clone, Debug, PartialEq)]
+        struct Name(String);
+        #[derive(Component, Clone, Copy, Debug, PartialEq)]
+        struct Pos(f32, f32);
+        #[derive(Component, Clone, Copy, Debug, PartialEq)]
+        struct Team(u32);
+        let mut world = World::new();
+        world.register_clone::<Name>();
+        let prefab = world
+            .make_insert::<(Name, Pos, Age0)>()
+            .insert(&world, (Name("orc".to_string()), Pos(0.0, 0.0), Age0(7)));
+        let mut added = world.make_query::<Entity, Added<Name>>();
+        added.align(&world);
+        drop(added.get_param(&world));
+        world.increment_tick();
+        let es = world
+            .stamp(prefab, 1000, |i, w| {
+                w.set(Pos(i as f32, 1.0));
+                // 不在预制体原型上的组件
+                if i % 2 == 0 {
+                    w.set(Team(1));
+                }
+            })
+            .unwrap();
+        assert_eq!(es.len(), 1000);
+        for (i, e) in es.iter().enumerate() {
+            assert_eq!(world.get_component::<Name>(*e).unwrap().0, "orc");
+            assert_eq!(*world.get_component::<Pos>(*e).unwrap(), Pos(i as f32, 1.0));
+            assert_eq!(world.get_component::<Age0>(*e).unwrap().0, 7);
+            assert_eq!(world.get_component::<Team>(*e).is_ok(), i % 2 == 0);
+        }
+        // 预制体不变
+        assert_eq!(*world.get_component::<Pos>(prefab).unwrap(), Pos(0.0, 0.0));
+        assert!(world.get_component::<Team>(prefab).is_err());
+        // 全部实例都是新添加的
+        added.align(&world);
+        assert_eq!(added.get_param(&world).iter().count(), 1000);
+        // 实例之间互相独立
+        world.get_component_mut::<Name>(es[0]).unwrap().0.push('!');
+        assert_eq!(world.get_component::<Name>(es[1]).unwrap().0, "orc");
+        assert_eq!(world.get_component::<Name>(prefab).unwrap().0, "orc");
+        assert_eq!(world.stamp(es[0], 1, |_, _| {}).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_res_res_mut() {
+        use crate::system_params::SystemParam;
+        struct Score(u32);
+        let mut world = World::new();
+        world.insert_single_res(Score(1));
+        let mut m1 = SystemMeta::new(TypeInfo::of::<u8>());
+        let mut s1 = <ResMut<Score> as SystemParam>::init_state(&mut world, &mut m1);
+        let mut m2 = SystemMeta::new(TypeInfo::of::<u16>());
+        let _s2 = <ResMut<Score> as SystemParam>::init_state(&mut world, &mut m2);
+        let mut m3 = SystemMeta::new(TypeInfo::of::<u32>());
+        let mut s3 = <Res<Score> as SystemParam>::init_state(&mut world, &mut m3);
+        // 两个写冲突，读写冲突
+        let id = TypeId::of::<Score>();
+        assert_eq!(m1.res_related.check_rw(&m2.res_related), Some(id));
+        assert_eq!(m3.res_related.check_rw(&m1.res_related), Some(id));
+        assert!(!m1.is_read_only() && m3.is_read_only());
+
+        world.increment_tick();
+        {
+            let tick = world.tick();
+            let mut r = <ResMut<Score> as SystemParam>::get_param(&world, &m1, &mut s1, tick);
+            r.0 = 5;
+            assert_eq!(r.changed_tick(), tick);
+        }
+        let tick = world.tick();
+        let r = <Res<Score> as SystemParam>::get_param(&world, &m3, &mut s3, tick);
+        assert_eq!(r.0, 5);
+        assert!(r.is_changed());
+    }
+
+    #[test]
+    fn test_query_contains() {
+        let mut world = World::new();
+        let e0 = world.make_insert::<(Age0,)>().insert(&world, (Age0(0),));
+        let e1 = world
+            .make_insert::<(Age0, Age1)>()
+            .insert(&world, (Age0(1), Age1(1)));
+        let e2 = world.make_insert::<(Age1,)>().insert(&world, (Age1(2),));
+        let mut q = world.make_query::<&Age0, Without<Age1>>();
+        q.align(&world);
+        assert!(q.contains(&world, e0));
+        assert!(!q.contains(&world, e1));
+        assert!(!q.contains(&world, e2));
+        {
+            let p = q.get_param(&world);
+            // 和get的检查一致
+            for e in [e0, e1, e2] {
+                assert_eq!(p.contains(e), p.get(e).is_ok());
+            }
+        }
+        world.destroy_entity(e0).unwrap();
+        assert!(!q.contains(&world, e0));
+    }
+
+    #[test]
+    fn test_entity_ref() {
+        let mut world = World::new();
+        let mut qc = world.make_query::<Entity, Changed<Age0>>();
+        let e0 = world
+            .make_insert::<(Age0, Age1)>()
+            .insert(&world, (Age0(1), Age1(2)));
+        let e1 = world.make_insert::<(Age0,)>().insert(&world, (Age0(3),));
+        let empty = world.spawn_empty();
+        qc.align(&world);
+        assert_eq!(qc.get_param(&world).iter().count(), 2);
+        {
+            let r = world.entity(e0).unwrap();
+            assert_eq!(r.id(), e0);
+            assert_eq!(r.get::<Age0>().unwrap().0, 1);
+            assert_eq!(r.get::<Age1>().unwrap().0, 2);
+            assert!(r.get::<Age2>().is_none());
+            assert!(r.contains::<Age1>());
+            assert!(!r.contains::<Age2>());
+            assert!(r.archetype().contains(world.get_component_index(&TypeId::of::<Age1>())));
+            let r = world.entity(empty).unwrap();
+            assert!(r.get::<Age0>().is_none());
+            assert!(!r.contains::<Age0>());
+        }
+        world.increment_tick();
+        {
+            let mut m = world.entity_mut(e1).unwrap();
+            assert!(m.get_mut::<Age1>().is_none());
+            // 只读取不记录修改
+            assert_eq!(m.get_mut::<Age0>().unwrap().0, 3);
+        }
+        {
+            let mut m = world.entity_mut(e0).unwrap();
+            m.get_mut::<Age0>().unwrap().0 += 10;
+            assert_eq!(m.get::<Age0>().unwrap().0, 11);
+        }
+        world.increment_tick();
+        let r: Vec<_> = qc.get_param(&world).iter().collect();
+        assert_eq!(r, vec![e0]);
+        world.destroy_entity(e1).unwrap();
+        assert!(world.entity(e1).is_none());
+        assert!(world.entity_mut(e1).is_none());
+    }
+
+    #[test]
+    fn test_generic_fetch() {
+        #[derive(Component, Clone, Copy, Debug, Default, PartialEq)]
+        struct Mirror0(usize);
+        #[derive(Component, Clone, Copy, Debug, Default, PartialEq)]
+        struct Mirror2(usize);
+        impl From<Age0> for Mirror0 {
+            fn from(v: Age0) -> Self {
+                Mirror0(v.0)
+            }
+        }
+        impl From<Age2> for Mirror2 {
+            fn from(v: Age2) -> Self {
+                Mirror2(v.0)
+            }
+        }
+        // 泛型的同步system，ON为false时不写入
+        fn sync<S: Copy + Send + Sync + 'static, D: From<S> + Send + Sync + 'static, const ON: bool>(
+            mut q: Query<(&S, Pass<&mut D, ON>, std::marker::PhantomData<(S, D)>)>,
+        ) {
+            for (s, d, _) in q.iter_mut() {
+                if let Some(mut d) = d {
+                    *d = D::from(*s);
+                }
+            }
+        }
+        let mut app = crate::prelude::App::new();
+        let e0 = app
+            .world
+            .make_insert::<(Age0, Mirror0)>()
+            .insert(&app.world, (Age0(3), Mirror0(0)));
+        let e2 = app
+            .world
+            .make_insert::<(Age2, Mirror2)>()
+            .insert(&app.world, (Age2(5), Mirror2(0)));
+        let e3 = app.world.make_insert::<(Age2,)>().insert(&app.world, (Age2(7),));
+        app.add_system(Update, sync::<Age0, Mirror0, true>);
+        app.add_system(Update, sync::<Age2, Mirror2, false>);
+        app.run();
+        assert_eq!(app.world.get_component::<Mirror0>(e0).unwrap(), &Mirror0(3));
+        assert_eq!(app.world.get_component::<Mirror2>(e2).unwrap(), &Mirror2(0));
+        assert!(Pass::<&Age0>::ENABLED);
+        // 关闭的项不过滤原型，没有Mirror2的实体也匹配
+        let mut q = app.world.make_query::<(Entity, &Age2, Pass<&Mirror2, false>), ()>();
+        let mut r: Vec<_> = q.iter(&app.world).map(|(e, _, m)| (e, m.is_none())).collect();
+        r.sort();
+        let mut expect = vec![(e2, true), (e3, true)];
+        expect.sort();
+        assert_eq!(r, expect);
+        // 启用时和直接查询相同
+        let mut q = app.world.make_query::<(Entity, Pass<&Mirror2>), ()>();
+        let r: Vec<_> = q.iter(&app.world).map(|(e, m)| (e, m.copied())).collect();
+        assert_eq!(r, vec![(e2, Some(Mirror2(0)))]);
+        // 0元组
+        let mut q = app.world.make_query::<(), ()>();
+        assert_eq!(q.iter(&app.world).count(), 3);
+    }
+
+    #[test]
+    fn test_insert_batch() {
+        let mut world = World::new();
+        // Added让Age0记录添加tick
+        let _qa = world.make_query::<Entity, Added<Age0>>();
+        // 原型中已有的行，批量插入的行接在后面
+        let e = world.make_insert::<(Age0, Age1)>().insert(&world, (Age0(100), Age1(100)));
+        let old = world.tick();
+        world.increment_tick();
+        let tick = world.tick();
+        let es = world.insert_batch((0..1000).map(|n| (Age0(n), Age1(n * 2))));
+        assert_eq!(es.len(), 1000);
+        let ar = world.get_archetype(es.archetype_index()).unwrap();
+        assert_eq!(ar.len(), Row(1001));
+        let c = world.get_column_by_id(&TypeId::of::<Age0>()).unwrap().blob_ref(ar.index()).unwrap();
+        for (n, e) in es.iter().enumerate() {
+            let addr = world.entities.get(*e).unwrap();
+            assert_eq!(addr.archetype_index(), ar.index());
+            assert_eq!(addr.row, Row(n as u32 + 1));
+            assert_eq!(ar.get_unchecked(addr.row), *e);
+            assert_eq!(c.get_added_tick_unchecked(addr.row), tick);
+            assert_eq!(world.get_component::<Age1>(*e).unwrap().0, n * 2);
+        }
+        // 原有的行不受影响
+        assert_eq!(world.get_component::<Age0>(e).unwrap().0, 100);
+        assert_eq!(c.get_added_tick_unchecked(Row(0)), old);
+        let mut q = world.make_query::<&Age0, ()>();
+        assert_eq!(q.iter(&world).map(|a| a.0).sum::<usize>(), 100 + 999 * 1000 / 2);
+
+        // len()和实际数量不符的迭代器
+        struct Lying(usize, usize);
+        impl Iterator for Lying {
+            type Item = (Age2,);
+            fn next(&mut self) -> Option<(Age2,)> {
+                if self.0 == 0 {
+                    return None;
+                }
+                self.0 -= 1;
+                Some((Age2(self.0),))
+            }
+        }
+        impl ExactSizeIterator for Lying {
+            fn len(&self) -> usize {
+                self.1
+            }
+        }
+        let len = world.len();
+        // 实际更多时，只取len()项
+        let es = world.insert_batch(Lying(10, 4));
+        assert_eq!(es.len(), 4);
+        // 实际更少时，没有用到的行被移除
+        let es = world.insert_batch(Lying(2, 5));
+        assert_eq!(es.len(), 2);
+        let ar = world.get_archetype(es.archetype_index()).unwrap().clone();
+        assert_eq!(ar.len(), Row(9));
+        assert_eq!(ar.live_len(), 6);
+        assert_eq!(world.len(), len + 6);
+        let mut q = world.make_query::<&Age2, ()>();
+        assert_eq!(q.iter(&world).count(), 6);
+        world.settle();
+        assert_eq!(ar.live_len(), 6);
+        assert_eq!(q.iter(&world).count(), 6);
+    }
+
+    #[test]
+    fn test_event_tombstone() {
+        // 每帧读取时的(未读数量, 估计存活数量, 存活事件的迭代数量)
+        struct Stats(Vec<(usize, usize, usize)>);
+        fn reader(c: ComponentChanged<Age0>, mut s: SingleResMut<Stats>) {
+            let len = c.len();
+            let live = c.live_len();
+            let n = c.iter_live().count();
+            s.0.push((len, live, n));
+        }
+        fn write_all(world: &mut World) {
+            world.increment_tick();
+            let mut q = world.make_query::<&mut Age0, ()>();
+            for mut a in q.iter_mut(world) {
+                a.0 += 1;
+            }
+        }
+        let mut app = crate::prelude::App::new();
+        app.world.insert_single_res(Stats(Vec::new()));
+        let i = app.world.make_insert::<(Age0,)>();
+        let es: Vec<_> = (0..10_000).map(|n| i.insert(&app.world, (Age0(n),))).collect();
+        app.add_system(Update, reader);
+        app.run();
+        // 记录1万个修改，销毁一半
+        write_all(&mut app.world);
+        for e in es.iter().step_by(2) {
+            app.world.destroy_entity(*e).unwrap();
+        }
+        app.run();
+        // 剩下的再修改，然后全部销毁
+        write_all(&mut app.world);
+        for e in es.iter().skip(1).step_by(2) {
+            app.world.destroy_entity(*e).unwrap();
+        }
+        app.run();
+        let stats = &app.world.get_single_res::<Stats>().unwrap().0;
+        assert_eq!(
+            stats,
+            &vec![(10_000, 10_000, 10_000), (10_000, 5_000, 5_000), (5_000, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn test_query_iter_double_ended() {
+        let mut world = World::new();
+        let i0 = world.make_insert::<(Age0,)>();
+        let i1 = world.make_insert::<(Age0, Age1)>();
+        let i2 = world.make_insert::<(Age0, Age2)>();
+        for n in 0..5 {
+            i0.insert(&world, (Age0(n),));
+            i1.insert(&world, (Age0(10 + n), Age1(0)));
+            i2.insert(&world, (Age0(20 + n), Age2(0)));
+        }
+        // 空位
+        let e = i1.insert(&world, (Age0(100), Age1(0)));
+        world.destroy_entity(e).unwrap();
+        let mut q = world.make_query::<&Age0, ()>();
+        let forward: Vec<_> = q.iter(&world).map(|a| a.0).collect();
+        assert_eq!(forward.len(), 15);
+        // 只用next_back，和正向相反
+        let mut back: Vec<_> = q.iter(&world).rev().map(|a| a.0).collect();
+        back.reverse();
+        assert_eq!(back, forward);
+        // 两端交替，在中间相遇，剩余数量是确切的
+        for first in 0..4 {
+            let mut it = q.iter(&world);
+            let mut head = Vec::new();
+            let mut tail = Vec::new();
+            for _ in 0..first {
+                head.push(it.next().unwrap().0);
+            }
+            loop {
+                assert_eq!(it.exact_len(), Some(15 - head.len() - tail.len()));
+                match it.next_back() {
+                    Some(a) => tail.push(a.0),
+                    None => break,
+                }
+                match it.next() {
+                    Some(a) => head.push(a.0),
+                    None => break,
+                }
+            }
+            assert!(it.next().is_none());
+            assert!(it.next_back().is_none());
+            assert_eq!(it.exact_len(), Some(0));
+            tail.reverse();
+            head.extend(tail);
+            assert_eq!(head, forward);
+        }
+    }
+
+    #[test]
+    fn test_event_tombstone_added() {
+        struct Stats(Vec<(usize, usize, Vec<Entity>)>);
+        fn reader(c: ComponentChanged<Age0>, mut s: SingleResMut<Stats>) {
+            let len = c.len();
+            let live = c.live_len();
+            let v: Vec<_> = c.iter_live().collect();
+            s.0.push((len, live, v));
+        }
+        let mut app = crate::prelude::App::new();
+        app.world.insert_single_res(Stats(Vec::new()));
+        let i = app.world.make_insert::<(Age0,)>();
+        let e = i.insert(&app.world, (Age0(0),));
+        app.add_system(Update, reader);
+        app.run();
+        // 存活实体的修改，和读取后新增又销毁的实体。新增的实体没有修改事件，不能产生墓碑
+        app.world.increment_tick();
+        app.world.get_component_mut::<Age0>(e).unwrap().0 += 1;
+        let e1 = i.insert(&app.world, (Age0(1),));
+        app.world.destroy_entity(e1).unwrap();
+        app.run();
+        let stats = &app.world.get_single_res::<Stats>().unwrap().0;
+        assert_eq!(stats[1], (1, 1, vec![e]));
+    }
+    #[test]
+    fn test_column_guard_other_archetype() {
+        let mut world = World::new();
+        let e = world.make_insert::<(Age0,)>().insert(&world, (Age0(1),));
+        let e1 = world.make_insert::<(Age0, Age1)>().insert(&world, (Age0(2), Age1(2)));
+        world.settle();
+        let index = world.init_component::<Age0>();
+        let ar_index = world.get_entity_location(e).unwrap().archetype_index;
+        let ar_index1 = world.get_entity_location(e1).unwrap().archetype_index;
+        let column = world.get_column(index).unwrap().clone();
+        // 持有原型A上的BlobRef，整理同一组件在原型B上的blob
+        let r = column.blob_ref(ar_index).unwrap();
+        let mut c = column.clone();
+        unsafe { Share::get_mut_unchecked(&mut c) }.settle_by_index(ar_index1, 1, 8, &Vec::new());
+        assert_eq!(r.get::<Age0>(Row(0), e).0, 1);
+        assert!(!r.is_changed(Row(0), world.tick()));
+        r.changed_tick(e, Row(0), world.tick());
+        let r1 = column.blob_ref(ar_index1).unwrap();
+        assert_eq!(r1.get::<Age0>(Row(0), e1).0, 2);
+    }
+    #[test]
+    fn test_query_iter_back_resume() {
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0,)>();
+        for n in 0..3 {
+            i.insert(&world, (Age0(n),));
+        }
+        world.make_insert::<(Age0, Age1)>().insert(&world, (Age0(3), Age1(0)));
+        world.settle();
+        let mut q = world.make_query::<&Age0, ()>();
+        assert_eq!(q.iter(&world).rev().count(), 4);
+        assert_eq!(q.statistics().archetypes_checked, 2);
+        // 反向迭代过后resume，反向的位置回到起点
+        let cursor = {
+            let mut it = q.iter(&world);
+            it.next();
+            it.cursor()
+        };
+        let mut it = q.iter(&world);
+        assert!(it.next_back().is_some());
+        it.resume(cursor);
+        assert_eq!(it.count(), 3);
+    }
+    #[cfg(feature = "strict_iter")]
+    #[test]
+    #[should_panic(expected = "while it is being iterated by this thread")]
+    fn test_strict_iter_back() {
+        let mut world = World::new();
+        let i = world.make_insert::<(Age0,)>();
+        i.insert(&world, (Age0(0),));
+        world.settle();
+        let mut q = world.make_query::<&Age0, ()>();
+        for _ in q.iter(&world).rev() {
+            // 反向迭代时在同一原型上插入
+            i.insert(&world, (Age0(1),));
+        }
+    }
+    #[test]
+    fn test_event_batch_record() {
+        use crate::event::EventVec;
+        let mut vec = EventVec::<usize>::new("batch");
+        let l = vec.insert_listener("reader".into());
+        vec.record(0);
+        vec.batch_record(&[1, 2, 3]);
+        vec.batch_record(&[]);
+        assert_eq!(vec.len(l), 4);
+        assert_eq!(vec.get_iter(l).map(|v| *v).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        // 读取者只会看到完整的批次
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for n in 0..100 {
+                        vec.batch_record(&[n; 16]);
+                    }
+                });
+            }
+            s.spawn(|| {
+                for _ in 0..1000 {
+                    assert_eq!(vec.len(l) % 16, 0);
+                }
+            });
+        });
+        assert_eq!(vec.len(l), 4 * 100 * 16);
+        assert!(!vec.settle());
+        vec.mark_read(l);
+        assert!(vec.settle());
+        assert_eq!(vec.len(l), 0);
+
+        // 部分清理后，发布的长度从剩余的事件继续
+        let slow = vec.insert_listener("slow".into());
+        vec.batch_record(&[1, 2, 3]);
+        assert_eq!(vec.get_iter(slow).count(), 3);
+        vec.batch_record(&[4, 5]);
+        vec.mark_read(l);
+        assert!(!vec.settle());
+        assert_eq!((vec.len(l), vec.len(slow)), (0, 2));
+        vec.record(6);
+        vec.batch_record(&[7, 8]);
+        assert_eq!(vec.get_iter(l).map(|v| *v).collect::<Vec<_>>(), vec![6, 7, 8]);
+        assert_eq!(vec.get_iter(slow).map(|v| *v).collect::<Vec<_>>(), vec![4, 5, 6, 7, 8]);
+        assert!(vec.settle());
+    }
+    #[test]
+    fn test_event_clear_slowest_listener() {
+        use crate::event::EventVec;
+        let mut vec = EventVec::<usize>::new("clear");
+        let fast = vec.insert_listener("fast".into());
+        let slow = vec.insert_listener("slow".into());
+        for n in 0..10 {
+            vec.record(n);
+        }
+        assert_eq!(vec.get_iter(slow).count(), 10);
+        for n in 10..1000 {
+            vec.record(n);
+        }
+        vec.mark_read(fast);
+        // 慢的监听器还有未读的事件，整理时不能清空，最多清理到它的读取位置
+        assert!(!vec.settle());
+        assert_eq!(vec.len(fast), 0);
+        assert_eq!(vec.len(slow), 990);
+        assert_eq!(vec.get_iter(slow).map(|v| *v).collect::<Vec<_>>(), (10..1000).collect::<Vec<_>>());
+        assert!(vec.settle());
+    }
+    #[test]
+    fn test_event_record_after_clear_part() {
+        use crate::event::EventVec;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct Counted(usize);
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let mut vec = EventVec::<Counted>::new("part");
+        let fast = vec.insert_listener("fast".into());
+        let slow = vec.insert_listener("slow".into());
+        for n in 0..60 {
+            vec.record(Counted(n));
+        }
+        assert_eq!(vec.get_iter(slow).count(), 60);
+        for n in 60..100 {
+            vec.record(Counted(n));
+        }
+        vec.mark_read(fast);
+        // 慢的监听器读到60，整理时清理前60个事件
+        assert!(!vec.settle());
+        assert_eq!(DROPS.load(Ordering::Relaxed), 60);
+        assert_eq!(vec.len(slow), 40);
+        // 部分清理后仍然可以记录，不会卡住
+        for n in 100..110 {
+            vec.record(Counted(n));
+        }
+        assert_eq!(vec.len(fast), 10);
+        assert_eq!(vec.get_iter(slow).map(|c| c.0).collect::<Vec<_>>(), (60..110).collect::<Vec<_>>());
+        assert_eq!(vec.get_iter(fast).map(|c| c.0).collect::<Vec<_>>(), (100..110).collect::<Vec<_>>());
+        // 每个事件只释放一次
+        assert!(vec.settle());
+        assert_eq!(DROPS.load(Ordering::Relaxed), 110);
+        drop(vec);
+        assert_eq!(DROPS.load(Ordering::Relaxed), 110);
+    }
+    #[test]
+    fn test_run_frozen_cached() {
+        fn inspect(c: ComponentChanged<Age0>, q: Query<&Age0>) -> usize {
+            c.iter().count();
+            q.iter().count()
+        }
+        let mut world = World::new();
+        world.make_insert::<(Age0,)>().insert(&world, (Age0(0),));
+        for _ in 0..3 {
+            assert_eq!(world.run_frozen(inspect), 1);
+        }
+        // 同一个system只初始化一次，只注册一个监听器
+        let column = world.get_column_by_id(&TypeId::of::<Age0>()).unwrap().clone();
+        assert_eq!(column.changed_listener_count(), 1);
+        // 缓存的system能看到之后创建的原型
+        world.make_insert::<(Age0, Age1)>().insert(&world, (Age0(1), Age1(1)));
+        assert_eq!(world.run_frozen(inspect), 2);
+        assert_eq!(column.changed_listener_count(), 1);
+    }
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "ticks are frozen")]
+    fn test_run_frozen_alter() {
+        fn inspect(mut a: Alter<Entity, (), (Age1,), ()>) {
+            let es: Vec<Entity> = a.iter().collect();
+            for e in es {
+                a.alter(e, (Age1(0),)).unwrap();
+            }
+        }
+        let mut world = World::new();
+        world.make_insert::<(Age0,)>().insert(&world, (Age0(0),));
+        world.run_frozen(inspect);
+    }
+    #[test]
+    fn test_align_snapshot_system() {
+        use crate::archetype::{ArchetypeIndex, ArchetypeInfo};
+        use crate::fetch::FetchComponents;
+        use pi_share::ShareBool;
+        use std::sync::atomic::Ordering;
+        // 第一次对齐到相关原型时，创建一个新原型，模拟两个参数对齐之间其他线程创建了原型
+        struct SpawnOnAlign;
+        impl FetchComponents for SpawnOnAlign {
+            type Fetch<'w> = ();
+            type Item<'w> = ();
+            type ReadOnly = Self;
+            type State = (Vec<Share<Column>>, ShareBool);
+
+            fn init_state(world: &mut World, _meta: &mut SystemMeta) -> Self::State {
+                let columns = [
+                    world.init_component::<Age0>(),
+                    world.init_component::<Age1>(),
+                    world.init_component::<Age2>(),
+                ]
+                .iter()
+                .map(|i| world.get_column(*i).unwrap().clone())
+                .collect();
+                (columns, ShareBool::new(false))
+            }
+            fn init_fetch<'w>(
+                _world: &'w World,
+                _state: &'w Self::State,
+                _index: ArchetypeIndex,
+                _tick: Tick,
+                _last_run: Tick,
+            ) -> Self::Fetch<'w> {
+            }
+            fn fetch<'w>(_fetch: &Self::Fetch<'w>, _row: Row, _e: Entity) -> Self::Item<'w> {}
+            fn try_init_archetype(
+                world: &World,
+                state: &Self::State,
+                _ar: &Archetype,
+            ) -> Result<(), FetchInitError> {
+                if !state.1.swap(true, Ordering::Relaxed) {
+                    world.find_archtype(ArchetypeInfo::sort(state.0.clone()));
+                }
+                Ok(())
+            }
+        }
+        struct Lens(Vec<(usize, usize)>);
+        fn sys(
+            q1: Query<(&Age0, SpawnOnAlign)>,
+            q2: Query<(&Age0, &Age2)>,
+            mut lens: SingleResMut<Lens>,
+        ) {
+            lens.0.push((q1.archetypes_len(), q2.archetypes_len()));
+        }
+        use crate::system::{IntoSystem, RunSystem, System};
+        let mut world = World::new();
+        world.insert_single_res(Lens(Vec::new()));
+        world.make_insert::<(Age0, Age2)>().insert(&world, (Age0(0), Age2(0)));
+        // 直接运行system，每次运行只对齐一次。App的执行图在运行前还会对齐一次，会掩盖两个查询的差异
+        let mut system = IntoSystem::into_system(sys);
+        system.initialize(&mut world);
+        let before = world.archetype_arr.len();
+        system.run(&world);
+        // 新原型在第一个查询对齐时创建，两个查询都对齐到运行开始时的原型数量
+        assert_eq!(world.archetype_arr.len(), before + 1);
+        system.run(&world);
+        let lens = &world.get_single_res::<Lens>().unwrap().0;
+        assert_eq!(lens, &vec![(1, 1), (2, 2)]);
+    }
 }
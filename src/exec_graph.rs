@@ -34,6 +34,7 @@ use pi_null::Null;
 use pi_share::{Share, ShareMutex, ShareU32, ShareU64};
 
 use crate::archetype::{Archetype, ArchetypeDependResult, Flags};
+use crate::component_id::ComponentId;
 #[cfg(debug_assertions)]
 use crate::column::ARCHETYPE_INDEX;
 #[cfg(debug_assertions)]
@@ -296,7 +297,7 @@ impl ExecGraph {
     ) {
         let inner = self.0.as_ref();
         let _unused = inner.lock.lock();
-        let id = unsafe { transmute(*tid) };
+        let id = ComponentId::of(tid);
         // 如果图已经存在该节点，则返回，否则插入
         let (node_index, is_new) = inner.find_node((id, 0u32.into()), NodeType::Res(name.clone()), &self.1);
         if is_new {// 如果该资源为新的，则遍历全部system节点，否则只遍历新增的system节点
@@ -343,7 +344,7 @@ impl ExecGraph {
         let inner = self.0.as_ref();
         let _unused = inner.lock.lock();
 
-        let aid = archetype.id() as u128;
+        let aid = ComponentId::from_archetype(archetype.id());
         let mut nodes = Vec::with_capacity(256);
         let mut ar_component_index_node_index_map = Vec::with_capacity(256);
         // 遍历该原型的全部组件
@@ -465,8 +466,10 @@ impl ExecGraph {
         }
 
         let mut completed_set_conditions = FixedBitSet::with_capacity(set_conditions.len());
+        // Stable分配时，本阶段system的分配按拓扑排序中的位置排序
+        let stage = world.begin_alloc_stage();
         // let t = pi_time::Instant::now();
-        for i in self.2.iter() {
+        for (pos, i) in self.2.iter().enumerate() {
             let node = unsafe { inner.nodes.load_unchecked(*i) };
             
             match node.label {
@@ -511,6 +514,7 @@ impl ExecGraph {
                     {
                         println!("run start===={:?}", sys.system.name());
                     }
+                    world.enter_system_alloc(stage, pos);
                     #[cfg(feature = "trace")]
                     {
                         use tracing::Instrument;
@@ -519,6 +523,7 @@ impl ExecGraph {
                     }
                     #[cfg(not(feature = "trace"))]
                     sys.system.run(world).await;
+                    world.leave_system_alloc();
                 }
                 NodeType::Set(start, end, _) => {
                     for i in start..end {
@@ -669,7 +674,7 @@ impl ExecGraph {
 pub struct GraphInner {
     nodes: AppendVec<Node>,
     edges: AppendVec<Edge>,
-    map: DashMap<(u128, ComponentIndex), NodeIndex>,
+    map: DashMap<(ComponentId, ComponentIndex), NodeIndex>,
     to_len: ShareU32,
     froms: Vec<NodeIndex>,
     lock: ShareMutex<()>,
@@ -683,7 +688,7 @@ pub struct GraphInner {
 impl GraphInner {
 
     // 查找图节点， 如果不存在将该label放入图的节点中，保存id到图节点索引的对应关系， 图的to_len也加1
-    fn find_node(&self, id: (u128, ComponentIndex), label: NodeType, name: &str) -> (NodeIndex, bool) {
+    fn find_node(&self, id: (ComponentId, ComponentIndex), label: NodeType, name: &str) -> (NodeIndex, bool) {
         match self.map.entry(id) {
             Entry::Occupied(entry) => (entry.get().clone(), false),
             Entry::Vacant(entry) => {
@@ -996,7 +1001,7 @@ impl Debug for NeighborIter<'_> {
 pub enum NodeType {
     None,
     System(usize, Cow<'static, str>),
-    ArchetypeComponent(u128, Cow<'static, str>),
+    ArchetypeComponent(ComponentId, Cow<'static, str>),
     Res(Cow<'static, str>),
     Set(usize/*条件开始索引*/, usize/*条件结束索引*/, Cow<'static, str>),
 }
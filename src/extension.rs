@@ -0,0 +1,159 @@
+//! world扩展
+//! 引擎级的子系统（比如空间哈希、导航网格、渲染提取状态）可以作为扩展放在world上，并在world的生命周期中被回调。
+//! 扩展只在同步点被访问：整理时回调on_archetype_created、on_settle和on_frame_end。
+//! World::destroy_entity销毁的实体立即回调on_entity_destroyed，Alter、EntityEditor和归档销毁的实体在整理时回调。
+//! 回调时world是独占的，所以扩展不参与system的读写依赖分析，system中不应访问扩展。
+//!
+
+use std::any::{Any, TypeId};
+use std::mem::take;
+
+use pi_append_vec::SafeVec;
+
+use crate::archetype::Archetype;
+use crate::table::Fragmentation;
+use crate::world::{Entity, World};
+
+/// 整理的统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SettleReport {
+    pub archetypes_len: usize, // 整理后的原型数量
+    pub new_archetypes: usize, // 上次整理后新增的原型数量
+    pub moved_rows: usize,     // 整理时移动的行数
+    pub fragmentation_before: Fragmentation, // 整理前所有原型的碎片
+    pub fragmentation_after: Fragmentation,  // 整理后所有原型的碎片
+}
+
+/// world扩展，回调都有默认的空实现
+pub trait WorldExtension: Send + Sync + 'static {
+    /// 新原型创建，在创建后的下次整理时回调
+    fn on_archetype_created(&mut self, _ar: &Archetype) {}
+    /// 整理完毕
+    fn on_settle(&mut self, _report: &SettleReport) {}
+    /// 一帧结束，在整理完毕后回调
+    fn on_frame_end(&mut self, _world: &World) {}
+    /// 实体被销毁，World::destroy_entity以外的销毁在整理时回调
+    fn on_entity_destroyed(&mut self, _e: Entity) {}
+}
+
+pub(crate) trait AnyExtension: Send + Sync {
+    fn ext(&mut self) -> &mut dyn WorldExtension;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+impl<T: WorldExtension> AnyExtension for T {
+    fn ext(&mut self) -> &mut dyn WorldExtension {
+        self
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Extensions {
+    vec: Vec<(TypeId, Box<dyn AnyExtension>)>,
+    archetypes_len: usize, // 已回调过on_archetype_created的原型数量
+    destroyed: SafeVec<Entity>, // 还未回调on_entity_destroyed的实体
+}
+impl Extensions {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.vec.is_empty()
+    }
+    // 记录销毁的实体，没有扩展时不记录
+    #[inline(always)]
+    pub(crate) fn record_destroyed(&self, e: Entity) {
+        if !self.vec.is_empty() {
+            self.destroyed.insert(e);
+        }
+    }
+}
+
+impl World {
+    /// 插入扩展，如果同类型的扩展已存在，则替换并返回旧的扩展
+    pub fn insert_extension<T: WorldExtension>(&mut self, value: T) -> Option<T> {
+        let id = TypeId::of::<T>();
+        for (t, ext) in self.extensions.vec.iter_mut() {
+            if *t == id {
+                let old = std::mem::replace(
+                    ext.as_any_mut().downcast_mut::<T>().unwrap(),
+                    value,
+                );
+                return Some(old);
+            }
+        }
+        if self.extensions.vec.is_empty() {
+            // 已有的原型不再回调
+            self.extensions.archetypes_len = self.archetype_arr.len();
+        }
+        self.extensions.vec.push((id, Box::new(value)));
+        None
+    }
+    /// 获得扩展
+    pub fn extension<T: WorldExtension>(&self) -> Option<&T> {
+        let id = TypeId::of::<T>();
+        self.extensions
+            .vec
+            .iter()
+            .find(|(t, _)| *t == id)
+            .and_then(|(_, ext)| ext.as_any().downcast_ref::<T>())
+    }
+    /// 获得扩展的可变引用
+    pub fn extension_mut<T: WorldExtension>(&mut self) -> Option<&mut T> {
+        let id = TypeId::of::<T>();
+        self.extensions
+            .vec
+            .iter_mut()
+            .find(|(t, _)| *t == id)
+            .and_then(|(_, ext)| ext.as_any_mut().downcast_mut::<T>())
+    }
+    /// 移除扩展
+    pub fn remove_extension<T: WorldExtension>(&mut self) -> Option<T> {
+        let id = TypeId::of::<T>();
+        let index = self.extensions.vec.iter().position(|(t, _)| *t == id)?;
+        let (_, ext) = self.extensions.vec.remove(index);
+        // 类型已用TypeId检查过
+        let raw = Box::into_raw(ext) as *mut T;
+        Some(*unsafe { Box::from_raw(raw) })
+    }
+
+    // 整理后回调扩展
+    pub(crate) fn notify_extensions_settle(&mut self, report: &SettleReport) {
+        if self.extensions.is_empty() {
+            return;
+        }
+        self.notify_extensions_destroyed();
+        let mut extensions = take(&mut self.extensions);
+        let len = self.archetype_arr.len();
+        for i in extensions.archetypes_len..len {
+            let ar = unsafe { self.archetype_arr.get_unchecked(i) };
+            for (_, ext) in extensions.vec.iter_mut() {
+                ext.ext().on_archetype_created(ar);
+            }
+        }
+        extensions.archetypes_len = len;
+        for (_, ext) in extensions.vec.iter_mut() {
+            ext.ext().on_settle(report);
+        }
+        for (_, ext) in extensions.vec.iter_mut() {
+            ext.ext().on_frame_end(self);
+        }
+        self.extensions = extensions;
+    }
+    // 回调扩展记录的销毁的实体
+    pub(crate) fn notify_extensions_destroyed(&mut self) {
+        if self.extensions.destroyed.len() == 0 {
+            return;
+        }
+        let destroyed: Vec<Entity> = self.extensions.destroyed.iter().map(|e| *e).collect();
+        self.extensions.destroyed.clear(0);
+        for e in destroyed {
+            for (_, ext) in self.extensions.vec.iter_mut() {
+                ext.ext().on_entity_destroyed(e);
+            }
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! 渲染提取的只读视图
+//! 在同步点用World::extract_view复制指定组件的列和实体列表，得到紧凑、不可变、可以发送到其他线程的ExtractView，
+//! 提取system在其他线程读取视图的同时，world可以继续下一帧的模拟。
+//! 只匹配包含全部指定组件的原型。组件数据按位复制，所以和ArchetypeSnapshot一样只支持不需要释放的组件，读取时组件须为Copy和Sync。
+//! 每列同时复制修改tick，用view.changed_since(index, tick)做增量上传。列的复制互不依赖，extract_view_par用指定数量的线程分配各列。
+//!
+
+use std::any::TypeId;
+use std::mem::{align_of, size_of};
+use std::ptr::NonNull;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::archetype::{ArchetypeIndex, Row, COMPONENT_TICK};
+use crate::world::{ComponentIndex, Entity, Tick, World};
+
+/// 不能提取的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtractError {
+    NoSuchComponent(ComponentIndex),
+    NeedsDrop(ComponentIndex),   // 组件需要释放，不能按位复制
+    Overaligned(ComponentIndex), // 组件的对齐超过16字节
+}
+
+/// 提取时复制的组件视图
+pub struct ExtractView {
+    tick: Tick,
+    components: Vec<ComponentIndex>,
+    archetypes: Vec<ExtractArchetype>,
+}
+
+/// 一个原型上复制的数据
+pub struct ExtractArchetype {
+    index: ArchetypeIndex,
+    entities: Vec<Entity>,       // 行 -> 实体，只包含存活的行
+    columns: Vec<ExtractColumn>, // 和ExtractView::components的顺序一致
+}
+
+struct ExtractColumn {
+    type_id: TypeId,
+    data: Vec<u128>,          // 按行连续存放，用u128保证对齐
+    ticks: Option<Vec<Tick>>, // 每行的修改tick，组件不记录tick时为None
+}
+
+impl ExtractView {
+    /// 提取时world的tick
+    pub fn tick(&self) -> Tick {
+        self.tick
+    }
+    pub fn components(&self) -> &[ComponentIndex] {
+        &self.components
+    }
+    pub fn archetypes(&self) -> &[ExtractArchetype] {
+        &self.archetypes
+    }
+    /// 视图中的实体数量
+    pub fn len(&self) -> usize {
+        self.archetypes.iter().map(|ar| ar.entities.len()).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.archetypes.is_empty()
+    }
+    /// 遍历视图中的实体及组件T，视图可以在线程间共享，组件须为Copy和Sync
+    pub fn iter<T: Copy + Sync + 'static>(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.archetypes.iter().flat_map(|ar| {
+            ar.entities
+                .iter()
+                .copied()
+                .zip(ar.get::<T>().unwrap_or(&[]).iter())
+        })
+    }
+    /// 提取前组件在tick后被添加或修改的实体，组件不记录tick时返回全部实体
+    pub fn changed_since(&self, index: ComponentIndex, tick: Tick) -> Vec<Entity> {
+        let pos = match self.components.iter().position(|i| *i == index) {
+            Some(pos) => pos,
+            None => return Vec::new(),
+        };
+        let mut vec = Vec::new();
+        for ar in self.archetypes.iter() {
+            match &ar.columns[pos].ticks {
+                Some(ticks) => vec.extend(
+                    ar.entities
+                        .iter()
+                        .zip(ticks.iter())
+                        .filter(|(_, t)| **t > tick)
+                        .map(|(e, _)| *e),
+                ),
+                None => vec.extend_from_slice(&ar.entities),
+            }
+        }
+        vec
+    }
+}
+
+impl ExtractArchetype {
+    /// 原型在world上的索引
+    pub fn index(&self) -> ArchetypeIndex {
+        self.index
+    }
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+    /// 组件T的数据，和entities一一对应，组件须为Copy和Sync
+    pub fn get<T: Copy + Sync + 'static>(&self) -> Option<&[T]> {
+        let c = self.columns.iter().find(|c| c.type_id == TypeId::of::<T>())?;
+        // 提取时已拒绝对齐超过u128的组件
+        debug_assert!(align_of::<T>() <= align_of::<u128>());
+        let ptr = if size_of::<T>() == 0 {
+            NonNull::<T>::dangling().as_ptr() as *const T
+        } else {
+            c.data.as_ptr() as *const T
+        };
+        Some(unsafe { slice::from_raw_parts(ptr, self.entities.len()) })
+    }
+}
+
+// 复制一个组件在所有匹配原型上的列
+fn copy_component(
+    world: &World,
+    index: ComponentIndex,
+    rows: &[(ArchetypeIndex, Vec<(Row, Entity)>)],
+) -> Vec<ExtractColumn> {
+    let c = world.get_column(index).unwrap();
+    let size = c.info().size();
+    let is_tick = c.info().tick_info & COMPONENT_TICK != 0;
+    rows.iter()
+        .map(|(ar_index, rows)| {
+            let b = c.blob_ref_unchecked(*ar_index);
+            let mut data = vec![0u128; (rows.len() * size).div_ceil(size_of::<u128>())];
+            if size > 0 {
+                let dst = data.as_mut_ptr() as *mut u8;
+                for (i, (row, e)) in rows.iter().enumerate() {
+                    unsafe { b.get_row(*row, *e).copy_to_nonoverlapping(dst.add(i * size), size) };
+                }
+            }
+            let ticks = is_tick.then(|| {
+                rows.iter()
+                    .map(|(row, _)| b.get_tick_unchecked(*row).max(b.get_added_tick_unchecked(*row)))
+                    .collect()
+            });
+            ExtractColumn {
+                type_id: *c.info().type_id(),
+                data,
+                ticks,
+            }
+        })
+        .collect()
+}
+
+impl World {
+    /// 复制指定组件的只读视图，组件不存在、需要释放或对齐超过16字节，则返回错误。
+    /// 必须在没有system修改world的同步点调用
+    pub fn extract_view(&self, components: &[ComponentIndex]) -> Result<ExtractView, ExtractError> {
+        self.extract_view_by(components, 1)
+    }
+    /// 同extract_view，用threads个线程（包括当前线程）复制，线程间动态分配各列，适合组件多且数据量大的提取
+    pub fn extract_view_par(
+        &self,
+        components: &[ComponentIndex],
+        threads: usize,
+    ) -> Result<ExtractView, ExtractError> {
+        self.extract_view_by(components, threads)
+    }
+    fn extract_view_by(
+        &self,
+        components: &[ComponentIndex],
+        threads: usize,
+    ) -> Result<ExtractView, ExtractError> {
+        for index in components {
+            let c = self
+                .get_column(*index)
+                .ok_or(ExtractError::NoSuchComponent(*index))?;
+            if c.info().drop_fn.is_some() {
+                return Err(ExtractError::NeedsDrop(*index));
+            }
+            if c.info().mem_align as usize > align_of::<u128>() {
+                return Err(ExtractError::Overaligned(*index));
+            }
+        }
+        // 匹配的原型及其存活的行
+        let mut rows = Vec::new();
+        for ar in self.archetype_arr.iter() {
+            if !components.iter().all(|i| ar.contains(*i)) {
+                continue;
+            }
+            let vec: Vec<(Row, Entity)> = (0..ar.len().index())
+                .filter_map(|row| {
+                    let row = Row(row as u32);
+                    ar.live_entity(row).map(|e| (row, e))
+                })
+                .collect();
+            if !vec.is_empty() {
+                rows.push((ar.index(), vec));
+            }
+        }
+        let threads = threads.min(components.len());
+        let columns: Vec<Vec<ExtractColumn>> = if threads > 1 {
+            let (rows, next) = (&rows, AtomicUsize::new(0));
+            // 每个线程取下一个还未复制的组件，返回(组件的位置, 列)
+            let work = || {
+                let mut vec = Vec::new();
+                loop {
+                    let pos = next.fetch_add(1, Ordering::Relaxed);
+                    match components.get(pos) {
+                        Some(index) => vec.push((pos, copy_component(self, *index, rows))),
+                        None => return vec,
+                    }
+                }
+            };
+            let mut result = std::thread::scope(|s| {
+                let handles: Vec<_> = (1..threads).map(|_| s.spawn(work)).collect();
+                let mut result = work();
+                for h in handles {
+                    result.extend(h.join().unwrap());
+                }
+                result
+            });
+            result.sort_by_key(|(pos, _)| *pos);
+            result.into_iter().map(|(_, vec)| vec).collect()
+        } else {
+            components
+                .iter()
+                .map(|index| copy_component(self, *index, &rows))
+                .collect()
+        };
+        let mut columns: Vec<_> = columns.into_iter().map(|vec| vec.into_iter()).collect();
+        let archetypes = rows
+            .into_iter()
+            .map(|(index, rows)| ExtractArchetype {
+                index,
+                entities: rows.into_iter().map(|(_, e)| e).collect(),
+                columns: columns.iter_mut().map(|it| it.next().unwrap()).collect(),
+            })
+            .collect();
+        Ok(ExtractView {
+            tick: self.tick(),
+            components: components.to_vec(),
+            archetypes,
+        })
+    }
+}
@@ -5,8 +5,8 @@ use std::ops::{Deref, DerefMut};
 use pi_proc_macros::all_tuples;
 use pi_share::Share;
 
-use crate::archetype::{ArchetypeIndex, ComponentInfo, Row, COMPONENT_TICK};
-use crate::column::{BlobRef, Column};
+use crate::archetype::{Archetype, ArchetypeIndex, ComponentInfo, Row, COMPONENT_TICK};
+use crate::column::{BlobRef, ChangeSpanItem, Column};
 use crate::prelude::FromWorld;
 use crate::single_res::TickRes;
 use crate::system::{Relation, SystemMeta};
@@ -57,6 +57,25 @@ pub trait FetchComponents {
     /// that `fetch` is called no more than once for each `entity`/`table_row` in each archetype.
     /// If `Self` implements [`ReadOnlyFetchComponents`], then this can safely be called multiple times.
     fn fetch<'w>(fetch: &Self::Fetch<'w>, row: Row, e: Entity) -> Self::Item<'w>;
+
+    /// 检查原型能否被本查询匹配，返回错误时查询跳过该原型，并记录到QState::init_warnings。
+    /// 默认总是成功，依赖外部设施的自定义Fetch可以重载
+    fn try_init_archetype(
+        _world: &World,
+        _state: &Self::State,
+        _ar: &Archetype,
+    ) -> Result<(), FetchInitError> {
+        Ok(())
+    }
+}
+
+/// 自定义Fetch在原型上初始化失败的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FetchInitError {
+    /// 组件索引已失效
+    StaleComponent(ComponentIndex),
+    /// 缺少Fetch依赖的外部设施
+    Unavailable(Cow<'static, str>),
 }
 
 impl FetchComponents for Entity {
@@ -145,17 +164,18 @@ impl<T: 'static> FetchComponents for &mut T {
     }
 }
 
-pub struct Ref<T: 'static>(PhantomData<T>);
-impl<T: 'static> FetchComponents for Ref<T> {
+/// 返回组件的只读裸指针，用于将组件传给ffi等场合，和&T一样声明对组件的读。
+/// 指针只在本次system运行期间有效，任何结构性修改（插入、删除、alter、整理）后都不能再使用
+impl<T: 'static> FetchComponents for *const T {
     type Fetch<'w> = ColumnTick<'w>;
-    type Item<'w> = TickRef<'w, T>;
+    type Item<'w> = *const T;
     type ReadOnly = Self;
     type State = Share<Column>;
 
     fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
         meta.component_relate(
             world,
-            ComponentInfo::of::<T>(COMPONENT_TICK),
+            ComponentInfo::of::<T>(0),
             Relation::Read(0usize.into()),
         )
         .1
@@ -171,6 +191,82 @@ impl<T: 'static> FetchComponents for Ref<T> {
         ColumnTick::new(state.blob_ref_unchecked(index), tick, last_run)
     }
 
+    #[inline(always)]
+    fn fetch<'w>(fetch: &Self::Fetch<'w>, row: Row, e: Entity) -> Self::Item<'w> {
+        fetch.column.get::<T>(row, e) as *const T
+    }
+}
+
+/// 返回组件的可变裸指针，和&mut T一样声明对组件的写。
+/// 通过指针的修改无法被跟踪，所以取指针时就记录修改的tick。
+/// 指针只在本次system运行期间有效，任何结构性修改（插入、删除、alter、整理）后都不能再使用
+impl<T: 'static> FetchComponents for *mut T {
+    type Fetch<'w> = ColumnTick<'w>;
+    type Item<'w> = *mut T;
+    type ReadOnly = *const T;
+    type State = Share<Column>;
+
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        meta.component_relate(
+            world,
+            ComponentInfo::of::<T>(0),
+            Relation::Write(0usize.into()),
+        )
+        .1
+    }
+    #[inline]
+    fn init_fetch<'w>(
+        _world: &'w World,
+        state: &'w Self::State,
+        index: ArchetypeIndex,
+        tick: Tick,
+        last_run: Tick,
+    ) -> Self::Fetch<'w> {
+        ColumnTick::new(state.blob_ref_unchecked(index), tick, last_run)
+    }
+
+    #[inline(always)]
+    fn fetch<'w>(fetch: &Self::Fetch<'w>, row: Row, e: Entity) -> Self::Item<'w> {
+        fetch.column.changed_tick(e, row, fetch.tick);
+        fetch.column.get_mut::<T>(row, e) as *mut T
+    }
+}
+
+/// 组件的只读引用，同时可以取得组件添加和修改的tick
+pub struct Ref<T: 'static>(PhantomData<T>);
+impl<T: 'static> FetchComponents for Ref<T> {
+    type Fetch<'w> = ColumnTick<'w>;
+    type Item<'w> = TickRef<'w, T>;
+    type ReadOnly = Self;
+    type State = Share<Column>;
+
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        let c = meta
+            .component_relate(
+                world,
+                ComponentInfo::of::<T>(COMPONENT_TICK),
+                Relation::Read(0usize.into()),
+            )
+            .1;
+        // 注册时已开启tick，已有的行也补上了tick
+        assert!(
+            c.info().is_tick(),
+            "Ref<{}> requires component ticks",
+            std::any::type_name::<T>()
+        );
+        c
+    }
+    #[inline]
+    fn init_fetch<'w>(
+        _world: &'w World,
+        state: &'w Self::State,
+        index: ArchetypeIndex,
+        tick: Tick,
+        last_run: Tick,
+    ) -> Self::Fetch<'w> {
+        ColumnTick::new(state.blob_ref_unchecked(index), tick, last_run)
+    }
+
     #[inline(always)]
     fn fetch<'w>(fetch: &Self::Fetch<'w>, row: Row, e: Entity) -> Self::Item<'w> {
         TickRef::new(fetch, row, e)
@@ -538,6 +634,158 @@ impl<T: 'static> FetchComponents for Has<T> {
     }
 }
 
+/// 获得组件添加时的tick
+pub struct AddedTick<T: 'static>(PhantomData<T>);
+impl<T: 'static> FetchComponents for AddedTick<T> {
+    type Fetch<'w> = BlobRef<'w>;
+    type Item<'w> = Tick;
+    type ReadOnly = Self;
+    type State = Share<Column>;
+
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        meta.component_relate(
+            world,
+            ComponentInfo::of::<T>(COMPONENT_TICK),
+            Relation::Read(0usize.into()),
+        )
+        .1
+    }
+    #[inline]
+    fn init_fetch<'w>(
+        _world: &'w World,
+        state: &'w Self::State,
+        index: ArchetypeIndex,
+        _tick: Tick,
+        _last_run: Tick,
+    ) -> Self::Fetch<'w> {
+        state.blob_ref_unchecked(index)
+    }
+    #[inline(always)]
+    fn fetch<'w>(fetch: &Self::Fetch<'w>, row: Row, _e: Entity) -> Self::Item<'w> {
+        fetch.get_added_tick_unchecked(row)
+    }
+}
+
+/// 获得组件最后修改时的tick，添加也算修改
+pub struct ChangedTick<T: 'static>(PhantomData<T>);
+impl<T: 'static> FetchComponents for ChangedTick<T> {
+    type Fetch<'w> = BlobRef<'w>;
+    type Item<'w> = Tick;
+    type ReadOnly = Self;
+    type State = Share<Column>;
+
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        meta.component_relate(
+            world,
+            ComponentInfo::of::<T>(COMPONENT_TICK),
+            Relation::Read(0usize.into()),
+        )
+        .1
+    }
+    #[inline]
+    fn init_fetch<'w>(
+        _world: &'w World,
+        state: &'w Self::State,
+        index: ArchetypeIndex,
+        _tick: Tick,
+        _last_run: Tick,
+    ) -> Self::Fetch<'w> {
+        state.blob_ref_unchecked(index)
+    }
+    #[inline(always)]
+    fn fetch<'w>(fetch: &Self::Fetch<'w>, row: Row, _e: Entity) -> Self::Item<'w> {
+        fetch.get_tick_unchecked(row)
+    }
+}
+
+/// 组件的添加tick和最后修改tick
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ComponentTicks {
+    pub added: Tick,
+    pub changed: Tick,
+}
+impl ComponentTicks {
+    /// 是否在当前tick添加
+    #[inline]
+    pub fn is_added(&self, current_tick: Tick) -> bool {
+        self.added == current_tick
+    }
+    /// 是否在(last_run, current_tick]内修改，添加也算修改
+    #[inline]
+    pub fn is_changed(&self, last_run: Tick, current_tick: Tick) -> bool {
+        self.changed > last_run && self.changed <= current_tick
+    }
+}
+
+/// 同时获得组件的添加tick和最后修改tick，用于区分新添加和修改
+pub struct ChangeTrackers<T: 'static>(PhantomData<T>);
+impl<T: 'static> FetchComponents for ChangeTrackers<T> {
+    type Fetch<'w> = BlobRef<'w>;
+    type Item<'w> = ComponentTicks;
+    type ReadOnly = Self;
+    type State = Share<Column>;
+
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        meta.component_relate(
+            world,
+            ComponentInfo::of::<T>(COMPONENT_TICK),
+            Relation::Read(0usize.into()),
+        )
+        .1
+    }
+    #[inline]
+    fn init_fetch<'w>(
+        _world: &'w World,
+        state: &'w Self::State,
+        index: ArchetypeIndex,
+        _tick: Tick,
+        _last_run: Tick,
+    ) -> Self::Fetch<'w> {
+        state.blob_ref_unchecked(index)
+    }
+    #[inline(always)]
+    fn fetch<'w>(fetch: &Self::Fetch<'w>, row: Row, _e: Entity) -> Self::Item<'w> {
+        ComponentTicks {
+            added: fetch.get_added_tick_unchecked(row),
+            changed: fetch.get_tick_unchecked(row),
+        }
+    }
+}
+
+/// 获得组件在本帧内的修改范围：第一次和最后一次修改的tick及修改次数，可以和Changed<T>一起使用。
+/// 没有用World::set_change_span设置计数方式时，按SpanDedup::PerTick记录
+pub struct ChangeSpan<T: 'static>(PhantomData<T>);
+impl<T: 'static> FetchComponents for ChangeSpan<T> {
+    type Fetch<'w> = BlobRef<'w>;
+    type Item<'w> = ChangeSpanItem;
+    type ReadOnly = Self;
+    type State = Share<Column>;
+
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        let (index, c) = meta.component_relate(
+            world,
+            ComponentInfo::of::<T>(COMPONENT_TICK),
+            Relation::Read(0usize.into()),
+        );
+        world.init_change_span(index, None);
+        c
+    }
+    #[inline]
+    fn init_fetch<'w>(
+        _world: &'w World,
+        state: &'w Self::State,
+        index: ArchetypeIndex,
+        _tick: Tick,
+        _last_run: Tick,
+    ) -> Self::Fetch<'w> {
+        state.blob_ref_unchecked(index)
+    }
+    #[inline(always)]
+    fn fetch<'w>(fetch: &Self::Fetch<'w>, row: Row, _e: Entity) -> Self::Item<'w> {
+        fetch.change_span(row)
+    }
+}
+
 #[derive(Debug)]
 pub struct ComponentId<T: 'static>(pub ComponentIndex, PhantomData<T>);
 impl<T: 'static> FetchComponents for ComponentId<T> {
@@ -591,6 +839,38 @@ impl FetchComponents for ArchetypeName<'_> {
     }
 }
 
+/// 实体所在的原型，可以读取原型的用户数据
+#[derive(Debug, Clone, Copy)]
+pub struct ArchetypeRef<'a>(pub &'a Archetype);
+impl<'a> Deref for ArchetypeRef<'a> {
+    type Target = Archetype;
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+impl FetchComponents for ArchetypeRef<'_> {
+    type Fetch<'w> = &'w Archetype;
+    type Item<'w> = ArchetypeRef<'w>;
+    type ReadOnly = Self;
+    type State = ();
+
+    fn init_state(_world: &mut World, _meta: &mut SystemMeta) -> Self::State {}
+
+    fn init_fetch<'w>(
+        world: &'w World,
+        _state: &'w Self::State,
+        index: ArchetypeIndex,
+        _tick: Tick,
+        _last_run: Tick,
+    ) -> Self::Fetch<'w> {
+        world.get_archetype(index).unwrap()
+    }
+
+    fn fetch<'w>(fetch: &Self::Fetch<'w>, _row: Row, _e: Entity) -> Self::Item<'w> {
+        ArchetypeRef(fetch)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ColumnTick<'a> {
     pub(crate) column: BlobRef<'a>,
@@ -639,7 +919,32 @@ impl<'a, T> TickRef<'a, T> {
     }
     #[inline(always)]
     pub fn is_changed(&self) -> bool {
-        self.c.column.get_tick_unchecked(self.row) > self.c.last_run
+        self.c.column.is_changed(self.row, self.c.last_run)
+    }
+    /// 最后修改的tick，添加也算修改，同tick
+    #[inline(always)]
+    pub fn last_changed(&self) -> Tick {
+        self.tick()
+    }
+    /// 添加的tick
+    #[inline(always)]
+    pub fn added_tick(&self) -> Tick {
+        self.c.column.get_added_tick_unchecked(self.row)
+    }
+    /// 是否在system上次运行后被添加
+    #[inline(always)]
+    pub fn is_added(&self) -> bool {
+        self.c.column.is_added(self.row, self.c.last_run)
+    }
+    /// 是否在指定的tick后被修改
+    #[inline(always)]
+    pub fn is_changed_since(&self, tick: Tick) -> bool {
+        self.c.column.is_changed(self.row, tick)
+    }
+    /// 是否在指定的tick后被添加
+    #[inline(always)]
+    pub fn is_added_since(&self, tick: Tick) -> bool {
+        self.c.column.is_added(self.row, tick)
     }
 }
 impl<'a, T: 'static> Deref for TickRef<'a, T> {
@@ -718,7 +1023,7 @@ impl<'a, T> Ticker<'a, T> {
     }
 
     pub fn is_changed(&self) -> bool {
-        self.c.column.get_tick_unchecked(self.row) > self.c.last_run
+        self.c.column.is_changed(self.row, self.c.last_run)
     }
 }
 impl<'a, T: 'static> Deref for Ticker<'a, &'_ T> {
@@ -785,11 +1090,25 @@ impl<'a, T: Sized> Mut<'a, T> {
     }
     #[inline(always)]
     pub fn is_changed(&self) -> bool {
-        self.c.column.get_tick_unchecked(self.row) > self.c.last_run
+        self.c.column.is_changed(self.row, self.c.last_run)
     }
+    /// 获得可写引用，不记录修改
     pub fn bypass_change_detection(&mut self) -> &mut T {
         self.c.column.get_mut::<T>(self.row, self.e)
     }
+    /// 值不同时才写入并记录修改，返回是否写入。大量写入相同值时，避免无效的修改记录
+    pub fn set_if_neq(&mut self, value: T) -> bool
+    where
+        T: PartialEq,
+    {
+        let v = self.c.column.get_mut::<T>(self.row, self.e);
+        if *v == value {
+            return false;
+        }
+        *v = value;
+        self.c.column.changed_tick(self.e, self.row, self.c.tick);
+        true
+    }
 
     pub fn set_changed(&mut self) {
         self.c.column.changed_tick(self.e, self.row, self.c.tick);
@@ -816,6 +1135,86 @@ impl<'a, T: 'static> DerefMut for Mut<'a, T> {
     }
 }
 
+/// 粗粒度修改的组件，用于大批量改写有很多修改监听的组件。
+/// 修改时和Mut一样设置行的tick，所以Changed过滤器是精确的；
+/// 但同一个tick内每个原型只记录一次修改事件，事件为原型中的全部实体，
+/// 所以ComponentChanged等事件的监听者收到的是原型级别的近似，可能包含没有修改的实体
+pub struct CoarseMut<'a, T: 'static> {
+    pub(crate) c: ColumnTick<'a>,
+    pub(crate) ar: &'a Archetype,
+    pub(crate) e: Entity,
+    pub(crate) row: Row,
+    _p: PhantomData<T>,
+}
+impl<'a, T: 'static> CoarseMut<'a, T> {
+    #[inline(always)]
+    pub fn entity(&self) -> Entity {
+        self.e
+    }
+    #[inline(always)]
+    pub fn is_changed(&self) -> bool {
+        self.c.column.is_changed(self.row, self.c.last_run)
+    }
+    pub fn bypass_change_detection(&mut self) -> &mut T {
+        self.c.column.get_mut::<T>(self.row, self.e)
+    }
+    pub fn set_changed(&mut self) {
+        self.c.column.coarse_changed_tick(self.row, self.c.tick, self.ar);
+    }
+}
+impl<'a, T: 'static> Deref for CoarseMut<'a, T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        self.c.column.get::<T>(self.row, self.e)
+    }
+}
+impl<'a, T: 'static> DerefMut for CoarseMut<'a, T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.c.column.coarse_changed_tick(self.row, self.c.tick, self.ar);
+        self.c.column.get_mut::<T>(self.row, self.e)
+    }
+}
+impl<T: 'static> FetchComponents for CoarseMut<'_, T> {
+    type Fetch<'w> = (ColumnTick<'w>, &'w Archetype);
+    type Item<'w> = CoarseMut<'w, T>;
+    type ReadOnly = &'static T;
+    type State = Share<Column>;
+
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        meta.component_relate(
+            world,
+            ComponentInfo::of::<T>(0),
+            Relation::Write(0usize.into()),
+        )
+        .1
+    }
+    #[inline]
+    fn init_fetch<'w>(
+        world: &'w World,
+        state: &'w Self::State,
+        index: ArchetypeIndex,
+        tick: Tick,
+        last_run: Tick,
+    ) -> Self::Fetch<'w> {
+        (
+            ColumnTick::new(state.blob_ref_unchecked(index), tick, last_run),
+            &**unsafe { world.get_archetype_unchecked(index) },
+        )
+    }
+    #[inline(always)]
+    fn fetch<'w>(fetch: &Self::Fetch<'w>, row: Row, e: Entity) -> Self::Item<'w> {
+        CoarseMut {
+            c: fetch.0.clone(),
+            ar: fetch.1,
+            e,
+            row,
+            _p: PhantomData,
+        }
+    }
+}
+
 macro_rules! impl_tuple_fetch {
     ($(($name: ident, $state: ident)),*) => {
         #[allow(non_snake_case)]
@@ -842,6 +1241,16 @@ macro_rules! impl_tuple_fetch {
                 let ($($state,)*) = _state;
                 ($($name::init_fetch(_world, $state, _index, _tick, _last_run),)*)
             }
+            // 返回第一个错误
+            fn try_init_archetype(
+                _world: &World,
+                _state: &Self::State,
+                _ar: &Archetype,
+            ) -> Result<(), FetchInitError> {
+                let ($($state,)*) = _state;
+                $($name::try_init_archetype(_world, $state, _ar)?;)*
+                Ok(())
+            }
 
             #[allow(clippy::unused_unit)]
             #[inline(always)]
@@ -857,4 +1266,152 @@ macro_rules! impl_tuple_fetch {
 
     };
 }
+// 元组默认最多15项，开启large-tuples特性后最多24项。
+// 超过上限时可以嵌套元组，比如Query<((&A, .., &O), (&P, &Q))>，嵌套的元组本身也是FetchComponents，不需要开启特性
+// all_tuples!生成的标识符数量只与区间长度有关，所以开启特性时从0重新生成到24
+#[cfg(not(feature = "large-tuples"))]
 all_tuples!(impl_tuple_fetch, 0, 15, F, S);
+#[cfg(feature = "large-tuples")]
+all_tuples!(impl_tuple_fetch, 0, 24, F, S);
+
+/// 至少有其中一项的实体，不存在的项为None
+/// Query<(Entity, AnyOf<(&A, &mut B)>)>，只有A的实体返回(Some(&A), None)
+pub struct AnyOf<T>(PhantomData<T>);
+
+// 该项必须存在的组件都在原型上
+fn any_of_has(ar: &Archetype, required: &[ComponentIndex]) -> bool {
+    required.iter().all(|i| ar.contains(*i))
+}
+
+macro_rules! impl_any_of_fetch {
+    ($(($name: ident, $state: ident)),*) => {
+        #[allow(non_snake_case)]
+        impl<$($name: FetchComponents),*> FetchComponents for AnyOf<($($name,)*)> {
+            type Fetch<'w> = ($(Option<$name::Fetch<'w>>,)*);
+            type Item<'w> = ($(Option<$name::Item<'w>>,)*);
+            type ReadOnly = AnyOf<($($name::ReadOnly,)*)>;
+            // 每项的状态和该项必须存在的组件
+            type State = ($(($name::State, Vec<ComponentIndex>),)*);
+
+            fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+                let start = meta.related_len();
+                meta.relate(Relation::Or);
+                let state = ($({
+                    meta.relate(Relation::And);
+                    let i = meta.related_len();
+                    let s = $name::init_state(world, meta);
+                    let required = meta.required_since(i);
+                    meta.relate(Relation::End);
+                    (s, required)
+                },)*);
+                meta.relate(Relation::End);
+                meta.any_of_related(start);
+                state
+            }
+            #[inline(always)]
+            fn init_fetch<'w>(
+                world: &'w World,
+                state: &'w Self::State,
+                index: ArchetypeIndex,
+                tick: Tick,
+                last_run: Tick,
+            ) -> Self::Fetch<'w> {
+                let ar = unsafe { world.archetype_arr.get_unchecked(index.index()) };
+                let ($($state,)*) = state;
+                ($(if any_of_has(ar, &$state.1) {
+                    Some($name::init_fetch(world, &$state.0, index, tick, last_run))
+                } else {
+                    None
+                },)*)
+            }
+            // 只检查原型上存在的项
+            fn try_init_archetype(
+                world: &World,
+                state: &Self::State,
+                ar: &Archetype,
+            ) -> Result<(), FetchInitError> {
+                let ($($state,)*) = state;
+                $(if any_of_has(ar, &$state.1) {
+                    $name::try_init_archetype(world, &$state.0, ar)?;
+                })*
+                Ok(())
+            }
+            #[inline(always)]
+            fn fetch<'w>(fetch: &Self::Fetch<'w>, row: Row, e: Entity) -> Self::Item<'w> {
+                let ($($name,)*) = fetch;
+                ($($name.as_ref().map(|f| $name::fetch(f, row, e)),)*)
+            }
+        }
+    };
+}
+all_tuples!(impl_any_of_fetch, 1, 8, F, S);
+
+/// 不访问任何组件的占位项，Item为()。泛型system中用来携带类型参数
+/// Query<(&T, &mut Mirror, PhantomData<T>)>
+impl<T: ?Sized + 'static> FetchComponents for PhantomData<T> {
+    type Fetch<'w> = ();
+    type Item<'w> = ();
+    type ReadOnly = Self;
+    type State = ();
+
+    fn init_state(_world: &mut World, _meta: &mut SystemMeta) -> Self::State {}
+    #[inline(always)]
+    fn init_fetch<'w>(
+        _world: &'w World,
+        _state: &'w Self::State,
+        _index: ArchetypeIndex,
+        _tick: Tick,
+        _last_run: Tick,
+    ) -> Self::Fetch<'w> {
+    }
+    #[inline(always)]
+    fn fetch<'w>(_fetch: &Self::Fetch<'w>, _row: Row, _e: Entity) -> Self::Item<'w> {}
+}
+
+/// 可关闭的转发项，ON为true时和T相同，Item为Some；为false时不声明访问、不过滤原型，Item总是None。
+/// 泛型system用const参数选择是否启用某一项，比如测试中关掉写入：Query<(&A, Pass<&mut B, ON>)>
+pub struct Pass<T, const ON: bool = true>(PhantomData<T>);
+impl<T, const ON: bool> Pass<T, ON> {
+    /// 是否启用
+    pub const ENABLED: bool = ON;
+}
+impl<T: FetchComponents, const ON: bool> FetchComponents for Pass<T, ON> {
+    type Fetch<'w> = Option<T::Fetch<'w>>;
+    type Item<'w> = Option<T::Item<'w>>;
+    type ReadOnly = Pass<T::ReadOnly, ON>;
+    type State = Option<T::State>;
+
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        if Self::ENABLED {
+            Some(T::init_state(world, meta))
+        } else {
+            None
+        }
+    }
+    #[inline(always)]
+    fn init_fetch<'w>(
+        world: &'w World,
+        state: &'w Self::State,
+        index: ArchetypeIndex,
+        tick: Tick,
+        last_run: Tick,
+    ) -> Self::Fetch<'w> {
+        state
+            .as_ref()
+            .map(|s| T::init_fetch(world, s, index, tick, last_run))
+    }
+    fn try_init_archetype(
+        world: &World,
+        state: &Self::State,
+        ar: &Archetype,
+    ) -> Result<(), FetchInitError> {
+        match state {
+            Some(s) => T::try_init_archetype(world, s, ar),
+            None => Ok(()),
+        }
+    }
+    #[inline(always)]
+    fn fetch<'w>(fetch: &Self::Fetch<'w>, row: Row, e: Entity) -> Self::Item<'w> {
+        fetch.as_ref().map(|f| T::fetch(f, row, e))
+    }
+}
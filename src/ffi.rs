@@ -0,0 +1,354 @@
+//! C接口，开启ffi特性后可用，用于嵌入C编写的脚本虚拟机
+//! 组件用稳定id（稳定名称的FNV-1a，见ComponentInfo::stable_id）指定，组件必须先在Rust侧用World::register_stable_name注册，
+//! 没有注册稳定名称的组件在C侧不可见，类型名会随编译器版本变化，不能作为C侧的id。
+//! 创建实体时，和Bundle插入一样补上依赖组件（见required模块），用默认值初始化。
+//! 实体在C侧用u64表示，pi_entity_null()为空实体。
+//! 指针的有效期：
+//! - pi_world_spawn的values只在调用期间读取，组件按位复制进world，所有权转移给world；
+//! - pi_query_iter回调的ptrs及其指向的组件只在本次回调内有效，回调内不能调用同一world的其他接口；
+//! - WorldHandle和QueryHandle由对应的destroy释放，QueryHandle必须在WorldHandle之前释放。
+//! 返回i32的接口，0表示成功，负数为PiWorldError。
+//!
+
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::ptr;
+use std::slice;
+
+use pi_key_alloter::{Key, KeyData};
+use pi_null::Null;
+use pi_share::Share;
+
+use crate::archetype::{ArchetypeInfo, Row};
+use crate::column::Column;
+use crate::query::QueryError;
+use crate::world::{ComponentIndex, Entity, World};
+
+/// 错误码
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PiWorldError {
+    Ok = 0,
+    NullPointer = -1,
+    NoSuchComponent = -2,
+    NoSuchEntity = -3,
+    DuplicateComponent = -4,
+    MissingComponent = -5,
+    NoMatchArchetype = -6,
+    NoMatchEntity = -7,
+    NoSuchRow = -8,
+    NoSuchRes = -9,
+    RepeatAlter = -10,
+    GroupLocked = -11,
+    MissingReadAccess = -12,
+    NotDefaultable = -13,
+    DuplicateEntity = -14,
+    TooManyEntities = -15,
+    NotSerializable = -17,
+}
+impl From<&QueryError> for PiWorldError {
+    fn from(e: &QueryError) -> Self {
+        match e {
+            QueryError::MissingComponent(..) => Self::MissingComponent,
+            QueryError::NoMatchArchetype => Self::NoMatchArchetype,
+            QueryError::NoMatchEntity(_) => Self::NoMatchEntity,
+            QueryError::NoSuchComponent(_) => Self::NoSuchComponent,
+            QueryError::NoSuchEntity(_) => Self::NoSuchEntity,
+            QueryError::NoSuchRow(_) => Self::NoSuchRow,
+            QueryError::NoSuchRes => Self::NoSuchRes,
+            QueryError::RepeatAlter => Self::RepeatAlter,
+            QueryError::GroupLocked(_) => Self::GroupLocked,
+            QueryError::MissingReadAccess(_) => Self::MissingReadAccess,
+            QueryError::NotDefaultable(..) => Self::NotDefaultable,
+            QueryError::DuplicateEntity(_) => Self::DuplicateEntity,
+            QueryError::TooManyEntities => Self::TooManyEntities,
+            QueryError::NotSerializable(_) => Self::NotSerializable,
+        }
+    }
+}
+
+/// world的句柄
+pub struct WorldHandle {
+    world: World,
+    ids: HashMap<u64, Share<Column>>, // 注册了稳定名称的组件，稳定id -> 列
+}
+impl WorldHandle {
+    /// 将world转为C侧使用的句柄，用pi_world_destroy释放
+    pub fn into_raw(world: World) -> *mut WorldHandle {
+        Box::into_raw(Box::new(WorldHandle {
+            world,
+            ids: HashMap::new(),
+        }))
+    }
+    /// 获得句柄中的world
+    /// # Safety
+    /// handle必须是into_raw返回的且还未释放
+    pub unsafe fn world<'a>(handle: *mut WorldHandle) -> &'a mut World {
+        &mut (*handle).world
+    }
+    // 找不到时重新收集注册了稳定名称的组件，之后注册的稳定名称也能找到
+    fn column(&mut self, id: u64) -> Result<Share<Column>, PiWorldError> {
+        if !self.ids.contains_key(&id) {
+            for c in self.world.component_arr.iter() {
+                if c.info().stable_name.is_some() {
+                    self.ids.insert(c.info().stable_id(), c.clone());
+                }
+            }
+        }
+        self.ids.get(&id).cloned().ok_or(PiWorldError::NoSuchComponent)
+    }
+    fn columns(&mut self, ids: *const u64, count: usize) -> Result<Vec<Share<Column>>, PiWorldError> {
+        let ids = unsafe { ids_slice(ids, count)? };
+        let mut vec: Vec<Share<Column>> = Vec::with_capacity(count);
+        for id in ids {
+            let c = self.column(*id)?;
+            if vec.iter().any(|v| v.info().index == c.info().index) {
+                return Err(PiWorldError::DuplicateComponent);
+            }
+            vec.push(c);
+        }
+        Ok(vec)
+    }
+}
+
+/// 动态查询的句柄
+pub struct QueryHandle {
+    read: Vec<Share<Column>>,
+    write: Vec<Share<Column>>,
+    with: Vec<ComponentIndex>,
+    without: Vec<ComponentIndex>,
+}
+
+/// 查询的回调，entity为实体，ptrs依次为读的组件和写的组件的指针，只在本次回调内有效
+pub type PiQueryCallback = extern "C" fn(entity: u64, ptrs: *const *mut u8, user_data: *mut c_void);
+
+unsafe fn ids_slice<'a>(ids: *const u64, count: usize) -> Result<&'a [u64], PiWorldError> {
+    if count == 0 {
+        return Ok(&[]);
+    }
+    if ids.is_null() {
+        return Err(PiWorldError::NullPointer);
+    }
+    Ok(slice::from_raw_parts(ids, count))
+}
+fn entity_bits(e: Entity) -> u64 {
+    e.data().as_ffi()
+}
+fn bits_entity(bits: u64) -> Entity {
+    KeyData::from_ffi(bits).into()
+}
+fn code<T>(r: Result<T, PiWorldError>) -> i32 {
+    match r {
+        Ok(_) => PiWorldError::Ok as i32,
+        Err(e) => e as i32,
+    }
+}
+
+/// 空实体
+#[no_mangle]
+pub extern "C" fn pi_entity_null() -> u64 {
+    entity_bits(Entity::null())
+}
+
+/// 释放world的句柄
+/// # Safety
+/// handle必须是WorldHandle::into_raw返回的且还未释放
+#[no_mangle]
+pub unsafe extern "C" fn pi_world_destroy(handle: *mut WorldHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// 创建实体，comp_ids和values都有count项，values[i]指向comp_ids[i]组件的值，按位复制。
+/// 失败返回pi_entity_null()
+/// # Safety
+/// 指针必须有效，values[i]指向的内存至少为组件的大小
+#[no_mangle]
+pub unsafe extern "C" fn pi_world_spawn(
+    handle: *mut WorldHandle,
+    comp_ids: *const u64,
+    values: *const *const u8,
+    count: usize,
+) -> u64 {
+    match spawn(handle, comp_ids, values, count) {
+        Ok(e) => entity_bits(e),
+        Err(_) => pi_entity_null(),
+    }
+}
+unsafe fn spawn(
+    handle: *mut WorldHandle,
+    comp_ids: *const u64,
+    values: *const *const u8,
+    count: usize,
+) -> Result<Entity, PiWorldError> {
+    if handle.is_null() || (count > 0 && values.is_null()) {
+        return Err(PiWorldError::NullPointer);
+    }
+    let h = &mut *handle;
+    let columns = h.columns(comp_ids, count)?;
+    let values = if count == 0 { &[] } else { slice::from_raw_parts(values, count) };
+    if values.iter().zip(columns.iter()).any(|(v, c)| v.is_null() && c.info().size() > 0) {
+        return Err(PiWorldError::NullPointer);
+    }
+    let world = &h.world;
+    // 和Bundle插入一样补上依赖组件
+    let present: Vec<ComponentIndex> = columns.iter().map(|c| c.info().index).collect();
+    let mut all = columns.clone();
+    for r in world.required.missing(&present, &present) {
+        all.push(world.get_column(r).unwrap().clone());
+    }
+    let ar = world.find_archtype(ArchetypeInfo::sort(all));
+    let required = world.required_defaults(&ar, |info| present.contains(&info.index));
+    let (r, row) = ar.alloc();
+    let row: Row = row.into();
+    let e = world.insert_addr(ar.index(), row);
+    let tick = world.tick();
+    for (c, v) in columns.iter().zip(values.iter()) {
+        let b = c.blob_ref_unchecked(ar.index());
+        if c.info().size() > 0 {
+            ptr::copy_nonoverlapping(*v, b.load(row, e), c.info().size());
+        }
+        b.added_tick(e, row, tick);
+    }
+    for (c, f) in required.iter() {
+        let b = c.blob_ref_unchecked(ar.index());
+        f(b.load(row, e));
+        b.added_tick(e, row, tick);
+    }
+    *r = e;
+    ar.record_allocated(row, e);
+    Ok(e)
+}
+
+/// 销毁实体
+/// # Safety
+/// handle必须有效
+#[no_mangle]
+pub unsafe extern "C" fn pi_world_despawn(handle: *mut WorldHandle, entity: u64) -> i32 {
+    if handle.is_null() {
+        return PiWorldError::NullPointer as i32;
+    }
+    let world = &mut (*handle).world;
+    code(world.destroy_entity(bits_entity(entity)).map_err(|e| PiWorldError::from(&e)))
+}
+
+/// 实体是否存在
+/// # Safety
+/// handle必须有效
+#[no_mangle]
+pub unsafe extern "C" fn pi_world_contains(handle: *mut WorldHandle, entity: u64) -> bool {
+    !handle.is_null() && (*handle).world.contains(bits_entity(entity))
+}
+
+/// 整理world，一帧结束时调用
+/// # Safety
+/// handle必须有效
+#[no_mangle]
+pub unsafe extern "C" fn pi_world_settle(handle: *mut WorldHandle) {
+    if !handle.is_null() {
+        (*handle).world.settle();
+    }
+}
+
+/// 创建动态查询，成功时将句柄写入out，用pi_query_destroy释放
+/// # Safety
+/// 指针必须有效，各id数组的长度为对应的count
+#[allow(clippy::too_many_arguments)]
+#[no_mangle]
+pub unsafe extern "C" fn pi_query_create(
+    handle: *mut WorldHandle,
+    read_ids: *const u64,
+    read_count: usize,
+    write_ids: *const u64,
+    write_count: usize,
+    with_ids: *const u64,
+    with_count: usize,
+    without_ids: *const u64,
+    without_count: usize,
+    out: *mut *mut QueryHandle,
+) -> i32 {
+    if handle.is_null() || out.is_null() {
+        return PiWorldError::NullPointer as i32;
+    }
+    let h = &mut *handle;
+    let r = (|| {
+        let read = h.columns(read_ids, read_count)?;
+        let write = h.columns(write_ids, write_count)?;
+        let with = h.columns(with_ids, with_count)?;
+        let without = h.columns(without_ids, without_count)?;
+        for c in write.iter() {
+            if read.iter().any(|r| r.info().index == c.info().index) {
+                return Err(PiWorldError::DuplicateComponent);
+            }
+        }
+        Ok(QueryHandle {
+            read,
+            write,
+            with: with.iter().map(|c| c.info().index).collect(),
+            without: without.iter().map(|c| c.info().index).collect(),
+        })
+    })();
+    match r {
+        Ok(q) => {
+            *out = Box::into_raw(Box::new(q));
+            PiWorldError::Ok as i32
+        }
+        Err(e) => e as i32,
+    }
+}
+
+/// 释放动态查询
+/// # Safety
+/// query必须是pi_query_create创建的且还未释放
+#[no_mangle]
+pub unsafe extern "C" fn pi_query_destroy(query: *mut QueryHandle) {
+    if !query.is_null() {
+        drop(Box::from_raw(query));
+    }
+}
+
+/// 遍历查询的实体，每个实体调用一次callback，写的组件会记录修改
+/// # Safety
+/// 指针必须有效，回调内不能调用同一world的其他接口
+#[no_mangle]
+pub unsafe extern "C" fn pi_query_iter(
+    handle: *mut WorldHandle,
+    query: *mut QueryHandle,
+    callback: PiQueryCallback,
+    user_data: *mut c_void,
+) -> i32 {
+    if handle.is_null() || query.is_null() {
+        return PiWorldError::NullPointer as i32;
+    }
+    let world = &(*handle).world;
+    let q = &*query;
+    let tick = world.increment_tick();
+    let mut ptrs: Vec<*mut u8> = Vec::with_capacity(q.read.len() + q.write.len());
+    for ar in world.archetype_arr.iter() {
+        if !q.read.iter().chain(q.write.iter()).all(|c| ar.contains(c.info().index))
+            || !q.with.iter().all(|i| ar.contains(*i))
+            || q.without.iter().any(|i| ar.contains(*i))
+        {
+            continue;
+        }
+        let reads: Vec<_> = q.read.iter().map(|c| c.blob_ref_unchecked(ar.index())).collect();
+        let writes: Vec<_> = q.write.iter().map(|c| c.blob_ref_unchecked(ar.index())).collect();
+        for row in 0..ar.len().index() {
+            let row = Row(row as u32);
+            let e = match ar.live_entity(row) {
+                Some(e) => e,
+                None => continue,
+            };
+            ptrs.clear();
+            for b in reads.iter().chain(writes.iter()) {
+                ptrs.push(b.get_row(row, e));
+            }
+            callback(entity_bits(e), ptrs.as_ptr(), user_data);
+            for b in writes.iter() {
+                b.changed_tick(e, row, tick);
+            }
+        }
+    }
+    PiWorldError::Ok as i32
+}
@@ -2,22 +2,30 @@
 //! Empty表示取World的空原型
 //! 2种原型过滤器 Without<C> With<C>
 //! Or只支持多个With<C>，表示原型上只要有任何1个C就可以
-//! Changed Removed Destroyed为迭代器，多个迭代器是或关系， 原型上只要有1个可迭代的组件就可以
+//! 原型过滤不需要单独的archetype_filter方法，init_state时用world将组件解析为ComponentIndex并记录到Relation上，
+//! QState在新原型创建时用原型的组件索引位图判断是否匹配，所以原型上不需要TypeId到组件的映射
+//! Changed Added Removed Destroyed为迭代器，多个迭代器是或关系， 原型上只要有1个可迭代的组件就可以
 //! Query<(&T, &mut C8>), (Without<C1>,With<C2>,With<C3>,Or<(With<C4>, With<C5>)>, Changed<C6>, Destroyed, Removed<C8>)>
 //!
 
 use pi_proc_macros::all_tuples;
-use pi_share::Share;
+use pi_share::{Share, ShareMutex};
+use std::any::TypeId;
+use std::collections::HashSet;
 use std::marker::PhantomData;
 
 use crate::archetype::{ArchetypeIndex, ComponentInfo, Row, COMPONENT_TICK};
 use crate::column::{BlobRef, Column};
+use crate::event::{init_removed_state, ComponentEventVec};
 use crate::prelude::{Entity, Tick};
 use crate::system::SystemMeta;
 use crate::world::{ComponentIndex, World};
 
 pub trait FilterComponents {
     // const LISTENER_COUNT: usize;
+    /// 是否逐行过滤（按组件的修改、帧内组件等），逐行过滤时查询迭代的数量只有上限，也不支持稠密序号。
+    /// 默认为true，只按原型过滤、filter总是返回false的实现应设为false
+    const ROW_FILTER: bool = true;
     type Filter<'w>;
     type State: Send + Sync + Sized;
     /// initializes ReadWrite for this [`FilterComponents`] type.
@@ -38,7 +46,7 @@ pub trait FilterComponents {
 
 pub struct Without<T: 'static>(PhantomData<T>);
 impl<T: 'static> FilterComponents for Without<T> {
-
+    const ROW_FILTER: bool = false;
     type Filter<'w> = ();
     type State = ComponentIndex;
     fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
@@ -64,7 +72,7 @@ impl<T: 'static> FilterComponents for Without<T> {
 
 pub struct With<T: 'static>(PhantomData<T>);
 impl<T: 'static> FilterComponents for With<T> {
-
+    const ROW_FILTER: bool = false;
     type Filter<'w> = ();
     type State = ComponentIndex;
     fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
@@ -88,9 +96,77 @@ impl<T: 'static> FilterComponents for With<T> {
 
 }
 
-pub struct Changed<T: 'static>(PhantomData<T>);
-impl<T: 'static> FilterComponents for Changed<T> {
+/// 声明对组件T的额外读，不影响原型的匹配和过滤，用于Query::get_extra按需读取Q中没有声明的组件
+pub struct ExtraRead<T: 'static>(PhantomData<T>);
+impl<T: 'static> FilterComponents for ExtraRead<T> {
+    const ROW_FILTER: bool = false;
+    type Filter<'w> = ();
+    type State = ComponentIndex;
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        meta.component_relate(
+            world,
+            ComponentInfo::of::<T>(0),
+            crate::system::Relation::OptRead(0usize.into()),
+        )
+        .0
+    }
+    #[inline]
+    fn init_filter<'w>(
+        _world: &'w World,
+        _state: &'w Self::State,
+        _index: ArchetypeIndex,
+        _tick: Tick,
+        _last_run: Tick,
+    ) -> Self::Filter<'w> {
+        ()
+    }
+}
 
+/// 组件修改过滤，N为0时，只保留上次运行后修改的行。
+/// N大于0时，保留最近N个tick内修改的行（当前tick减去修改tick不大于N），和上次运行的时间无关，用于平滑插值等需要多帧窗口的system
+pub struct Changed<T: 'static, const N: usize = 0>(PhantomData<T>);
+impl<T: 'static, const N: usize> FilterComponents for Changed<T, N> {
+    const ROW_FILTER: bool = true;
+    type Filter<'w> = (Option<BlobRef<'w>>, Tick);
+    type State = Share<Column>;
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        meta.component_relate(
+            world,
+            ComponentInfo::of::<T>(COMPONENT_TICK),
+            crate::system::Relation::Read(0usize.into()),
+        )
+        .1
+    }
+
+    #[inline(always)]
+    fn init_filter<'w>(
+        _world: &'w World,
+        state: &'w Self::State,
+        index: ArchetypeIndex,
+        tick: Tick,
+        last_run: Tick,
+    ) -> Self::Filter<'w> {
+        let last = if N == 0 {
+            last_run
+        } else {
+            // 修改tick不大于该值的行，都在窗口外
+            tick.saturating_sub(N as u32 + 1).into()
+        };
+        (state.blob_ref(index), last)
+    }
+
+    #[inline(always)]
+    fn filter<'w>(filter: &Self::Filter<'w>, row: Row, _e: Entity) -> bool {
+        if let Some(r) = &filter.0 {
+            !r.is_changed(row, filter.1)
+        }else{
+            true
+        }
+    }
+}
+pub struct Added<T: 'static>(PhantomData<T>);
+impl<T: 'static> FilterComponents for Added<T> {
+    const ROW_FILTER: bool = true;
     type Filter<'w> = (Option<BlobRef<'w>>, Tick);
     type State = Share<Column>;
     fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
@@ -116,12 +192,139 @@ impl<T: 'static> FilterComponents for Changed<T> {
     #[inline(always)]
     fn filter<'w>(filter: &Self::Filter<'w>, row: Row, _e: Entity) -> bool {
         if let Some(r) = &filter.0 {
-            r.get_tick_unchecked(row) <= filter.1
+            !r.is_added(row, filter.1)
         }else{
             true
         }
     }
 }
+
+/// 组件未修改过滤，只保留上次运行后没有修改过T的行，用于只处理增量system没有处理过的实体的补偿system。
+/// 只是逐行比较修改tick和上次运行的tick，不注册监听，本system自己的修改tick不大于下次运行时的上次运行tick，不影响判断。
+/// tick不回绕，直接比较大小。原型必须有T，可以和With、Without等正常组合
+pub struct Unchanged<T: 'static>(PhantomData<T>);
+impl<T: 'static> FilterComponents for Unchanged<T> {
+    const ROW_FILTER: bool = true;
+    type Filter<'w> = (Option<BlobRef<'w>>, Tick);
+    type State = Share<Column>;
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        meta.component_relate(
+            world,
+            ComponentInfo::of::<T>(COMPONENT_TICK),
+            crate::system::Relation::Read(0usize.into()),
+        )
+        .1
+    }
+
+    #[inline(always)]
+    fn init_filter<'w>(
+        _world: &'w World,
+        state: &'w Self::State,
+        index: ArchetypeIndex,
+        _tick: Tick,
+        last_run: Tick,
+    ) -> Self::Filter<'w> {
+        (state.blob_ref(index), last_run)
+    }
+
+    #[inline(always)]
+    fn filter<'w>(filter: &Self::Filter<'w>, row: Row, _e: Entity) -> bool {
+        match &filter.0 {
+            Some(r) => r.is_changed(row, filter.1),
+            None => true,
+        }
+    }
+}
+/// 组件未添加过滤，只保留上次运行前就已有T的行，其他同Unchanged
+pub struct NotAdded<T: 'static>(PhantomData<T>);
+impl<T: 'static> FilterComponents for NotAdded<T> {
+    const ROW_FILTER: bool = true;
+    type Filter<'w> = (Option<BlobRef<'w>>, Tick);
+    type State = Share<Column>;
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        meta.component_relate(
+            world,
+            ComponentInfo::of::<T>(COMPONENT_TICK),
+            crate::system::Relation::Read(0usize.into()),
+        )
+        .1
+    }
+
+    #[inline(always)]
+    fn init_filter<'w>(
+        _world: &'w World,
+        state: &'w Self::State,
+        index: ArchetypeIndex,
+        _tick: Tick,
+        last_run: Tick,
+    ) -> Self::Filter<'w> {
+        (state.blob_ref(index), last_run)
+    }
+
+    #[inline(always)]
+    fn filter<'w>(filter: &Self::Filter<'w>, row: Row, _e: Entity) -> bool {
+        match &filter.0 {
+            Some(r) => r.is_added(row, filter.1),
+            None => true,
+        }
+    }
+}
+
+/// 组件移除过滤，只保留上次运行后被移除了T的实体，和ComponentRemoved读取相同的移除记录。
+/// 移除后实体一般已不在有T的原型上，所以不限制原型，用其他组件限制查询的原型。
+/// 移除记录在第一次初始化过滤器时读取，同一tick内的多次迭代共用，没有匹配的原型时不读取，留到下次
+pub struct Removed<T: 'static>(PhantomData<T>);
+impl<T: 'static> FilterComponents for Removed<T> {
+    const ROW_FILTER: bool = true;
+    type Filter<'w> = Share<HashSet<Entity>>;
+    type State = RemovedState;
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        let (record, listener_index) = init_removed_state(
+            world,
+            meta,
+            TypeId::of::<Removed<T>>(),
+            ComponentInfo::of::<T>(0),
+        );
+        RemovedState {
+            record,
+            listener_index,
+            cache: ShareMutex::new((None, Share::new(HashSet::new()))),
+        }
+    }
+
+    #[inline(always)]
+    fn init_filter<'w>(
+        _world: &'w World,
+        state: &'w Self::State,
+        _index: ArchetypeIndex,
+        tick: Tick,
+        _last_run: Tick,
+    ) -> Self::Filter<'w> {
+        state.entities(tick)
+    }
+
+    #[inline(always)]
+    fn filter<'w>(filter: &Self::Filter<'w>, _row: Row, e: Entity) -> bool {
+        !filter.contains(&e)
+    }
+}
+pub struct RemovedState {
+    record: Share<ComponentEventVec>,
+    listener_index: usize,
+    // 本tick读取到的被移除的实体
+    cache: ShareMutex<(Option<Tick>, Share<HashSet<Entity>>)>,
+}
+impl RemovedState {
+    fn entities(&self, tick: Tick) -> Share<HashSet<Entity>> {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.0 != Some(tick) {
+            let range = self.record.take_unread(self.listener_index);
+            let set = range.filter_map(|i| self.record.get(i).copied()).collect();
+            *cache = (Some(tick), Share::new(set));
+        }
+        cache.1.clone()
+    }
+}
 pub struct Or<T: 'static>(PhantomData<T>);
 
 
@@ -135,6 +338,7 @@ macro_rules! impl_tuple_filter {
             type State = ($($name::State,)*);
 
             // const LISTENER_COUNT: usize = $($name::LISTENER_COUNT + )* 0;
+            const ROW_FILTER: bool = $($name::ROW_FILTER || )* false;
 	        fn init_state(_world: &mut World, _meta: &mut SystemMeta) -> Self::State {
                 ($($name::init_state(_world, _meta),)*)
             }
@@ -172,6 +376,7 @@ macro_rules! impl_tuple_filter {
             type State = ($($name::State,)*);
 
             // const LISTENER_COUNT: usize = $($name::LISTENER_COUNT + )* 0;
+            const ROW_FILTER: bool = $($name::ROW_FILTER || )* false;
 	        fn init_state(_world: &mut World, _meta: &mut SystemMeta) -> Self::State {
                 _meta.relate(crate::system::Relation::Or);
                 let s = ($($name::init_state(_world, _meta),)*);
@@ -205,4 +410,9 @@ macro_rules! impl_tuple_filter {
 
     };
 }
+// 和FetchComponents一样，默认最多15项，开启large-tuples特性后最多24项，超过上限时可以嵌套元组
+// all_tuples!生成的标识符数量只与区间长度有关，所以开启特性时从0重新生成到24
+#[cfg(not(feature = "large-tuples"))]
 all_tuples!(impl_tuple_filter, 0, 15, F, S);
+#[cfg(feature = "large-tuples")]
+all_tuples!(impl_tuple_filter, 0, 24, F, S);
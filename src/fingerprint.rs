@@ -0,0 +1,137 @@
+//! 执行图的兼容性指纹
+//! 序列化的执行图，其依赖边是按上次world的组件索引计算的，加载时如果组件的注册顺序变了，边就会不正确。
+//! 组件注册表的指纹按注册顺序计算，system的读写指纹用组件的稳定id，和world上的索引无关。
+//! 指纹用固定的FNV-1a计算，组件的稳定id由World::register_stable_name注册的名称计算，不随编译器版本变化。
+//! 没有注册稳定名称的组件、system和资源用类型名，升级编译器后指纹可能变化，执行图会被重新构建。
+//! 保存执行图时，一起保存graph_fingerprints的结果，加载时调用validate_graph_compat，不兼容则重新构建执行图。
+//!
+
+use crate::system::{Relation, SystemMeta};
+use crate::world::{ComponentIndex, World};
+
+// FNV-1a，数值按小端序写入，结果和平台及编译器版本无关
+struct Fnv(u64);
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf29ce484222325)
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+    fn u64(&mut self, v: u64) {
+        self.write(&v.to_le_bytes());
+    }
+    // 先写长度，避免相邻的字符串拼接后相同
+    fn str(&mut self, s: &str) {
+        self.u64(s.len() as u64);
+        self.write(s.as_bytes());
+    }
+}
+
+/// 指纹不兼容的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphMismatch {
+    Registry, // 组件注册表不同
+    SystemCount { saved: usize, current: usize }, // system数量不同
+    System(usize), // 指定位置的system读写不同
+}
+
+impl World {
+    /// 组件注册表的指纹，组件的类型或注册顺序变化，指纹都会变化
+    pub fn registry_fingerprint(&self) -> u64 {
+        let mut hasher = Fnv::new();
+        hasher.u64(self.component_arr.len() as u64);
+        for c in self.component_arr.iter() {
+            hasher.u64(c.info().stable_id());
+        }
+        hasher.0
+    }
+    // 组件的稳定id，见ComponentInfo::stable_id
+    fn component_stable_id(&self, index: ComponentIndex) -> u64 {
+        match self.get_column(index) {
+            Some(c) => c.info().stable_id(),
+            None => index.index() as u64,
+        }
+    }
+}
+
+impl SystemMeta {
+    /// system声明的组件和资源读写的指纹，组件用稳定id计算，和组件在world上的索引无关
+    pub fn access_fingerprint(&self, world: &World) -> u64 {
+        let mut hasher = Fnv::new();
+        hasher.str(self.type_name());
+        for related in self.vec.iter() {
+            hasher.u64(related.vec.len() as u64);
+            for r in related.vec.iter() {
+                let (tag, index) = match r {
+                    Relation::With(i) => (0u8, Some(*i)),
+                    Relation::Without(i) => (1, Some(*i)),
+                    Relation::Read(i) => (2, Some(*i)),
+                    Relation::Write(i) => (3, Some(*i)),
+                    Relation::ShareWrite(i) => (4, Some(*i)),
+                    Relation::OptRead(i) => (5, Some(*i)),
+                    Relation::OptWrite(i) => (6, Some(*i)),
+                    Relation::Count(c) => {
+                        hasher.u64(*c as u64);
+                        (7, None)
+                    }
+                    Relation::ReadAll => (8, None),
+                    Relation::WriteAll => (9, None),
+                    Relation::Or => (10, None),
+                    Relation::And => (11, None),
+                    Relation::End => (12, None),
+                };
+                hasher.write(&[tag]);
+                if let Some(i) = index {
+                    hasher.u64(world.component_stable_id(i));
+                }
+            }
+        }
+        // 资源的读写用类型名，按名字排序保证顺序稳定
+        for map in [&self.res_reads, &self.res_writes] {
+            let mut names: Vec<_> = map.values().collect();
+            names.sort();
+            hasher.u64(names.len() as u64);
+            for name in names {
+                hasher.str(name);
+            }
+        }
+        hasher.0
+    }
+}
+
+/// 计算执行图的指纹，第一个是组件注册表的指纹，然后依次是每个system的读写指纹
+pub fn graph_fingerprints(world: &World, metas: &[&SystemMeta]) -> Vec<u64> {
+    let mut vec = Vec::with_capacity(metas.len() + 1);
+    vec.push(world.registry_fingerprint());
+    for meta in metas {
+        vec.push(meta.access_fingerprint(world));
+    }
+    vec
+}
+
+/// 检查保存的指纹和当前的world及system是否兼容，不兼容则需要重新构建执行图
+pub fn validate_graph_compat(
+    saved: &[u64],
+    world: &World,
+    metas: &[&SystemMeta],
+) -> Result<(), GraphMismatch> {
+    if saved.first() != Some(&world.registry_fingerprint()) {
+        return Err(GraphMismatch::Registry);
+    }
+    if saved.len() != metas.len() + 1 {
+        return Err(GraphMismatch::SystemCount {
+            saved: saved.len().saturating_sub(1),
+            current: metas.len(),
+        });
+    }
+    for (i, meta) in metas.iter().enumerate() {
+        if saved[i + 1] != meta.access_fingerprint(world) {
+            return Err(GraphMismatch::System(i));
+        }
+    }
+    Ok(())
+}
@@ -34,7 +34,7 @@ where
     F: SystemParamFunction<Marker, Out>,
 {
     func: F,
-    param: ParamSystem<F::Param>,
+    pub(crate) param: ParamSystem<F::Param>,
 }
 
 impl<Marker: 'static, Out: 'static + Send + Sync, F> IntoSystem<Marker, Out> for F
@@ -68,6 +68,10 @@ where
     fn initialize(&mut self, world: &mut World) {
         self.param.initialize(world)
     }
+    #[inline]
+    fn is_read_only(&self) -> bool {
+        self.param.is_read_only()
+    }
     // /// system depend the archetype.
     // fn archetype_depend(
     //     &self,
@@ -126,6 +130,10 @@ impl<P: SystemParam> ParamSystem<P> {
         self.system_meta.type_info.type_id
     }
     #[inline]
+    pub(crate) fn is_read_only(&self) -> bool {
+        self.system_meta.is_read_only()
+    }
+    #[inline]
     pub(crate) fn initialize(&mut self, world: &mut World) {
         if self.param_state.is_none() {
             // println!("self.system_meta: {:?}", self.system_meta);
@@ -168,8 +176,10 @@ impl<P: SystemParam> ParamSystem<P> {
     // }
     #[inline]
     pub(crate) fn align(&mut self, world: &World) {
+        // 所有参数都对齐到同一个原型数量的快照
+        self.system_meta.snapshot_archetypes(world);
         let param_state = self.param_state.as_mut().unwrap();
-        P::align(world, &mut self.system_meta, param_state);
+        P::align(world, &self.system_meta, param_state);
     }
     #[inline]
     pub fn get_param<'w>(&'w mut self, world: &'w World) -> SystemParamItem<'w, P> {
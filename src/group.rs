@@ -0,0 +1,234 @@
+//! 组件组
+//! 将一组组件定义为一个语义单元（比如物理组件、渲染组件），Query可按组声明访问，不需要逐个列举组件类型。
+//! 组用类型标签GroupLabel来指定，组的成员在world上运行时定义。
+//! 组被system初始化使用后锁定，之后不允许再修改组的成员。
+//! Query<(Entity, GroupPtrs<Physics>), InAnyGroup<Physics>>
+//!
+
+use std::borrow::Cow;
+use std::marker::PhantomData;
+
+use pi_null::Null;
+use pi_share::Share;
+
+use crate::archetype::{ArchetypeIndex, Row};
+use crate::column::Column;
+use crate::fetch::FetchComponents;
+use crate::filter::FilterComponents;
+use crate::query::QueryError;
+use crate::system::{Relation, SystemMeta};
+use crate::world::{ComponentIndex, Entity, Tick, World};
+
+#[derive(Debug, Clone, Copy, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GroupId(pub(crate) u32);
+impl GroupId {
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+impl From<u32> for GroupId {
+    fn from(index: u32) -> Self {
+        Self(index)
+    }
+}
+impl From<usize> for GroupId {
+    fn from(index: usize) -> Self {
+        Self(index as u32)
+    }
+}
+impl Null for GroupId {
+    fn null() -> Self {
+        Self(u32::null())
+    }
+    fn is_null(&self) -> bool {
+        self.0 == u32::null()
+    }
+}
+
+/// 组标签，用类型来指定world上定义的同名组件组
+pub trait GroupLabel: Send + Sync + 'static {
+    const NAME: &'static str;
+}
+
+#[derive(Debug, Clone)]
+pub struct ComponentGroup {
+    name: Cow<'static, str>,
+    members: Vec<ComponentIndex>, // 升序
+    locked: bool, // 是否已被system使用
+}
+impl ComponentGroup {
+    pub fn name(&self) -> &Cow<'static, str> {
+        &self.name
+    }
+    pub fn members(&self) -> &[ComponentIndex] {
+        &self.members
+    }
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+}
+
+impl World {
+    /// 定义组件组，如果同名组已存在且未被使用，则替换组的成员。如果同名组已被system使用，则返回错误
+    pub fn define_component_group(
+        &mut self,
+        name: impl Into<Cow<'static, str>>,
+        mut members: Vec<ComponentIndex>,
+    ) -> Result<GroupId, QueryError> {
+        members.sort_unstable();
+        members.dedup();
+        let name = name.into();
+        if let Some(id) = self.group_map.get(&name) {
+            let group = &mut self.group_arr[id.index()];
+            if group.locked {
+                return Err(QueryError::GroupLocked(*id));
+            }
+            group.members = members;
+            return Ok(*id);
+        }
+        let id: GroupId = self.group_arr.len().into();
+        self.group_arr.push(ComponentGroup {
+            name: name.clone(),
+            members,
+            locked: false,
+        });
+        self.group_map.insert(name, id);
+        Ok(id)
+    }
+    /// 获得指定名字的组件组的索引
+    pub fn get_component_group_id(&self, name: &str) -> Option<GroupId> {
+        self.group_map.get(name).copied()
+    }
+    /// 获得指定的组件组
+    pub fn get_component_group(&self, id: GroupId) -> Option<&ComponentGroup> {
+        self.group_arr.get(id.index())
+    }
+    /// system使用组件组，组被锁定
+    pub(crate) fn lock_component_group(&mut self, name: &str) -> GroupId {
+        let id = match self.group_map.get(name) {
+            Some(id) => *id,
+            None => panic!("component group not defined, name:{}", name),
+        };
+        self.group_arr[id.index()].locked = true;
+        id
+    }
+}
+
+/// 原型上只要有组内任何1个组件就可以
+pub struct InAnyGroup<G: GroupLabel>(PhantomData<G>);
+impl<G: GroupLabel> FilterComponents for InAnyGroup<G> {
+    const ROW_FILTER: bool = false;
+    type Filter<'w> = ();
+    type State = GroupId;
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        let id = world.lock_component_group(G::NAME);
+        meta.relate(Relation::Or);
+        for index in world.group_arr[id.index()].members.iter() {
+            meta.relate(Relation::With(*index));
+        }
+        meta.relate(Relation::End);
+        id
+    }
+
+    #[inline]
+    fn init_filter<'w>(
+        _world: &'w World,
+        _state: &'w Self::State,
+        _index: ArchetypeIndex,
+        _tick: Tick,
+        _last_run: Tick,
+    ) -> Self::Filter<'w> {
+        ()
+    }
+}
+
+/// 原型上必须有组内全部的组件
+pub struct InAllGroup<G: GroupLabel>(PhantomData<G>);
+impl<G: GroupLabel> FilterComponents for InAllGroup<G> {
+    const ROW_FILTER: bool = false;
+    type Filter<'w> = ();
+    type State = GroupId;
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        let id = world.lock_component_group(G::NAME);
+        for index in world.group_arr[id.index()].members.iter() {
+            meta.relate(Relation::With(*index));
+        }
+        id
+    }
+
+    #[inline]
+    fn init_filter<'w>(
+        _world: &'w World,
+        _state: &'w Self::State,
+        _index: ArchetypeIndex,
+        _tick: Tick,
+        _last_run: Tick,
+    ) -> Self::Filter<'w> {
+        ()
+    }
+}
+
+/// 获得组内各组件在该行上的指针，原型上没有的组件不返回。组内的全部组件都注册为可选读
+pub struct GroupPtrs<G: GroupLabel>(PhantomData<G>);
+impl<G: GroupLabel> FetchComponents for GroupPtrs<G> {
+    type Fetch<'w> = (&'w [Share<Column>], ArchetypeIndex);
+    type Item<'w> = GroupRow<'w>;
+    type ReadOnly = Self;
+    type State = Vec<Share<Column>>;
+
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        let id = world.lock_component_group(G::NAME);
+        let mut columns = Vec::new();
+        for index in world.group_arr[id.index()].members.iter() {
+            meta.relate(Relation::OptRead(*index));
+            columns.push(world.get_column(*index).unwrap().clone());
+        }
+        columns
+    }
+    #[inline]
+    fn init_fetch<'w>(
+        _world: &'w World,
+        state: &'w Self::State,
+        index: ArchetypeIndex,
+        _tick: Tick,
+        _last_run: Tick,
+    ) -> Self::Fetch<'w> {
+        (state.as_slice(), index)
+    }
+    #[inline(always)]
+    fn fetch<'w>(fetch: &Self::Fetch<'w>, row: Row, e: Entity) -> Self::Item<'w> {
+        GroupRow {
+            columns: fetch.0,
+            index: fetch.1,
+            row,
+            e,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GroupRow<'a> {
+    columns: &'a [Share<Column>],
+    index: ArchetypeIndex,
+    row: Row,
+    e: Entity,
+}
+impl<'a> GroupRow<'a> {
+    #[inline(always)]
+    pub fn entity(&self) -> Entity {
+        self.e
+    }
+    /// 获得指定组件在该行的指针，如果组内没有该组件或原型上没有该组件，则返回None
+    pub fn get(&self, index: ComponentIndex) -> Option<*const u8> {
+        let c = self.columns.iter().find(|c| c.info().index == index)?;
+        c.blob_ref(self.index)
+            .map(|b| b.get_row(self.row, self.e) as *const u8)
+    }
+    /// 迭代原型上存在的组内组件及其指针
+    pub fn iter(&self) -> impl Iterator<Item = (ComponentIndex, *const u8)> + '_ {
+        self.columns.iter().filter_map(|c| {
+            c.blob_ref(self.index)
+                .map(|b| (c.info().index, b.get_row(self.row, self.e) as *const u8))
+        })
+    }
+}
@@ -0,0 +1,140 @@
+//! 按键分组的迭代
+//! 渲染合批等需要跨原型按某个键（比如材质id）分组，连续访问每一组，每组发出一次绘制，不需要HashMap<K, Vec<_>>。
+//! 先遍历查询，将(键, 实体)收集到缓冲中，按键排序（键相同时按实体的位排序，结果是确定的），
+//! 然后每个不同的键调用一次回调，回调中的迭代器用按实体查询的缓存路径读取该组的项。
+//! 键就是某个组件时，用group_by_component直接读取该组件的列，不调用闭包。该组件必须在Q中读取或用ExtraRead<K>声明。
+//! q.group_by(|_, (m, _)| m.0, |material, items| { for (e, (m, t)) in items { ... } });
+//!
+
+use std::any::TypeId;
+use std::slice;
+
+use pi_key_alloter::Key;
+use pi_null::Null;
+
+use crate::archetype::ArchetypeIndex;
+use crate::fetch::FetchComponents;
+use crate::filter::FilterComponents;
+use crate::query::{Query, QueryError};
+use crate::world::Entity;
+
+/// 一组的迭代器，按实体的位递增
+pub struct GroupIter<'a, 'w, Q: FetchComponents + 'static, F: FilterComponents + 'static, K> {
+    query: &'a Query<'w, Q, F>,
+    iter: slice::Iter<'a, (K, Entity)>,
+}
+impl<'a, 'w, Q: FetchComponents, F: FilterComponents, K> GroupIter<'a, 'w, Q, F, K> {
+    /// 组内的实体数量
+    pub fn len(&self) -> usize {
+        self.iter.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.iter.len() == 0
+    }
+    /// 组内的实体
+    pub fn entities(&self) -> impl Iterator<Item = Entity> + 'a {
+        self.iter.clone().map(|(_, e)| *e)
+    }
+}
+impl<'a, 'w, Q: FetchComponents, F: FilterComponents, K> Iterator for GroupIter<'a, 'w, Q, F, K> {
+    type Item = (
+        Entity,
+        <<Q as FetchComponents>::ReadOnly as FetchComponents>::Item<'a>,
+    );
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (_, e) = self.iter.next()?;
+            // 收集后没有结构变化，实体总是能查到
+            if let Ok(item) = self.query.get(*e) {
+                return Some((*e, item));
+            }
+        }
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.iter.len()))
+    }
+}
+
+impl<'w, Q: FetchComponents, F: FilterComponents> Query<'w, Q, F> {
+    /// 按key计算的键分组，每个不同的键按递增顺序调用一次per_group
+    pub fn group_by<'a, K: Ord + Copy>(
+        &'a self,
+        key: impl Fn(Entity, &<<Q as FetchComponents>::ReadOnly as FetchComponents>::Item<'a>) -> K,
+        per_group: impl FnMut(K, GroupIter<'_, 'w, Q, F, K>),
+    ) {
+        let mut buf = Vec::new();
+        self.group_by_with(&mut buf, key, per_group);
+    }
+    /// 同group_by，用buf收集，可以复用buf的内存
+    pub fn group_by_with<'a, K: Ord + Copy>(
+        &'a self,
+        buf: &mut Vec<(K, Entity)>,
+        key: impl Fn(Entity, &<<Q as FetchComponents>::ReadOnly as FetchComponents>::Item<'a>) -> K,
+        per_group: impl FnMut(K, GroupIter<'_, 'w, Q, F, K>),
+    ) {
+        buf.clear();
+        buf.reserve(self.len());
+        let mut it = self.iter();
+        while let Some(item) = it.next() {
+            buf.push((key(it.e, &item), it.e));
+        }
+        self.emit_groups(buf, per_group);
+    }
+    /// 按组件K的值分组，直接读取K的列。K的列不在原型上的实体不参与分组。
+    /// K必须在Q中读取或用ExtraRead<K>声明，否则调度器不知道对K的读，返回MissingReadAccess
+    pub fn group_by_component<K: Ord + Copy + 'static>(
+        &self,
+        buf: &mut Vec<(K, Entity)>,
+        per_group: impl FnMut(K, GroupIter<'_, 'w, Q, F, K>),
+    ) -> Result<(), QueryError> {
+        buf.clear();
+        let index = self.world.get_component_index(&TypeId::of::<K>());
+        if !self.state.has_read(index) {
+            return Err(QueryError::MissingReadAccess(index));
+        }
+        let column = match self.world.get_column(index) {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+        buf.reserve(self.len());
+        let mut it = self.iter();
+        let mut index = ArchetypeIndex::null();
+        let mut blob = None;
+        while it.next().is_some() {
+            if it.ar.index() != index {
+                index = it.ar.index();
+                blob = column.blob_ref(index);
+            }
+            if let Some(b) = &blob {
+                buf.push((*b.get::<K>(it.row, it.e), it.e));
+            }
+        }
+        self.emit_groups(buf, per_group);
+        Ok(())
+    }
+    // 排序后，每段相同的键调用一次回调
+    fn emit_groups<K: Ord + Copy>(
+        &self,
+        buf: &mut [(K, Entity)],
+        mut per_group: impl FnMut(K, GroupIter<'_, 'w, Q, F, K>),
+    ) {
+        buf.sort_unstable_by(|a, b| {
+            a.0.cmp(&b.0)
+                .then_with(|| a.1.data().as_ffi().cmp(&b.1.data().as_ffi()))
+        });
+        let mut start = 0;
+        while start < buf.len() {
+            let k = buf[start].0;
+            let end = start + buf[start..].partition_point(|(x, _)| *x == k);
+            per_group(
+                k,
+                GroupIter {
+                    query: self,
+                    iter: buf[start..end].iter(),
+                },
+            );
+            start = end;
+        }
+    }
+}
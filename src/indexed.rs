@@ -0,0 +1,117 @@
+//! 带稠密序号的查询迭代
+//! 每个匹配的实体产生一个输出元素的system（渲染实例、物理刚体），需要一个0..n的序号写入预分配的缓冲区。
+//! 序号为实体在查询迭代顺序中的位置（原型按对齐的倒序，行倒序，跳过空位），
+//! 同一帧内没有结构变化时，迭代同一查询的多个system得到相同的序号，不需要互相协调就可以写同一个缓冲区。
+//! 逐行过滤的查询（Changed、Added等，见FilterComponents::ROW_FILTER）的结果依赖各system的运行时间，不支持序号。
+//! let mut buf = vec![0; q.dense_len()]; for (i, item) in q.iter_indexed() { buf[i] = ... }
+//!
+
+use crate::archetype::Row;
+use crate::fetch::FetchComponents;
+use crate::filter::FilterComponents;
+use crate::query::{Query, QueryIter};
+
+/// 带稠密序号的迭代器
+pub struct IndexedIter<'w, Q: FetchComponents + 'static, F: FilterComponents + 'static> {
+    iter: QueryIter<'w, Q, F>,
+    index: usize,
+    // 迭代开始时各原型的结构版本，迭代结束时检查没有变化
+    #[cfg(debug_assertions)]
+    versions: Vec<(Row, usize, u32)>,
+}
+impl<'w, Q: FetchComponents, F: FilterComponents> Iterator for IndexedIter<'w, Q, F> {
+    type Item = (usize, Q::Item<'w>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.iter.next() {
+            Some(item) => {
+                let i = self.index;
+                self.index += 1;
+                Some((i, item))
+            }
+            None => {
+                #[cfg(debug_assertions)]
+                debug_assert!(
+                    self.iter
+                        .state
+                        .archetypes
+                        .iter()
+                        .map(|ar| ar.structure_version())
+                        .eq(self.versions.iter().copied()),
+                    "structural change during iter_indexed"
+                );
+                None
+            }
+        }
+    }
+}
+
+#[cold]
+fn row_filter_unsupported<F>() -> ! {
+    panic!(
+        "dense index is not supported on row filtered query, filter:{}",
+        std::any::type_name::<F>()
+    )
+}
+
+impl<'w, Q: FetchComponents, F: FilterComponents> Query<'w, Q, F> {
+    /// 迭代查询项和稠密序号，序号为0..dense_len()
+    pub fn iter_indexed(&self) -> IndexedIter<'_, <Q as FetchComponents>::ReadOnly, F> {
+        if F::ROW_FILTER {
+            row_filter_unsupported::<F>();
+        }
+        IndexedIter {
+            #[cfg(debug_assertions)]
+            versions: self
+                .state
+                .archetypes
+                .iter()
+                .map(|ar| ar.structure_version())
+                .collect(),
+            iter: self.iter(),
+            index: 0,
+        }
+    }
+    /// 迭代可写的查询项和稠密序号
+    pub fn iter_indexed_mut(&mut self) -> IndexedIter<'_, Q, F> {
+        if F::ROW_FILTER {
+            row_filter_unsupported::<F>();
+        }
+        IndexedIter {
+            #[cfg(debug_assertions)]
+            versions: self
+                .state
+                .archetypes
+                .iter()
+                .map(|ar| ar.structure_version())
+                .collect(),
+            iter: self.iter_mut(),
+            index: 0,
+        }
+    }
+    /// 稠密序号的数量，即iter_indexed返回的项数。需要遍历行，跳过空位和被过滤的行
+    pub fn dense_len(&self) -> usize {
+        if F::ROW_FILTER {
+            row_filter_unsupported::<F>();
+        }
+        let mut len = 0;
+        for ar in self.state.archetypes.iter() {
+            let filter = F::init_filter(
+                self.world,
+                &self.state.filter_state,
+                ar.index(),
+                self.tick,
+                self.state.last_run,
+            );
+            for row in 0..ar.len().0 {
+                let row = Row(row);
+                if let Some(e) = ar.live_entity(row) {
+                    if !F::filter(&filter, row, e) {
+                        len += 1;
+                    }
+                }
+            }
+        }
+        len
+    }
+}
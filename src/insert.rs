@@ -9,6 +9,7 @@ use pi_slot::SlotMap;
 
 use crate::archetype::*;
 use crate::column::Column;
+use crate::required::DefaultFn;
 use crate::system::SystemMeta;
 use crate::system_params::SystemParam;
 use crate::world::*;
@@ -42,6 +43,50 @@ impl<'world, B: Bundle> Insert<'world, B> {
     ) -> InsertBatchIter<'_, <I as IntoIterator>::IntoIter, B> {
         InsertBatchIter::new(self.world, self.state, self.tick, iter.into_iter())
     }
+    /// 批量插入，返回插入的实体
+    #[inline]
+    pub fn insert_batch<I: IntoIterator<Item = B>>(&self, iter: I) -> InsertedEntities {
+        InsertedEntities {
+            archetype_index: self.state.archetype.index(),
+            entities: self.batch(iter).collect(),
+        }
+    }
+    /// 插入的目标原型，在初始化时确定
+    #[inline]
+    pub fn archetype_index(&self) -> ArchetypeIndex {
+        self.state.archetype.index()
+    }
+}
+
+/// 批量插入的实体，都在同一个原型中
+#[derive(Debug, Clone, Default)]
+pub struct InsertedEntities {
+    archetype_index: ArchetypeIndex,
+    entities: Vec<Entity>,
+}
+impl InsertedEntities {
+    /// 实体所在的原型
+    pub fn archetype_index(&self) -> ArchetypeIndex {
+        self.archetype_index
+    }
+}
+impl std::ops::Deref for InsertedEntities {
+    type Target = [Entity];
+    fn deref(&self) -> &Self::Target {
+        &self.entities
+    }
+}
+impl From<InsertedEntities> for Vec<Entity> {
+    fn from(value: InsertedEntities) -> Self {
+        value.entities
+    }
+}
+impl IntoIterator for InsertedEntities {
+    type Item = Entity;
+    type IntoIter = std::vec::IntoIter<Entity>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.entities.into_iter()
+    }
 }
 
 impl<B: Bundle + 'static> SystemParam for Insert<'_, B> {
@@ -50,10 +95,10 @@ impl<B: Bundle + 'static> SystemParam for Insert<'_, B> {
 
     fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
         // 加meta 如果world上没有找到对应的原型，则创建并放入world中
-        let components = B::components(Vec::with_capacity(256));
+        let components = world.add_required_infos(B::components(Vec::with_capacity(256)));
         let ar = meta.insert(world, components);
         let s = B::init_item(world, &ar);
-        InsertState::new(ar, s)
+        InsertState::new(ar, s).init_required(world)
     }
     #[inline]
     fn get_param<'world>(
@@ -78,12 +123,28 @@ impl<B: Bundle + 'static> SystemParam for Insert<'_, B> {
 pub struct InsertState<B: Bundle> {
     pub(crate) archetype: ShareArchetype,
     pub(crate) item: B::Item,
+    required: Vec<(Share<Column>, DefaultFn)>, // Bundle中没有的依赖组件
 }
 
 impl<B: Bundle> InsertState<B> {
     #[inline(always)]
     pub fn new(archetype: ShareArchetype, item: B::Item) -> Self {
-        Self { archetype, item }
+        Self {
+            archetype,
+            item,
+            required: Vec::new(),
+        }
+    }
+    // 原型中Bundle没有提供的组件，都是补上的依赖组件，插入时用默认值初始化
+    pub(crate) fn init_required(mut self, world: &World) -> Self {
+        if world.required.is_empty() {
+            return self;
+        }
+        let components = B::components(Vec::new());
+        self.required = world.required_defaults(&self.archetype, |c| {
+            components.iter().any(|info| info.type_id() == c.type_id())
+        });
+        self
     }
     #[inline(always)]
     pub fn insert(&self, world: &World, components: B) -> Entity {
@@ -94,7 +155,13 @@ impl<B: Bundle> InsertState<B> {
         let (r, row) = self.archetype.alloc();
         let e = world.insert_addr(self.archetype.index(), row.into());
         B::insert(&self.item, components, e, row.into(), tick);
+        for (c, f) in self.required.iter() {
+            let c = c.blob_ref_unchecked(self.archetype.index());
+            f(c.load(row.into(), e));
+            c.added_tick(e, row.into(), tick);
+        }
         *r = e;
+        self.archetype.record_allocated(row.into(), e);
         e
     }
     #[inline(always)]
@@ -105,6 +172,57 @@ impl<B: Bundle> InsertState<B> {
     ) -> InsertBatchIter<'_, <I as IntoIterator>::IntoIter, B> {
         InsertBatchIter::new(world, self, world.tick(), iter.into_iter())
     }
+    /// 批量插入，返回插入的实体
+    #[inline]
+    pub fn insert_batch<I: IntoIterator<Item = B>>(&self, world: &World, iter: I) -> InsertedEntities {
+        InsertedEntities {
+            archetype_index: self.archetype.index(),
+            entities: self.batch(world, iter).collect(),
+        }
+    }
+    /// 独占world时批量插入，预先连续分配全部行，写入各列后再发布实体，比逐个插入少了每次的分配和扩容。
+    /// 按迭代器的len()分配，最多取len()项；实际少于len()时，没有用到的行标记为移除
+    pub fn insert_exact<I>(&self, world: &mut World, iter: I) -> InsertedEntities
+    where
+        I: IntoIterator<Item = B>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let iter = iter.into_iter();
+        let n = iter.len();
+        let tick = world.tick();
+        let index = self.archetype.index();
+        world.entities.settle(n);
+        // 独占world，原型没有其他借用
+        let ptr = ShareArchetype::as_ptr(&self.archetype);
+        let ar_mut: &mut Archetype = unsafe { transmute(ptr) };
+        let start = ar_mut.alloc_n(n).0;
+        let mut entities = Vec::with_capacity(n);
+        for (i, components) in iter.take(n).enumerate() {
+            let row = Row(start + i as u32);
+            let e = world.insert_addr(index, row);
+            B::insert(&self.item, components, e, row, tick);
+            for (c, f) in self.required.iter() {
+                let c = c.blob_ref_unchecked(index);
+                f(c.load(row, e));
+                c.added_tick(e, row, tick);
+            }
+            entities.push(e);
+        }
+        // len()可能不准确，没有用到的行不能留在原型中被当作存活的行
+        for i in entities.len()..n {
+            self.archetype.mark_unused(Row(start + i as u32));
+        }
+        // 各列都写完后再发布实体
+        for (i, e) in entities.iter().enumerate() {
+            let row = Row(start + i as u32);
+            self.archetype.set(row, *e);
+            self.archetype.record_allocated(row, *e);
+        }
+        InsertedEntities {
+            archetype_index: index,
+            entities,
+        }
+    }
     #[inline]
     pub fn get_param<'w>(&'w mut self, world: &'w World) -> Insert<'w, B> {
         Insert::new(world, self, world.tick())
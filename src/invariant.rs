@@ -0,0 +1,101 @@
+//! world的一致性检查
+//! 列的Share<Column>从world的注册表流入原型的sorted_components，alter时又从源原型复制到目标原型。
+//! 列持有所有原型上该组件的数据，移动行时用源原型的列句柄按目标原型索引写入，
+//! 所以同一组件在所有原型上必须是同一个列句柄，否则会写入没有初始化的数据块，或者按错误的大小复制。
+//! check_invariants检查所有原型，debug下第一次建立alter边时也会检查两端的原型，在数据被破坏前发现问题。
+//!
+
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
+
+use pi_share::Share;
+
+use crate::archetype::{Archetype, ArchetypeIndex};
+use crate::column::Column;
+use crate::world::{ComponentIndex, World};
+
+/// 违反的不变量
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvariantViolation {
+    /// 原型上的列句柄和world注册的不是同一个，size为(注册的, 原型上的)，drop_differs为释放函数是否不同
+    ColumnMismatch {
+        component: ComponentIndex,
+        type_name: Cow<'static, str>,
+        archetype: ArchetypeIndex,
+        size: (usize, usize),
+        drop_differs: bool,
+    },
+}
+impl Display for InvariantViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvariantViolation::ColumnMismatch {
+                component,
+                type_name,
+                archetype,
+                size,
+                drop_differs,
+            } => write!(
+                f,
+                "column of {} ({:?}) in archetype {:?} is not the registered one, size {} vs {}, drop_fn differs: {}",
+                type_name, component, archetype, size.0, size.1, drop_differs
+            ),
+        }
+    }
+}
+
+// 比较列句柄，不是同一个时返回差异
+fn compare(registered: &Share<Column>, c: &Share<Column>, archetype: ArchetypeIndex) -> Result<(), InvariantViolation> {
+    if Share::ptr_eq(registered, c) {
+        return Ok(());
+    }
+    let (a, b) = (registered.info(), c.info());
+    Err(InvariantViolation::ColumnMismatch {
+        component: b.index,
+        type_name: b.type_name().clone(),
+        archetype,
+        size: (a.size(), b.size()),
+        drop_differs: a.drop_fn.map(|f| f as usize) != b.drop_fn.map(|f| f as usize),
+    })
+}
+
+// 两个列的大小和释放函数是否相同，移动行时按此复制
+pub(crate) fn same_layout(a: &Column, b: &Column) -> bool {
+    a.info().size() == b.info().size()
+        && a.info().drop_fn.map(|f| f as usize) == b.info().drop_fn.map(|f| f as usize)
+}
+
+impl World {
+    /// 检查world的不变量，返回第一个违反的
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        for ar in self.archetype_arr.iter() {
+            self.check_archetype_columns(ar)?;
+        }
+        Ok(())
+    }
+    // 原型上的每个列句柄都是world注册的
+    fn check_archetype_columns(&self, ar: &Archetype) -> Result<(), InvariantViolation> {
+        for c in ar.get_columns().iter() {
+            let registered = &self.component_arr[c.info().index.index()];
+            compare(registered, c, ar.index())?;
+        }
+        Ok(())
+    }
+    // alter边建立时检查两端的原型，同一组件的列句柄必须相同
+    pub(crate) fn check_edge_columns(&self, src: &Archetype, dst: &Archetype) -> Result<(), InvariantViolation> {
+        for c in src.get_columns().iter() {
+            if let Some(d) = dst.get_column(c.info().index) {
+                compare(c, d, dst.index())?;
+            }
+        }
+        self.check_archetype_columns(src)?;
+        self.check_archetype_columns(dst)
+    }
+    /// 测试用，用改变了大小的信息重新注册组件的列，之后创建的原型使用新的列句柄
+    #[cfg(test)]
+    pub(crate) fn fork_column(&mut self, index: ComponentIndex, mem_size: u32) {
+        let mut info = self.component_arr[index.index()].info().clone();
+        info.mem_size = mem_size;
+        self.component_arr[index.index()] = Share::new(Column::new(info));
+    }
+}
@@ -16,19 +16,46 @@ pub mod prelude {
     #[doc(hidden)]
     pub use crate::{
         app::App,
-        query::{Query, QueryError},
+        query::{Query, QueryError, QueryCursor, QueryIterMut},
+        budget::{Budget, BudgetedIter},
+        group_by::GroupIter,
+        invariant::InvariantViolation,
+        world_builder::{WorldBuilder, WorldBuildError, RegistrationReport},
+        query_many::{QueryManyIter, QueryManyStrictIter, QueryManyMut},
+        deferred_drop::{DeferredDropRunner, DeferredDropStats},
+        recent_changed::RecentChangedIter,
+        update::{ChangeMask, UpdateMut},
+        remove_hook::RemovalContext,
+        indexed::IndexedIter,
+        par_iter::QueryChunk,
+        cached_query::CachedQuery,
         insert::{Insert, Bundle, Component},
         alter::Alter,
         editor::EntityEditor,
         event:: {Event, EventReader, EventSender, EventWriter, ComponentChanged, ComponentAdded, ComponentRemoved},
         param_set::{ParamSet, ParamSetElement},
-        single_res::{SingleRes, SingleResMut},
+        single_res::{SingleRes, SingleResMut, Res, ResMut},
         multi_res::{MultiRes, MultiResMut},
-        filter::{Changed, With, Without, Or, FilterComponents},
-        fetch::{Has, Ref, Mut, OrDefault, OrDefaultRef, Ticker, ComponentId, ArchetypeName},
+        filter::{Changed, Added, Removed, Unchanged, NotAdded, With, Without, Or, ExtraRead, FilterComponents},
+        group::{GroupLabel, InAnyGroup, InAllGroup, GroupPtrs},
+        column_view::{ColumnsMut, ColumnViewMut},
+        fetch::{Has, AnyOf, Pass, Ref, Mut, CoarseMut, AddedTick, ChangedTick, ChangeTrackers, ComponentTicks, ChangeSpan, OrDefault, OrDefaultRef, Ticker, ComponentId, ArchetypeName, ArchetypeRef, FetchInitError},
+        column::{SpanDedup, ChangeSpanItem},
         system::{BoxedSystem, IntoSystem, IntoAsyncSystem, SystemMeta},
         system_params::{SystemParam, Local},
-        world::{Entity, World, FromWorld, Tick},
+        system_builder::{SystemBuilder, BuiltSystem, SystemBuildError, ParamInfo},
+        world::{Entity, World, FromWorld, Tick, WorldOptions, EntityAllocation},
+        extension::{WorldExtension, SettleReport},
+        reference::{MapEntities, ReferencePolicy, ReferenceStats},
+        merge::{EntityMapper, MergeReport, MergeError},
+        stamp::OverrideWriter,
+        entity_ref::{EntityRef, EntityMut},
+        required::RequiredComponents,
+        transient::{Transient, FrameScoped, WithFrameScoped, AddedFrameScoped},
+        cleanup::CleanupPolicy,
+        extract::{ExtractView, ExtractArchetype, ExtractError},
+        raw_dump::{DumpManifest, RawWorldView, RawPod},
+        table::{RemovesStrategy, StructuralChange, StructuralCursor, Fragmentation, FragmentationReport},
         listener::Listener,
         plugin::{Plugin, Plugins},
         plugin_group::WorldPluginExtent,
@@ -41,11 +68,27 @@ pub mod prelude {
 }
 
 pub mod column;
+pub mod column_view;
 pub mod table;
 pub mod archetype;
 pub mod query;
+pub mod cached_query;
+pub mod budget;
+pub mod group_by;
+pub mod invariant;
+pub mod world_builder;
+pub mod query_many;
+pub mod deferred_drop;
+pub mod recent_changed;
+pub mod update;
+pub mod component_id;
+pub mod remove_hook;
+pub mod indexed;
+pub mod par_iter;
+pub mod deferred_add;
 pub mod fetch;
 pub mod filter;
+pub mod group;
 pub mod event;
 pub mod param_set;
 pub mod single_res;
@@ -56,9 +99,27 @@ pub mod app;
 pub mod system;
 pub mod system_params;
 pub mod function_system;
+pub mod system_builder;
 pub mod async_function_system;
 pub mod insert;
 pub mod alter;
+pub mod archive;
+pub mod merge;
+pub mod stamp;
+pub mod entity_ref;
+pub mod fingerprint;
+pub mod extension;
+pub mod reference;
+pub mod required;
+pub mod transient;
+pub mod cleanup;
+pub mod extract;
+#[cfg(feature = "debug-names")]
+pub mod debug_name;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod diff;
+pub mod raw_dump;
 // pub mod safe_vec;
 pub mod exec_graph;
 pub mod dot;
@@ -0,0 +1,149 @@
+//! 合并World
+//! 编辑器在独立的子World中构建预制体，再整体导入主World。子World被消耗，每个原型的组件数据按位移动到主World上对应的原型（没有则创建），
+//! 实体重新分配，新旧实体记录在EntityMapper中。注册了引用策略（register_reference_policy）的组件，其中引用的实体在所有实体分配后用映射修正。
+//! 导入的组件按主World的当前tick记录添加，Added查询可以看到。子World的资源不合并，随子World释放。
+//! let mut mapper = EntityMapper::default(); let report = world.merge(prefab, &mut mapper)?;
+//!
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use pi_null::Null;
+use pi_share::Share;
+
+use crate::archetype::{ArchetypeIndex, ArchetypeInfo, Row};
+use crate::column::Column;
+use crate::reference::MapFn;
+use crate::world::{Entity, World};
+
+/// 合并时旧实体到新实体的映射
+#[derive(Debug, Default, Clone)]
+pub struct EntityMapper {
+    map: HashMap<Entity, Entity>,
+}
+impl EntityMapper {
+    /// 添加映射。合并前添加的映射用于修正组件中引用的子World外的实体
+    pub fn insert(&mut self, old: Entity, new: Entity) {
+        self.map.insert(old, new);
+    }
+    /// 旧实体映射到的新实体
+    pub fn get(&self, old: Entity) -> Option<Entity> {
+        self.map.get(&old).copied()
+    }
+    /// 映射实体，没有映射时返回原实体
+    pub fn map(&self, e: Entity) -> Entity {
+        self.get(e).unwrap_or(e)
+    }
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+    /// 所有映射，(旧实体, 新实体)
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, Entity)> + '_ {
+        self.map.iter().map(|(k, v)| (*k, *v))
+    }
+}
+
+/// 合并的统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MergeReport {
+    pub entities: usize,           // 导入的实体数量，包括空实体
+    pub archetypes: usize,         // 导入了实体的原型数量
+    pub archetypes_created: usize, // 主World上新建的原型数量
+}
+
+/// 合并失败的原因，失败时主World不变
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeError {
+    /// 同一类型的组件在两个World上的内存大小不同
+    IncompatibleComponent(Cow<'static, str>),
+}
+
+impl World {
+    /// 合并另一个World的所有实体和组件，组件按类型匹配，新旧实体记录在mapper中
+    pub fn merge(
+        &mut self,
+        other: World,
+        mapper: &mut EntityMapper,
+    ) -> Result<MergeReport, MergeError> {
+        // 先检查所有组件，失败时不修改self
+        for c in other.component_arr.iter() {
+            let info = c.info();
+            if let Some(index) = self.component_map.get(info.type_id()) {
+                if self.component_arr[index.index()].info().mem_size != info.mem_size {
+                    return Err(MergeError::IncompatibleComponent(info.type_name().clone()));
+                }
+            }
+        }
+        let tick = self.tick();
+        let mut report = MergeReport::default();
+        // 需要修正实体引用的组件，所有实体分配后再修正，子World内部的引用可以是任意顺序
+        let mut fixes: Vec<(Share<Column>, ArchetypeIndex, Row, Entity, MapFn)> = Vec::new();
+        for oar in other.archetype_arr.iter() {
+            let mut rows = Vec::new();
+            for row in 0..oar.len().index() {
+                let row: Row = row.into();
+                if let Some(e) = oar.live_entity(row) {
+                    rows.push((row, e));
+                }
+            }
+            if rows.is_empty() {
+                continue;
+            }
+            // 和子World原型的列顺序相同的主World的列
+            let columns: Vec<(Share<Column>, Option<MapFn>)> = oar
+                .get_columns()
+                .iter()
+                .map(|c| {
+                    let type_id = c.info().type_id();
+                    let map = self
+                        .references
+                        .map_fn(type_id)
+                        .or_else(|| other.references.map_fn(type_id));
+                    (self.add_component_info(c.info().clone()).1, map)
+                })
+                .collect();
+            let info = ArchetypeInfo::sort(columns.iter().map(|(c, _)| c.clone()).collect());
+            if self.get_archetype_by_info(&info).is_none() {
+                report.archetypes_created += 1;
+            }
+            let ar = self.find_archtype(info);
+            report.archetypes += 1;
+            for (orow, old) in rows {
+                let (r, row) = ar.alloc();
+                let row: Row = row.into();
+                let e = self.insert_addr(ar.index(), row);
+                for (oc, (c, map)) in oar.get_columns().iter().zip(columns.iter()) {
+                    let dst = c.blob_ref_unchecked(ar.index());
+                    if dst.info.size() > 0 {
+                        let src = oc.blob_ref_unchecked(oar.index()).get_row(orow, old);
+                        dst.write_row(row, e, src);
+                    }
+                    dst.added_tick(e, row, tick);
+                    if let Some(map) = map {
+                        fixes.push((c.clone(), ar.index(), row, e, *map));
+                    }
+                }
+                *r = e;
+                ar.record_allocated(row, e);
+                // 组件的所有权已移动到self，只标记移除，other释放时不再释放这些组件
+                oar.mark_remove(orow);
+                mapper.insert(old, e);
+                report.entities += 1;
+            }
+        }
+        // 空实体
+        for (old, addr) in other.entities.iter() {
+            if addr.row.is_null() {
+                mapper.insert(old, self.spawn_empty());
+                report.entities += 1;
+            }
+        }
+        for (c, index, row, e, map) in fixes {
+            map(c.blob_ref_unchecked(index).get_row(row, e), &mut |r| mapper.map(r));
+        }
+        Ok(report)
+    }
+}
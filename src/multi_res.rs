@@ -4,13 +4,13 @@
 //! 每个独立的资源都有自己的Tick， 并且多例资源有一个共享的Tick。
 
 use std::any::TypeId;
-use std::mem::{replace, transmute};
+use std::mem::transmute;
 use std::ops::{Deref, DerefMut};
 use std::sync::atomic::Ordering;
 
 use pi_share::{Share, ShareUsize};
 
-use crate::single_res::{SingleRes, SingleResMut, TickRes};
+use crate::single_res::{advance_last_run, SingleRes, SingleResMut, TickRes};
 use crate::system::{Relation, SystemMeta};
 use crate::system_params::SystemParam;
 use crate::world::*;
@@ -106,12 +106,12 @@ impl<T: 'static> SystemParam for MultiRes<'_, T> {
 
     #[inline]
     fn get_param<'world>(
-        _world: &'world World,
+        world: &'world World,
         _system_meta: &'world SystemMeta,
         state: &'world mut Self::State,
         tick: Tick,
     ) -> Self::Item<'world> {
-        let last_run = replace(&mut state.2, tick);
+        let last_run = advance_last_run(world, &mut state.2, tick);
         MultiRes::new(
             &state.0,
             state.1.load(Ordering::Relaxed).into(),
@@ -0,0 +1,241 @@
+//! 查询的并行迭代
+//! 实体很多时单线程的QueryIter是瓶颈。将每个匹配原型的行按chunk_size分为若干块，每块是独立的迭代器，可以在不同线程上消费。
+//! 块之间的行不相交，所以可写的块也可以并行，Mut的修改记录和单线程迭代相同。
+//! 块在创建时确定行的范围，迭代时的过滤和单线程迭代相同，结果为单线程迭代的一个划分。
+//! q.par_for_each(4, |a| { sum.fetch_add(a.0, Ordering::Relaxed); });
+//! for chunk in q.chunks(1024) { scope.spawn(move || for a in chunk { ... }); }
+//!
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::archetype::{Archetype, Row};
+use crate::fetch::FetchComponents;
+use crate::filter::FilterComponents;
+use crate::query::{Query, QueryState};
+use crate::world::{Tick, World};
+
+/// 原型上一段行的迭代器，倒序迭代[start, end)
+pub struct QueryChunk<'w, Q: FetchComponents + 'static, F: FilterComponents + 'static> {
+    world: &'w World,
+    state: &'w QueryState<Q, F>,
+    tick: Tick,
+    ar: &'w Archetype,
+    start: u32,
+    row: u32,
+    // 第一次迭代时才初始化，块可以先发送到其他线程
+    fetch_filter: Option<(Q::Fetch<'w>, F::Filter<'w>)>,
+}
+// 不同块的行不相交，迭代出的项及块缓存的fetch、filter可以发送到其他线程时，块也可以在不同线程上迭代
+unsafe impl<'w, Q: FetchComponents, F: FilterComponents> Send for QueryChunk<'w, Q, F> where
+    Q::Item<'w>: Send,
+    Q::Fetch<'w>: Send,
+    F::Filter<'w>: Send,
+{
+}
+
+impl<'w, Q: FetchComponents, F: FilterComponents> QueryChunk<'w, Q, F> {
+    /// 块所在的原型
+    pub fn archetype(&self) -> &'w Archetype {
+        self.ar
+    }
+    /// 块还未迭代的行数，包括空位和被过滤的行
+    pub fn rows(&self) -> usize {
+        (self.row - self.start) as usize
+    }
+}
+impl<'w, Q: FetchComponents, F: FilterComponents> Iterator for QueryChunk<'w, Q, F> {
+    type Item = Q::Item<'w>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.row == self.start {
+            return None;
+        }
+        let (fetch, filter) = self.fetch_filter.get_or_insert_with(|| {
+            (
+                Q::init_fetch(
+                    self.world,
+                    &self.state.fetch_state,
+                    self.ar.index(),
+                    self.tick,
+                    self.state.last_run,
+                ),
+                F::init_filter(
+                    self.world,
+                    &self.state.filter_state,
+                    self.ar.index(),
+                    self.tick,
+                    self.state.last_run,
+                ),
+            )
+        });
+        while self.row > self.start {
+            self.row -= 1;
+            let row = Row(self.row);
+            let e = match self.ar.live_entity(row) {
+                Some(e) => e,
+                None => continue,
+            };
+            if F::filter(filter, row, e) {
+                continue;
+            }
+            return Some(Q::fetch(fetch, row, e));
+        }
+        None
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.rows()))
+    }
+}
+
+// 按查询的迭代顺序划分行，返回(原型, 开始行, 结束行)
+fn split_rows<'w, Q: FetchComponents, F: FilterComponents>(
+    state: &'w QueryState<Q, F>,
+    chunk_size: usize,
+) -> Vec<(&'w Archetype, u32, u32)> {
+    let size = chunk_size.max(1) as u32;
+    let mut vec = Vec::new();
+    for ar in state.archetypes.iter().rev() {
+        let mut end = ar.len().0;
+        while end > 0 {
+            let start = end.saturating_sub(size);
+            vec.push((&**ar, start, end));
+            end = start;
+        }
+    }
+    vec
+}
+
+fn make_chunks<'w, Q: FetchComponents, F: FilterComponents>(
+    world: &'w World,
+    state: &'w QueryState<Q, F>,
+    tick: Tick,
+    chunk_size: usize,
+) -> Vec<QueryChunk<'w, Q, F>> {
+    split_rows(state, chunk_size)
+        .into_iter()
+        .map(|(ar, start, end)| QueryChunk {
+            world,
+            state,
+            tick,
+            ar,
+            start,
+            row: end,
+            fetch_filter: None,
+        })
+        .collect()
+}
+
+// 并行时每个线程的默认块大小
+const PAR_CHUNK_SIZE: usize = 1024;
+
+struct ParContext<'w, Q: FetchComponents + 'static, F: FilterComponents + 'static> {
+    world: &'w World,
+    state: &'w QueryState<Q, F>,
+    tick: Tick,
+    ranges: Vec<(&'w Archetype, u32, u32)>,
+    next: AtomicUsize,
+}
+// 线程间只共享只读的引用，取块用原子计数，迭代出的项、fetch和filter在各线程上产生，所以都必须可以发送
+unsafe impl<'w, Q: FetchComponents, F: FilterComponents> Sync for ParContext<'w, Q, F> where
+    Q::Item<'w>: Send,
+    Q::Fetch<'w>: Send,
+    F::Filter<'w>: Send,
+{
+}
+
+impl<'w, Q: FetchComponents, F: FilterComponents> ParContext<'w, Q, F>
+where
+    Q::Item<'w>: Send,
+    Q::Fetch<'w>: Send,
+    F::Filter<'w>: Send,
+{
+    // 取下一个还未被其他线程迭代的块
+    fn next_chunk(&self) -> Option<QueryChunk<'w, Q, F>> {
+        let i = self.next.fetch_add(1, Ordering::Relaxed);
+        let (ar, start, end) = *self.ranges.get(i)?;
+        Some(QueryChunk {
+            world: self.world,
+            state: self.state,
+            tick: self.tick,
+            ar,
+            start,
+            row: end,
+            fetch_filter: None,
+        })
+    }
+    fn run(&self, threads: usize, f: &(impl Fn(Q::Item<'w>) + Sync)) {
+        let threads = threads.min(self.ranges.len());
+        if threads <= 1 {
+            while let Some(chunk) = self.next_chunk() {
+                chunk.for_each(f);
+            }
+            return;
+        }
+        std::thread::scope(|s| {
+            for _ in 1..threads {
+                s.spawn(|| {
+                    while let Some(chunk) = self.next_chunk() {
+                        chunk.for_each(f);
+                    }
+                });
+            }
+            while let Some(chunk) = self.next_chunk() {
+                chunk.for_each(f);
+            }
+        });
+    }
+}
+
+impl<'w, Q: FetchComponents, F: FilterComponents> Query<'w, Q, F> {
+    /// 将匹配的行划分为最多chunk_size行的块，每块可以在不同线程上迭代
+    pub fn chunks(
+        &self,
+        chunk_size: usize,
+    ) -> Vec<QueryChunk<'_, <Q as FetchComponents>::ReadOnly, F>> {
+        make_chunks(self.world, self.state.as_readonly(), self.tick, chunk_size)
+    }
+    /// 可写的块，块之间的行不相交
+    pub fn chunks_mut(&mut self, chunk_size: usize) -> Vec<QueryChunk<'_, Q, F>> {
+        make_chunks(self.world, self.state, self.tick, chunk_size)
+    }
+    /// 用threads个线程（包括当前线程）并行迭代，线程间动态分配块。项必须可以发送，即只读的组件须为Sync
+    pub fn par_for_each<'a>(
+        &'a self,
+        threads: usize,
+        f: impl Fn(<<Q as FetchComponents>::ReadOnly as FetchComponents>::Item<'a>) + Sync,
+    ) where
+        <<Q as FetchComponents>::ReadOnly as FetchComponents>::Item<'a>: Send,
+        <<Q as FetchComponents>::ReadOnly as FetchComponents>::Fetch<'a>: Send,
+        F::Filter<'a>: Send,
+    {
+        let state = self.state.as_readonly();
+        ParContext {
+            world: self.world,
+            state,
+            tick: self.tick,
+            ranges: split_rows(state, PAR_CHUNK_SIZE),
+            next: AtomicUsize::new(0),
+        }
+        .run(threads, &f);
+    }
+    /// 可写的并行迭代，可写的组件须为Send
+    pub fn par_for_each_mut<'a>(
+        &'a mut self,
+        threads: usize,
+        f: impl Fn(<Q as FetchComponents>::Item<'a>) + Sync,
+    ) where
+        <Q as FetchComponents>::Item<'a>: Send,
+        <Q as FetchComponents>::Fetch<'a>: Send,
+        F::Filter<'a>: Send,
+    {
+        let state: &'a QueryState<Q, F> = &*self.state;
+        ParContext {
+            world: self.world,
+            state,
+            tick: self.tick,
+            ranges: split_rows(state, PAR_CHUNK_SIZE),
+            next: AtomicUsize::new(0),
+        }
+        .run(threads, &f);
+    }
+}
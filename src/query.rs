@@ -2,19 +2,25 @@
 
 use core::fmt::*;
 use core::result::Result;
+use std::any::{Any, TypeId};
+use std::borrow::Cow;
 use std::cell::SyncUnsafeCell;
 use std::mem::{transmute, MaybeUninit};
 use std::ops::{Deref, DerefMut};
 
 use crate::archetype::{Archetype, ArchetypeIndex, Row, ShareArchetype};
-use crate::fetch::FetchComponents;
+use crate::column::{BlobRef, Column};
+use crate::fetch::{FetchComponents, FetchInitError};
 use crate::filter::FilterComponents;
-use crate::system::{relate, Related, SystemMeta};
+use crate::group::GroupId;
+use crate::system::{relate, Related, Relation, SystemMeta};
 use crate::system_params::SystemParam;
+use crate::utils::prefetch_read;
 use crate::world::*;
 use fixedbitset::FixedBitSet;
 use pi_null::*;
-use pi_share::Share;
+use pi_share::{Share, ShareUsize};
+use std::sync::atomic::Ordering;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum QueryError {
@@ -26,6 +32,29 @@ pub enum QueryError {
     NoSuchRow(Row),
     NoSuchRes,
     RepeatAlter,
+    GroupLocked(GroupId),
+    MissingReadAccess(ComponentIndex),
+    NotDefaultable(ComponentIndex, ArchetypeIndex, &'static str), // 没有默认值的组件，要添加到的原型，操作
+    DuplicateEntity(Entity), // 可写地按列表查询或get_many_mut时，同一实体出现多次
+    TooManyEntities, // single查询匹配了多个实体
+    NotSerializable(ComponentIndex), // 组件不能转为归档数据，或不能从归档数据还原
+}
+impl QueryError {
+    /// 用于错误信息的描述，实体用World::entity_label格式化
+    pub fn describe(&self, world: &World) -> String {
+        match self {
+            QueryError::NoMatchEntity(e) => format!("NoMatchEntity({})", world.entity_label(*e)),
+            QueryError::NoSuchEntity(e) => format!("NoSuchEntity({})", world.entity_label(*e)),
+            QueryError::DuplicateEntity(e) => format!("DuplicateEntity({})", world.entity_label(*e)),
+            QueryError::NotDefaultable(c, ar, op) => format!(
+                "NotDefaultable({} has no default value, {} can't add it to archetype {})",
+                world.get_column(*c).map_or(Cow::Borrowed("?"), |c| c.info().type_name().clone()),
+                op,
+                world.get_archetype(*ar).map_or(Cow::Borrowed("?"), |a| a.name().clone()),
+            ),
+            _ => format!("{:?}", self),
+        }
+    }
 }
 // // todo 移除
 // pub struct Queryer<'w, Q: FetchComponents + 'static, F: FilterComponents + 'static = ()> {
@@ -129,6 +158,7 @@ impl<'w, Q: FetchComponents, F: FilterComponents> Query<'w, Q, F> {
         self.state.last_run
     }
 
+    /// 实体是否在查询匹配的原型上，不获取组件，只读，可以在并行的只读遍历中调用。不检查Changed等行过滤
     pub fn contains(&self, entity: Entity) -> bool {
         self.state.contains(self.world, entity)
     }
@@ -155,6 +185,42 @@ impl<'w, Q: FetchComponents, F: FilterComponents> Query<'w, Q, F> {
         unsafe { transmute(r) }
     }
 
+    /// 同时获得多个实体的查询项，任何一个实体不匹配则返回第一个错误
+    pub fn get_many<const N: usize>(
+        &self,
+        entities: [Entity; N],
+    ) -> Result<[<<Q as FetchComponents>::ReadOnly as FetchComponents>::Item<'_>; N], QueryError> {
+        collect_many(entities.map(|e| self.get(e)))
+    }
+    /// 同时可写地获得多个实体的查询项，实体重复时返回DuplicateEntity
+    pub fn get_many_mut<const N: usize>(
+        &mut self,
+        entities: [Entity; N],
+    ) -> Result<[<Q as FetchComponents>::Item<'_>; N], QueryError> {
+        for i in 1..N {
+            if entities[..i].contains(&entities[i]) {
+                return Err(QueryError::DuplicateEntity(entities[i]));
+            }
+        }
+        let r = collect_many(entities.map(|e| {
+            self.state
+                .get_by_tick(self.world, self.tick, e, &self.cache_index, &self.fetch_filter)
+        }));
+        // 实体各不相同，可写项之间没有别名
+        unsafe { transmute(r) }
+    }
+
+    /// 按需读取Q中没有声明的组件，该组件必须用ExtraRead<T>声明，否则返回MissingReadAccess。
+    /// 只在调用时才读取，适用于只有少数实体需要读取的组件
+    pub fn get_extra<T: 'static>(&self, e: Entity) -> Result<&T, QueryError> {
+        let index = self.world.get_component_index(&TypeId::of::<T>());
+        if !self.state.has_read(index) {
+            return Err(QueryError::MissingReadAccess(index));
+        }
+        self.state.check(self.world, e)?;
+        self.world.get_component_by_index(e, index)
+    }
+
     pub fn is_empty(&self) -> bool {
         self.state.is_empty()
     }
@@ -166,13 +232,65 @@ impl<'w, Q: FetchComponents, F: FilterComponents> Query<'w, Q, F> {
     pub fn archetypes_len(&self) -> usize {
         self.state.archetypes_len()
     }
+    /// 查询匹配的原型
+    pub fn archetypes(&self) -> &[ShareArchetype] {
+        self.state.archetypes()
+    }
+    /// 设置迭代时的预取距离，见QState::set_prefetch_hint
+    pub fn prefetch_hint(&mut self, distance: usize) {
+        self.state.set_prefetch_hint(self.world, distance);
+    }
 
     pub fn iter(&self) -> QueryIter<'_, <Q as FetchComponents>::ReadOnly, F> {
         QueryIter::new(self.world, self.state.as_readonly(), self.tick)
     }
-    pub fn iter_mut(&mut self) -> QueryIter<'_, Q, F> {
+    /// 可写的迭代，&mut self保证迭代期间不能再调用iter或get
+    pub fn iter_mut(&mut self) -> QueryIterMut<'_, Q, F> {
         QueryIter::new(self.world, &self.state, self.tick)
     }
+    /// 对每个查询项调用f，f不会超出本次调用，可以在f中借用其他数据
+    pub fn for_each(&mut self, f: impl FnMut(<Q as FetchComponents>::Item<'_>)) {
+        self.iter_mut().for_each(f)
+    }
+    /// 同for_each，同时传入实体
+    pub fn for_each_with_entity(&mut self, f: impl FnMut(Entity, <Q as FetchComponents>::Item<'_>)) {
+        for_each_entity(self.iter_mut(), f)
+    }
+    /// 查询只匹配一个实体时返回该项，没有匹配返回NoSuchEntity，多于一个返回TooManyEntities
+    pub fn single(&self) -> Result<<<Q as FetchComponents>::ReadOnly as FetchComponents>::Item<'_>, QueryError> {
+        single_item(self.iter())
+    }
+    pub fn single_mut(&mut self) -> Result<<Q as FetchComponents>::Item<'_>, QueryError> {
+        single_item(self.iter_mut())
+    }
+    /// 将当前的查询结果映射为不借用world的数据，用于异步任务在world继续运行时处理某一时刻的结果
+    pub fn materialize<'a, M: 'static>(
+        &'a self,
+        map: impl FnMut(Entity, <<Q as FetchComponents>::ReadOnly as FetchComponents>::Item<'a>) -> M,
+    ) -> Vec<M> {
+        let mut vec = Vec::new();
+        self.materialize_into(&mut vec, map);
+        vec
+    }
+    /// 将当前的查询结果映射后追加到buf中，可以复用buf的内存
+    pub fn materialize_into<'a, M: 'static>(
+        &'a self,
+        buf: &mut Vec<M>,
+        mut map: impl FnMut(Entity, <<Q as FetchComponents>::ReadOnly as FetchComponents>::Item<'a>) -> M,
+    ) {
+        buf.reserve(self.len());
+        let mut it = self.iter();
+        while let Some(item) = it.next() {
+            buf.push(map(it.e, item));
+        }
+    }
+}
+
+impl<'w, T: Clone + 'static, F: FilterComponents> Query<'w, &'static T, F> {
+    /// 复制当前查询到的组件
+    pub fn materialize_cloned(&self) -> Vec<(Entity, T)> {
+        self.materialize(|e, v| (e, v.clone()))
+    }
 }
 
 impl<'a, Q: FetchComponents + 'static, F: FilterComponents + Send + Sync> SystemParam
@@ -184,8 +302,9 @@ impl<'a, Q: FetchComponents + 'static, F: FilterComponents + Send + Sync> System
     fn init_state(world: &mut World, system_meta: &mut SystemMeta) -> Self::State {
         Self::State::create(world, system_meta)
     }
-    fn align(world: &World, _system_meta: &SystemMeta, state: &mut Self::State) {
-        state.align(world);
+    fn align(world: &World, system_meta: &SystemMeta, state: &mut Self::State) {
+        let len = system_meta.align_len(world);
+        state.align_to(world, len);
     }
 
     fn get_param<'w>(
@@ -209,7 +328,10 @@ impl<'a, Q: FetchComponents + 'static, F: FilterComponents + Send + Sync> System
 
 impl<'w, Q: FetchComponents, F: FilterComponents> Drop for Query<'w, Q, F> {
     fn drop(&mut self) {
-        self.state.last_run = self.tick;
+        // tick冻结时不推进last_run
+        if !self.world.is_ticks_frozen() {
+            self.state.last_run = self.tick;
+        }
     }
 }
 
@@ -239,13 +361,55 @@ impl pi_null::Null for LocalIndex {
         self.0 == u16::MAX
     }
 }
-#[derive(Debug)]
+// 全部成功时返回所有项，否则返回第一个错误
+fn collect_many<T, const N: usize>(results: [Result<T, QueryError>; N]) -> Result<[T; N], QueryError> {
+    let mut err = None;
+    let items = results.map(|r| match r {
+        Ok(item) => Some(item),
+        Err(e) => {
+            err.get_or_insert(e);
+            None
+        }
+    });
+    match err {
+        Some(e) => Err(e),
+        None => Ok(items.map(|item| item.unwrap())),
+    }
+}
+// 取出唯一的一项，再取一项检查是否唯一
+fn single_item<I: Iterator>(mut it: I) -> Result<I::Item, QueryError> {
+    let item = it.next().ok_or(QueryError::NoSuchEntity(Entity::null()))?;
+    if it.next().is_some() {
+        return Err(QueryError::TooManyEntities);
+    }
+    Ok(item)
+}
 pub struct QueryState<Q: FetchComponents + 'static, F: FilterComponents + 'static> {
     pub(crate) fetch_state: Q::State,
     pub(crate) filter_state: F::State,
     pub(crate) qstate: QState,
 }
 
+// 只输出摘要，避免输出每个原型的全部实体
+impl<Q: FetchComponents + 'static, F: FilterComponents + 'static> Debug for QueryState<Q, F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("QueryState")
+            .field("fetch", &std::any::type_name::<Q>())
+            .field("filter", &std::any::type_name::<F>())
+            .field("archetypes_len", &self.archetypes.len())
+            .field(
+                "archetypes",
+                &self
+                    .archetypes
+                    .iter()
+                    .map(|ar| (ar.name(), ar.len().index(), ar.removes.len()))
+                    .collect::<Vec<_>>(),
+            )
+            .field("last_run", &self.last_run)
+            .finish()
+    }
+}
+
 impl<Q: FetchComponents + 'static, F: FilterComponents + 'static> Deref for QueryState<Q, F> {
     type Target = QState;
     fn deref(&self) -> &Self::Target {
@@ -271,6 +435,18 @@ impl<Q: FetchComponents, F: FilterComponents> QueryState<Q, F> {
             qstate: QState::new(system_meta),
         }
     }
+    /// 对齐到world上的原型
+    pub fn align(&mut self, world: &World) {
+        let len = world.archetype_arr.len();
+        self.align_to(world, len);
+    }
+    /// 同QState::align_to，被Q::try_init_archetype拒绝的原型不加入查询
+    pub fn align_to(&mut self, world: &World, len: usize) {
+        let fetch_state = &self.fetch_state;
+        self.qstate
+            .align_by(world, len, &mut |ar| Q::try_init_archetype(world, fetch_state, ar));
+    }
+    /// 实体是否在匹配的原型上，和get使用相同的检查，但不获取组件
     pub fn contains(&self, world: &World, entity: Entity) -> bool {
         self.check(world, entity).is_ok()
     }
@@ -311,11 +487,41 @@ impl<Q: FetchComponents, F: FilterComponents> QueryState<Q, F> {
         let tick = world.tick();
         QueryIter::new(world, self.as_readonly(), tick)
     }
-    pub fn iter_mut<'w>(&'w mut self, world: &'w mut World) -> QueryIter<'_, Q, F> {
+    pub fn iter_mut<'w>(&'w mut self, world: &'w mut World) -> QueryIterMut<'_, Q, F> {
         self.align(world);
         let tick = world.tick();
         QueryIter::new(world, self, tick)
     }
+    /// 对齐后用指定的tick迭代，对每个查询项调用f
+    pub fn for_each(
+        &mut self,
+        world: &World,
+        tick: Tick,
+        f: impl FnMut(<Q as FetchComponents>::Item<'_>),
+    ) {
+        self.align(world);
+        QueryIter::new(world, self, tick).for_each(f)
+    }
+    /// 同for_each，同时传入实体
+    pub fn for_each_with_entity(
+        &mut self,
+        world: &World,
+        tick: Tick,
+        f: impl FnMut(Entity, <Q as FetchComponents>::Item<'_>),
+    ) {
+        self.align(world);
+        for_each_entity(QueryIter::new(world, self, tick), f)
+    }
+    /// 同Query::single
+    pub fn single<'w>(
+        &'w mut self,
+        world: &'w World,
+    ) -> Result<<<Q as FetchComponents>::ReadOnly as FetchComponents>::Item<'w>, QueryError> {
+        single_item(self.iter(world))
+    }
+    pub fn single_mut<'w>(&'w mut self, world: &'w mut World) -> Result<<Q as FetchComponents>::Item<'w>, QueryError> {
+        single_item(self.iter_mut(world))
+    }
 
     #[inline(always)]
     pub fn get_by_tick<'w>(
@@ -332,6 +538,11 @@ impl<Q: FetchComponents, F: FilterComponents> QueryState<Q, F> {
         >,
     ) -> Result<Q::Item<'w>, QueryError> {
         let addr = *self.check(world, e /* cache_mapping, */)?;
+        // 和迭代的判断一致，行上必须是存活的该实体
+        let ar = unsafe { world.archetype_arr.get_unchecked(addr.archetype_index().index()) };
+        if ar.live_entity(addr.row) != Some(e) {
+            return Err(QueryError::NoSuchRow(addr.row));
+        }
 
         // println!("get======{:?}", (entity, addr.archetype_index(), addr,  world.get_archetype(addr.archetype_index())));
         if addr.archetype_index() != unsafe { *cache_index.get() } {
@@ -361,50 +572,154 @@ impl<Q: FetchComponents, F: FilterComponents> QueryState<Q, F> {
     }
 }
 
+/// 可注册在World上的持久查询状态
+pub trait AnyQueryState: Any {
+    /// 对齐world上新增的原型
+    fn align(&mut self, world: &World);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+impl<Q: FetchComponents + 'static, F: FilterComponents + 'static> AnyQueryState
+    for QueryState<Q, F>
+{
+    fn align(&mut self, world: &World) {
+        QueryState::align(self, world);
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
 #[derive(Debug)]
 pub struct QState {
     pub(crate) related: Share<Related<ComponentIndex>>, // 组件关系表
+    pub(crate) required: Vec<ComponentIndex>, // 原型必须包含的组件，用于对齐时只检查包含原型最少的组件所在的原型
     pub(crate) archetypes_len: usize, // 脏的最新的原型，如果world上有更新的，则检查是否和自己相关
     pub(crate) archetypes: Vec<ShareArchetype>, // 每原型
     pub(crate) bit_set: FixedBitSet,  // world上的原型索引是否在本地
     pub(crate) bit_set_start: usize,
     pub(crate) last_run: Tick, // 上次运行的tick
+    pub(crate) counters: QueryCounters, // 迭代的统计
+    pub(crate) prefetch_distance: usize, // 迭代时预取的行距离，0表示不预取
+    pub(crate) prefetch_columns: Vec<Share<Column>>, // 需要预取的列
+    pub(crate) init_warnings: Vec<(ArchetypeIndex, FetchInitError)>, // 被Fetch拒绝的原型
 }
 
 impl QState {
     pub fn new(system_meta: &mut SystemMeta) -> Self {
         let related = system_meta.related_ok();
+        let required = Self::required_components(&related);
         Self {
             // id,
             related,
+            required,
             archetypes_len: 0,
             archetypes: Vec::with_capacity(256),
             bit_set: Default::default(),
             bit_set_start: 0,
             last_run: Tick::default(),
+            counters: QueryCounters::default(),
+            prefetch_distance: 0,
+            prefetch_columns: Vec::new(),
+            init_warnings: Vec::new(),
         }
     }
 
+    // 获得关系表中原型必须包含的组件，不包括Or等节点中的组件
+    fn required_components(related: &Related<ComponentIndex>) -> Vec<ComponentIndex> {
+        let mut vec = Vec::new();
+        let mut depth = 0;
+        for r in related.vec.iter() {
+            match r {
+                Relation::Or | Relation::And => depth += 1,
+                Relation::End => depth -= 1,
+                Relation::With(i) | Relation::Read(i) | Relation::Write(i) if depth == 0 => {
+                    vec.push(*i)
+                }
+                _ => (),
+            }
+        }
+        vec
+    }
     // 对齐world上新增的原型
     pub fn align(&mut self, world: &World) {
         let len = world.archetype_arr.len();
-        if len == self.archetypes_len {
+        self.align_to(world, len);
+    }
+    /// 对齐到world上前len个原型，同一system的多个查询用相同的len对齐，保证看到相同的原型集合
+    pub fn align_to(&mut self, world: &World, len: usize) {
+        self.align_by(world, len, &mut |_| Ok(()))
+    }
+    // check检查原型能否被匹配
+    pub(crate) fn align_by(
+        &mut self,
+        world: &World,
+        len: usize,
+        check: &mut dyn FnMut(&Archetype) -> Result<(), FetchInitError>,
+    ) {
+        if len <= self.archetypes_len {
             return;
         }
-        // 检查新增的原型
+        // 找到包含原型最少的必须组件，只检查该组件所在的新增原型
+        let column = self
+            .required
+            .iter()
+            .filter_map(|i| world.get_column(*i))
+            .min_by_key(|c| c.archetypes_len());
+        match column {
+            Some(c) => {
+                let start = self.archetypes_len;
+                let mut vec: Vec<ArchetypeIndex> = c
+                    .archetype_indexs()
+                    .filter(|i| i.index() >= start && i.index() < len)
+                    .collect();
+                // 必须按原型索引递增的顺序添加
+                vec.sort_unstable();
+                for i in vec {
+                    let ar = unsafe { world.archetype_arr.get_unchecked(i.index()) };
+                    self.add_archetype_by(ar, i, check);
+                }
+            }
+            None => self.scan(world, len, check),
+        }
+        self.archetypes_len = len;
+    }
+    // 逐个检查新增的原型
+    pub(crate) fn scan(
+        &mut self,
+        world: &World,
+        len: usize,
+        check: &mut dyn FnMut(&Archetype) -> Result<(), FetchInitError>,
+    ) {
         for i in self.archetypes_len..len {
             let ar = unsafe { world.archetype_arr.get_unchecked(i) };
-            self.add_archetype(ar, i.into());
+            self.add_archetype_by(ar, i.into(), check);
         }
-        self.archetypes_len = len;
     }
     // 新增的原型
     pub fn add_archetype(&mut self, ar: &ShareArchetype, index: ArchetypeIndex) {
-        // 判断原型是否和查询相关
+        self.add_archetype_by(ar, index, &mut |_| Ok(()))
+    }
+    fn add_archetype_by(
+        &mut self,
+        ar: &ShareArchetype,
+        index: ArchetypeIndex,
+        check: &mut dyn FnMut(&Archetype) -> Result<(), FetchInitError>,
+    ) {
+        // 判断原型是否和查询相关，原型创建时不做判断，只在对齐时判断一次
+        self.counters.archetypes_filtered.fetch_add(1, Ordering::Relaxed);
         // println!("add_archetype======{:?}", (ar.name(), self.related.relate(ar, 0), &self.related));
         if !relate(&self.related, ar, 0) {
             return;
         }
+        if let Err(err) = check(ar) {
+            log::warn!("query skip archetype {}: {:?}", ar.name(), err);
+            self.init_warnings.push((index, err));
+            return;
+        }
         if self.archetypes.len() == 0 {
             self.bit_set_start = index.index();
         }
@@ -453,6 +768,99 @@ impl QState {
     pub fn archetypes_len(&self) -> usize {
         self.archetypes.len()
     }
+    /// 匹配的原型，按对齐的顺序
+    pub fn archetypes(&self) -> &[ShareArchetype] {
+        &self.archetypes
+    }
+    /// 是否声明了对指定组件的读
+    pub fn has_read(&self, index: ComponentIndex) -> bool {
+        self.related.vec.iter().any(|r| match r {
+            Relation::Read(i)
+            | Relation::OptRead(i)
+            | Relation::Write(i)
+            | Relation::OptWrite(i)
+            | Relation::ShareWrite(i) => *i == index,
+            Relation::ReadAll | Relation::WriteAll => true,
+            _ => false,
+        })
+    }
+    /// 获得迭代的统计数据，visited和yielded的比值高，表示过滤器的匹配很差，应该调整查询
+    pub fn statistics(&self) -> QueryStatistics {
+        QueryStatistics {
+            archetypes_checked: self.counters.archetypes_checked.load(Ordering::Relaxed),
+            entities_visited: self.counters.entities_visited.load(Ordering::Relaxed),
+            entities_yielded: self.counters.entities_yielded.load(Ordering::Relaxed),
+            records_deduplicated: self.counters.records_deduplicated.load(Ordering::Relaxed),
+            archetypes_filtered: self.counters.archetypes_filtered.load(Ordering::Relaxed),
+        }
+    }
+    /// 被Fetch拒绝而没有加入查询的原型及原因
+    pub fn init_warnings(&self) -> &[(ArchetypeIndex, FetchInitError)] {
+        &self.init_warnings
+    }
+    /// 设置迭代时的预取距离，迭代到某行时，预取之后第distance行上查询读写的组件数据，0表示不预取（默认）。
+    /// 适用于大原型上读写多个组件的查询，预取只是对cpu的提示，不影响迭代的结果
+    pub fn set_prefetch_hint(&mut self, world: &World, distance: usize) {
+        self.prefetch_distance = distance;
+        self.prefetch_columns.clear();
+        if distance == 0 {
+            return;
+        }
+        for r in self.related.vec.iter() {
+            match r {
+                Relation::Read(i)
+                | Relation::Write(i)
+                | Relation::OptRead(i)
+                | Relation::OptWrite(i)
+                | Relation::ShareWrite(i) => {
+                    if let Some(c) = world.get_column(*i) {
+                        if c.info().size() > 0
+                            && !self.prefetch_columns.iter().any(|p| p.info().index == *i)
+                        {
+                            self.prefetch_columns.push(c.clone());
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    /// 重置统计数据，一般每帧重置
+    pub fn reset_statistics(&mut self) {
+        self.counters = QueryCounters::default();
+    }
+}
+
+/// 查询迭代的统计数据
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueryStatistics {
+    pub archetypes_checked: usize, // 检查过的原型数量
+    pub entities_visited: usize,   // 访问过的实体数量
+    pub entities_yielded: usize,   // 过滤后返回的实体数量
+    pub records_deduplicated: usize, // 事件记录去重的数量，当前的迭代不走事件记录，始终为0
+    pub archetypes_filtered: usize, // 对齐时判断原型是否匹配的次数，每个新增原型最多判断一次
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct QueryCounters {
+    archetypes_checked: ShareUsize,
+    entities_visited: ShareUsize,
+    entities_yielded: ShareUsize,
+    records_deduplicated: ShareUsize,
+    archetypes_filtered: ShareUsize,
+}
+
+/// 查询的迭代位置，原型和行都是倒序迭代的
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryCursor {
+    ar_index: u16, // 当前迭代的原型位置，未开始时为原型的数量
+    row: u32,      // 当前原型上还未迭代的行数
+}
+impl QueryCursor {
+    /// 迭代已结束
+    pub fn is_finished(&self) -> bool {
+        self.ar_index == 0 && self.row == 0
+    }
 }
 
 pub struct QueryIter<'w, Q: FetchComponents + 'static, F: FilterComponents + 'static> {
@@ -466,7 +874,41 @@ pub struct QueryIter<'w, Q: FetchComponents + 'static, F: FilterComponents + 'st
     fetch_filter: MaybeUninit<(Q::Fetch<'w>, F::Filter<'w>)>,
     pub(crate) e: Entity,
     pub(crate) row: Row,
+    // 本地统计，迭代器销毁时累加到查询状态上
+    archetypes_checked: usize,
+    entities_visited: usize,
+    entities_yielded: usize,
+    // 进入当前原型时，剩余的存活行数和已访问的实体数，用于计算剩余数量
+    ar_live: usize,
+    ar_visited: usize,
+    // 当前原型上需要预取的列
+    prefetch: Vec<BlobRef<'w>>,
+    // 预取的行距离，创建时从查询状态复制
+    prefetch_distance: u32,
+    // 反向迭代的原型位置，从0开始正序，和ar_index相遇时结束
+    pub(crate) ar_index_back: usize,
+    // 反向迭代在当前原型上的下一行，之前的行都已迭代
+    row_back: Row,
+    fetch_filter_back: Option<(Q::Fetch<'w>, F::Filter<'w>)>,
+    // 反向迭代是否已进入当前原型，进入时计数，严格模式下标记迭代
+    back_entered: bool,
+    // 反向迭代在当前原型上访问的实体数，及访问的实体总数
+    back_visited: usize,
+    back_visited_total: usize,
+}
+// 迭代时同时取出当前项的实体
+fn for_each_entity<'w, Q: FetchComponents, F: FilterComponents>(
+    mut iter: QueryIter<'w, Q, F>,
+    mut f: impl FnMut(Entity, Q::Item<'w>),
+) {
+    while let Some(item) = iter.next() {
+        f(iter.entity(), item);
+    }
 }
+
+/// 可写的查询迭代器，和QueryIter是同一类型，Q的可写项为Mut，修改时记录tick
+pub type QueryIterMut<'w, Q, F = ()> = QueryIter<'w, Q, F>;
+
 impl<'w, Q: FetchComponents, F: FilterComponents> QueryIter<'w, Q, F> {
     /// # Safety
     /// - `world` must have permission to access any of the components registered in `query_state`.
@@ -481,70 +923,258 @@ impl<'w, Q: FetchComponents, F: FilterComponents> QueryIter<'w, Q, F> {
             fetch_filter: MaybeUninit::uninit(),
             e: Entity::null(),
             row: Row(0),
+            archetypes_checked: 0,
+            entities_visited: 0,
+            entities_yielded: 0,
+            ar_live: 0,
+            ar_visited: 0,
+            prefetch: Vec::new(),
+            prefetch_distance: state.prefetch_distance as u32,
+            ar_index_back: 0,
+            row_back: Row(0),
+            fetch_filter_back: None,
+            back_entered: false,
+            back_visited: 0,
+            back_visited_total: 0,
         }
     }
     #[inline(always)]
     pub fn entity(&self) -> Entity {
         self.e
     }
-    fn next_archetype(&mut self) {
-        // 下一个原型
-        self.ar_index.0 -= 1;
-        self.ar = unsafe { &self.state.archetypes.get_unchecked(self.ar_index.index()) };
-        self.row = self.ar.len();
+    // 冷路径：切换到下一个非空的原型，初始化原型上的fetch和filter，所有原型都迭代完毕返回false
+    #[cold]
+    #[inline(never)]
+    fn advance_archetype(&mut self) -> bool {
+        loop {
+            if self.ar_index.index() <= self.ar_index_back {
+                // 所有原型都迭代过了，或者和反向迭代相遇
+                return false;
+            }
+            #[cfg(feature = "strict_iter")]
+            if self.ar_index.index() < self.state.archetypes.len() {
+                self.ar.iter_leave();
+            }
+            // 下一个原型
+            self.ar_index.0 -= 1;
+            self.ar = unsafe { &self.state.archetypes.get_unchecked(self.ar_index.index()) };
+            #[cfg(feature = "strict_iter")]
+            self.ar.iter_enter(self.ar.name());
+            self.archetypes_checked += 1;
+            self.row = self.ar.len();
+            if self.row.0 > 0 {
+                break;
+            }
+        }
+        self.ar_live = self.ar.live_len();
+        self.ar_visited = self.entities_visited;
+        self.init_fetch_filter();
+        true
+    }
+    // 初始化当前原型上的fetch和filter
+    fn init_fetch_filter(&mut self) {
+        let fetch = Q::init_fetch(
+            self.world,
+            &self.state.fetch_state,
+            self.ar.index(),
+            self.tick,
+            self.state.last_run,
+        );
+        let filter = F::init_filter(
+            self.world,
+            &self.state.filter_state,
+            self.ar.index(),
+            self.tick,
+            self.state.last_run,
+        );
+        self.fetch_filter = MaybeUninit::new((fetch, filter));
+        if self.prefetch_distance > 0 {
+            self.prefetch.clear();
+            for c in self.state.prefetch_columns.iter() {
+                if let Some(b) = c.blob_ref(self.ar.index()) {
+                    self.prefetch.push(b);
+                }
+            }
+        }
+    }
+    /// 当前的迭代位置，下次从该位置继续
+    pub fn cursor(&self) -> QueryCursor {
+        QueryCursor {
+            ar_index: self.ar_index.0,
+            row: self.row.0,
+        }
+    }
+    /// 从cursor的位置继续迭代，只能在开始迭代前调用。
+    /// 位置之后新增的原型不会被迭代，原型的行在整理时被移动，可能遗漏或重复
+    pub fn resume(&mut self, cursor: QueryCursor) {
+        let index = cursor.ar_index as usize;
+        if index >= self.state.archetypes.len() {
+            return;
+        }
+        // 离开已进入的原型，反向迭代回到起点
+        #[cfg(feature = "strict_iter")]
+        if self.ar_index.index() < self.state.archetypes.len() {
+            self.ar.iter_leave();
+        }
+        self.leave_back();
+        self.ar_index_back = 0;
+        self.row_back = Row(0);
+        self.ar_index = index.into();
+        self.ar = unsafe { &self.state.archetypes.get_unchecked(index) };
+        #[cfg(feature = "strict_iter")]
+        self.ar.iter_enter(self.ar.name());
+        self.archetypes_checked += 1;
+        self.row = Row(cursor.row.min(self.ar.len().0));
+        self.ar_live = (0..self.row.0)
+            .filter(|r| self.ar.live_entity(Row(*r)).is_some())
+            .count();
+        self.ar_visited = self.entities_visited;
         if self.row.0 > 0 {
-            let fetch = Q::init_fetch(
-                self.world,
-                &self.state.fetch_state,
-                self.ar.index(),
-                self.tick,
-                self.state.last_run,
-            );
-            let filter = F::init_filter(
-                self.world,
-                &self.state.filter_state,
-                self.ar.index(),
-                self.tick,
-                self.state.last_run,
-            );
-            self.fetch_filter = MaybeUninit::new((fetch, filter));
+            self.init_fetch_filter();
+        }
+    }
+    // 行是倒序迭代的，预取之前的行
+    #[inline(always)]
+    fn prefetch_rows(&self, row: Row) {
+        if row.0 >= self.prefetch_distance {
+            let target = Row(row.0 - self.prefetch_distance);
+            for b in self.prefetch.iter() {
+                prefetch_read(b.get_blob(target));
+            }
         }
     }
+    // 热路径只在当前原型内推进，跳过空位和已被移除待整理的行，不访问查询状态
     #[inline(always)]
     fn iter_normal(&mut self) -> Option<Q::Item<'w>> {
         loop {
-            // println!("iter_normal: {:?}", (self.e, self.row, self.ar.index(), self.ar.name()));
-            if self.row.0 > 0 {
+            // 和反向迭代在同一原型时，只迭代到反向迭代的位置
+            let lower = if self.ar_index.index() == self.ar_index_back {
+                self.row_back.0
+            } else {
+                0
+            };
+            while self.row.0 > lower {
                 self.row.0 -= 1;
-                self.e = self.ar.get_unchecked(self.row);
-                // 要求条目不为空
-                // println!("iter_normal1: {:?}", (self.e, self.row));
-                if !self.e.is_null() {
-                    let (fetch, filter) = unsafe { self.fetch_filter.assume_init_ref() };
-                    // println!("iter_normal1111: {:?}", (self.e, self.row));
-                    if F::filter(filter, self.row, self.e) {
+                let row = self.row;
+                let e = match self.ar.live_entity(row) {
+                    Some(e) => e,
+                    None => continue,
+                };
+                self.e = e;
+                self.entities_visited += 1;
+                if self.prefetch_distance > 0 {
+                    self.prefetch_rows(row);
+                }
+                let (fetch, filter) = unsafe { self.fetch_filter.assume_init_ref() };
+                if F::filter(filter, row, e) {
+                    continue;
+                }
+                self.entities_yielded += 1;
+                return Some(Q::fetch(fetch, row, e));
+            }
+            // 当前的原型已经迭代完毕
+            if !self.advance_archetype() {
+                return None;
+            }
+        }
+    }
+
+    // 反向迭代，原型正序，行正序，迭代到正向迭代的位置为止
+    fn iter_back(&mut self) -> Option<Q::Item<'w>> {
+        let state = self.state;
+        loop {
+            if self.ar_index_back >= state.archetypes.len()
+                || self.ar_index_back > self.ar_index.index()
+            {
+                return None;
+            }
+            let ar: &'w Archetype = unsafe { state.archetypes.get_unchecked(self.ar_index_back) };
+            if !self.back_entered {
+                #[cfg(feature = "strict_iter")]
+                ar.iter_enter(ar.name());
+                self.back_entered = true;
+                self.archetypes_checked += 1;
+            }
+            // 和正向迭代在同一原型时，只迭代到正向迭代的位置
+            let upper = if self.ar_index_back == self.ar_index.index() {
+                self.row.0
+            } else {
+                ar.len().0
+            };
+            if self.row_back.0 < upper {
+                let (world, tick) = (self.world, self.tick);
+                let (fetch, filter) = self.fetch_filter_back.get_or_insert_with(|| {
+                    (
+                        Q::init_fetch(world, &state.fetch_state, ar.index(), tick, state.last_run),
+                        F::init_filter(world, &state.filter_state, ar.index(), tick, state.last_run),
+                    )
+                });
+                while self.row_back.0 < upper {
+                    let row = self.row_back;
+                    self.row_back.0 += 1;
+                    let e = match ar.live_entity(row) {
+                        Some(e) => e,
+                        None => continue,
+                    };
+                    self.e = e;
+                    self.back_visited += 1;
+                    self.back_visited_total += 1;
+                    if F::filter(filter, row, e) {
                         continue;
                     }
-                    // println!("iter_normal2222: {:?}", (self.e, self.row));
-                    let item = Q::fetch(fetch, self.row, self.e);
-                    return Some(item);
+                    self.entities_yielded += 1;
+                    return Some(Q::fetch(fetch, row, e));
                 }
-                continue;
             }
-            // 当前的原型已经迭代完毕
-            if self.ar_index.0 == 0 {
-                // 所有原型都迭代过了
+            if self.ar_index_back >= self.ar_index.index() {
+                // 和正向迭代相遇
                 return None;
             }
             // 下一个原型
-            self.next_archetype();
+            self.leave_back();
+            self.ar_index_back += 1;
+            self.row_back = Row(0);
         }
     }
+    // 反向迭代离开当前原型
+    fn leave_back(&mut self) {
+        if self.back_entered {
+            #[cfg(feature = "strict_iter")]
+            {
+                let ar = unsafe { self.state.archetypes.get_unchecked(self.ar_index_back) };
+                ar.iter_leave();
+            }
+            self.back_entered = false;
+        }
+        self.fetch_filter_back = None;
+        self.back_visited = 0;
+    }
 
+    // 剩余的存活行数，没有逐行过滤时就是剩余的数量
     fn size_hint_normal(&self) -> (usize, Option<usize>) {
-        let it = self.state.archetypes[0..self.ar_index.index()].iter();
-        let count = it.map(|ar| ar.len()).count();
-        (self.row.index(), Some(self.row.index() + count))
+        let (f, b) = (self.ar_index.index(), self.ar_index_back);
+        let forward = self
+            .ar_live
+            .saturating_sub(self.entities_visited - self.ar_visited);
+        let n = if f == b {
+            // 同一原型，减去两端访问过的
+            forward.saturating_sub(self.back_visited)
+        } else {
+            let rest: usize = self.state.archetypes[b + 1..f]
+                .iter()
+                .map(|ar| ar.live_len())
+                .sum();
+            // b < f，反向迭代所在的原型一定存在
+            let back = self.state.archetypes[b]
+                .live_len()
+                .saturating_sub(self.back_visited);
+            rest + forward + back
+        };
+        if F::ROW_FILTER {
+            (0, Some(n))
+        } else {
+            (n, Some(n))
+        }
     }
 }
 
@@ -559,3 +1189,37 @@ impl<'w, Q: FetchComponents, F: FilterComponents> Iterator for QueryIter<'w, Q,
         self.size_hint_normal()
     }
 }
+/// 从后往前迭代，和next相遇时结束，两端的结果合起来和只用next相同
+impl<'w, Q: FetchComponents, F: FilterComponents> DoubleEndedIterator for QueryIter<'w, Q, F> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.iter_back()
+    }
+}
+impl<'w, Q: FetchComponents, F: FilterComponents> QueryIter<'w, Q, F> {
+    /// 剩余的确切数量，逐行过滤的查询（见FilterComponents::ROW_FILTER）只有上限，返回None
+    pub fn exact_len(&self) -> Option<usize> {
+        if F::ROW_FILTER {
+            return None;
+        }
+        Some(self.size_hint_normal().0)
+    }
+}
+impl<'w, Q: FetchComponents, F: FilterComponents> Drop for QueryIter<'w, Q, F> {
+    fn drop(&mut self) {
+        #[cfg(feature = "strict_iter")]
+        if self.ar_index.index() < self.state.archetypes.len() {
+            self.ar.iter_leave();
+        }
+        self.leave_back();
+        let counters = &self.state.counters;
+        counters
+            .archetypes_checked
+            .fetch_add(self.archetypes_checked, Ordering::Relaxed);
+        counters
+            .entities_visited
+            .fetch_add(self.entities_visited + self.back_visited_total, Ordering::Relaxed);
+        counters
+            .entities_yielded
+            .fetch_add(self.entities_yielded, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,141 @@
+//! 按给定的实体列表查询
+//! 子节点列表、选择集、碰撞对等场景，需要按列表的顺序获得指定实体的查询项。
+//! 逐个查询使用查询上的单项原型缓存，连续的同原型实体只初始化一次fetch。
+//! iter_many跳过不匹配的实体，iter_many_strict对每个实体返回Result。
+//! iter_many_mut是借出迭代器，同时只能持有一项，重复的实体返回DuplicateEntity，保证同一实体不会在一次遍历中被写两次。
+//! let mut it = q.iter_many_mut(children); while let Some(r) = it.fetch_next() { if let Ok(mut item) = r { ... } }
+//!
+
+use std::collections::HashSet;
+
+use pi_null::Null;
+
+use crate::fetch::FetchComponents;
+use crate::filter::FilterComponents;
+use crate::query::{Query, QueryError};
+use crate::world::Entity;
+
+/// 只读的实体列表迭代器，跳过不匹配的实体
+pub struct QueryManyIter<'a, 'w, Q: FetchComponents + 'static, F: FilterComponents + 'static, I> {
+    query: &'a Query<'w, Q, F>,
+    entities: I,
+}
+impl<'a, 'w, Q: FetchComponents, F: FilterComponents, I: Iterator<Item = Entity>> Iterator
+    for QueryManyIter<'a, 'w, Q, F, I>
+{
+    type Item = <<Q as FetchComponents>::ReadOnly as FetchComponents>::Item<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for e in self.entities.by_ref() {
+            if let Ok(item) = self.query.get(e) {
+                return Some(item);
+            }
+        }
+        None
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.entities.size_hint().1)
+    }
+}
+
+/// 只读的实体列表迭代器，每个实体返回查询的结果
+pub struct QueryManyStrictIter<'a, 'w, Q: FetchComponents + 'static, F: FilterComponents + 'static, I> {
+    query: &'a Query<'w, Q, F>,
+    entities: I,
+}
+impl<'a, 'w, Q: FetchComponents, F: FilterComponents, I: Iterator<Item = Entity>> Iterator
+    for QueryManyStrictIter<'a, 'w, Q, F, I>
+{
+    type Item = Result<<<Q as FetchComponents>::ReadOnly as FetchComponents>::Item<'a>, QueryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let e = self.entities.next()?;
+        Some(self.query.get(e))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entities.size_hint()
+    }
+}
+
+// 已访问的实体，少量时在数组中线性查找，超出后转为HashSet
+const SEEN_INLINE: usize = 16;
+struct SeenSet {
+    inline: [Entity; SEEN_INLINE],
+    len: usize,
+    set: HashSet<Entity>,
+}
+impl SeenSet {
+    fn new() -> Self {
+        Self {
+            inline: [Entity::null(); SEEN_INLINE],
+            len: 0,
+            set: HashSet::new(),
+        }
+    }
+    // 返回是否是新的实体
+    fn insert(&mut self, e: Entity) -> bool {
+        if self.set.is_empty() {
+            if self.inline[..self.len].contains(&e) {
+                return false;
+            }
+            if self.len < SEEN_INLINE {
+                self.inline[self.len] = e;
+                self.len += 1;
+                return true;
+            }
+            self.set.extend(self.inline.iter().copied());
+        }
+        self.set.insert(e)
+    }
+}
+
+/// 可写的实体列表借出迭代器
+pub struct QueryManyMut<'a, 'w, Q: FetchComponents + 'static, F: FilterComponents + 'static, I> {
+    query: &'a mut Query<'w, Q, F>,
+    entities: I,
+    seen: SeenSet,
+}
+impl<'a, 'w, Q: FetchComponents, F: FilterComponents, I: Iterator<Item = Entity>> QueryManyMut<'a, 'w, Q, F, I> {
+    /// 下一个实体的查询结果，列表结束返回None。重复出现的实体返回DuplicateEntity
+    pub fn fetch_next(&mut self) -> Option<Result<Q::Item<'_>, QueryError>> {
+        let e = self.entities.next()?;
+        if !self.seen.insert(e) {
+            return Some(Err(QueryError::DuplicateEntity(e)));
+        }
+        Some(self.query.get_mut(e))
+    }
+}
+
+impl<'w, Q: FetchComponents, F: FilterComponents> Query<'w, Q, F> {
+    /// 按entities的顺序迭代查询项，跳过不匹配和已销毁的实体
+    pub fn iter_many<'a, E: IntoIterator<Item = Entity>>(
+        &'a self,
+        entities: E,
+    ) -> QueryManyIter<'a, 'w, Q, F, E::IntoIter> {
+        QueryManyIter {
+            query: self,
+            entities: entities.into_iter(),
+        }
+    }
+    /// 按entities的顺序迭代，每个实体返回查询的结果
+    pub fn iter_many_strict<'a, E: IntoIterator<Item = Entity>>(
+        &'a self,
+        entities: E,
+    ) -> QueryManyStrictIter<'a, 'w, Q, F, E::IntoIter> {
+        QueryManyStrictIter {
+            query: self,
+            entities: entities.into_iter(),
+        }
+    }
+    /// 按entities的顺序可写地访问查询项，用fetch_next逐个获得
+    pub fn iter_many_mut<'a, E: IntoIterator<Item = Entity>>(
+        &'a mut self,
+        entities: E,
+    ) -> QueryManyMut<'a, 'w, Q, F, E::IntoIter> {
+        QueryManyMut {
+            query: self,
+            entities: entities.into_iter(),
+            seen: SeenSet::new(),
+        }
+    }
+}
@@ -0,0 +1,434 @@
+//! 列内存的原始导出
+//! 用于崩溃转储等外部工具，在尽量少执行代码的情况下抓取world的状态，不调用任何组件的序列化，没有serde的组件也能导出。
+//! 按原型依次写出布局描述（组件稳定id、大小、是否有tick、行数）、实体数组、每列的原始字节和tick数组，数值都是小端序。
+//! 只有用register_raw_pod声明为RawPod（没有填充字节，任意字节都是合法值）的组件导出字节，可以按偏移读取；
+//! 其他组件的字节可能有未初始化的填充，只导出布局，不导出字节，在清单中标记。
+//! 已被销毁的行，实体为空实体，组件字节为0。
+//! load_raw_readonly重建的是只读的查看视图，不是可运行的world。
+//! let manifest = world.dump_raw(&mut file)?; let view = World::load_raw_readonly(&mut file)?;
+//!
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::mem::size_of;
+use std::ptr;
+use std::slice;
+
+use pi_key_alloter::{Key, KeyData};
+use pi_null::Null;
+use pi_share::Share;
+
+use crate::archetype::{ArchetypeIndex, ComponentInfo, Row};
+use crate::world::{Entity, Tick, World};
+
+const MAGIC: &[u8; 8] = b"PIWRAW01";
+const FLAG_TICKS: u8 = 1;
+const FLAG_POD: u8 = 2;
+
+/// 可以按原始字节导出和读取的类型
+/// # Safety
+/// 实现者保证类型没有填充字节，并且任意位模式都是合法的值（不能是bool、枚举、引用等）
+pub unsafe trait RawPod: Copy + 'static {}
+
+macro_rules! impl_raw_pod {
+    ($($t: ty),*) => {
+        $(unsafe impl RawPod for $t {})*
+    };
+}
+impl_raw_pod!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+unsafe impl<T: RawPod, const N: usize> RawPod for [T; N] {}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+// FNV-1a
+fn fnv(mut hash: u64, bytes: &[u8]) -> u64 {
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// 导出数据中的一段，offset为相对导出开始的偏移
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DumpSection {
+    pub offset: u64,
+    pub len: u64,
+    pub checksum: u64, // 本段字节的FNV-1a
+}
+
+/// 一列的导出信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnManifest {
+    pub stable_id: u64,
+    pub type_name: Cow<'static, str>,
+    pub size: usize,
+    pub pod: bool, // 为false时字节是不透明的
+    pub data: DumpSection,
+    pub ticks: Option<DumpSection>, // 修改tick数组和添加tick数组
+}
+
+/// 一个原型的导出信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchetypeManifest {
+    pub index: ArchetypeIndex,
+    pub name: Cow<'static, str>,
+    pub rows: usize,
+    pub entities: DumpSection,
+    pub columns: Vec<ColumnManifest>,
+}
+
+/// 导出的清单
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DumpManifest {
+    pub archetypes: Vec<ArchetypeManifest>,
+    pub len: u64,      // 导出的总字节数
+    pub checksum: u64, // 全部字节的FNV-1a
+}
+
+// 记录位置和校验和的写入
+struct DumpWriter<'a> {
+    writer: &'a mut dyn Write,
+    pos: u64,
+    hash: u64,
+    section: (u64, u64), // 当前段的开始位置和校验和
+}
+impl<'a> DumpWriter<'a> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.pos += bytes.len() as u64;
+        self.hash = fnv(self.hash, bytes);
+        self.section.1 = fnv(self.section.1, bytes);
+        Ok(())
+    }
+    fn begin(&mut self) {
+        self.section = (self.pos, FNV_OFFSET);
+    }
+    fn end(&self) -> DumpSection {
+        DumpSection {
+            offset: self.section.0,
+            len: self.pos - self.section.0,
+            checksum: self.section.1,
+        }
+    }
+}
+
+impl World {
+    /// 声明组件为RawPod，dump_raw导出其字节
+    pub fn register_raw_pod<T: RawPod>(&mut self) {
+        let mut column = self.add_component_info(ComponentInfo::of::<T>(0)).1;
+        let info = unsafe { Share::get_mut_unchecked(&mut column) }.info_mut();
+        info.raw_pod = true;
+    }
+    /// 导出所有原型的原始列内存，返回清单
+    pub fn dump_raw(&self, writer: &mut dyn Write) -> io::Result<DumpManifest> {
+        let mut w = DumpWriter {
+            writer,
+            pos: 0,
+            hash: FNV_OFFSET,
+            section: (0, FNV_OFFSET),
+        };
+        let mut manifest = DumpManifest::default();
+        w.write(MAGIC)?;
+        w.write(&(self.archetype_arr.len() as u32).to_le_bytes())?;
+        for ar in self.archetype_arr.iter() {
+            let rows = ar.len().index();
+            let columns = ar.get_columns();
+            // 布局描述
+            w.write(&(ar.index().index() as u32).to_le_bytes())?;
+            w.write(&(rows as u32).to_le_bytes())?;
+            w.write(&(columns.len() as u32).to_le_bytes())?;
+            for c in columns.iter() {
+                let info = c.info();
+                let mut flags = 0;
+                if info.is_tick() {
+                    flags |= FLAG_TICKS;
+                }
+                if info.raw_pod {
+                    flags |= FLAG_POD;
+                }
+                w.write(&info.stable_id().to_le_bytes())?;
+                w.write(&info.mem_size.to_le_bytes())?;
+                w.write(&[flags])?;
+            }
+            // 实体数组
+            w.begin();
+            for row in 0..rows {
+                let e = ar.live_entity(Row(row as u32)).unwrap_or(Entity::null());
+                w.write(&e.data().as_ffi().to_le_bytes())?;
+            }
+            let entities = w.end();
+            let mut cms = Vec::with_capacity(columns.len());
+            for c in columns.iter() {
+                let info = c.info();
+                let size = info.size();
+                let blob = c.blob_ref_unchecked(ar.index());
+                let zero = vec![0u8; size];
+                w.begin();
+                // 只有声明了RawPod的组件没有填充字节，可以直接读取内存
+                if size > 0 && info.raw_pod {
+                    for row in 0..rows {
+                        let r = Row(row as u32);
+                        let p = blob.get_blob(r);
+                        if p.is_null() || ar.live_entity(r).is_none() {
+                            w.write(&zero)?;
+                        } else {
+                            w.write(unsafe { slice::from_raw_parts(p, size) })?;
+                        }
+                    }
+                }
+                let data = w.end();
+                let ticks = if info.is_tick() {
+                    w.begin();
+                    for row in 0..rows {
+                        w.write(&blob.get_tick_unchecked(Row(row as u32)).to_le_bytes())?;
+                    }
+                    for row in 0..rows {
+                        w.write(&blob.get_added_tick_unchecked(Row(row as u32)).to_le_bytes())?;
+                    }
+                    Some(w.end())
+                } else {
+                    None
+                };
+                cms.push(ColumnManifest {
+                    stable_id: info.stable_id(),
+                    type_name: info.type_name().clone(),
+                    size,
+                    pod: info.raw_pod,
+                    data,
+                    ticks,
+                });
+            }
+            manifest.archetypes.push(ArchetypeManifest {
+                index: ar.index(),
+                name: ar.name().clone(),
+                rows,
+                entities,
+                columns: cms,
+            });
+        }
+        w.writer.flush()?;
+        manifest.len = w.pos;
+        manifest.checksum = w.hash;
+        Ok(manifest)
+    }
+    /// 读取dump_raw导出的数据，重建只读的查看视图
+    pub fn load_raw_readonly(reader: &mut dyn Read) -> io::Result<RawWorldView> {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        let mut cur = Cursor { buf: &buf, pos: 0 };
+        if cur.bytes(MAGIC.len())? != MAGIC {
+            return Err(invalid("bad magic"));
+        }
+        let len = cur.u32()? as usize;
+        let mut archetypes = Vec::with_capacity(len);
+        let mut entities = HashMap::new();
+        for a in 0..len {
+            let index = ArchetypeIndex::from(cur.u32()? as usize);
+            let rows = cur.u32()? as usize;
+            let column_len = cur.u32()? as usize;
+            let mut layout = Vec::with_capacity(column_len);
+            for _ in 0..column_len {
+                let stable_id = cur.u64()?;
+                let size = cur.u32()? as usize;
+                let flags = cur.bytes(1)?[0];
+                layout.push((stable_id, size, flags));
+            }
+            let mut es = Vec::with_capacity(rows);
+            for row in 0..rows {
+                let e: Entity = KeyData::from_ffi(cur.u64()?).into();
+                if !e.is_null() {
+                    entities.insert(e, (a, row));
+                }
+                es.push(e);
+            }
+            let mut columns = Vec::with_capacity(column_len);
+            for (stable_id, size, flags) in layout {
+                let data = if flags & FLAG_POD != 0 {
+                    cur.bytes(rows * size)?.to_vec()
+                } else {
+                    Vec::new()
+                };
+                let (changed_ticks, added_ticks) = if flags & FLAG_TICKS != 0 {
+                    let changed = (0..rows).map(|_| cur.u32().map(|t| Tick::from(t as usize))).collect::<io::Result<Vec<_>>>()?;
+                    let added = (0..rows).map(|_| cur.u32().map(|t| Tick::from(t as usize))).collect::<io::Result<Vec<_>>>()?;
+                    (changed, added)
+                } else {
+                    (Vec::new(), Vec::new())
+                };
+                columns.push(RawColumn {
+                    stable_id,
+                    size,
+                    pod: flags & FLAG_POD != 0,
+                    data,
+                    changed_ticks,
+                    added_ticks,
+                });
+            }
+            archetypes.push(RawArchetype {
+                index,
+                entities: es,
+                columns,
+            });
+        }
+        if cur.pos != buf.len() {
+            return Err(invalid("trailing bytes"));
+        }
+        Ok(RawWorldView {
+            archetypes,
+            entities,
+            len: buf.len() as u64,
+            checksum: fnv(FNV_OFFSET, &buf),
+        })
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("raw dump: {}", msg))
+}
+
+// 读取导出数据
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+impl<'a> Cursor<'a> {
+    fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.buf.len() - self.pos < len {
+            return Err(invalid("unexpected end"));
+        }
+        let r = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(r)
+    }
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+/// 导出的一列
+#[derive(Debug, Clone)]
+pub struct RawColumn {
+    stable_id: u64,
+    size: usize,
+    pod: bool,
+    data: Vec<u8>,
+    changed_ticks: Vec<Tick>,
+    added_ticks: Vec<Tick>,
+}
+impl RawColumn {
+    pub fn stable_id(&self) -> u64 {
+        self.stable_id
+    }
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    pub fn is_pod(&self) -> bool {
+        self.pod
+    }
+    pub fn has_ticks(&self) -> bool {
+        !self.changed_ticks.is_empty() || !self.added_ticks.is_empty()
+    }
+    /// 指定行的原始字节，不是RawPod的组件没有导出字节，返回None
+    pub fn bytes(&self, row: usize) -> Option<&[u8]> {
+        self.data.get(row * self.size..(row + 1) * self.size)
+    }
+    pub fn changed_tick(&self, row: usize) -> Option<Tick> {
+        self.changed_ticks.get(row).copied()
+    }
+    pub fn added_tick(&self, row: usize) -> Option<Tick> {
+        self.added_ticks.get(row).copied()
+    }
+}
+
+/// 导出的一个原型
+#[derive(Debug, Clone)]
+pub struct RawArchetype {
+    index: ArchetypeIndex,
+    entities: Vec<Entity>, // 已销毁的行为空实体
+    columns: Vec<RawColumn>,
+}
+impl RawArchetype {
+    pub fn index(&self) -> ArchetypeIndex {
+        self.index
+    }
+    /// 原型的行数，包括已销毁的行
+    pub fn rows(&self) -> usize {
+        self.entities.len()
+    }
+    /// 遍历存活的实体及其行
+    pub fn entities(&self) -> impl Iterator<Item = (usize, Entity)> + '_ {
+        self.entities
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| !e.is_null())
+            .map(|(row, e)| (row, *e))
+    }
+    pub fn columns(&self) -> &[RawColumn] {
+        &self.columns
+    }
+    pub fn column(&self, stable_id: u64) -> Option<&RawColumn> {
+        self.columns.iter().find(|c| c.stable_id == stable_id)
+    }
+}
+
+/// 原始导出的只读查看视图
+#[derive(Debug, Clone)]
+pub struct RawWorldView {
+    archetypes: Vec<RawArchetype>,
+    entities: HashMap<Entity, (usize, usize)>, // 实体 -> (原型位置, 行)
+    len: u64,
+    checksum: u64,
+}
+impl RawWorldView {
+    pub fn archetypes(&self) -> &[RawArchetype] {
+        &self.archetypes
+    }
+    /// 存活的实体数量
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+    /// 遍历存活的实体，按原型和行的顺序
+    pub fn entities(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.archetypes.iter().flat_map(|ar| ar.entities().map(|(_, e)| e))
+    }
+    pub fn contains(&self, e: Entity) -> bool {
+        self.entities.contains_key(&e)
+    }
+    /// 实体所在的原型及行
+    pub fn location(&self, e: Entity) -> Option<(&RawArchetype, usize)> {
+        let (a, row) = *self.entities.get(&e)?;
+        Some((&self.archetypes[a], row))
+    }
+    /// 实体组件的原始字节
+    pub fn get_bytes(&self, e: Entity, stable_id: u64) -> Option<&[u8]> {
+        let (ar, row) = self.location(e)?;
+        ar.column(stable_id)?.bytes(row)
+    }
+    /// 按位读取实体的RawPod组件，组件不是RawPod或大小不符时返回None
+    pub fn get_pod<T: RawPod>(&self, e: Entity, stable_id: u64) -> Option<T> {
+        let (ar, row) = self.location(e)?;
+        let c = ar.column(stable_id)?;
+        if !c.pod || c.size != size_of::<T>() {
+            return None;
+        }
+        let bytes = c.bytes(row)?;
+        Some(unsafe { ptr::read_unaligned(bytes.as_ptr() as *const T) })
+    }
+    /// 读取的总字节数
+    pub fn byte_len(&self) -> u64 {
+        self.len
+    }
+    /// 全部字节的FNV-1a，和导出清单的checksum一致
+    pub fn checksum(&self) -> u64 {
+        self.checksum
+    }
+}
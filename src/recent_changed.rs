@@ -0,0 +1,80 @@
+//! 按修改的先后，优先处理最近修改的实体
+//! LOD更新、网络同步优先级等system每帧只处理最近修改的N个实体，不需要遍历全部再按tick排序。
+//! 修改事件列表大致按修改的顺序记录，从列表末尾向前读取未读的事件，去重后最多返回limit个查询项。
+//! 迭代开始时，监听器的读取位置就推进到末尾，没有处理到的较早的事件视为已确认，下一帧不会再报告，
+//! 这是按优先级处理的代价，调用者用skipped检查被跳过的事件数，持续大于0时说明处理不过来。
+//! for (e, item) in q.iter_changed_recent(&changed, 3) { ... }
+//!
+
+use std::collections::HashSet;
+use std::ops::Range;
+
+use crate::event::{ComponentChanged, ComponentEventVec};
+use crate::fetch::FetchComponents;
+use crate::filter::FilterComponents;
+use crate::query::Query;
+use crate::world::Entity;
+
+/// 最近修改的实体的迭代器，从最近的修改开始
+pub struct RecentChangedIter<'a, 'w, Q: FetchComponents + 'static, F: FilterComponents + 'static> {
+    query: &'a Query<'w, Q, F>,
+    record: &'a ComponentEventVec,
+    unread: Range<usize>, // 还未读取的事件，从末尾向前读取
+    limit: usize,
+    seen: HashSet<Entity>,
+}
+impl<'a, 'w, Q: FetchComponents, F: FilterComponents> RecentChangedIter<'a, 'w, Q, F> {
+    /// 没有读取到的较早的事件数量（包括重复的实体），这些事件已被确认，不会再报告
+    pub fn skipped(&self) -> usize {
+        self.unread.len()
+    }
+    /// 已返回的数量
+    pub fn yielded(&self) -> usize {
+        self.seen.len()
+    }
+}
+impl<'a, 'w, Q: FetchComponents, F: FilterComponents> Iterator for RecentChangedIter<'a, 'w, Q, F> {
+    type Item = (
+        Entity,
+        <<Q as FetchComponents>::ReadOnly as FetchComponents>::Item<'a>,
+    );
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.seen.len() < self.limit {
+            let index = self.unread.next_back()?;
+            let e = match self.record.get(index) {
+                Some(e) => *e,
+                None => continue,
+            };
+            if self.seen.contains(&e) {
+                continue;
+            }
+            // 已销毁或不匹配查询的实体不计入limit
+            if let Ok(item) = self.query.get(e) {
+                self.seen.insert(e);
+                return Some((e, item));
+            }
+        }
+        None
+    }
+}
+
+impl<'w, Q: FetchComponents, F: FilterComponents> Query<'w, Q, F> {
+    /// 按修改的先后，返回最近修改的最多limit个实体的查询项，changed的读取位置推进到末尾
+    pub fn iter_changed_recent<'a, T: 'static>(
+        &'a self,
+        changed: &'a ComponentChanged<'_, T>,
+        limit: usize,
+    ) -> RecentChangedIter<'a, 'w, Q, F> {
+        changed
+            .record
+            .set_read_tick(changed.listener_index, changed.tick);
+        RecentChangedIter {
+            query: self,
+            record: changed.record,
+            unread: changed.record.take_unread(changed.listener_index),
+            limit,
+            seen: HashSet::with_capacity(limit.min(64)),
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! 实体引用的完整性
+//! 组件中保存的Entity（比如AI的目标、挂接的父节点）在目标销毁后会悬空。
+//! 注册了引用策略的组件，由反向索引记录每个目标被哪些实体引用，整理时根据本帧销毁的实体，只访问引用了它们的实体，按策略修正。
+//! 反向索引由组件的添加和修改事件增量维护，所以用bypass_change_detection等方式绕过修改记录写入的引用不会被跟踪。
+//!
+
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::mem::take;
+
+use pi_append_vec::SafeVec;
+use pi_null::Null;
+use pi_share::Share;
+
+use crate::column::Column;
+use crate::event::{ComponentAdded, ComponentChanged, ComponentEventVec};
+use crate::merge::EntityMapper;
+use crate::system::{SystemMeta, TypeInfo};
+use crate::system_params::SystemParam;
+use crate::world::{Entity, World};
+
+/// 组件中引用的实体，f返回映射后的实体
+pub trait MapEntities {
+    fn map_entities(&mut self, f: &mut dyn FnMut(Entity) -> Entity);
+}
+
+/// 引用的目标被销毁时的处理策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferencePolicy {
+    /// 将引用设为Entity::null()，并记录组件的修改
+    Nullify,
+    /// 移除持有者上的该组件
+    RemoveComponent,
+    /// 销毁持有者，持有者被其他实体引用时会继续处理
+    DespawnHolder,
+}
+
+/// 上次整理时引用修正的统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReferenceStats {
+    pub holders_visited: usize,    // 访问的持有者数量
+    pub references_fixed: usize,   // 修正的引用数量
+    pub components_removed: usize, // 移除的组件数量
+    pub holders_despawned: usize,  // 销毁的持有者数量
+}
+
+// 修改组件中引用的实体
+pub(crate) type MapFn = fn(*mut u8, &mut dyn FnMut(Entity) -> Entity);
+
+struct Registration {
+    column: Share<Column>,
+    policy: ReferencePolicy,
+    changed: (Share<ComponentEventVec>, usize),
+    added: (Share<ComponentEventVec>, usize),
+    map: MapFn,
+}
+
+#[derive(Default)]
+pub(crate) struct References {
+    regs: Vec<Registration>,
+    forward: HashMap<(Entity, usize), Vec<(Entity, usize)>>, // (持有者, 注册的位置) -> (引用的目标, 在目标的反向索引中的位置)
+    reverse: HashMap<Entity, Vec<(Entity, usize)>>, // 目标 -> (持有者, 注册的位置)
+    destroyed: SafeVec<Entity>,                     // 上次整理后销毁的实体
+    stats: ReferenceStats,
+}
+impl References {
+    #[inline(always)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.regs.is_empty()
+    }
+    // 记录销毁的实体，没有注册引用策略时不记录
+    #[inline(always)]
+    pub(crate) fn record_destroyed(&self, e: Entity) {
+        if !self.regs.is_empty() {
+            self.destroyed.insert(e);
+        }
+    }
+    // 注册了引用策略的组件的映射函数
+    pub(crate) fn map_fn(&self, type_id: &TypeId) -> Option<MapFn> {
+        self.regs
+            .iter()
+            .find(|reg| reg.column.info().type_id() == type_id)
+            .map(|reg| reg.map)
+    }
+    // 读取持有者当前引用的目标
+    fn targets(&self, world: &World, holder: Entity, index: usize) -> Vec<Entity> {
+        let mut vec = Vec::new();
+        let addr = match world.entities.get(holder) {
+            Some(addr) if !addr.row.is_null() => *addr,
+            _ => return vec,
+        };
+        let reg = &self.regs[index];
+        if let Some(c) = reg.column.blob_ref(addr.archetype_index()) {
+            (reg.map)(c.get_row(addr.row, holder), &mut |e| {
+                if !e.is_null() {
+                    vec.push(e);
+                }
+                e
+            });
+        }
+        vec
+    }
+    // 移除持有者的索引，按记录的位置从反向索引中交换移除
+    fn unindex(&mut self, holder: Entity, index: usize) {
+        let targets = match self.forward.remove(&(holder, index)) {
+            Some(targets) => targets,
+            None => return,
+        };
+        for (t, pos) in targets {
+            let vec = match self.reverse.get_mut(&t) {
+                Some(vec) if vec.get(pos) == Some(&(holder, index)) => vec,
+                _ => continue,
+            };
+            vec.swap_remove(pos);
+            if vec.is_empty() {
+                self.reverse.remove(&t);
+            } else if let Some(&moved) = vec.get(pos) {
+                // 最后一个持有者被移到了pos，修改它记录的位置
+                if let Some(ts) = self.forward.get_mut(&moved) {
+                    for r in ts.iter_mut().filter(|r| r.0 == t) {
+                        r.1 = pos;
+                    }
+                }
+            }
+        }
+    }
+    // 重新建立持有者的索引
+    fn reindex(&mut self, world: &World, holder: Entity, index: usize) {
+        self.unindex(holder, index);
+        let mut targets = self.targets(world, holder, index);
+        if targets.is_empty() {
+            return;
+        }
+        targets.sort_unstable();
+        targets.dedup();
+        let mut vec = Vec::with_capacity(targets.len());
+        for t in targets {
+            let holders = self.reverse.entry(t).or_default();
+            vec.push((t, holders.len()));
+            holders.push((holder, index));
+        }
+        self.forward.insert((holder, index), vec);
+    }
+    // 根据组件的添加和修改事件更新索引
+    fn update(&mut self, world: &World) {
+        let mut holders = Vec::new();
+        for (i, reg) in self.regs.iter().enumerate() {
+            for e in reg.added.0.get_iter(reg.added.1) {
+                holders.push((*e, i));
+            }
+            for e in reg.changed.0.get_iter(reg.changed.1) {
+                holders.push((*e, i));
+            }
+        }
+        for (e, i) in holders {
+            self.reindex(world, e, i);
+        }
+    }
+}
+
+impl World {
+    /// 注册组件的引用策略，组件中引用的实体被销毁后，在整理时按策略修正。重复注册则修改策略
+    pub fn register_reference_policy<T: MapEntities + 'static>(&mut self, policy: ReferencePolicy) {
+        let mut meta = SystemMeta::new(TypeInfo::of::<ReferencePolicy>());
+        let changed = ComponentChanged::<T>::init_state(self, &mut meta);
+        let added = ComponentAdded::<T>::init_state(self, &mut meta);
+        let column = self.get_column_by_id(&TypeId::of::<T>()).unwrap().clone();
+        for reg in self.references.regs.iter_mut() {
+            if reg.column.info().index == column.info().index {
+                reg.policy = policy;
+                return;
+            }
+        }
+        // 已有的实体直接建立索引
+        changed.0.mark_read(changed.1);
+        added.0.mark_read(added.1);
+        let mut holders = Vec::new();
+        for ar in self.archetype_arr.iter() {
+            if !column.contains(ar.index()) {
+                continue;
+            }
+            for row in 0..ar.len().index() {
+                if let Some(e) = ar.live_entity(row.into()) {
+                    holders.push(e);
+                }
+            }
+        }
+        let mut refs = take(&mut self.references);
+        let index = refs.regs.len();
+        refs.regs.push(Registration {
+            column,
+            policy,
+            changed,
+            added,
+            map: |ptr, f| unsafe { &mut *(ptr as *mut T) }.map_entities(f),
+        });
+        for e in holders {
+            refs.reindex(self, e, index);
+        }
+        self.references = refs;
+    }
+    // 修正注册了引用策略的组件中引用的实体，本帧写入的引用还没有索引，所以访问所有持有者
+    pub(crate) fn remap_references(&self, mapper: &EntityMapper) {
+        for reg in self.references.regs.iter() {
+            for ar in self.archetype_arr.iter() {
+                let c = match reg.column.blob_ref(ar.index()) {
+                    Some(c) => c,
+                    None => continue,
+                };
+                for row in 0..ar.len().index() {
+                    if let Some(e) = ar.live_entity(row.into()) {
+                        (reg.map)(c.get_row(row.into(), e), &mut |t| mapper.map(t));
+                    }
+                }
+            }
+        }
+    }
+    /// 上次整理时引用修正的统计
+    pub fn reference_stats(&self) -> ReferenceStats {
+        self.references.stats
+    }
+    // 整理时修正引用了已销毁实体的持有者
+    pub(crate) fn sweep_references(&mut self) {
+        let mut refs = take(&mut self.references);
+        refs.stats = ReferenceStats::default();
+        refs.update(self);
+        let mut destroyed: Vec<Entity> = refs.destroyed.iter().map(|e| *e).collect();
+        refs.destroyed.clear(0);
+        while let Some(t) = destroyed.pop() {
+            // 销毁的持有者不再引用其他实体
+            for i in 0..refs.regs.len() {
+                refs.unindex(t, i);
+            }
+            let holders = match refs.reverse.remove(&t) {
+                Some(holders) => holders,
+                None => continue,
+            };
+            for (holder, i) in holders {
+                if !self.entities.contains_key(holder) {
+                    continue;
+                }
+                refs.stats.holders_visited += 1;
+                match refs.regs[i].policy {
+                    ReferencePolicy::Nullify => {
+                        let addr = *self.entities.get(holder).unwrap();
+                        let reg = &refs.regs[i];
+                        let c = match reg.column.blob_ref(addr.archetype_index()) {
+                            Some(c) => c,
+                            None => continue,
+                        };
+                        let mut fixed = 0;
+                        (reg.map)(c.get_row(addr.row, holder), &mut |e| {
+                            if e == t {
+                                fixed += 1;
+                                Entity::null()
+                            } else {
+                                e
+                            }
+                        });
+                        c.changed_tick(holder, addr.row, self.tick());
+                        refs.stats.references_fixed += fixed;
+                        if let Some(vec) = refs.forward.get_mut(&(holder, i)) {
+                            vec.retain(|r| r.0 != t);
+                        }
+                    }
+                    ReferencePolicy::RemoveComponent => {
+                        refs.unindex(holder, i);
+                        let index = refs.regs[i].column.info().index;
+                        if self
+                            .make_entity_editor()
+                            .remove_components_by_index(holder, &[index])
+                            .is_ok()
+                        {
+                            refs.stats.references_fixed += 1;
+                            refs.stats.components_removed += 1;
+                        }
+                    }
+                    ReferencePolicy::DespawnHolder => {
+                        if self.destroy_entity(holder).is_ok() {
+                            refs.stats.references_fixed += 1;
+                            refs.stats.holders_despawned += 1;
+                            // 继续处理引用了该持有者的实体
+                            destroyed.push(holder);
+                        }
+                    }
+                }
+            }
+        }
+        self.references = refs;
+    }
+}
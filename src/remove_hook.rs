@@ -0,0 +1,86 @@
+//! 组件移除的钩子
+//! 组件的Drop看不到同一实体的其他组件，比如PhysicsBody移除时要读取同一实体上的PhysicsWorldHandle才能注销自己。
+//! 销毁实体和Alter移除组件时，先对所有被移除的组件调用钩子，再释放组件，钩子中能读取该实体上所有组件的当前值，
+//! 包括同一次alter中一起被移除的组件。被移除的组件有多个时，钩子按原型的列顺序（组件索引递增）调用。
+//! 钩子在执行移除的system的线程上调用，只能读取，不能修改world。延迟释放的组件同样先调用钩子。
+//! world.on_remove::<PhysicsBody>(|ctx| { let h = ctx.get::<PhysicsWorldHandle>(); ... });
+//!
+
+use std::any::TypeId;
+
+use pi_share::Share;
+
+use crate::archetype::{Archetype, Row};
+use crate::column::Column;
+use crate::world::{ComponentIndex, Entity, World};
+
+/// 组件移除的钩子
+pub type RemoveHook = Share<dyn Fn(&RemovalContext<'_>) + Send + Sync>;
+
+/// 钩子的上下文，可以读取实体上还未释放的组件
+pub struct RemovalContext<'a> {
+    world: &'a World,
+    ar: &'a Archetype,
+    row: Row,
+    e: Entity,
+    removed: ComponentIndex,
+}
+impl<'a> RemovalContext<'a> {
+    /// 被移除组件的实体
+    pub fn entity(&self) -> Entity {
+        self.e
+    }
+    /// 被移除的组件
+    pub fn removed(&self) -> ComponentIndex {
+        self.removed
+    }
+    pub fn world(&self) -> &'a World {
+        self.world
+    }
+    /// 读取实体上的组件T，包括正在被移除的组件
+    pub fn get<T: 'static>(&self) -> Option<&'a T> {
+        let index = self.world.get_component_index(&TypeId::of::<T>());
+        self.get_by_index(index)
+    }
+    pub fn get_by_index<T: 'static>(&self, index: ComponentIndex) -> Option<&'a T> {
+        let c = self.ar.get_column(index)?;
+        let blob = c.blob_ref(self.ar.index())?;
+        Some(blob.get::<T>(self.row, self.e))
+    }
+}
+
+impl World {
+    /// 设置组件T被移除时的钩子，替换之前的钩子
+    pub fn on_remove<T: 'static>(&mut self, hook: impl Fn(&RemovalContext<'_>) + Send + Sync + 'static) {
+        let index = self.init_component::<T>();
+        let column = unsafe { self.component_arr.get_unchecked_mut(index.index()) };
+        let c = unsafe { Share::get_mut_unchecked(column) };
+        c.info.on_remove = Some(Share::new(hook));
+    }
+    // 释放行之前，按列顺序调用被移除组件的钩子
+    pub(crate) fn run_remove_hooks<'a>(
+        &self,
+        ar: &Archetype,
+        row: Row,
+        e: Entity,
+        removed: impl Iterator<Item = &'a Share<Column>>,
+    ) {
+        for c in removed {
+            if let Some(hook) = &c.info.on_remove {
+                hook(&RemovalContext {
+                    world: self,
+                    ar,
+                    row,
+                    e,
+                    removed: c.info().index,
+                });
+            }
+        }
+    }
+    // 销毁行之前，调用该行所有组件的钩子
+    pub(crate) fn run_destroy_hooks(&self, ar: &Archetype, row: Row) {
+        if let Some(e) = ar.live_entity(row) {
+            self.run_remove_hooks(ar, row, e, ar.get_columns().iter());
+        }
+    }
+}
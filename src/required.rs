@@ -0,0 +1,167 @@
+//! 组件的依赖
+//! 注册World::register_required_components::<Mesh, (Aabb, Visibility)>()后，Bundle插入和Alter添加Mesh时，
+//! 目标原型会自动补上缺少的Aabb和Visibility（传递展开），并用默认值初始化，记录添加tick。
+//! Bundle或Alter中显式提供的依赖组件使用提供的值。
+//! 依赖只在添加时生效：移除Mesh不会移除它的依赖，同一次Alter中被移除的依赖也不会被补上。
+//! 插入和Alter时只有&World，所以依赖组件必须实现Default。
+//!
+
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::ptr;
+
+use pi_proc_macros::all_tuples;
+use pi_share::Share;
+
+use crate::archetype::{Archetype, ComponentInfo};
+use crate::column::Column;
+use crate::world::{ComponentIndex, World};
+
+/// 初始化依赖组件的函数
+pub type DefaultFn = fn(*mut u8);
+
+/// 依赖的组件列表，为元组，每个组件都必须实现Default
+pub trait RequiredComponents {
+    fn components(world: &mut World, vec: &mut Vec<(ComponentIndex, DefaultFn)>);
+}
+
+macro_rules! impl_tuple_required {
+    ($($name: ident),*) => {
+        impl<$($name: Default + 'static),*> RequiredComponents for ($($name,)*) {
+            fn components(_world: &mut World, _vec: &mut Vec<(ComponentIndex, DefaultFn)>) {
+                $(
+                    _vec.push((
+                        _world.init_component::<$name>(),
+                        |ptr| unsafe { ptr::write(ptr as *mut $name, $name::default()) },
+                    ));
+                )*
+            }
+        }
+    };
+}
+all_tuples!(impl_tuple_required, 1, 16, R);
+
+#[derive(Default)]
+pub(crate) struct Requirements {
+    map: HashMap<ComponentIndex, Vec<ComponentIndex>>, // 组件 -> 直接依赖的组件
+    defaults: HashMap<ComponentIndex, DefaultFn>,      // 依赖组件的初始化函数
+}
+impl Requirements {
+    #[inline(always)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+    #[inline(always)]
+    pub(crate) fn default_fn(&self, index: ComponentIndex) -> Option<DefaultFn> {
+        self.defaults.get(&index).copied()
+    }
+    // 展开sources的依赖（传递），返回present中没有的依赖，present中已有的组件不再展开
+    pub(crate) fn missing(
+        &self,
+        sources: &[ComponentIndex],
+        present: &[ComponentIndex],
+    ) -> Vec<ComponentIndex> {
+        let mut result = Vec::new();
+        let mut stack = sources.to_vec();
+        while let Some(i) = stack.pop() {
+            if let Some(vec) = self.map.get(&i) {
+                for r in vec {
+                    if present.contains(r) || result.contains(r) {
+                        continue;
+                    }
+                    result.push(*r);
+                    stack.push(*r);
+                }
+            }
+        }
+        result
+    }
+    // 从from出发，沿依赖查找to，返回依赖路径
+    fn path(&self, from: ComponentIndex, to: ComponentIndex) -> Option<Vec<ComponentIndex>> {
+        let mut stack = vec![vec![from]];
+        let mut visited = vec![from];
+        while let Some(path) = stack.pop() {
+            let last = *path.last().unwrap();
+            if let Some(vec) = self.map.get(&last) {
+                for r in vec {
+                    let mut p = path.clone();
+                    p.push(*r);
+                    if *r == to {
+                        return Some(p);
+                    }
+                    if !visited.contains(r) {
+                        visited.push(*r);
+                        stack.push(p);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+impl World {
+    /// 注册组件T的依赖组件，T被插入或添加时，自动补上缺少的依赖组件，重复注册则追加依赖。
+    /// 依赖有环时panic，需要在插入T的Insert和Alter初始化前注册
+    pub fn register_required_components<T: 'static, R: RequiredComponents>(&mut self) {
+        let index = self.init_component::<T>();
+        let mut vec = Vec::new();
+        R::components(self, &mut vec);
+        for (r, f) in vec {
+            self.required.defaults.insert(r, f);
+            let reqs = self.required.map.entry(index).or_default();
+            if !reqs.contains(&r) {
+                reqs.push(r);
+            }
+        }
+        if let Some(path) = self.required.path(index, index) {
+            let names: Vec<_> = path
+                .iter()
+                .map(|i| self.get_column(*i).unwrap().info().type_name().clone())
+                .collect();
+            panic!("required components cycle: {}", names.join(" -> "));
+        }
+    }
+    /// 组件T直接依赖的组件
+    pub fn required_components<T: 'static>(&self) -> Vec<ComponentIndex> {
+        let index = match self.component_map.get(&TypeId::of::<T>()) {
+            Some(index) => *index,
+            None => return Vec::new(),
+        };
+        self.required.map.get(&index).cloned().unwrap_or_default()
+    }
+    // 给插入的组件列表补上缺少的依赖组件
+    pub(crate) fn add_required_infos(&self, mut infos: Vec<ComponentInfo>) -> Vec<ComponentInfo> {
+        if self.required.is_empty() {
+            return infos;
+        }
+        let present: Vec<ComponentIndex> = infos
+            .iter()
+            .filter_map(|info| self.component_map.get(info.type_id()).copied())
+            .collect();
+        for r in self.required.missing(&present, &present) {
+            infos.push(self.get_column(r).unwrap().info().clone());
+        }
+        infos
+    }
+    // 原型中provided之外的依赖组件，插入时用默认值初始化
+    pub(crate) fn required_defaults(
+        &self,
+        ar: &Archetype,
+        provided: impl Fn(&ComponentInfo) -> bool,
+    ) -> Vec<(Share<Column>, DefaultFn)> {
+        let mut vec = Vec::new();
+        if self.required.is_empty() {
+            return vec;
+        }
+        for c in ar.get_columns().iter() {
+            if provided(c.info()) {
+                continue;
+            }
+            if let Some(f) = self.required.default_fn(c.info().index) {
+                vec.push((c.clone(), f));
+            }
+        }
+        vec
+    }
+}
@@ -151,6 +151,8 @@ impl Schedule {
         for stage in self.stage_sort.iter() {
             if let Some(stage) = g.get_mut(stage) {
                 Self::run_graph(world, rt, stage, &self.systems, &self.set_conditions);
+                // 阶段之间独占world，添加本阶段延迟添加的组件
+                world.flush_deferred_adds();
             }
         }
 
@@ -206,6 +208,7 @@ impl Schedule {
         for stage in self.stage_sort.iter() {
             if let Some(stage) = g.get_mut(stage) {
                 Self::async_run_graph(world, rt, stage, &mut self.systems, &mut self.set_conditions).await;
+                world.flush_deferred_adds();
             }
         }
 
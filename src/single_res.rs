@@ -60,6 +60,11 @@ impl<T: 'static> DerefMut for TickRes<T> {
         &mut self.res
     }
 }
+/// 单例资源的简称，和SingleRes相同
+pub type Res<'w, T> = SingleRes<'w, T>;
+/// 可写单例资源的简称，和SingleResMut相同
+pub type ResMut<'w, T> = SingleResMut<'w, T>;
+
 #[derive(Debug)]
 pub struct SingleRes<'w, T: 'static> {
     pub(crate) value: &'w TickRes<T>,
@@ -86,6 +91,7 @@ impl<T: 'static + Send + Sync> SystemParam for SingleRes<'_, T> {
     type Item<'w> = SingleRes<'w, T>;
 
     fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        meta.require_single_res(TypeInfo::of::<T>());
         init_read_state(world, meta)
     }
     fn get_param<'world>(
@@ -98,7 +104,7 @@ impl<T: 'static + Send + Sync> SystemParam for SingleRes<'_, T> {
             init_opt_state(world, &mut state.0, state.1);
         }
         let r = state.0.as_ref().unwrap();
-        let last_run = replace(&mut state.2, tick);
+        let last_run = advance_last_run(world, &mut state.2, tick);
         SingleRes::new(&r, last_run)
     }
 
@@ -144,6 +150,7 @@ impl<T: 'static> SystemParam for SingleResMut<'_, T> {
     type Item<'w> = SingleResMut<'w, T>;
 
     fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        meta.require_single_res(TypeInfo::of::<T>());
         init_write_state(world, meta)
     }
 
@@ -204,7 +211,7 @@ impl<T: 'static> SystemParam for Option<SingleRes<'_, T>> {
             state.0 = Some(Share::downcast::<TickRes<T>>(s.into_any()).unwrap());
         }
         if let Some(r) = &state.0 {
-            let last_run = replace(&mut state.2, tick);
+            let last_run = advance_last_run(world, &mut state.2, tick);
             Some(SingleRes::new(r.as_any().downcast_ref().unwrap(), last_run))
         } else {
             None
@@ -298,6 +305,13 @@ fn init_write_state<T: 'static>(
     }
 }
 
+// 返回上次运行的tick，并记录本次的tick，tick冻结时不推进
+pub(crate) fn advance_last_run(world: &World, last_run: &mut Tick, tick: Tick) -> Tick {
+    if world.is_ticks_frozen() {
+        return *last_run;
+    }
+    replace(last_run, tick)
+}
 fn init_opt_state<T: 'static>(world: &World, state: &mut Option<Share<TickRes<T>>>, index: usize) {
     log::error!("t==============={:?}", std::any::type_name::<T>());
     let s = world.index_single_res_any(index).unwrap().clone();
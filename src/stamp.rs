@@ -0,0 +1,175 @@
+//! 预制体实例化
+//! 以一个实体为预制体，批量创建count个实例，每个实例可以覆盖部分组件的值。
+//! 实例和预制体在同一原型上，只查找一次原型，行连续分配，逐列复制后再逐列设置添加tick，Added可以看到全部实例。
+//! 组件的复制：注册了复制函数（register_clone）的组件调用复制函数；没有复制函数且不需要释放的组件按位复制；
+//! 否则用默认值（Default或FromWorld），都没有则返回NotDefaultable，除非每个实例都覆盖了该组件。
+//! 覆盖的组件不在预制体的原型上时，在实例创建后用Alter添加。
+//! let es = world.stamp(prefab, 100, |i, w| w.set(Position(i as f32, 0.0)))?;
+//!
+
+use std::any::TypeId;
+
+use pi_null::Null;
+use pi_share::Share;
+
+use crate::archetype::{ComponentInfo, Row};
+use crate::column::Column;
+use crate::insert::Bundle;
+use crate::query::QueryError;
+use crate::world::{Entity, World};
+
+/// 复制组件，dst为未初始化的位置
+pub type CloneFn = fn(src: *const u8, dst: *mut u8);
+
+fn clone_value<T: Clone>(src: *const u8, dst: *mut u8) {
+    unsafe { (dst as *mut T).write((*(src as *const T)).clone()) }
+}
+
+type WriteFn = Box<dyn FnOnce(*mut u8)>;
+type AddFn = Box<dyn FnOnce(&mut World, Entity)>;
+
+/// 一个实例的组件覆盖
+pub struct OverrideWriter<'a> {
+    world: &'a World,
+    columns: &'a [Share<Column>],
+    writes: Vec<(usize, WriteFn)>, // 预制体原型上的列位置和写入函数
+    adds: Vec<(TypeId, AddFn)>,    // 不在原型上，创建后添加的组件
+}
+impl<'a> OverrideWriter<'a> {
+    /// 设置实例的组件值，同一组件多次设置时保留最后的值
+    pub fn set<T: Bundle + 'static>(&mut self, value: T) {
+        let tid = TypeId::of::<T>();
+        let pos = self
+            .world
+            .component_map
+            .get(&tid)
+            .and_then(|index| self.columns.iter().position(|c| c.info().index == *index));
+        match pos {
+            Some(pos) => {
+                self.writes.retain(|(p, _)| *p != pos);
+                self.writes.push((
+                    pos,
+                    Box::new(move |dst| unsafe { (dst as *mut T).write(value) }),
+                ));
+            }
+            None => {
+                self.adds.retain(|(t, _)| *t != tid);
+                self.adds.push((
+                    tid,
+                    Box::new(move |world, e| {
+                        let _ = world.make_entity_editor().add_components(e, value);
+                    }),
+                ));
+            }
+        }
+    }
+}
+
+impl World {
+    /// 注册组件的复制函数，stamp时用于复制预制体的组件
+    pub fn register_clone<T: Clone + 'static>(&mut self) {
+        let mut column = self.add_component_info(ComponentInfo::of::<T>(0)).1;
+        let info = unsafe { Share::get_mut_unchecked(&mut column) }.info_mut();
+        info.clone_fn = Some(clone_value::<T>);
+    }
+    /// 以prefab为预制体创建count个实例，overrides设置第i个实例覆盖的组件，返回创建的实例
+    pub fn stamp(
+        &mut self,
+        prefab: Entity,
+        count: usize,
+        overrides: impl Fn(usize, &mut OverrideWriter),
+    ) -> Result<Vec<Entity>, QueryError> {
+        let addr = match self.entities.get(prefab) {
+            Some(addr) => *addr,
+            None => return Err(QueryError::NoSuchEntity(prefab)),
+        };
+        let ar = if addr.row.is_null() {
+            self.empty_archetype.clone()
+        } else {
+            unsafe { self.archetype_arr.get_unchecked(addr.archetype_index().index()) }.clone()
+        };
+        let columns = ar.get_columns().clone();
+        let mut instances = Vec::with_capacity(count);
+        for i in 0..count {
+            let mut w = OverrideWriter {
+                world: self,
+                columns: &columns,
+                writes: Vec::new(),
+                adds: Vec::new(),
+            };
+            overrides(i, &mut w);
+            instances.push((w.writes, w.adds));
+        }
+        // 无法复制的组件，必须每个实例都覆盖
+        for (pos, c) in columns.iter().enumerate() {
+            let info = c.info();
+            if info.clone_fn.is_none()
+                && info.drop_fn.is_some()
+                && info.set_fn.is_none()
+                && !instances
+                    .iter()
+                    .all(|(writes, _)| writes.iter().any(|(p, _)| *p == pos))
+            {
+                return Err(QueryError::NotDefaultable(info.index, ar.index(), "World::stamp"));
+            }
+        }
+        let mut entities = Vec::with_capacity(count);
+        if addr.row.is_null() {
+            // 空实体的预制体
+            for _ in 0..count {
+                entities.push(self.spawn_empty());
+            }
+        } else {
+            let mut slots = Vec::with_capacity(count);
+            let mut start = Row::null();
+            for _ in 0..count {
+                let (r, row) = ar.alloc();
+                let row: Row = row.into();
+                if start.is_null() {
+                    start = row;
+                }
+                entities.push(self.insert_addr(ar.index(), row));
+                slots.push(r);
+            }
+            // 独占world，分配的行是连续的
+            for (pos, column) in columns.iter().enumerate() {
+                let c = column.blob_ref_unchecked(ar.index());
+                let info = column.info();
+                for (i, e) in entities.iter().enumerate() {
+                    let dst = c.load(Row(start.0 + i as u32), *e);
+                    let writes = &mut instances[i].0;
+                    if let Some(j) = writes.iter().position(|(p, _)| *p == pos) {
+                        (writes.swap_remove(j).1)(dst);
+                    } else if let Some(f) = info.clone_fn {
+                        f(c.get_row(addr.row, prefab), dst);
+                    } else if info.drop_fn.is_none() {
+                        if info.size() > 0 {
+                            let src = c.get_row(addr.row, prefab);
+                            unsafe { src.copy_to_nonoverlapping(dst, info.size()) };
+                        }
+                    } else {
+                        (info.set_fn.unwrap())(self, dst);
+                    }
+                }
+            }
+            let tick = self.tick();
+            for column in columns.iter() {
+                let c = column.blob_ref_unchecked(ar.index());
+                for (i, e) in entities.iter().enumerate() {
+                    c.added_tick(*e, Row(start.0 + i as u32), tick);
+                }
+            }
+            for (i, (r, e)) in slots.into_iter().zip(entities.iter()).enumerate() {
+                *r = *e;
+                ar.record_allocated(Row(start.0 + i as u32), *e);
+            }
+        }
+        // 不在预制体原型上的覆盖
+        for ((_, adds), e) in instances.into_iter().zip(entities.iter()) {
+            for (_, f) in adds {
+                f(self, *e);
+            }
+        }
+        Ok(entities)
+    }
+}
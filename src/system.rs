@@ -9,6 +9,8 @@ use std::{
     pin::Pin,
 };
 
+use fixedbitset::FixedBitSet;
+use pi_null::Null;
 use pi_share::Share;
 
 use crate::{
@@ -164,6 +166,32 @@ where
     node.result
 }
 
+// 关系表是否要求组件t必须存在。顶层和And节点有一项要求即可，Or节点需要每一项都要求
+fn requires<T: Eq>(vec: &Vec<Relation<T>>, t: &T, start: &mut usize, is_and: bool) -> bool {
+    let mut any = false;
+    let mut all = true;
+    let mut empty = true;
+    while *start < vec.len() {
+        let r = unsafe { vec.get_unchecked(*start) };
+        *start += 1;
+        if r.is_end() {
+            break;
+        }
+        let b = match r.node() {
+            Some(is_and) => requires(vec, t, start, is_and),
+            None => matches!(r, Relation::With(id) | Relation::Read(id) | Relation::Write(id) | Relation::ShareWrite(id) if id == t),
+        };
+        any |= b;
+        all &= b;
+        empty = false;
+    }
+    if is_and {
+        any
+    } else {
+        all && !empty
+    }
+}
+
 struct ArchetypeFilter<'a>(&'a Archetype);
 
 // 判断原型是否和该关系相关
@@ -182,6 +210,37 @@ fn archetype_relate<'a>(r: &Relation<ComponentIndex>, arg: &mut ArchetypeFilter<
 pub struct Related<T: Eq> {
     pub(crate) vec: Vec<Relation<T>>,
 }
+impl Related<ComponentIndex> {
+    // 读写的组件集合，冲突检查前用于快速排除不相交的关系表。有ReadAll或WriteAll时返回None，必须逐个检查
+    pub(crate) fn access_set(&self) -> Option<FixedBitSet> {
+        let mut set = FixedBitSet::new();
+        for r in self.vec.iter() {
+            match r {
+                Relation::Read(i)
+                | Relation::OptRead(i)
+                | Relation::Write(i)
+                | Relation::OptWrite(i)
+                | Relation::ShareWrite(i) => {
+                    if i.is_null() {
+                        return None;
+                    }
+                    set.grow(i.index() + 1);
+                    set.insert(i.index());
+                }
+                Relation::ReadAll | Relation::WriteAll => return None,
+                _ => (),
+            }
+        }
+        Some(set)
+    }
+}
+// 两个关系表读写的组件不相交，一定不冲突
+fn access_disjoint(a: &Option<FixedBitSet>, b: &Option<FixedBitSet>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.is_disjoint(b),
+        _ => false,
+    }
+}
 impl<T: Eq + Copy + Debug> Related<T> {
     pub fn new() -> Self {
         Self { vec: Vec::new() }
@@ -212,15 +271,7 @@ impl<T: Eq + Copy + Debug> Related<T> {
         for w in other.vec.iter() {
             match w {
                 Relation::Without(t) => {
-                    let mut t = *t;
-                    let mut start = 0;
-                    if traversal(
-                        &self.vec,
-                        &Relation::without,
-                        &mut t,
-                        &mut start,
-                        RelateNode::new(false),
-                    ) {
+                    if requires(&self.vec, t, &mut 0, true) {
                         return true;
                     }
                 }
@@ -325,6 +376,15 @@ pub fn relate(r: &Related<ComponentIndex>, archetype: &Archetype, mut start: usi
 pub fn depend(r: &Related<ComponentIndex>, archetype: &Archetype) {
     todo!()
 }
+// 顶层参数在各关系表中的起始位置
+#[derive(Debug, Clone)]
+pub(crate) struct ParamMark {
+    pub(crate) related: usize,  // 在vec中的起始位置
+    pub(crate) res: usize,      // 在res_related中的起始位置
+    pub(crate) required: usize, // 在required_res中的起始位置
+    pub(crate) type_name: &'static str,
+}
+
 /// The metadata of a [`System`].
 pub struct SystemMeta {
     pub(crate) type_info: TypeInfo,
@@ -335,6 +395,11 @@ pub struct SystemMeta {
 
     pub(crate) res_reads: HashMap<TypeId, Cow<'static, str>>, // 读Res
     pub(crate) res_writes: HashMap<TypeId, Cow<'static, str>>, // 写ResMut
+    pub(crate) align_snapshot: Option<usize>, // 本次运行对齐时的原型数量
+    pub(crate) param_depth: usize,            // 初始化时元组参数的嵌套深度
+    pub(crate) param_marks: Vec<ParamMark>,   // 顶层参数的起始位置，用于定位冲突的参数
+    pub(crate) res_names: HashMap<TypeId, Cow<'static, str>>, // 单例资源的类型名
+    pub(crate) required_res: Vec<TypeInfo>,   // 运行时必须存在的单例资源
 }
 
 impl SystemMeta {
@@ -348,8 +413,36 @@ impl SystemMeta {
 
             res_reads: Default::default(),
             res_writes: Default::default(),
+            align_snapshot: None,
+            param_depth: 0,
+            param_marks: Default::default(),
+            res_names: Default::default(),
+            required_res: Default::default(),
+        }
+    }
+    // 每次运行前记录原型数量，本次运行的所有参数都对齐到该数量
+    pub(crate) fn snapshot_archetypes(&mut self, world: &World) {
+        self.align_snapshot = Some(world.archetype_arr.len());
+    }
+    /// 参数对齐时使用的原型数量，没有快照时（直接使用参数）取当前的原型数量
+    pub fn align_len(&self, world: &World) -> usize {
+        match self.align_snapshot {
+            Some(len) => len,
+            None => world.archetype_arr.len(),
         }
     }
+    /// 是否只读，所有参数都没有声明组件和资源的写
+    pub fn is_read_only(&self) -> bool {
+        fn is_write<T: Eq>(r: &Relation<T>) -> bool {
+            matches!(
+                r,
+                Relation::Write(_) | Relation::ShareWrite(_) | Relation::OptWrite(_) | Relation::WriteAll
+            )
+        }
+        self.res_writes.is_empty()
+            && !self.res_related.vec.iter().any(is_write)
+            && !self.vec.iter().any(|r| r.vec.iter().any(is_write))
+    }
     /// Returns the system's type_id
     #[inline]
     pub fn type_id(&self) -> &TypeId {
@@ -384,6 +477,48 @@ impl SystemMeta {
         world.find_ar(components)
     }
 
+    /// 当前关系表的长度，用于标记参数关系的起始位置
+    pub fn related_len(&self) -> usize {
+        self.cur_related.vec.len()
+    }
+    /// 当前关系表从start开始，顶层必须存在的组件
+    pub fn required_since(&self, start: usize) -> Vec<ComponentIndex> {
+        let mut vec = Vec::new();
+        let mut depth = 0;
+        for r in self.cur_related.vec[start..].iter() {
+            match r {
+                Relation::Or | Relation::And => depth += 1,
+                Relation::End => depth -= 1,
+                Relation::With(i) | Relation::Read(i) | Relation::Write(i) | Relation::ShareWrite(i)
+                    if depth == 0 =>
+                {
+                    vec.push(*i)
+                }
+                _ => (),
+            }
+        }
+        vec
+    }
+    /// 将从start开始的Or节点改为任一项存在即匹配：节点内的读写改为With，可选读写移到节点后，用于冲突检查
+    pub fn any_of_related(&mut self, start: usize) {
+        let vec = self.cur_related.vec.split_off(start);
+        let mut access = Vec::new();
+        for r in vec {
+            match r {
+                Relation::Read(i) => {
+                    self.cur_related.vec.push(Relation::With(i));
+                    access.push(Relation::OptRead(i));
+                }
+                Relation::Write(i) | Relation::ShareWrite(i) => {
+                    self.cur_related.vec.push(Relation::With(i));
+                    access.push(Relation::OptWrite(i));
+                }
+                Relation::OptRead(_) | Relation::OptWrite(_) => access.push(r),
+                _ => self.cur_related.vec.push(r),
+            }
+        }
+        self.cur_related.vec.extend(access);
+    }
     /// 用当前的关系表记录关系
     pub fn relate(&mut self, r: Relation<ComponentIndex>) {
         self.cur_related.vec.push(r);
@@ -409,8 +544,58 @@ impl SystemMeta {
         r: Relation<TypeId>,
     ) -> usize {
         self.res_related.vec.push(r);
+        self.res_names
+            .entry(info.type_id)
+            .or_insert_with(|| info.type_name.clone());
         world.or_register_single_res(info)
     }
+    /// 声明运行时必须存在的单例资源，SystemBuilder构建时检查
+    pub fn require_single_res(&mut self, info: TypeInfo) {
+        self.required_res.push(info);
+    }
+    // 记录顶层参数的起始位置，只在最外层的元组中记录
+    pub(crate) fn mark_param(&mut self, type_name: &'static str) {
+        if self.param_depth > 0 {
+            return;
+        }
+        self.param_marks.push(ParamMark {
+            related: self.vec.len(),
+            res: self.res_related.vec.len(),
+            required: self.required_res.len(),
+            type_name,
+        });
+    }
+    // 查找组件的读写冲突，返回冲突的两个关系表的位置和组件，参数集内的关系表互不检查
+    pub(crate) fn find_conflict(&self) -> Option<(usize, usize, ComponentIndex)> {
+        for (i, r) in self.vec.iter().enumerate() {
+            if let Some(c) = r.check_self() {
+                return Some((i, i, c));
+            }
+        }
+        let sets: Vec<_> = self.vec.iter().map(|r| r.access_set()).collect();
+        for i in 0..self.vec.len() {
+            for j in i + 1..self.vec.len() {
+                if access_disjoint(&sets[i], &sets[j]) {
+                    continue;
+                }
+                if self
+                    .param_set_locations
+                    .iter()
+                    .any(|range| range.contains(&i) && range.contains(&j))
+                {
+                    continue;
+                }
+                let (r1, r2) = (&self.vec[i], &self.vec[j]);
+                if r1.check_without(r2) || r2.check_without(r1) {
+                    continue;
+                }
+                if let Some(c) = r1.check_rw(r2).or_else(|| r2.check_rw(r1)) {
+                    return Some((i, j, c));
+                }
+            }
+        }
+        None
+    }
     /// 加入一个资源
     pub fn add_res<'w>(&mut self, r: Relation<TypeId>) {
         self.res_related.vec.push(r);
@@ -429,10 +614,12 @@ impl SystemMeta {
             // 先检查自身
             assert_eq!(r.check_self(), None, "self conflict, related:{:?}", r);
         }
+        let sets: Vec<_> = self.vec.iter().map(|r| r.access_set()).collect();
         let mut range_it = self.param_set_locations.iter();
         let mut set_loc = range_it.next();
         for mut i in 0..self.vec.len() {
             let r = &self.vec[i];
+            let set = &sets[i];
             if let Some(range) = set_loc {
                 if range.end == i {
                     // 本次param_set_location结束，跳到下一个param_set_location
@@ -445,6 +632,9 @@ impl SystemMeta {
             i += 1;
             // 依次和后面的Related比较
             for j in i..self.vec.len() {
+                if access_disjoint(set, &sets[j]) {
+                    continue;
+                }
                 let r2 = &self.vec[j];
                 r.check_conflict(r2);
             }
@@ -488,6 +678,11 @@ pub trait System: Send + Sync + 'static {
 
     /// system align the world archetypes
     fn align(&mut self, world: &World);
+
+    /// system是否只读，不写组件和资源，需要在初始化后调用
+    fn is_read_only(&self) -> bool {
+        false
+    }
 }
 
 pub trait RunSystem: System {
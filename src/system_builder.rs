@@ -0,0 +1,160 @@
+//! 预先构建的system
+//! 普通的system在执行图初始化时才初始化参数，参数间的读写冲突不检查，缺少的单例资源要到第一次运行才panic。
+//! SystemBuilder在构建时立即初始化所有参数，检查组件和资源的读写冲突，检查SingleRes和SingleResMut的资源是否存在，
+//! 出错时返回SystemBuildError，包含出错参数在参数列表中的位置和类型名。
+//! 构建得到的BuiltSystem已持有初始化好的参数状态，可以直接运行，也可以加入App，执行图中的初始化不会再重复执行。
+//! 参数状态和构建时的world相关，BuiltSystem只能在构建时的world上运行。
+//! let s = SystemBuilder::new(sys).build(&mut world)?; app.add_system(Update, s);
+//!
+
+use std::borrow::Cow;
+use std::marker::PhantomData;
+
+use crate::function_system::{FunctionSystem, SystemParamFunction};
+use crate::schedule_config::{BaseConfig, IntoSystemConfigs, SystemConfig};
+use crate::system::{BoxedSystem, IntoSystem, ParamMark, Relation, RunSystem, System, SystemMeta};
+use crate::world::World;
+
+/// 参数在system参数列表中的位置和类型名
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamInfo {
+    pub index: usize,
+    pub type_name: Cow<'static, str>,
+}
+
+/// 构建system的错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SystemBuildError {
+    /// 两个参数对同一组件的读写冲突，同一参数内部冲突时两个参数相同
+    ComponentConflict {
+        system: Cow<'static, str>,
+        params: (ParamInfo, ParamInfo),
+        component: Cow<'static, str>,
+    },
+    /// 两个参数对同一单例资源的读写冲突
+    ResConflict {
+        system: Cow<'static, str>,
+        params: (ParamInfo, ParamInfo),
+        res: Cow<'static, str>,
+    },
+    /// 参数需要的单例资源不存在
+    MissingRes {
+        system: Cow<'static, str>,
+        param: ParamInfo,
+        res: Cow<'static, str>,
+    },
+}
+
+/// system的构建器
+pub struct SystemBuilder<Marker, Out, F> {
+    func: F,
+    _marker: PhantomData<fn() -> (Marker, Out)>,
+}
+
+impl<Marker: 'static, Out: 'static + Send + Sync, F: SystemParamFunction<Marker, Out>>
+    SystemBuilder<Marker, Out, F>
+{
+    pub fn new(func: F) -> Self {
+        Self {
+            func,
+            _marker: PhantomData,
+        }
+    }
+    /// 初始化所有参数并检查，成功返回可直接运行的system
+    pub fn build(self, world: &mut World) -> Result<BuiltSystem<Marker, Out, F>, SystemBuildError> {
+        let mut system = IntoSystem::into_system(self.func);
+        system.initialize(world);
+        check(&system.param.system_meta, world)?;
+        Ok(BuiltSystem { system })
+    }
+}
+
+/// 已构建的system，参数状态已初始化
+pub struct BuiltSystem<Marker: 'static, Out: 'static, F: SystemParamFunction<Marker, Out>> {
+    system: FunctionSystem<Marker, Out, F>,
+}
+
+impl<Marker: 'static, Out: 'static + Send + Sync, F: SystemParamFunction<Marker, Out>>
+    BuiltSystem<Marker, Out, F>
+{
+    pub fn name(&self) -> &Cow<'static, str> {
+        self.system.name()
+    }
+    pub fn meta(&self) -> &SystemMeta {
+        &self.system.param.system_meta
+    }
+    /// 在构建时的world上运行
+    pub fn run(&mut self, world: &World) -> Out {
+        self.system.run(world)
+    }
+    pub fn into_inner(self) -> FunctionSystem<Marker, Out, F> {
+        self.system
+    }
+}
+
+impl<Marker: 'static, F: SystemParamFunction<Marker, ()>> IntoSystemConfigs<()>
+    for BuiltSystem<Marker, (), F>
+{
+    fn into_configs(self) -> SystemConfig {
+        SystemConfig {
+            system: BoxedSystem::Sync(Box::new(self.system)),
+            config: BaseConfig::default(),
+        }
+    }
+}
+
+// 找出关系表位置所属的参数，没有记录参数位置时（参数不是元组）取整个参数列表
+fn param_info(meta: &SystemMeta, start: fn(&ParamMark) -> usize, i: usize) -> ParamInfo {
+    match meta.param_marks.iter().rposition(|m| start(m) <= i) {
+        Some(index) => ParamInfo {
+            index,
+            type_name: Cow::Borrowed(meta.param_marks[index].type_name),
+        },
+        None => ParamInfo {
+            index: 0,
+            type_name: meta.type_info.type_name.clone(),
+        },
+    }
+}
+
+fn check(meta: &SystemMeta, world: &World) -> Result<(), SystemBuildError> {
+    let system = meta.type_info.type_name.clone();
+    if let Some((i, j, index)) = meta.find_conflict() {
+        let component = world.get_column(index).unwrap().info().type_name().clone();
+        return Err(SystemBuildError::ComponentConflict {
+            system,
+            params: (param_info(meta, |m| m.related, i), param_info(meta, |m| m.related, j)),
+            component,
+        });
+    }
+    if let Some(tid) = meta.res_related.check_self() {
+        // 冲突的资源第一次和最后一次出现的参数
+        let mut it = meta
+            .res_related
+            .vec
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| match r {
+                Relation::Read(t) | Relation::Write(t) | Relation::OptRead(t) | Relation::OptWrite(t) => *t == tid,
+                _ => false,
+            })
+            .map(|(i, _)| i);
+        let first = it.next().unwrap_or(0);
+        let last = it.last().unwrap_or(first);
+        return Err(SystemBuildError::ResConflict {
+            params: (param_info(meta, |m| m.res, first), param_info(meta, |m| m.res, last)),
+            res: meta.res_names.get(&tid).cloned().unwrap_or_default(),
+            system,
+        });
+    }
+    for (i, info) in meta.required_res.iter().enumerate() {
+        if world.get_single_res_any(&info.type_id).is_none() {
+            return Err(SystemBuildError::MissingRes {
+                system,
+                param: param_info(meta, |m| m.required, i),
+                res: info.type_name.clone(),
+            });
+        }
+    }
+    Ok(())
+}
@@ -233,7 +233,14 @@ macro_rules! impl_system_param_tuple {
             type Item<'w> = ($($param::Item::<'w>,)*);
 
             fn init_state(_world: &mut World, _system_meta: &mut SystemMeta) -> Self::State {
-                (($($param::init_state(_world, _system_meta),)*))
+                // 最外层的元组是system的参数列表，记录每个参数的起始位置
+                (($({
+                    _system_meta.mark_param(std::any::type_name::<$param>());
+                    _system_meta.param_depth += 1;
+                    let s = $param::init_state(_world, _system_meta);
+                    _system_meta.param_depth -= 1;
+                    s
+                },)*))
             }
             // #[inline]
             // fn archetype_depend(_world: &World, _system_meta: &SystemMeta, state: &Self::State, _archetype: &Archetype, _result: &mut ArchetypeDependResult) {
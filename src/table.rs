@@ -4,8 +4,10 @@
 ///
 /// Alter所操作的源table， 在执行图中，会被严格保证不会同时有其他system进行操作。
 use core::fmt::*;
+use std::borrow::Cow;
 use std::mem::replace;
 use std::mem::size_of;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use fixedbitset::FixedBitSet;
 use pi_append_vec::AppendVec;
@@ -14,9 +16,133 @@ use pi_share::Share;
 
 use crate::archetype::ArchetypeIndex;
 use crate::archetype::Row;
+use crate::cleanup::CleanupQueue;
 use crate::column::Column;
+use crate::event::EventVec;
 use crate::world::{ComponentIndex, Entity, Tick, World};
 
+/// 严格模式下，检查同一线程在迭代原型时，是否对该原型做了结构修改（分配、销毁、标记移除和整理）。
+/// 这些修改会使正在迭代的行失效，应该用Alter等延迟的方式修改，Alter的修改在DeferredScope中进行，不检查。
+#[cfg(feature = "strict_iter")]
+mod strict {
+    use std::cell::{Cell, RefCell};
+
+    thread_local! {
+        // 本线程正在迭代的table及原型名
+        static ITERATING: RefCell<Vec<(usize, String)>> = RefCell::new(Vec::new());
+        // 本线程所在的延迟修改作用域的层数
+        pub(super) static DEFERRED: Cell<usize> = Cell::new(0);
+    }
+    pub(super) fn enter(ptr: usize, name: &str) {
+        ITERATING.with(|v| v.borrow_mut().push((ptr, name.to_string())));
+    }
+    pub(super) fn leave(ptr: usize) {
+        ITERATING.with(|v| {
+            let mut v = v.borrow_mut();
+            if let Some(i) = v.iter().rposition(|(p, _)| *p == ptr) {
+                v.remove(i);
+            }
+        });
+    }
+    pub(super) fn check(ptr: usize, op: &str) {
+        if DEFERRED.with(|d| d.get()) > 0 {
+            return;
+        }
+        ITERATING.with(|v| {
+            if let Some((_, name)) = v.borrow().iter().find(|(p, _)| *p == ptr) {
+                panic!(
+                    "strict_iter: {} on archetype {} while it is being iterated by this thread, use Alter or CommandQueue to defer the structural change",
+                    op, name
+                );
+            }
+        });
+    }
+}
+
+/// 延迟结构修改的作用域，作用域内对正在迭代的原型做结构修改是允许的。没有开启strict_iter时为空结构
+pub(crate) struct DeferredScope;
+impl DeferredScope {
+    #[inline(always)]
+    pub(crate) fn enter() -> Self {
+        #[cfg(feature = "strict_iter")]
+        strict::DEFERRED.with(|d| d.set(d.get() + 1));
+        DeferredScope
+    }
+}
+#[cfg(feature = "strict_iter")]
+impl Drop for DeferredScope {
+    fn drop(&mut self) {
+        strict::DEFERRED.with(|d| d.set(d.get() - 1));
+    }
+}
+
+/// table的结构变化，用于在外部镜像table的行（比如渲染的实例缓冲）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructuralChange {
+    /// 分配了行，实体插入或移动到本table
+    RowAllocated(Row, Entity),
+    /// 行被销毁或实体移动到其他table，整理前行仍然占位
+    RowDestroyed(Row, Entity),
+    /// 整理时实体从from行移动到to行
+    RowMoved { from: Row, to: Row, entity: Entity },
+    /// 两行的实体互相交换，a行的实体移动到b行，b行的实体移动到a行
+    RowSwapped { a: Row, b: Row },
+}
+
+/// 整理时计算移动对的策略，用于测试和基准比较，整理时使用Auto
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RemovesStrategy {
+    /// 根据移除的数量和位置选择
+    #[default]
+    Auto,
+    /// 用尾部存活的行逐个填补空位，只标记尾部，消耗为移除数量
+    SwapTail,
+    /// 排序移除的行，消耗为r*log2(r)
+    Sorted,
+    /// 标记全部的行，消耗为行数
+    Bitset,
+}
+
+/// 结构变化的读取位置，每个读取者一个，所有读取者都读取后，整理时清空变化列表
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StructuralCursor {
+    archetype: ArchetypeIndex,
+    listener_index: usize,
+}
+
+/// table的碎片统计，用于判断是否值得整理
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Fragmentation {
+    pub len: usize,             // 行数，包括空位
+    pub holes: usize,           // 空位数量
+    pub pending_removes: usize, // 待整理的移除数量
+    pub wasted_bytes: usize,    // 空位浪费的组件内存
+}
+impl Fragmentation {
+    /// 空位占行数的比例
+    pub fn hole_ratio(&self) -> f32 {
+        if self.len == 0 {
+            0.0
+        } else {
+            self.holes as f32 / self.len as f32
+        }
+    }
+    // 累加其他table的统计
+    pub(crate) fn add(&mut self, other: &Fragmentation) {
+        self.len += other.len;
+        self.holes += other.holes;
+        self.pending_removes += other.pending_removes;
+        self.wasted_bytes += other.wasted_bytes;
+    }
+}
+
+/// world上所有原型的碎片统计
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FragmentationReport {
+    pub total: Fragmentation,                          // 所有原型的合计
+    pub tables: Vec<(ArchetypeIndex, Fragmentation)>, // 有空位的原型，浪费的内存从大到小
+}
+
 pub struct Table {
     entities: AppendVec<Entity>, // 记录entity
     pub(crate) index: ArchetypeIndex,
@@ -24,7 +150,22 @@ pub struct Table {
     per_entity_mem_size: usize,         // 每实体的内存大小
     bit_set: FixedBitSet,               // 记录组件是否在table中
     pub(crate) removes: AppendVec<Row>, // 整理前被移除的实例
+    settle_generation: u32,             // 整理时移动过行的次数
+    remove_generation: u32,             // 整理时移除过行的次数
+    high_water: usize,                  // 整理前曾达到的最大行数
+    holes: AtomicUsize,                 // 销毁和移出产生的空位，整理时清零
+    structural: EventVec<StructuralChange>, // 结构变化，有读取者时才记录
+}
+// 没有默认值的组件无法初始化行
+#[cold]
+fn no_default(column: &Column, index: ArchetypeIndex) -> ! {
+    panic!(
+        "component {} has no default value, can't init row in archetype {:?}",
+        column.info().type_name(),
+        index
+    )
 }
+
 impl Table {
     pub fn new(sorted_columns: Vec<Share<Column>>) -> Self {
         let len = sorted_columns.len();
@@ -37,7 +178,9 @@ impl Table {
         let mut bit_set = FixedBitSet::with_capacity(max);
         for c in sorted_columns.iter() {
             per_entity_mem_size += c.info().mem_size as usize;
-            unsafe { bit_set.set_unchecked(c.info().index.index(), true) };
+            let index = c.info().index.index();
+            bit_set.grow(index + 1);
+            bit_set.insert(index);
         }
         Self {
             entities: AppendVec::default(),
@@ -46,6 +189,11 @@ impl Table {
             per_entity_mem_size,
             bit_set,
             removes: AppendVec::default(),
+            settle_generation: 0,
+            remove_generation: 0,
+            high_water: 0,
+            holes: AtomicUsize::new(0),
+            structural: EventVec::new("structural_changes"),
         }
     }
     /// Returns the number of elements in the archetype.
@@ -59,12 +207,122 @@ impl Table {
         c + self.sorted_columns.capacity() * size_of::<Share<Column>>()
             + self.removes.capacity() * size_of::<Row>() + size_of::<Self>()
     }
+    /// 全部行占用的内存，包括实体、每列的组件及tick，按各列实际分配的容量计算，不包括table自身的结构
+    pub fn total_memory(&self) -> usize {
+        let mut size = self.entities.capacity() * size_of::<Entity>();
+        let len = self.entities.len();
+        for c in self.sorted_columns.iter() {
+            let mem_size = c.info().mem_size as usize;
+            size += c.blob_ref_unchecked(self.index).blob.data_memsize(len, mem_size);
+        }
+        size
+    }
+    /// 存活的行占用的内存，和total_memory的比值为内存的利用率，可用来判断是否需要整理
+    pub fn live_memory(&self) -> usize {
+        self.live_len() * self.row_memory()
+    }
+    /// 存活的行数，不包括整理前被移除的行
+    #[inline(always)]
+    pub fn live_len(&self) -> usize {
+        self.entities.len() - self.removes.len()
+    }
+    /// 碎片统计，空位数量是增量维护的，不扫描行
+    pub fn fragmentation(&self) -> Fragmentation {
+        let holes = self.holes.load(Ordering::Relaxed);
+        Fragmentation {
+            len: self.entities.len(),
+            holes,
+            pending_removes: self.removes.len(),
+            wasted_bytes: holes * (self.per_entity_mem_size - size_of::<Entity>()),
+        }
+    }
+    // 每行的内存大小，列是否记录tick可能在table创建后才设置，所以每次计算
+    fn row_memory(&self) -> usize {
+        let mut size = self.per_entity_mem_size;
+        for c in self.sorted_columns.iter() {
+            if c.info().is_tick() {
+                // 添加和修改的tick，压缩模式下每个tick一个字节
+                size += if c.tick_policy().is_compressed() {
+                    2
+                } else {
+                    2 * size_of::<Tick>()
+                };
+            }
+        }
+        size
+    }
     #[inline(always)]
     pub fn get_unchecked(&self, row: Row) -> Entity {
         // todo 改成load_unchecked
         *self.entities.load(row.index()).unwrap()
         // *unsafe { self.entities.load_unchecked(row.index()) }
     }
+    /// 获得行上存活的实体，行越界、是空位或已被移除待整理，则返回None。
+    /// 迭代、按实体查询和列视图都用该方法判断行是否有效，保证各种方式的结果一致
+    #[inline(always)]
+    pub fn live_entity(&self, row: Row) -> Option<Entity> {
+        if row.is_null() || row.index() >= self.entities.len() {
+            return None;
+        }
+        let e = self.get_unchecked(row);
+        if e.is_null() {
+            None
+        } else {
+            Some(e)
+        }
+    }
+    /// 行的代数，每次整理移动了本table的行，代数加1。外部缓存的行可以用代数判断是否失效
+    #[inline(always)]
+    pub fn row_generation(&self) -> u32 {
+        self.settle_generation
+    }
+    /// 判断外部缓存的位置是否仍然有效：代数相同，行存活，并且行上的实体就是缓存的实体
+    #[inline(always)]
+    pub fn validate_location(&self, row: Row, e: Entity, generation: u32) -> bool {
+        generation == self.settle_generation && self.live_entity(row) == Some(e)
+    }
+    /// 结构版本，行的分配、移除和整理都会改变版本，用于不遍历行判断table的结构是否变化
+    #[inline(always)]
+    pub fn structure_version(&self) -> (Row, usize, u32) {
+        (self.len(), self.removes.len(), self.remove_generation)
+    }
+    /// 创建结构变化的读取者，只读取创建后的变化
+    pub(crate) fn structural_cursor(&mut self, owner: Cow<'static, str>) -> StructuralCursor {
+        let listener_index = self.structural.insert_listener(owner);
+        self.structural.mark_read(listener_index);
+        StructuralCursor {
+            archetype: self.index,
+            listener_index,
+        }
+    }
+    /// 读取cursor上次读取后的结构变化，按发生的顺序
+    pub fn structural_changes(
+        &self,
+        cursor: &mut StructuralCursor,
+    ) -> impl Iterator<Item = StructuralChange> + '_ {
+        assert_eq!(cursor.archetype, self.index, "structural cursor of another archetype");
+        self.structural.get_iter(cursor.listener_index).map(|c| *c)
+    }
+    #[inline(always)]
+    fn record_structural(&self, change: StructuralChange) {
+        if self.structural.listener_count() > 0 {
+            self.structural.record(change);
+        }
+    }
+    /// 记录行的分配，在分配的行写入实体时调用
+    #[inline(always)]
+    pub(crate) fn record_allocated(&self, row: Row, e: Entity) {
+        self.record_structural(StructuralChange::RowAllocated(row, e));
+    }
+    /// 记录两行实体的交换
+    #[inline(always)]
+    pub(crate) fn record_swapped(&self, a: Row, b: Row) {
+        self.record_structural(StructuralChange::RowSwapped { a, b });
+    }
+    /// 曾经分配过的最大行数，外部按行索引的数组可以用它确定大小
+    pub fn high_water_row(&self) -> Row {
+        Row(self.high_water.max(self.entities.len()) as u32)
+    }
     #[inline(always)]
     pub fn set(&self, row: Row, e: Entity) {
         // todo 改成load_unchecked
@@ -83,12 +341,39 @@ impl Table {
         }
     }
 
-    // 判断指定组件索引的组件是否在table中
+    /// 判断指定组件索引的组件是否在table中，超出位图长度的索引（包括空索引）返回false
     #[inline(always)]
     pub fn contains(&self, index: ComponentIndex) -> bool {
-        self.bit_set.contains(index.index())
+        index.index() < self.bit_set.len() && self.bit_set.contains(index.index())
+    }
+    /// 组件索引的位图。长度只到table中最大的组件索引加1，不是world的组件数量，调用者不能假设长度
+    pub fn component_bitset(&self) -> &FixedBitSet {
+        &self.bit_set
+    }
+    /// set中的组件是否都在table中，set的长度可以和table的位图不同
+    pub fn contains_all(&self, set: &FixedBitSet) -> bool {
+        set.is_subset(&self.bit_set)
+    }
+    /// set中是否有任何组件在table中，set的长度可以和table的位图不同
+    pub fn contains_any(&self, set: &FixedBitSet) -> bool {
+        !set.is_disjoint(&self.bit_set)
     }
 
+    /// 迭代table中的组件索引，升序
+    pub fn component_indexs(&self) -> impl Iterator<Item = ComponentIndex> + '_ {
+        self.bit_set.ones().map(|i| i.into())
+    }
+
+    /// 获得指定组件索引的列
+    pub fn get_column(&self, index: ComponentIndex) -> Option<&Share<Column>> {
+        if !self.contains(index) {
+            return None;
+        }
+        self.sorted_columns
+            .binary_search_by(|c| c.info().index.cmp(&index))
+            .ok()
+            .map(|i| &self.sorted_columns[i])
+    }
     pub(crate) fn get_column_unchecked(&self, index: usize) -> &Share<Column> {
         unsafe { self.sorted_columns.get_unchecked(index) }
     }
@@ -109,34 +394,79 @@ impl Table {
         }
     }
 
+    // 严格模式下，标记本线程开始迭代该table
+    #[cfg(feature = "strict_iter")]
+    pub(crate) fn iter_enter(&self, name: &str) {
+        strict::enter(self as *const Self as usize, name);
+    }
+    // 严格模式下，标记本线程结束迭代该table
+    #[cfg(feature = "strict_iter")]
+    pub(crate) fn iter_leave(&self) {
+        strict::leave(self as *const Self as usize);
+    }
+    // 严格模式下，检查本线程是否正在迭代该table
+    #[inline(always)]
+    fn check_iter(&self, _op: &str) {
+        #[cfg(feature = "strict_iter")]
+        strict::check(self as *const Self as usize, _op);
+    }
     #[inline(always)]
     pub fn alloc(&self) -> (&mut Entity, usize) {
+        self.check_iter("alloc");
         self.entities.alloc()
     }
-    /// 销毁，用于destroy
-    pub(crate) fn destroy(&self, row: Row) -> Entity {
+    /// 独占时连续分配n行，返回第一行。先扩容实体和各列，分配的行都在连续内存中。
+    /// 行中的实体为null，迭代时视为空位，调用者写完各列后用set发布实体
+    pub fn alloc_n(&mut self, n: usize) -> Row {
+        self.check_iter("alloc_n");
+        let start = self.entities.len();
+        self.reserve(n);
+        for _ in 0..n {
+            *self.entities.alloc().0 = Entity::null();
+        }
+        Row(start as u32)
+    }
+    /// 预先分配但没有写入的行，标记为移除，整理时回收。行上没有组件，不释放也不记录结构变化
+    pub(crate) fn mark_unused(&self, row: Row) {
+        self.removes.insert(row);
+        self.holes.fetch_add(1, Ordering::Relaxed);
+    }
+    /// 销毁，用于destroy，组件按world的释放策略立即释放或记入释放队列
+    pub(crate) fn destroy(&self, row: Row, cleanup: &CleanupQueue) -> Entity {
+        self.check_iter("destroy");
         // todo 改成load_unchecked
         let e = self.entities.load(row.index()).unwrap();
         if e.is_null() {
             return *e;
         }
-        for c in self.sorted_columns.iter() {
-            let c = c.blob_ref_unchecked(self.index);
-            c.drop_row(row, *e);
+        let deferred = cleanup.is_deferred();
+        for column in self.sorted_columns.iter() {
+            let c = column.blob_ref_unchecked(self.index);
+            c.tombstone(row);
+            if deferred && column.info().drop_fn.is_some() {
+                cleanup.defer(column, self.index, row, *e);
+            } else {
+                c.drop_row(row, *e);
+            }
         }
         self.removes.insert(row);
+        self.holes.fetch_add(1, Ordering::Relaxed);
+        self.record_structural(StructuralChange::RowDestroyed(row, *e));
         replace(e, Entity::null())
     }
     /// 标记移出，用于alter
     /// mark removes a key from the archetype, returning the value at the key if the
     /// key was not previously removed.
     pub(crate) fn mark_remove(&self, row: Row) -> Entity {
+        self.check_iter("mark_remove");
         // todo 改成load_unchecked
         let e = self.entities.load(row.index()).unwrap();
         if e.is_null() {
             return *e;
         }
         self.removes.insert(row);
+        self.holes.fetch_add(1, Ordering::Relaxed);
+        self.record_structural(StructuralChange::RowDestroyed(row, *e));
         replace(e, Entity::null())
     }
     /// 初始化一个行，每个列都插入一个默认值
@@ -144,10 +474,62 @@ impl Table {
         for column in &self.sorted_columns {
             let c = column.blob_ref_unchecked(self.index);
             let dst_data: *mut u8 = c.load(row, e);
-            column.info().set_fn.unwrap()(world, dst_data);
+            column.info().set_fn.unwrap_or_else(|| no_default(column, self.index))(world, dst_data);
             c.added_tick(e, row, tick)
         }
     }
+    /// 批量初始化从start开始的count个连续行，每个列都插入默认值，行上的实体需要已经写入。
+    /// 先逐列写入全部行的组件，再逐列设置tick，比逐行初始化对缓存更友好
+    pub(crate) fn bulk_init_rows(&self, world: &mut World, start: Row, count: usize, tick: Tick) {
+        let rows = start.0..start.0 + count as u32;
+        for column in &self.sorted_columns {
+            let c = column.blob_ref_unchecked(self.index);
+            let set_fn = column.info().set_fn.unwrap_or_else(|| no_default(column, self.index));
+            for row in rows.clone() {
+                let row = Row(row);
+                let dst_data: *mut u8 = c.load(row, self.get_unchecked(row));
+                set_fn(world, dst_data);
+            }
+        }
+        for column in &self.sorted_columns {
+            let c = column.blob_ref_unchecked(self.index);
+            for row in rows.clone() {
+                let row = Row(row);
+                c.added_tick(self.get_unchecked(row), row, tick);
+            }
+        }
+    }
+
+    /// 输出前limit行的完整数据，包括实体及每列的原始字节和tick，用于调试
+    pub fn dump_rows(&self, limit: usize) -> String {
+        let mut s = String::new();
+        let len = self.entities.len().min(limit);
+        for row in 0..len {
+            let row = Row(row as u32);
+            let e = self.get_unchecked(row);
+            let _ = write!(s, "{:?} {:?}:", row, e);
+            if !e.is_null() {
+                for c in self.sorted_columns.iter() {
+                    let b = c.blob_ref_unchecked(self.index);
+                    let ptr = b.get_row(row, e);
+                    let bytes: &[u8] = if c.info().size() == 0 || ptr.is_null() {
+                        &[]
+                    } else {
+                        unsafe { std::slice::from_raw_parts(ptr, c.info().size()) }
+                    };
+                    let _ = write!(
+                        s,
+                        " {}={:?}@{:?}",
+                        c.info().type_name(),
+                        bytes,
+                        b.get_tick_unchecked(row)
+                    );
+                }
+            }
+            s.push('\n');
+        }
+        s
+    }
 
     /// 获得移除数组产生的动作， 返回新entitys的长度
     pub(crate) fn removes_action(
@@ -156,52 +538,125 @@ impl Table {
         entity_len: usize,
         action: &mut Vec<(Row, Row)>,
         set: &mut FixedBitSet,
+    ) -> usize {
+        Self::removes_action_with(removes, remove_len, entity_len, action, set, RemovesStrategy::Auto)
+    }
+    /// 用指定的策略获得移除数组产生的动作， 返回新entitys的长度。
+    /// 各策略都是用尾部存活的行填补前面的空位，移动的行数相同且最少，只是计算移动对的消耗不同
+    pub fn removes_action_with(
+        removes: &AppendVec<Row>,
+        remove_len: usize,
+        entity_len: usize,
+        action: &mut Vec<(Row, Row)>,
+        set: &mut FixedBitSet,
+        strategy: RemovesStrategy,
     ) -> usize {
         action.clear();
-        // 根据4种情况， 获得新长度new_entity_len，并且在action中放置了移动对
         if remove_len >= entity_len {
             // 全部移除
             return 0;
         }
-        if remove_len == 1 {
-            // 移除一个，用交换尾部的方式
-            let remove_row = unsafe { removes.get_unchecked(0) };
-            if remove_row.index() + 1 < entity_len {
-                action.push((Row(entity_len as u32 - 1), *remove_row));
+        let new_len = entity_len - remove_len;
+        // 移除行的位置分布
+        let (mut min, mut max) = (usize::MAX, 0);
+        for row in removes.iter() {
+            min = min.min(row.index());
+            max = max.max(row.index());
+        }
+        if min >= new_len {
+            // 移除的都在尾部，直接截断
+            return new_len;
+        }
+        let strategy = match strategy {
+            RemovesStrategy::Auto => {
+                let r = remove_len as f64;
+                if remove_len > 1 && r * r.log2() < new_len as f64 {
+                    // 少量移除，排序的结果按空位递增，移动时对缓存更友好
+                    RemovesStrategy::Sorted
+                } else {
+                    RemovesStrategy::SwapTail
+                }
             }
-            return entity_len - 1;
+            s => s,
+        };
+        match strategy {
+            RemovesStrategy::Sorted => Self::sorted_action(removes, entity_len, action),
+            RemovesStrategy::Bitset => Self::bitset_action(removes, entity_len, action, set),
+            _ => Self::swap_tail_action(removes, entity_len, new_len, max, action, set),
         }
-        let r = remove_len as f64;
-        if r * r.log2() < (entity_len - remove_len) as f64 {
-            // 少量移除， 走removes排序，计算好移动对
-            // 需要扫描removes一次，排序一次，再扫描action一次, 消耗为n*log2n+n
-            // 先将removes的数据放入action，然后排序
+    }
+    // 用尾部存活的行逐个填补空位，位标记只覆盖尾部[new_len, entity_len)，消耗为r
+    fn swap_tail_action(
+        removes: &AppendVec<Row>,
+        entity_len: usize,
+        new_len: usize,
+        max: usize,
+        action: &mut Vec<(Row, Row)>,
+        set: &mut FixedBitSet,
+    ) -> usize {
+        // 最大的移除行不在尾部，则尾部都是存活的行，不需要位标记
+        let tail_removed = max >= new_len;
+        if tail_removed {
+            set.clear();
+            set.grow(entity_len - new_len);
             for row in removes.iter() {
-                action.push((*row, *row));
+                if row.index() >= new_len {
+                    set.set(row.index() - new_len, true);
+                }
             }
-            action.sort_unstable();
-            // 按从后移动到前的方式，计算移动对
-            let mut start = 0;
-            let mut end = action.len();
-            let mut index = entity_len;
-            while start < end {
-                index -= 1;
-                let remove_row = unsafe { action.get_unchecked(end - 1) };
-                if remove_row.0.index() == index {
-                    // 最大的要移动的行就是entitys的最后一个，则跳过
-                    end -= 1;
-                    continue;
+        }
+        let mut end = entity_len;
+        for row in removes.iter() {
+            if row.index() >= new_len {
+                continue;
+            }
+            // 找到尾部最后一个未被移除的
+            loop {
+                end -= 1;
+                if !tail_removed || !set.contains(end - new_len) {
+                    break;
                 }
-                // 移动到前面
-                let r = unsafe { action.get_unchecked_mut(start) };
-                r.0 = Row(index as u32);
-                start += 1;
             }
-            action.truncate(end);
-            return index;
+            action.push((Row(end as u32), *row));
         }
-        // 大量移除，走fixbitset的位标记方式，再次扫描，计算移动对
-        // 需要扫描removes一次，entitys一次, 消耗为entity_len
+        new_len
+    }
+    // 走removes排序，计算好移动对
+    // 需要扫描removes一次，排序一次，再扫描action一次, 消耗为n*log2n+n
+    fn sorted_action(removes: &AppendVec<Row>, entity_len: usize, action: &mut Vec<(Row, Row)>) -> usize {
+        // 先将removes的数据放入action，然后排序
+        for row in removes.iter() {
+            action.push((*row, *row));
+        }
+        action.sort_unstable();
+        // 按从后移动到前的方式，计算移动对
+        let mut start = 0;
+        let mut end = action.len();
+        let mut index = entity_len;
+        while start < end {
+            index -= 1;
+            let remove_row = unsafe { action.get_unchecked(end - 1) };
+            if remove_row.0.index() == index {
+                // 最大的要移动的行就是entitys的最后一个，则跳过
+                end -= 1;
+                continue;
+            }
+            // 移动到前面
+            let r = unsafe { action.get_unchecked_mut(start) };
+            r.0 = Row(index as u32);
+            start += 1;
+        }
+        action.truncate(end);
+        index
+    }
+    // 走fixbitset的位标记方式，再次扫描，计算移动对
+    // 需要扫描removes一次，entitys一次, 消耗为entity_len
+    fn bitset_action(
+        removes: &AppendVec<Row>,
+        entity_len: usize,
+        action: &mut Vec<(Row, Row)>,
+        set: &mut FixedBitSet,
+    ) -> usize {
         set.clear();
         set.grow(entity_len);
         for row in removes.iter() {
@@ -235,6 +690,9 @@ impl Table {
         action: &mut Vec<(Row, Row)>,
         set: &mut FixedBitSet,
     ) -> bool {
+        self.check_iter("settle");
+        // 清空所有读取者都已读取的结构变化
+        self.structural.settle();
         let remove_len = self.removes.len();
         if remove_len == 0 {
             let entity_len = self.entities.len();
@@ -243,12 +701,19 @@ impl Table {
                 action.clear();
                 self.settle_columns(entity_len, 0, &action);
             }
+            self.rebase_ticks(world.tick());
             return true;
         }
+        self.high_water = self.high_water.max(self.entities.len());
         let new_entity_len =
             Self::removes_action(&self.removes, remove_len, self.entities.len(), action, set);
-        // 清理removes
+        if !action.is_empty() {
+            self.settle_generation = self.settle_generation.wrapping_add(1);
+        }
+        self.remove_generation = self.remove_generation.wrapping_add(1);
+        // 清理removes，空位都被合并
         self.removes.clear(0);
+        *self.holes.get_mut() = 0;
         // 整理全部的列, 合并空位
         self.settle_columns(new_entity_len, 0, &action);
         // 再移动entitys的空位
@@ -258,6 +723,11 @@ impl Table {
             *unsafe { self.entities.get_unchecked_mut(dst.index()) } = e;
             // 修改world上entity的地址
             world.replace_row(e, *dst);
+            self.record_structural(StructuralChange::RowMoved {
+                from: *src,
+                to: *dst,
+                entity: e,
+            });
         }
         // 设置成正确的长度
         unsafe {
@@ -265,8 +735,17 @@ impl Table {
         };
         // 整理合并内存
         self.entities.settle(0);
+        self.rebase_ticks(world.tick());
         true
     }
+    // 推进压缩tick的列的base
+    fn rebase_ticks(&mut self, tick: Tick) {
+        let len = self.entities.len();
+        for c in self.sorted_columns.iter_mut() {
+            let c = unsafe { Share::get_mut_unchecked(c) };
+            c.rebase_ticks(self.index, len, tick);
+        }
+    }
 }
 impl Drop for Table {
     fn drop(&mut self) {
@@ -292,11 +771,13 @@ impl Drop for Table {
 }
 
 impl Debug for Table {
+    // 只输出长度和容量，完整的行数据用dump_rows获得
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         f.debug_struct("Table")
-            .field("entitys", &self.entities)
-            .field("sorted_columns", &self.sorted_columns)
-            .field("removes", &self.removes)
+            .field("len", &self.entities.len())
+            .field("capacity", &self.entities.capacity())
+            .field("columns_len", &self.sorted_columns.len())
+            .field("removes_len", &self.removes.len())
             .finish()
     }
 }
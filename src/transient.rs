@@ -0,0 +1,255 @@
+//! 帧内组件
+//! 伤害、碰撞接触、输入命中等组件只在一帧内有效，用Alter添加再移除会让实体在原型间来回移动两次。
+//! 用TickPolicy::frame_scoped()注册的组件不放入原型，存放在world的帧内存储中，整理时整体清除（执行析构），不产生结构变化。
+//! 帧内组件不属于原型，所以&T、With<T>、Added<T>无法匹配，需要用FrameScoped<T>读取，用WithFrameScoped<T>过滤。
+//! 同一实体重复插入时，后插入的值覆盖之前的值。Transient<T>声明对帧内存储的共享写，FrameScoped<T>和WithFrameScoped<T>声明读，
+//! 调度器据此检查冲突，生产者和消费者的先后需要由system的顺序保证。
+//! fn damage(t: Transient<Damage>) { t.insert(e, Damage(10)).unwrap(); }
+//! fn react(q: Query<(Entity, FrameScoped<Damage>), WithFrameScoped<Damage>>) { ... }
+//!
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::mem::transmute;
+
+use dashmap::DashMap;
+use pi_append_vec::SafeVec;
+use pi_share::Share;
+
+use crate::archetype::{ArchetypeIndex, Row};
+use crate::fetch::FetchComponents;
+use crate::filter::FilterComponents;
+use crate::query::QueryError;
+use crate::system::{Relation, SystemMeta};
+use crate::system_params::SystemParam;
+use crate::world::{Entity, Tick, World};
+
+/// 一种帧内组件的存储
+pub struct TransientColumn<T> {
+    vec: SafeVec<(Entity, T)>,
+    map: DashMap<Entity, usize>, // 实体 -> 最后插入的位置
+}
+// 和原型中的列一样，组件不要求Send和Sync
+unsafe impl<T> Send for TransientColumn<T> {}
+unsafe impl<T> Sync for TransientColumn<T> {}
+impl<T> Default for TransientColumn<T> {
+    fn default() -> Self {
+        Self {
+            vec: SafeVec::default(),
+            map: DashMap::default(),
+        }
+    }
+}
+impl<T> TransientColumn<T> {
+    /// 插入实体的帧内组件，已有则覆盖
+    pub fn insert(&self, e: Entity, value: T) {
+        let index = self.vec.insert((e, value));
+        self.map.insert(e, index);
+    }
+    pub fn get(&self, e: Entity) -> Option<&T> {
+        let index = *self.map.get(&e)?;
+        self.vec.get(index).map(|(_, v)| v)
+    }
+    pub fn contains(&self, e: Entity) -> bool {
+        self.map.contains_key(&e)
+    }
+    /// 有帧内组件的实体数量
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+    /// 按插入顺序遍历，跳过被覆盖的值
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.vec.iter().enumerate().filter_map(|(i, (e, v))| {
+            match self.map.get(e) {
+                Some(index) if *index == i => Some((*e, &*v)),
+                _ => None,
+            }
+        })
+    }
+    // 清除所有的值，返回析构的数量
+    fn clear(&mut self) -> usize {
+        let len = self.vec.len();
+        self.vec.clear(0);
+        self.map.clear();
+        len
+    }
+}
+
+type ClearFn = fn(&mut Share<dyn Any + Send + Sync>) -> usize;
+
+#[derive(Default)]
+pub(crate) struct Transients {
+    map: HashMap<TypeId, (Share<dyn Any + Send + Sync>, ClearFn)>,
+    cleared: usize, // 上次整理时清除的数量
+}
+
+impl World {
+    // 初始化帧内组件的存储
+    pub(crate) fn init_transient<T: 'static>(&mut self) {
+        self.transients
+            .map
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| {
+                let column: Share<dyn Any + Send + Sync> =
+                    Share::new(TransientColumn::<T>::default());
+                (column, |c| {
+                    unsafe { Share::get_mut_unchecked(c) }
+                        .downcast_mut::<TransientColumn<T>>()
+                        .unwrap()
+                        .clear()
+                })
+            });
+    }
+    /// 获得帧内组件的存储，组件没有用TickPolicy::frame_scoped()注册则返回None
+    pub fn get_transient<T: 'static>(&self) -> Option<&TransientColumn<T>> {
+        self.transients
+            .map
+            .get(&TypeId::of::<T>())
+            .and_then(|(c, _)| c.downcast_ref::<TransientColumn<T>>())
+    }
+    fn get_transient_share<T: 'static>(&self) -> Option<Share<TransientColumn<T>>> {
+        self.transients
+            .map
+            .get(&TypeId::of::<T>())
+            .and_then(|(c, _)| Share::downcast(c.clone()).ok())
+    }
+    /// 上次整理时清除的帧内组件数量
+    pub fn transients_cleared(&self) -> usize {
+        self.transients.cleared
+    }
+    // 整理时清除所有的帧内组件
+    pub(crate) fn clear_transients(&mut self) {
+        let mut cleared = 0;
+        for (c, clear) in self.transients.map.values_mut() {
+            cleared += clear(c);
+        }
+        self.transients.cleared = cleared;
+    }
+}
+
+// 帧内存储在调度关系中的键
+#[inline]
+fn transient_id<T: 'static>() -> TypeId {
+    TypeId::of::<TransientColumn<T>>()
+}
+
+/// 插入和读取一种帧内组件的参数，对该帧内存储是共享写，多个Transient<T>可以并行插入
+pub struct Transient<'w, T: 'static> {
+    world: &'w World,
+    column: &'w TransientColumn<T>,
+}
+
+impl<'w, T: 'static> Transient<'w, T> {
+    /// 插入实体的帧内组件，已有则覆盖，本帧整理时清除
+    pub fn insert(&self, e: Entity, value: T) -> Result<(), QueryError> {
+        if !self.world.contains(e) {
+            return Err(QueryError::NoSuchEntity(e));
+        }
+        self.column.insert(e, value);
+        Ok(())
+    }
+    pub fn get(&self, e: Entity) -> Option<&'w T> {
+        self.column.get(e)
+    }
+    pub fn contains(&self, e: Entity) -> bool {
+        self.column.contains(e)
+    }
+    /// 遍历本帧的帧内组件
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &'w T)> {
+        self.column.iter()
+    }
+}
+
+impl<T: 'static> SystemParam for Transient<'_, T> {
+    type State = Share<TransientColumn<T>>;
+    type Item<'w> = Transient<'w, T>;
+
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        meta.add_res(Relation::ShareWrite(transient_id::<T>()));
+        world.init_transient::<T>();
+        world.get_transient_share::<T>().unwrap()
+    }
+
+    #[inline]
+    fn get_param<'world>(
+        world: &'world World,
+        _system_meta: &'world SystemMeta,
+        state: &'world mut Self::State,
+        _tick: Tick,
+    ) -> Self::Item<'world> {
+        Transient { world, column: &**state }
+    }
+    #[inline]
+    fn get_self<'world>(
+        world: &'world World,
+        system_meta: &'world SystemMeta,
+        state: &'world mut Self::State,
+        tick: Tick,
+    ) -> Self {
+        unsafe { transmute(Self::get_param(world, system_meta, state, tick)) }
+    }
+}
+
+/// 读取实体的帧内组件，没有则为None
+pub struct FrameScoped<T: 'static>(PhantomData<T>);
+impl<T: 'static> FetchComponents for FrameScoped<T> {
+    type Fetch<'w> = &'w TransientColumn<T>;
+    type Item<'w> = Option<&'w T>;
+    type ReadOnly = Self;
+    type State = Share<TransientColumn<T>>;
+
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        meta.add_res(Relation::Read(transient_id::<T>()));
+        world.init_transient::<T>();
+        world.get_transient_share::<T>().unwrap()
+    }
+    #[inline]
+    fn init_fetch<'w>(
+        _world: &'w World,
+        state: &'w Self::State,
+        _index: ArchetypeIndex,
+        _tick: Tick,
+        _last_run: Tick,
+    ) -> Self::Fetch<'w> {
+        state
+    }
+
+    #[inline(always)]
+    fn fetch<'w>(fetch: &Self::Fetch<'w>, _row: Row, e: Entity) -> Self::Item<'w> {
+        fetch.get(e)
+    }
+}
+
+/// 过滤出本帧有该帧内组件的实体
+pub struct WithFrameScoped<T: 'static>(PhantomData<T>);
+impl<T: 'static> FilterComponents for WithFrameScoped<T> {
+    const ROW_FILTER: bool = true;
+    type Filter<'w> = &'w TransientColumn<T>;
+    type State = Share<TransientColumn<T>>;
+
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        meta.add_res(Relation::Read(transient_id::<T>()));
+        world.init_transient::<T>();
+        world.get_transient_share::<T>().unwrap()
+    }
+    #[inline]
+    fn init_filter<'w>(
+        _world: &'w World,
+        state: &'w Self::State,
+        _index: ArchetypeIndex,
+        _tick: Tick,
+        _last_run: Tick,
+    ) -> Self::Filter<'w> {
+        state
+    }
+    #[inline(always)]
+    fn filter<'w>(filter: &Self::Filter<'w>, _row: Row, e: Entity) -> bool {
+        !filter.contains(e)
+    }
+}
+/// 帧内组件每帧都会清除，所以本帧有的都是本帧添加的
+pub type AddedFrameScoped<T> = WithFrameScoped<T>;
@@ -0,0 +1,91 @@
+//! 一次决定多个组件的修改
+//! 同时更新Position、Velocity、Rotation时，每个Mut的deref_mut都各自记录修改，一次逻辑上的更新唤醒三组监听器，
+//! 读后写但最终没有改变值的写法也会产生修改记录。
+//! update将元组中的可写组件以&mut交给闭包，不记录修改，闭包返回实际修改的组件的位掩码，只有这些组件设置tick并记录修改事件。
+//! 位的顺序为组件在元组中的顺序。
+//! q.update(e, |(pos, vel, rot)| { pos.0 += vel.0; ChangeMask::bit(0) })?;
+//! for item in q.iter_mut() { item.update(|(pos, vel)| ...); }
+//!
+
+use std::ops::BitOr;
+
+use pi_proc_macros::all_tuples;
+
+use crate::fetch::{FetchComponents, Mut};
+use crate::filter::FilterComponents;
+use crate::query::{Query, QueryError};
+use crate::world::Entity;
+
+/// 修改了哪些组件，第i位为元组中第i个组件
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ChangeMask(pub u32);
+impl ChangeMask {
+    /// 没有修改
+    pub const NONE: ChangeMask = ChangeMask(0);
+    /// 全部修改
+    pub const ALL: ChangeMask = ChangeMask(u32::MAX);
+    /// 只修改了第i个组件
+    pub const fn bit(i: usize) -> Self {
+        ChangeMask(1 << i)
+    }
+    /// 加上第i个组件
+    pub const fn with(self, i: usize) -> Self {
+        ChangeMask(self.0 | (1 << i))
+    }
+    pub const fn contains(self, i: usize) -> bool {
+        self.0 & (1 << i) != 0
+    }
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+impl BitOr for ChangeMask {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        ChangeMask(self.0 | rhs.0)
+    }
+}
+
+/// 可写组件的元组，由闭包决定记录哪些组件的修改
+pub trait UpdateMut: Sized {
+    type Refs;
+    /// 闭包修改组件，只记录返回的掩码中的组件的修改，返回该掩码
+    fn update(self, f: impl FnOnce(Self::Refs) -> ChangeMask) -> ChangeMask;
+}
+
+macro_rules! impl_tuple_update {
+    ($($name: ident),*) => {
+        #[allow(non_snake_case)]
+        impl<'w, $($name: 'static),*> UpdateMut for ($(Mut<'w, $name>,)*) {
+            type Refs = ($(&'w mut $name,)*);
+            #[allow(unused_assignments)]
+            fn update(self, f: impl FnOnce(Self::Refs) -> ChangeMask) -> ChangeMask {
+                let ($(mut $name,)*) = self;
+                let mask = f(($($name.c.column.get_mut::<$name>($name.row, $name.e),)*));
+                let mut i = 0;
+                $(
+                    if mask.contains(i) {
+                        $name.set_changed();
+                    }
+                    i += 1;
+                )*
+                mask
+            }
+        }
+    };
+}
+all_tuples!(impl_tuple_update, 1, 15, T);
+
+impl<'w, Q: FetchComponents, F: FilterComponents> Query<'w, Q, F> {
+    /// 对实体的可写组件调用f，只记录f返回的掩码中的组件的修改。Q必须是可写组件的元组
+    pub fn update<'a>(
+        &'a mut self,
+        e: Entity,
+        f: impl FnOnce(<Q::Item<'a> as UpdateMut>::Refs) -> ChangeMask,
+    ) -> Result<ChangeMask, QueryError>
+    where
+        Q::Item<'a>: UpdateMut,
+    {
+        Ok(self.get_mut(e)?.update(f))
+    }
+}
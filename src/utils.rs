@@ -30,3 +30,17 @@ impl<T: Null + Clone> VecExt<T> for Vec<T> {
         unsafe { *self.get_unchecked_mut(index) = value; };
     }
 }
+
+/// 预取ptr所在的缓存行，只是对cpu的提示，ptr无效也不会出错，x86_64和aarch64以外的平台不做任何事
+#[inline(always)]
+#[allow(unused_unsafe, unused_variables)]
+pub(crate) fn prefetch_read(ptr: *const u8) {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        core::arch::x86_64::_mm_prefetch::<{ core::arch::x86_64::_MM_HINT_T0 }>(ptr as *const i8)
+    };
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!("prfm pldl1keep, [{0}]", in(reg) ptr, options(nostack, readonly, preserves_flags))
+    };
+}
@@ -13,31 +13,45 @@
 ///
 use crate::alter::{AlterState, QueryAlterState};
 use crate::archetype::{
-    Archetype, ArchetypeIndex, ArchetypeInfo, ComponentInfo, Row, ShareArchetype,
+    Archetype, ArchetypeIndex, ArchetypeInfo, ComponentInfo, Row, ShareArchetype, COMPONENT_TICK,
 };
-use crate::column::Column;
+use crate::column::{Column, SpanDedup, TickPolicy};
+use crate::component_id::{next_archetype_id, same_components};
 #[cfg(debug_assertions)]
 use crate::column::{ARCHETYPE_INDEX, COMPONENT_INDEX};
 use crate::editor::{EditorState, EntityEditor};
 use crate::fetch::{ColumnTick, FetchComponents};
+use crate::event::StalledReport;
 use crate::filter::FilterComponents;
-use crate::insert::{Bundle, InsertState};
+use crate::extension::{Extensions, SettleReport};
+use crate::reference::References;
+use crate::required::Requirements;
+use crate::transient::Transients;
+use crate::cleanup::CleanupQueue;
+#[cfg(feature = "debug-names")]
+use crate::debug_name::EntityNames;
+use crate::deferred_add::DeferredAdds;
+use crate::table::{Fragmentation, FragmentationReport, StructuralCursor};
+use crate::group::{ComponentGroup, GroupId};
+use crate::insert::{Bundle, InsertState, InsertedEntities};
 use crate::listener::{EventListKey, ListenerMgr};
 use crate::multi_res::ResVec;
 use crate::prelude::Mut;
-use crate::query::{QueryError, QueryState};
+use crate::merge::EntityMapper;
+use crate::query::{AnyQueryState, Query, QueryError, QueryState};
 use crate::single_res::TickRes;
-use crate::system::{SystemMeta, TypeInfo};
+use crate::system::{IntoSystem, RunSystem, System, SystemMeta, TypeInfo};
 use core::fmt::*;
 use core::result::Result;
 use std::marker::PhantomData;
 use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
 use fixedbitset::FixedBitSet;
-use pi_append_vec::{SafeVec, SafeVecIter};
-use pi_key_alloter::new_key_type;
+use pi_append_vec::{AppendVec, SafeVec, SafeVecIter};
+use pi_key_alloter::{new_key_type, Key};
 use std::any::{Any, TypeId};
 use std::borrow::Cow;
+use std::cell::{Cell, SyncUnsafeCell};
 use std::collections::{hash_map::Entry as StdEntry, HashMap};
 use std::mem::{self, size_of, transmute, ManuallyDrop};
 use std::ops::Deref;
@@ -46,7 +60,7 @@ use std::sync::atomic::Ordering;
 // use pi_map::hashmap::HashMap;
 // use pi_map::Map;
 use pi_null::Null;
-use pi_share::{Share, ShareUsize};
+use pi_share::{Share, ShareBool, ShareMutex, ShareUsize};
 use pi_slot::{Iter, SlotMap};
 
 new_key_type! {
@@ -113,6 +127,30 @@ impl From<usize> for Tick {
     }
 }
 
+/// 实体的分配方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EntityAllocation {
+    /// 任意线程并发分配，实体的位取决于各线程分配的先后，销毁的索引按销毁的先后复用
+    #[default]
+    Concurrent,
+    /// 确定的分配，相同的创建和销毁序列总是得到相同的实体，用于存档稳定的实体id。
+    /// 整理时空闲的索引从小到大排列，之后的分配优先复用最小的空闲索引。
+    /// 每次分配按(调度阶段, system在拓扑排序中的位置, system内的调用顺序)排序，整理时如果各线程实际分配的先后与该顺序不同，
+    /// 按该顺序交换本帧分配的实体，交换的映射见World::stable_remap
+    Stable,
+}
+
+thread_local! {
+    // 当前线程上分配实体的顺序：(调度阶段, system在拓扑排序中的位置, 已分配的数量)，不在system中时位置为u32::MAX
+    static ALLOC_ORDER: Cell<(u32, u32, u32)> = const { Cell::new((0, u32::MAX, 0)) };
+}
+
+/// 创建world的选项
+#[derive(Debug, Clone, Default)]
+pub struct WorldOptions {
+    pub entity_allocation: EntityAllocation,
+}
+
 #[derive(Clone, Debug)]
 pub struct ArchetypeInit<'a>(pub &'a ShareArchetype, pub &'a World);
 #[derive(Clone, Debug)]
@@ -132,10 +170,32 @@ pub struct World {
     pub(crate) empty_archetype: ShareArchetype,
     pub(crate) entity_editor_state: EditorState,
     pub(crate) listener_mgr: ListenerMgr,
+    pub(crate) group_map: HashMap<Cow<'static, str>, GroupId>,
+    pub(crate) group_arr: Vec<ComponentGroup>,
+    pub(crate) query_states: SyncUnsafeCell<Vec<Box<dyn AnyQueryState>>>, // 注册在world上的持久查询状态，查询器借用world时通过内部可变性访问
+    pub(crate) extensions: Extensions, // world扩展
+    pub(crate) references: References, // 实体引用的策略及反向索引
+    pub(crate) required: Requirements, // 组件的依赖
+    pub(crate) transients: Transients, // 帧内组件的存储
+    pub(crate) cleanup_queue: CleanupQueue, // 帧末释放的被移除组件
+    pub(crate) deferred_adds: DeferredAdds, // 迭代中延迟添加的组件
+    #[cfg(feature = "debug-names")]
+    pub(crate) names: EntityNames, // 实体的调试名称
     archetype_init_key: EventListKey,
     archetype_ok_key: EventListKey,
+    pending_archetypes: AppendVec<ArchetypeIndex>, // 还未通知监听器的新原型
+    sync_archetype_notify: bool, // 原型创建时是否同步通知监听器
+    archetype_hooks: Vec<Box<dyn Fn(&Archetype) + Send + Sync>>, // 原型就绪前调用的钩子
+    entity_allocation: EntityAllocation,
+    alloc_stage: ShareUsize, // Stable分配时，本帧已开始的调度阶段数
+    stable_log: ShareMutex<Vec<((u32, u32, u32), Entity)>>, // Stable分配时，本帧分配的顺序及实体，按实际分配的先后
+    stable_allocated: usize, // Stable分配时，上次整理后分配的数量
+    stable_free: (u32, usize), // Stable分配时，上次整理空闲索引时的最大索引和空闲数量
+    stable_remap: EntityMapper, // 上次整理时按分配顺序交换的实体
     // 世界当前的tick
     tick: ShareUsize,
+    ticks_frozen: ShareBool, // tick是否被冻结
+    frozen_systems: HashMap<TypeId, Box<dyn Any + Send + Sync>>, // run_frozen初始化过的system，按类型缓存
 }
 impl Debug for World {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -191,20 +251,101 @@ impl World {
             archetype_arr_len: 1,
             empty_archetype,
             listener_mgr,
+            group_map: Default::default(),
+            group_arr: Default::default(),
+            query_states: Default::default(),
+            extensions: Default::default(),
+            references: Default::default(),
+            required: Default::default(),
+            transients: Default::default(),
+            cleanup_queue: Default::default(),
+            deferred_adds: Default::default(),
+            #[cfg(feature = "debug-names")]
+            names: Default::default(),
             archetype_init_key,
             archetype_ok_key,
+            pending_archetypes: AppendVec::default(),
+            sync_archetype_notify: true,
+            archetype_hooks: Vec::new(),
+            entity_allocation: EntityAllocation::Concurrent,
+            alloc_stage: ShareUsize::new(0),
+            stable_log: ShareMutex::new(Vec::new()),
+            stable_allocated: 0,
+            stable_free: (0, 0),
+            stable_remap: EntityMapper::default(),
             tick: ShareUsize::new(1),
+            ticks_frozen: ShareBool::new(false),
+            frozen_systems: HashMap::new(),
             entity_editor_state: Default::default(),
         }
     }
+    /// 用指定的选项创建world
+    pub fn with_options(options: WorldOptions) -> Self {
+        let mut world = Self::new();
+        world.entity_allocation = options.entity_allocation;
+        world
+    }
+    pub fn entity_allocation(&self) -> EntityAllocation {
+        self.entity_allocation
+    }
+    /// 存活实体的最大索引，用于存档时确定实体表的大小，没有实体返回None
+    pub fn max_entity_index(&self) -> Option<u32> {
+        self.entities.iter().map(|(e, _)| e.data().as_ffi() as u32).max()
+    }
     // 获得世界当前的tick
     pub fn tick(&self) -> Tick {
         self.tick.load(Ordering::Relaxed).into()
     }
-    // 递增世界当前的tick，一般是每执行图执行时递增
+    // 递增世界当前的tick，一般是每执行图执行时递增。tick冻结时不递增，返回冻结的tick
     pub fn increment_tick(&self) -> Tick {
+        if self.ticks_frozen.load(Ordering::Relaxed) {
+            return self.tick();
+        }
         self.tick.fetch_add(1, Ordering::Relaxed).into()
     }
+    /// 冻结tick，用于调试器暂停模拟。冻结期间tick不递增，system的last_run也不推进，
+    /// 所以解冻后Changed和Added只对冻结前未读取的修改和解冻后的修改生效。
+    /// 冻结期间的写入会被标记为冻结的tick，对之前的last_run仍然是新的，检查用的system应该用run_frozen运行并且只读。
+    /// tick的比较是u32的直接比较，不处理回绕，冻结期间tick不前进，不消耗tick的空间
+    pub fn freeze_ticks(&self) {
+        self.ticks_frozen.store(true, Ordering::Relaxed);
+    }
+    /// 解冻tick
+    pub fn unfreeze_ticks(&self) {
+        self.ticks_frozen.store(false, Ordering::Relaxed);
+    }
+    pub fn is_ticks_frozen(&self) -> bool {
+        self.ticks_frozen.load(Ordering::Relaxed)
+    }
+    /// 在冻结tick的状态下运行一次检查用的system，运行后恢复原来的冻结状态，返回system的输出。
+    /// system按类型只初始化一次并缓存，之后的调用复用同一个system，不会重复注册监听器。
+    /// system必须只读，调试模式下写组件或资源的system会panic，Alter的结构修改也会panic
+    pub fn run_frozen<M, Out, S: IntoSystem<M, Out>>(&mut self, system: S) -> Out {
+        let id = TypeId::of::<S::System>();
+        let mut boxed = match self.frozen_systems.remove(&id) {
+            Some(s) => s,
+            None => {
+                let mut system = system.into_system();
+                system.initialize(self);
+                debug_assert!(
+                    system.is_read_only(),
+                    "run_frozen: system {} writes components or resources",
+                    system.name()
+                );
+                Box::new(system)
+            }
+        };
+        let system = boxed.downcast_mut::<S::System>().unwrap();
+        system.align(self);
+        let frozen = self.is_ticks_frozen();
+        self.freeze_ticks();
+        let r = system.run(self);
+        if !frozen {
+            self.unfreeze_ticks();
+        }
+        self.frozen_systems.insert(id, boxed);
+        r
+    }
     // /// 批量插入
     // pub fn batch_insert<'w, I, Ins>(&'w mut self, iter: I) -> InsertBatchIter<'w, I, Ins>
     // where
@@ -228,10 +369,49 @@ impl World {
             (ar.name(), ar_index.into())
         })
     }
+    /// 获得实体的位置，包括原型、行和原型的行代数，用于外部缓存实体的位置
+    pub fn get_entity_location(&self, entity: Entity) -> Option<EntityLocation> {
+        let addr = self.entities.get(entity)?;
+        let ar = self.archetype_arr.get(addr.archetype_index().index())?;
+        Some(EntityLocation {
+            archetype_index: addr.archetype_index(),
+            row: addr.row,
+            generation: ar.row_generation(),
+        })
+    }
+    /// 判断缓存的实体位置是否仍然有效
+    pub fn validate_location(&self, entity: Entity, location: &EntityLocation) -> bool {
+        match self.archetype_arr.get(location.archetype_index.index()) {
+            Some(ar) => ar.validate_location(location.row, entity, location.generation),
+            None => false,
+        }
+    }
     /// 是否存在实体
     pub fn contains(&self, entity: Entity) -> bool {
         self.entities.contains_key(entity)
     }
+    /// 迭代全部存活的实体及其位置，包括空实体，直接遍历实体表，跳过已分配但还未写入原型的实体。
+    /// 用于同步点等独占world的场合，有并发的结构性修改时，迭代的结果不确定
+    pub fn iter_entities(&self) -> impl Iterator<Item = (Entity, EntityLocation)> + '_ {
+        self.entities.iter().filter_map(move |(e, addr)| {
+            let ar = self.archetype_arr.get(addr.archetype_index().index())?;
+            if !addr.row.is_null() && ar.live_entity(addr.row) != Some(e) {
+                return None;
+            }
+            Some((
+                e,
+                EntityLocation {
+                    archetype_index: addr.archetype_index(),
+                    row: addr.row,
+                    generation: ar.row_generation(),
+                },
+            ))
+        })
+    }
+    /// 存活的实体数量，和iter_entities一致
+    pub fn entity_count(&self) -> usize {
+        self.iter_entities().count()
+    }
     /// 获得指定组件的索引
     pub fn get_component_index(&self, component_type_id: &TypeId) -> ComponentIndex {
         self.component_map
@@ -286,6 +466,7 @@ impl World {
             c.info.info.tick_info = tick_info;
             // 扫描当前列，将已有的实体设置tick
             c.update(&self.archetype_arr, |r, row, _| {
+                r.set_added_tick_unchecked(row, tick);
                 r.set_tick_unchecked(row, tick);
             });
         }
@@ -295,6 +476,78 @@ impl World {
     pub fn init_component<T: 'static>(&mut self) -> ComponentIndex {
         self.add_component_info(ComponentInfo::of::<T>(0)).0
     }
+    /// 注册组件的稳定名称，组件的stable_id由该名称计算，不随编译器版本变化。不同组件使用同一名称则panic
+    pub fn register_stable_name<T: 'static>(&mut self, name: &'static str) -> ComponentIndex {
+        let (index, mut column) = self.add_component_info(ComponentInfo::of::<T>(0));
+        if let Some(c) = self
+            .component_arr
+            .iter()
+            .find(|c| c.info().index != index && c.info().stable_name == Some(name))
+        {
+            panic!(
+                "stable name {} is registered by both {} and {}",
+                name,
+                c.info().type_name(),
+                std::any::type_name::<T>()
+            );
+        }
+        unsafe { Share::get_mut_unchecked(&mut column) }.info_mut().stable_name = Some(name);
+        index
+    }
+    /// 检查组件能不提供值添加（实现了Default或FromWorld），否则panic。用于在注册时提前发现问题
+    pub fn assert_defaultable<T: 'static>(&mut self) -> ComponentIndex {
+        let index = self.init_component::<T>();
+        if self.component_arr[index.index()].info().set_fn.is_none() {
+            panic!(
+                "component {} has no default value, implement Default or FromWorld, or always provide its value",
+                std::any::type_name::<T>()
+            );
+        }
+        index
+    }
+    // 结构操作用默认值初始化组件前检查，没有默认值的组件返回NotDefaultable
+    pub(crate) fn check_defaultable<'a>(
+        &self,
+        mut columns: impl Iterator<Item = &'a Share<Column>>,
+        ar_index: ArchetypeIndex,
+        op: &'static str,
+    ) -> Result<(), QueryError> {
+        match columns.find(|c| c.info().set_fn.is_none()) {
+            Some(c) => Err(QueryError::NotDefaultable(c.info().index, ar_index, op)),
+            None => Ok(()),
+        }
+    }
+    /// 设置指定组件tick的存储策略，必须在该组件被放入任何原型前设置，否则返回false
+    pub fn set_tick_policy<T: 'static>(&mut self, policy: TickPolicy) -> bool {
+        let index = self.init_component::<T>();
+        let column = unsafe { self.component_arr.get_unchecked_mut(index.index()) };
+        if column.archetypes_len() > 0 {
+            return column.tick_policy() == policy;
+        }
+        let c = unsafe { Share::get_mut_unchecked(column) };
+        c.info.policy = policy;
+        if policy.is_frame_scoped() {
+            self.init_transient::<T>();
+        }
+        true
+    }
+    /// 记录组件T在一帧内的修改范围，用ChangeSpan<T>读取，dedup为同一tick内多次修改的计数方式。
+    /// 只影响之后的修改
+    pub fn set_change_span<T: 'static>(&mut self, dedup: SpanDedup) {
+        let index = self.add_component_info(ComponentInfo::of::<T>(COMPONENT_TICK)).0;
+        self.init_change_span(index, Some(dedup));
+    }
+    // 开始记录组件的帧内修改范围，dedup为None时，已经在记录则不修改计数方式
+    pub(crate) fn init_change_span(&mut self, index: ComponentIndex, dedup: Option<SpanDedup>) {
+        let column = unsafe { self.component_arr.get_unchecked_mut(index.index()) };
+        let c = unsafe { Share::get_mut_unchecked(column) };
+        match dedup {
+            Some(d) => c.info.span = Some(d),
+            None => {
+                c.info.span.get_or_insert(SpanDedup::default());
+            }
+        }
+    }
     /// 计算所有原型信息，设置了所有组件的索引，按索引大小进行排序
     pub(crate) fn archetype_info(&mut self, components: Vec<ComponentInfo>) -> ArchetypeInfo {
         let vec: Vec<Share<Column>> = components
@@ -305,10 +558,20 @@ impl World {
     }
     /// 创建一个插入器
     pub fn make_insert<B: Bundle>(&mut self) -> InsertState<B> {
-        let components = B::components(Vec::new());
+        let components = self.add_required_infos(B::components(Vec::new()));
         let ar = self.find_ar(components);
         let s = B::init_item(self, &ar);
-        InsertState::new(ar, s)
+        InsertState::new(ar, s).init_required(self)
+    }
+    /// 批量插入同一原型的实体，连续分配行，见InsertState::insert_exact
+    pub fn insert_batch<B, I>(&mut self, iter: I) -> InsertedEntities
+    where
+        B: Bundle,
+        I: IntoIterator<Item = B>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let state = self.make_insert::<B>();
+        state.insert_exact(self, iter)
     }
     /// 兼容bevy的接口，提供query
     pub fn query<Q: FetchComponents + 'static, F: FilterComponents + 'static = ()>(
@@ -325,6 +588,52 @@ impl World {
         state.align(self);
         state
     }
+    /// 注册一个持久的查询状态，由world持有，返回索引。用于非system的代码，比如游戏循环直接查询
+    pub fn register_query_state<
+        Q: FetchComponents + 'static,
+        F: FilterComponents + 'static = (),
+    >(
+        &mut self,
+    ) -> usize {
+        let state = self.make_query::<Q, F>();
+        let states = self.query_states.get_mut();
+        states.push(Box::new(state));
+        states.len() - 1
+    }
+    /// 获得注册的查询状态，类型不匹配则返回None
+    pub fn get_query_state<Q: FetchComponents + 'static, F: FilterComponents + 'static = ()>(
+        &mut self,
+        index: usize,
+    ) -> Option<&mut QueryState<Q, F>> {
+        self.query_states
+            .get_mut()
+            .get_mut(index)?
+            .as_any_mut()
+            .downcast_mut()
+    }
+    /// 用注册的查询状态创建查询器
+    pub fn get_registered_query<
+        Q: FetchComponents + 'static,
+        F: FilterComponents + 'static = (),
+    >(
+        &mut self,
+        index: usize,
+    ) -> Option<Query<'_, Q, F>> {
+        let world: &World = self;
+        // 独占借用world期间，只有这里访问query_states；查询器只读取world的原型和实体，不会访问query_states
+        let states = unsafe { &mut *world.query_states.get() };
+        let state: &mut QueryState<Q, F> = states.get_mut(index)?.as_any_mut().downcast_mut()?;
+        state.align(world);
+        Some(Query::new(world, state, world.tick()))
+    }
+    /// 对齐所有注册的查询状态
+    pub fn align_query_states(&mut self) {
+        let mut states = mem::take(self.query_states.get_mut());
+        for state in states.iter_mut() {
+            state.align(self);
+        }
+        *self.query_states.get_mut() = states;
+    }
     /// 创建一个改变器
     pub fn make_alter<
         Q: FetchComponents + 'static,
@@ -500,6 +809,15 @@ impl World {
     pub(crate) fn get_event_record(&self, type_id: &TypeId) -> Option<Share<dyn Settle>> {
         self.event_map.get(type_id).map(|r| r.clone())
     }
+    /// 列出连续min_frames次整理都有未读事件、但没有读取的监听器，用于诊断没有被调度的system
+    pub fn stalled_consumers(&self, min_frames: u32) -> Vec<StalledReport> {
+        let mut result = Vec::new();
+        for r in self.event_map.values() {
+            r.stalled(min_frames, &mut result);
+        }
+        result.sort_by(|a, b| (&a.record, a.listener_index).cmp(&(&b.record, b.listener_index)));
+        result
+    }
 
     /// 获得指定实体的指定组件
     pub fn get_component<T: 'static>(&self, e: Entity) -> Result<&T, QueryError> {
@@ -563,29 +881,94 @@ impl World {
     pub fn get_archetype(&self, index: ArchetypeIndex) -> Option<&ShareArchetype> {
         self.archetype_arr.get(index.0 as usize)
     }
+    /// 创建原型结构变化的读取者，owner为读取者的名字，只读取创建后的变化
+    pub fn structural_cursor(
+        &mut self,
+        index: ArchetypeIndex,
+        owner: impl Into<Cow<'static, str>>,
+    ) -> Option<StructuralCursor> {
+        let ar = self.archetype_arr.get_mut(index.index())?;
+        let ar = unsafe { Share::get_mut_unchecked(ar) };
+        Some(ar.structural_cursor(owner.into()))
+    }
     pub(crate) unsafe fn get_archetype_unchecked(&self, index: ArchetypeIndex) -> &ShareArchetype {
         self.archetype_arr.get_unchecked(index.0 as usize)
     }
     pub fn archetype_list<'a>(&'a self) -> SafeVecIter<'a, ShareArchetype> {
         self.archetype_arr.iter()
     }
+    /// 预热插入B时使用的原型，在加载时调用，避免首次插入时才创建原型、初始化列和通知监听器。返回原型的索引
+    pub fn warm_spawn<B: Bundle>(&mut self) -> ArchetypeIndex {
+        let ar = self.find_ar(B::components(Vec::new()));
+        self.flush_archetype_notify();
+        ar.index()
+    }
+    /// 预热source原型的实体增加A组件、移除D组件后的目标原型，返回目标原型的索引，source原型不存在则返回None
+    pub fn warm_alter<A: Bundle, D: Bundle>(&mut self, source: ArchetypeIndex) -> Option<ArchetypeIndex> {
+        let src = self.archetype_arr.get(source.index())?.clone();
+        let mut columns: Vec<Share<Column>> = src.get_columns().clone();
+        let mut added = Vec::new();
+        for info in A::components(Vec::new()) {
+            let (index, c) = self.add_component_info(info);
+            added.push(index);
+            if !src.contains(index) {
+                columns.push(c);
+            }
+        }
+        let present: Vec<ComponentIndex> = columns.iter().map(|c| c.info().index).collect();
+        for r in self.required.missing(&added, &present) {
+            columns.push(self.get_column(r).unwrap().clone());
+        }
+        for info in D::components(Vec::new()) {
+            let index = self.get_component_index(info.type_id());
+            columns.retain(|c| c.info().index != index);
+        }
+        let ar = self.find_archtype(ArchetypeInfo::sort(columns));
+        self.flush_archetype_notify();
+        Some(ar.index())
+    }
+    // 按组件查找原型，id冲突时探测下一个id
+    pub(crate) fn get_archetype_by_info(&self, info: &ArchetypeInfo) -> Option<ShareArchetype> {
+        let mut id = info.id;
+        loop {
+            let ar = self.archetype_map.get(&id)?;
+            if same_components(&ar, info) {
+                return Some(ar.clone());
+            }
+            id = next_archetype_id(id);
+        }
+    }
     // 返回原型及是否新创建 todo 改成mut
     pub(crate) fn find_ar(&mut self, infos: Vec<ComponentInfo>) -> ShareArchetype {
         let info = self.archetype_info(infos);
         self.find_archtype(info)
     }
     // 返回原型及是否新创建
-    pub(crate) fn find_archtype(&self, info: ArchetypeInfo) -> ShareArchetype {
+    pub(crate) fn find_archtype(&self, mut info: ArchetypeInfo) -> ShareArchetype {
         // 如果world上没有找到对应的原型，则创建并放入world中
-        let (mut ar, b) = match self.archetype_map.entry(info.id) {
-            Entry::Occupied(entry) => (entry.get().clone(), false),
-            Entry::Vacant(entry) => {
-                let ar = Share::new(Archetype::new(info));
-                entry.insert(ar.clone());
-                (ar, true)
+        let (mut ar, b) = loop {
+            match self.archetype_map.entry(info.id) {
+                Entry::Occupied(entry) => {
+                    if same_components(entry.get(), &info) {
+                        break (entry.get().clone(), false);
+                    }
+                    // id冲突，探测下一个id
+                    info.id = next_archetype_id(info.id);
+                }
+                Entry::Vacant(entry) => {
+                    let ar = Share::new(Archetype::new(info));
+                    entry.insert(ar.clone());
+                    break (ar, true);
+                }
             }
         };
         if b {
+            if !self.sync_archetype_notify {
+                // 原型直接就绪，查询在对齐时会扫描到新原型，监听器的通知延迟到整理时批量进行
+                let ar_index = self.archtype_ok(&mut ar);
+                self.pending_archetypes.insert(ar_index);
+                return ar;
+            }
             // 通知原型创建，让各查询过滤模块初始化原型的记录列表，通知执行图更新
             self.listener_mgr
                 .notify_event(self.archetype_init_key, ArchetypeInit(&ar, &self));
@@ -605,6 +988,54 @@ impl World {
             }
         }
     }
+    /// 设置原型创建时是否同步通知监听器，默认同步，监听器在原型就绪前被调用。
+    /// 设为false时原型创建后直接就绪，监听器的通知延迟到整理时批量进行，适合一帧内创建大量原型的场合
+    pub fn set_sync_archetype_notify(&mut self, sync: bool) {
+        self.sync_archetype_notify = sync;
+        if sync {
+            self.flush_archetype_notify();
+        }
+    }
+    /// 还未通知监听器的新原型数量
+    pub fn pending_archetype_notify_len(&self) -> usize {
+        self.pending_archetypes.len()
+    }
+    /// 批量通知延迟的原型创建，整理时会自动调用
+    pub fn flush_archetype_notify(&mut self) {
+        if self.pending_archetypes.len() == 0 {
+            return;
+        }
+        for index in self.pending_archetypes.iter() {
+            let ar = unsafe { self.archetype_arr.get_unchecked(index.index()) };
+            self.listener_mgr
+                .notify_event(self.archetype_init_key, ArchetypeInit(ar, &self));
+            self.listener_mgr
+                .notify_event(self.archetype_ok_key, ArchetypeOk(ar, *index, &self));
+        }
+        self.pending_archetypes.clear(0);
+    }
+    /// 注册原型创建的钩子，在原型就绪前调用，可以用Archetype::set_user_data设置用户数据，
+    /// 保证任何system迭代该原型前数据已存在。注册时对已有的原型也调用一次
+    pub fn on_archetype_created(&mut self, hook: impl Fn(&Archetype) + Send + Sync + 'static) {
+        for ar in self.archetype_arr.iter() {
+            hook(ar);
+        }
+        self.archetype_hooks.push(Box::new(hook));
+    }
+    /// 在同步点设置原型的用户数据，原型不存在返回false
+    pub fn set_archetype_user_data<T: Send + Sync + 'static>(
+        &mut self,
+        index: ArchetypeIndex,
+        value: T,
+    ) -> bool {
+        match self.archetype_arr.get(index.index()) {
+            Some(ar) => {
+                ar.set_user_data(value);
+                true
+            }
+            None => false,
+        }
+    }
     // 先事件通知调度器，将原型放入数组，之后其他system可以看到该原型
     pub(crate) fn archtype_ok(&self, ar: &mut ShareArchetype) -> ArchetypeIndex {
         let entry = self.archetype_arr.alloc_entry();
@@ -612,6 +1043,9 @@ impl World {
         let mut_ar = unsafe { Share::get_mut_unchecked(ar) };
         mut_ar.set_index(index.into());
         mut_ar.init_blobs(); // 初始化原型中的blob
+        for hook in self.archetype_hooks.iter() {
+            hook(ar);
+        }
         ar.ready.store(true, Ordering::Relaxed);
         entry.insert(ar.clone()); // entry销毁后， 其他线程通过archetype_arr就可以看见该原型
         index.into()
@@ -619,8 +1053,105 @@ impl World {
     /// 插入一个新的EntityAddr
     #[inline(always)]
     pub(crate) fn insert_addr(&self, ar_index: ArchetypeIndex, row: Row) -> Entity {
+        if self.entity_allocation == EntityAllocation::Stable {
+            return self.insert_stable_addr(ar_index, row);
+        }
         self.entities.insert(EntityAddr::new(ar_index, row))
     }
+    // Stable分配时，分配和记录在同一个锁中，记录的先后就是实体实际分配的先后
+    fn insert_stable_addr(&self, ar_index: ArchetypeIndex, row: Row) -> Entity {
+        let order = ALLOC_ORDER.with(|o| {
+            let (mut stage, node, count) = o.get();
+            if node == u32::MAX {
+                // 不在system中的分配，排在当前阶段的system之后
+                stage = self.alloc_stage.load(Ordering::Relaxed) as u32;
+            }
+            o.set((stage, node, count + 1));
+            (stage, node, count)
+        });
+        let mut log = self.stable_log.lock().unwrap();
+        let e = self.entities.insert(EntityAddr::new(ar_index, row));
+        log.push((order, e));
+        e
+    }
+    /// 开始一个调度阶段，返回阶段的序号，Stable分配时用于确定本帧分配的顺序
+    pub(crate) fn begin_alloc_stage(&self) -> u32 {
+        self.alloc_stage.fetch_add(1, Ordering::Relaxed) as u32 + 1
+    }
+    /// 在当前线程上运行system前调用，之后的分配按system在拓扑排序中的位置排序
+    pub(crate) fn enter_system_alloc(&self, stage: u32, node: usize) {
+        if self.entity_allocation == EntityAllocation::Stable {
+            ALLOC_ORDER.with(|o| o.set((stage, node as u32, 0)));
+        }
+    }
+    /// system运行结束后调用
+    pub(crate) fn leave_system_alloc(&self) {
+        if self.entity_allocation == EntityAllocation::Stable {
+            ALLOC_ORDER.with(|o| o.set((0, u32::MAX, 0)));
+        }
+    }
+    /// 上次整理时，Stable分配按确定的顺序交换的实体(实际分配的实体, 交换后的实体)。
+    /// 组件事件及注册了引用策略的组件中的实体已被修正，原型的结构变化记录和系统保存的实体需要用它修正
+    pub fn stable_remap(&self) -> &EntityMapper {
+        &self.stable_remap
+    }
+    // 按(调度阶段, system在拓扑排序中的位置, 调用顺序)重新分配本帧的实体：实际分配的第i个实体交给排序后的第i次分配
+    fn merge_stable_entities(&mut self) {
+        let mut log = mem::take(self.stable_log.get_mut().unwrap());
+        *self.alloc_stage.get_mut() = 0;
+        self.stable_allocated = log.len();
+        log.retain(|(_, e)| self.entities.contains_key(*e));
+        let ids: Vec<Entity> = log.iter().map(|(_, e)| *e).collect();
+        // 排序是稳定的，同一线程上不在system中的分配保持实际的先后
+        log.sort_by_key(|(order, _)| *order);
+        let mut mapper = EntityMapper::default();
+        for ((_, old), new) in log.iter().zip(ids.iter()) {
+            if old != new {
+                mapper.insert(*old, *new);
+            }
+        }
+        if !mapper.is_empty() {
+            let moves: Vec<(Entity, EntityAddr)> = mapper
+                .iter()
+                .map(|(old, new)| (new, *self.entities.get(old).unwrap()))
+                .collect();
+            for (e, addr) in moves {
+                *unsafe { self.entities.load_unchecked(e) } = addr;
+                if !addr.row.is_null() {
+                    self.get_archetype(addr.archetype_index()).unwrap().set(addr.row, e);
+                }
+            }
+            self.remap_entities(&mapper);
+        }
+        self.stable_remap = mapper;
+    }
+    // 修正组件事件和注册了引用策略的组件中的实体
+    fn remap_entities(&self, mapper: &EntityMapper) {
+        for c in self.component_arr.iter() {
+            for r in [&c.info.added, &c.info.changed, &c.info.removed].into_iter().flatten() {
+                r.map_entities(&mut |e| mapper.map(e));
+            }
+        }
+        self.remap_references(mapper);
+    }
+    // 空闲的索引从小到大排列，之后的分配优先复用最小的索引。重新排列会让空闲索引的版本增加，所以只在有实体被销毁后排列
+    fn sort_free_entities(&mut self) {
+        let max = self.entities.max();
+        let free = max as usize - self.entities.len();
+        // 上次排列后从队首弹出的空闲索引，剩下的仍然有序
+        let popped = self.stable_allocated.saturating_sub((max - self.stable_free.0) as usize);
+        let destroyed = free + popped > self.stable_free.1;
+        self.stable_free = (max, free);
+        if !destroyed || free < 2 {
+            return;
+        }
+        let mut keys: Vec<Entity> = (0..free).map(|_| unsafe { self.entities.alloc_key() }).collect();
+        keys.sort_by_key(|k| k.data().index());
+        for k in keys {
+            let _ = unsafe { self.entities.set_value(k, EntityAddr::new(0usize.into(), Row::null())) };
+            self.entities.remove(k);
+        }
+    }
     /// 替换Entity的原型及行
     #[inline(always)]
     pub(crate) fn replace(&self, e: Entity, ar_index: ArchetypeIndex, row: Row) -> EntityAddr {
@@ -631,6 +1162,26 @@ impl World {
     pub fn contains_entity(&self, e: Entity) -> bool {
         self.entities.get(e).is_some()
     }
+    /// 从实体表移除实体，所有销毁实体的路径（destroy_entity、Alter、EntityEditor、归档）都经过这里。
+    /// 记录给引用的修正、调试名称的回收和扩展的回调，row为实体被移除前的行，空实体的原型为空原型，行为null
+    pub(crate) fn on_entity_removed(&self, ar: &Archetype, row: Row, e: Entity) {
+        if !self.entities.contains_key(e) {
+            panic!("entity {} removed from archetype {} row {:?} is not alive", self.entity_label(e), ar.name(), row);
+        }
+        self.entities.remove(e);
+        self.references.record_destroyed(e);
+        #[cfg(feature = "debug-names")]
+        self.names.record_destroyed(e);
+        self.extensions.record_destroyed(e);
+    }
+    /// 用于错误和日志的实体描述，开启debug-names特性且实体有名称时带上名称
+    pub fn entity_label(&self, e: Entity) -> String {
+        #[cfg(feature = "debug-names")]
+        if let Some(name) = self.entity_name(e) {
+            return format!("{:?} \"{}\"", e, name);
+        }
+        format!("{:?}", e)
+    }
     /// 销毁指定的实体
     pub fn destroy_entity(&mut self, e: Entity) -> Result<(), QueryError> {
         let addr = match self.entities.get(e) {
@@ -638,25 +1189,74 @@ impl World {
             None => return Err(QueryError::NoSuchEntity(e)),
         };
         if addr.row.is_null() {
-            self.entities.remove(e).unwrap();
+            self.on_entity_removed(&self.empty_archetype, addr.row, e);
+            #[cfg(feature = "debug-names")]
+            self.remove_entity_name(e);
+            self.notify_extensions_destroyed();
             return Ok(());
         }
         let ar = unsafe {
             self.archetype_arr
                 .get_unchecked(addr.archetype_index().index())
         };
-        let e = ar.destroy(addr.row);
+        self.run_destroy_hooks(ar, addr.row);
+        let e = ar.destroy(addr.row, &self.cleanup_queue);
         if e.is_null() {
             return Err(QueryError::NoSuchRow(addr.row));
         }
-        self.entities.remove(e).unwrap();
+        self.on_entity_removed(ar, addr.row, e);
+        #[cfg(feature = "debug-names")]
+        self.remove_entity_name(e);
+        self.notify_extensions_destroyed();
+        Ok(())
+    }
+
+    /// 交换同一原型内两个实体的行，用于空间排序等操作后调整实体的存储顺序，提高缓存命中
+    pub fn swap_entities(&mut self, a: Entity, b: Entity) -> Result<(), QueryError> {
+        let addr_a = match self.entities.get(a) {
+            Some(v) => *v,
+            None => return Err(QueryError::NoSuchEntity(a)),
+        };
+        let addr_b = match self.entities.get(b) {
+            Some(v) => *v,
+            None => return Err(QueryError::NoSuchEntity(b)),
+        };
+        let ar_index = addr_a.archetype_index();
+        if ar_index != addr_b.archetype_index() || addr_a.row.is_null() != addr_b.row.is_null() {
+            return Err(QueryError::NoMatchArchetype);
+        }
+        if a == b || addr_a.row.is_null() {
+            return Ok(());
+        }
+        let ar = unsafe { self.archetype_arr.get_unchecked(ar_index.index()) };
+        for c in ar.get_columns().iter() {
+            let column = c.blob_ref_unchecked(ar_index);
+            let size = c.info().size();
+            if size > 0 {
+                let pa = column.get_row(addr_a.row, a);
+                let pb = column.get_row(addr_b.row, b);
+                unsafe { ptr::swap_nonoverlapping(pa, pb, size) };
+            }
+            if c.info().is_tick() {
+                let tick = column.get_tick_unchecked(addr_a.row);
+                column.set_tick_unchecked(addr_a.row, column.get_tick_unchecked(addr_b.row));
+                column.set_tick_unchecked(addr_b.row, tick);
+                let tick = column.get_added_tick_unchecked(addr_a.row);
+                column.set_added_tick_unchecked(addr_a.row, column.get_added_tick_unchecked(addr_b.row));
+                column.set_added_tick_unchecked(addr_b.row, tick);
+            }
+        }
+        ar.set(addr_a.row, b);
+        ar.set(addr_b.row, a);
+        self.replace_row(a, addr_b.row);
+        self.replace_row(b, addr_a.row);
+        ar.record_swapped(addr_a.row, addr_b.row);
         Ok(())
     }
 
     /// 创建一个新的空实体
     pub fn spawn_empty(&self) -> Entity {
-        self.entities
-            .insert(EntityAddr::new(0usize.into(), Row::null()))
+        self.insert_addr(0usize.into(), Row::null())
     }
     /// 替换Entity的原型及行
     #[inline(always)]
@@ -683,6 +1283,33 @@ impl World {
         size += self.single_res_arr.len();
         size
     }
+    /// 统计每个组件的实体数量，按数量降序，用于找出使用最广的组件
+    pub fn component_usage_stats(&self) -> Vec<(ComponentIndex, usize)> {
+        let mut counts = vec![0; self.component_arr.len()];
+        for ar in self.archetype_arr.iter() {
+            // 不计算已被移除待整理的实体
+            let len = ar.len().index() - ar.removes.len();
+            if len == 0 {
+                continue;
+            }
+            for index in ar.component_indexs() {
+                counts[index.index()] += len;
+            }
+        }
+        let mut vec: Vec<(ComponentIndex, usize)> = counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| (i.into(), count))
+            .collect();
+        vec.sort_by(|a, b| b.1.cmp(&a.1));
+        vec
+    }
+    /// 立即依次执行命令，用于启动或测试等独占world的场合，和CommandQueue不同，命令不会被延迟
+    pub fn flush_commands_now<C: FnOnce(&mut World)>(&mut self, commands: impl IntoIterator<Item = C>) {
+        for c in commands {
+            c(self);
+        }
+    }
     /// 只有主调度完毕后，才能调用的整理方法，必须保证调用时没有其他线程读写world
     pub fn settle(&mut self) {
         self.settle_by(&mut Vec::new(), &mut FixedBitSet::new())
@@ -691,13 +1318,63 @@ impl World {
     pub fn init_ok(&mut self) {
         // todo 整理 self.listener_mgr.settle(0);
     }
+    /// 所有原型的碎片统计，有空位的原型按浪费的内存从大到小排列，可以优先整理
+    pub fn fragmentation_report(&self) -> FragmentationReport {
+        let mut report = FragmentationReport::default();
+        for ar in self.archetype_arr.iter() {
+            let f = ar.fragmentation();
+            report.total.add(&f);
+            if f.holes > 0 {
+                report.tables.push((ar.index(), f));
+            }
+        }
+        report
+            .tables
+            .sort_by(|a, b| b.1.wasted_bytes.cmp(&a.1.wasted_bytes));
+        report
+    }
     /// 只有主调度完毕后，才能调用的整理方法，必须保证调用时没有其他线程读写world
     pub fn settle_by(&mut self, action: &mut Vec<(Row, Row)>, set: &mut FixedBitSet) {
+        // 添加迭代中延迟添加的组件
+        self.flush_deferred_adds();
+        // 按确定的顺序重新分配本帧的实体，必须在修正引用前
+        if self.entity_allocation == EntityAllocation::Stable {
+            self.merge_stable_entities();
+        }
+        // 释放帧末策略下被移除的组件，必须在整理原型前
+        self.cleanup();
+        // 修正引用了已销毁实体的组件
+        if !self.references.is_empty() {
+            self.sweep_references();
+        }
+        // 清除本帧的帧内组件
+        self.clear_transients();
+        // 开始新的一帧，之前记录的帧内修改范围失效
+        for c in self.component_arr.iter() {
+            if c.info.span.is_some() {
+                c.info.span_frame.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        // 回收销毁的实体的名称
+        #[cfg(feature = "debug-names")]
+        self.sweep_names();
         // 整理实体
+        if self.entity_allocation == EntityAllocation::Stable {
+            self.sort_free_entities();
+        }
         self.entities.settle(0);
         // 整理原型数组
         self.archetype_arr.settle(0);
+        // 批量通知新原型
+        self.flush_archetype_notify();
         let len = self.archetype_arr.len();
+        let mut report = SettleReport {
+            archetypes_len: len,
+            new_archetypes: len - self.archetype_arr_len,
+            moved_rows: 0,
+            fragmentation_before: self.fragmentation_report().total,
+            fragmentation_after: Fragmentation::default(),
+        };
         if self.archetype_arr_len < len {
             // 原型增加，则整理所有的列
             for c in self.component_arr.iter_mut() {
@@ -714,8 +1391,15 @@ impl World {
         // 整理每个原型
         for ar in self.archetype_arr.iter() {
             let archetype = unsafe { Share::get_mut_unchecked(ar) };
+            action.clear();
             archetype.settle(self, action, set);
+            report.moved_rows += action.len();
         }
+        report.fragmentation_after = self.fragmentation_report().total;
+        // 对齐注册的查询状态
+        self.align_query_states();
+        // 回调world扩展
+        self.notify_extensions_settle(&report);
     }
 }
 unsafe impl Send for World {}
@@ -740,6 +1424,8 @@ pub trait TickMut: Downcast {
 
 pub trait Settle: Downcast {
     fn settle(&mut self);
+    /// 收集停滞的监听器
+    fn stalled(&self, _min_frames: u32, _result: &mut Vec<StalledReport>) {}
 }
 
 /// Creates an instance of the type this trait is implemented for
@@ -772,6 +1458,15 @@ impl<T: FromWorld> SetFromWorld for T {
 }
 
 
+/// 实体的位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntityLocation {
+    pub archetype_index: ArchetypeIndex,
+    pub row: Row,
+    pub generation: u32, // 原型的行代数
+}
+
+/// 实体在world上的地址，由原型索引和行组成，行为null表示实体还没有放入任何原型
 #[derive(Debug, Default, Clone, Copy)]
 pub struct EntityAddr {
     index: ArchetypeIndex,
@@ -796,6 +1491,11 @@ impl EntityAddr {
     pub(crate) fn mark(&mut self) {
         self.index = ArchetypeIndex(-self.index.0 - 1);
     }
+    /// 实体所在的行
+    #[inline(always)]
+    pub fn row(&self) -> Row {
+        self.row
+    }
     #[inline(always)]
     pub fn archetype_index(&self) -> ArchetypeIndex {
         if self.index.0 >= 0 || self.index.is_null() {
@@ -0,0 +1,225 @@
+//! world的构建器
+//! 预先注册组件、设置tick策略、创建Bundle的原型并预留容量，让最初几帧的运行不再创建原型和列、不再扩容。
+//! 注册和调用的顺序无关：组件按类型名的顺序注册，原型按Bundle类型名的顺序创建，所以组件索引和原型索引是确定的。
+//! 构建后在运行中首次出现的组件，索引仍取决于运行时的注册顺序。组件的稳定id由类型名计算，和顺序无关。
+//! 重复调用register注册同一组件、重复注册同一Bundle，在build时返回错误。
+//! let (world, report) = WorldBuilder::new().register::<Pos>(TickPolicy::full()).register_bundle::<(Pos, Vel)>().reserve_entities(1000).build()?;
+//!
+
+use std::any::{type_name, TypeId};
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
+
+use pi_share::Share;
+
+use crate::archetype::{ArchetypeIndex, ComponentInfo};
+use crate::cleanup::CleanupPolicy;
+use crate::column::TickPolicy;
+use crate::insert::Bundle;
+use crate::world::{ComponentIndex, EntityAllocation, World, WorldOptions};
+
+/// 构建world的错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorldBuildError {
+    /// 同一组件被register多次
+    DuplicateComponent(Cow<'static, str>),
+    /// 同一Bundle被注册多次
+    DuplicateBundle(Cow<'static, str>),
+}
+
+/// 注册的组件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisteredComponent {
+    pub index: ComponentIndex,
+    pub type_name: Cow<'static, str>,
+    pub size: usize,
+    pub policy: TickPolicy,
+}
+
+/// 预先创建的原型
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisteredArchetype {
+    pub index: ArchetypeIndex,
+    pub name: Cow<'static, str>,
+    pub reserved: usize, // 预留的行数
+}
+
+/// 注册报告，用于启动时的日志
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistrationReport {
+    pub components: Vec<RegisteredComponent>, // 按组件索引递增
+    pub archetypes: Vec<RegisteredArchetype>,
+    pub cleanup: CleanupPolicy,
+    pub entity_allocation: EntityAllocation,
+}
+impl Display for RegistrationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "components: {}, archetypes: {}, cleanup: {:?}, entity allocation: {:?}",
+            self.components.len(),
+            self.archetypes.len(),
+            self.cleanup,
+            self.entity_allocation
+        )?;
+        for c in self.components.iter() {
+            writeln!(f, "  component {:?} {} size: {} policy: {:?}", c.index, c.type_name, c.size, c.policy)?;
+        }
+        for a in self.archetypes.iter() {
+            writeln!(f, "  archetype {:?} {} reserved: {}", a.index, a.name, a.reserved)?;
+        }
+        Ok(())
+    }
+}
+
+// 注册组件并设置tick策略
+fn register_component<T: 'static>(world: &mut World, policy: TickPolicy) {
+    world.set_tick_policy::<T>(policy);
+}
+fn bundle_components<B: Bundle>() -> Vec<ComponentInfo> {
+    B::components(Vec::new())
+}
+
+struct ComponentEntry {
+    type_id: TypeId,
+    type_name: &'static str,
+    policy: TickPolicy,
+    register: fn(&mut World, TickPolicy),
+}
+
+struct BundleEntry {
+    type_id: TypeId,
+    type_name: &'static str,
+    components: fn() -> Vec<ComponentInfo>,
+}
+
+/// world的构建器
+#[derive(Default)]
+pub struct WorldBuilder {
+    components: Vec<ComponentEntry>,
+    bundles: Vec<BundleEntry>,
+    entities: usize,
+    reserves: Vec<(TypeId, usize)>,
+    cleanup: CleanupPolicy,
+    options: WorldOptions,
+}
+
+impl WorldBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// 注册组件，设置tick策略
+    pub fn register<T: 'static>(mut self, policy: TickPolicy) -> Self {
+        self.components.push(ComponentEntry {
+            type_id: TypeId::of::<T>(),
+            type_name: type_name::<T>(),
+            policy,
+            register: register_component::<T>,
+        });
+        self
+    }
+    /// 注册Bundle的所有组件，并创建Bundle的原型（包括依赖组件）
+    pub fn register_bundle<B: Bundle + 'static>(mut self) -> Self {
+        self.bundles.push(BundleEntry {
+            type_id: TypeId::of::<B>(),
+            type_name: type_name::<B>(),
+            components: bundle_components::<B>,
+        });
+        self
+    }
+    /// 每个Bundle的原型预留的行数
+    pub fn reserve_entities(mut self, n: usize) -> Self {
+        self.entities = n;
+        self
+    }
+    /// 包含组件T的Bundle原型预留的行数，和reserve_entities取大的
+    pub fn reserve_component<T: 'static>(mut self, n: usize) -> Self {
+        self.reserves.push((TypeId::of::<T>(), n));
+        self
+    }
+    /// 被移除组件的释放策略
+    pub fn cleanup_policy(mut self, policy: CleanupPolicy) -> Self {
+        self.cleanup = policy;
+        self
+    }
+    /// 是否用确定的实体分配，见EntityAllocation::Stable
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.options.entity_allocation = if deterministic {
+            EntityAllocation::Stable
+        } else {
+            EntityAllocation::Concurrent
+        };
+        self
+    }
+    /// 检查重复注册，创建world
+    pub fn build(mut self) -> Result<(World, RegistrationReport), WorldBuildError> {
+        self.components.sort_by(|a, b| a.type_name.cmp(b.type_name));
+        for w in self.components.windows(2) {
+            if w[0].type_id == w[1].type_id {
+                return Err(WorldBuildError::DuplicateComponent(w[0].type_name.into()));
+            }
+        }
+        self.bundles.sort_by(|a, b| a.type_name.cmp(b.type_name));
+        for w in self.bundles.windows(2) {
+            if w[0].type_id == w[1].type_id {
+                return Err(WorldBuildError::DuplicateBundle(w[0].type_name.into()));
+            }
+        }
+        let mut world = World::with_options(self.options);
+        world.set_cleanup_policy(self.cleanup);
+        for c in self.components.iter() {
+            (c.register)(&mut world, c.policy);
+        }
+        // 先按类型名注册Bundle的组件，再创建原型，组件索引和Bundle的顺序无关
+        let mut infos: Vec<ComponentInfo> = Vec::new();
+        let bundles: Vec<Vec<ComponentInfo>> = self
+            .bundles
+            .iter()
+            .map(|b| world.add_required_infos((b.components)()))
+            .collect();
+        for vec in bundles.iter() {
+            infos.extend(vec.iter().cloned());
+        }
+        infos.sort_by(|a, b| a.type_name().cmp(b.type_name()));
+        for info in infos {
+            world.add_component_info(info);
+        }
+        let mut archetypes = Vec::with_capacity(bundles.len());
+        for vec in bundles {
+            let mut ar = world.find_ar(vec);
+            let mut reserved = self.entities;
+            for (tid, n) in self.reserves.iter() {
+                if let Some(c) = world.get_column_by_id(tid) {
+                    if ar.contains(c.info().index) {
+                        reserved = reserved.max(*n);
+                    }
+                }
+            }
+            if reserved > 0 {
+                unsafe { Share::get_mut_unchecked(&mut ar) }.reserve(reserved);
+            }
+            archetypes.push(RegisteredArchetype {
+                index: ar.index(),
+                name: ar.name().clone(),
+                reserved,
+            });
+        }
+        let components = world
+            .component_arr
+            .iter()
+            .map(|c| RegisteredComponent {
+                index: c.info().index,
+                type_name: c.info().type_name().clone(),
+                size: c.info().size(),
+                policy: c.tick_policy(),
+            })
+            .collect();
+        let report = RegistrationReport {
+            components,
+            archetypes,
+            cleanup: world.cleanup_policy(),
+            entity_allocation: world.entity_allocation(),
+        };
+        Ok((world, report))
+    }
+}
@@ -0,0 +1,55 @@
+/* pi_world C接口的冒烟测试，由build.rs在ffi特性下编译，src/example.rs的test_ffi_c调用 */
+#include <stddef.h>
+#include <stdint.h>
+
+#include "pi_world.h"
+
+/* 读vel，写pos，pos += vel */
+static void integrate(uint64_t entity, uint8_t *const *ptrs, void *user_data) {
+    const size_t *vel = (const size_t *)ptrs[0];
+    size_t *pos = (size_t *)ptrs[1];
+    size_t *sum = (size_t *)user_data;
+    (void)entity;
+    *pos += *vel;
+    *sum += *pos;
+}
+
+/* pos_id和vel_id为两个size_t组件的稳定id，成功返回0，失败返回出错的行号 */
+int32_t pi_world_ffi_smoke(WorldHandle *handle, uint64_t pos_id, uint64_t vel_id) {
+    uint64_t ids[2] = {pos_id, vel_id};
+    uint64_t entities[3];
+    QueryHandle *query = NULL;
+    size_t sum = 0;
+    size_t i;
+
+    for (i = 0; i < 3; i++) {
+        size_t pos = i * 10;
+        size_t vel = i + 1;
+        const uint8_t *values[2] = {(const uint8_t *)&pos, (const uint8_t *)&vel};
+        entities[i] = pi_world_spawn(handle, ids, values, 2);
+        if (entities[i] == pi_entity_null() || !pi_world_contains(handle, entities[i])) {
+            return __LINE__;
+        }
+    }
+    if (pi_query_create(handle, &vel_id, 1, &pos_id, 1, NULL, 0, NULL, 0, &query) != PI_WORLD_OK) {
+        return __LINE__;
+    }
+    /* (0+1) + (10+2) + (20+3) */
+    if (pi_query_iter(handle, query, integrate, &sum) != PI_WORLD_OK || sum != 36) {
+        return __LINE__;
+    }
+    if (pi_world_despawn(handle, entities[1]) != PI_WORLD_OK) {
+        return __LINE__;
+    }
+    if (pi_world_despawn(handle, entities[1]) != PI_WORLD_NO_SUCH_ENTITY || pi_world_contains(handle, entities[1])) {
+        return __LINE__;
+    }
+    pi_world_settle(handle);
+    /* (1+1) + (23+3) */
+    sum = 0;
+    if (pi_query_iter(handle, query, integrate, &sum) != PI_WORLD_OK || sum != 28) {
+        return __LINE__;
+    }
+    pi_query_destroy(query);
+    return 0;
+}